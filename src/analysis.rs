@@ -0,0 +1,259 @@
+//! Analysis with the built-in search: the best lines of a position, and for a
+//! whole game the evaluation of every position, with the moves that gave
+//! away the most marked as inaccuracies, mistakes or blunders.
+
+use crate::search::{self, Score, SearchLimits};
+use crate::{Move, Player, State};
+
+/// Centipawns a move has to lose to count as an inaccuracy, a mistake or a blunder.
+pub const INACCURACY_LOSS: i32 = 50;
+pub const MISTAKE_LOSS: i32 = 100;
+pub const BLUNDER_LOSS: i32 = 300;
+// Evaluations are capped here when comparing them, as a position that stays
+// clearly won does not get worse by losing more of the lead
+const EVALUATION_CAP: i32 = 1000;
+
+/// A score and the player it is for, which is the player to move.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Evaluation {
+    pub score: Score,
+    pub player: Player,
+}
+
+impl Evaluation {
+    /// Centipawns from white's point of view, with mates as large values.
+    pub fn centipawns(&self) -> i32 {
+        match self.player {
+            Player::White => self.score.to_centipawns(),
+            Player::Black => -self.score.to_centipawns(),
+        }
+    }
+
+    /// The evaluation from white's point of view, like `+1.25`, `#3` when
+    /// white mates in three or `#-3` when black does.
+    pub fn format(&self) -> String {
+        let sign = if self.player == Player::White { 1 } else { -1 };
+        match self.score {
+            Score::Centipawns(centipawns) => format!("{:+.2}", (sign * centipawns) as f32 / 100.0),
+            Score::Mate(0) if self.player == Player::White => String::from("0-1"),
+            Score::Mate(0) => String::from("1-0"),
+            Score::Mate(moves) if sign * moves > 0 => format!("#{}", moves.abs()),
+            Score::Mate(moves) => format!("#-{}", moves.abs()),
+        }
+    }
+
+    /// White's part of an evaluation bar, from 0 to 1, with half for equal
+    /// positions and a pawn worth about a tenth more.
+    pub fn white_share(&self) -> f32 {
+        1.0 / (1.0 + (-self.centipawns() as f32 / 250.0).exp())
+    }
+
+    // Centipawns for `player`, capped
+    fn capped_for(&self, player: Player) -> i32 {
+        let centipawns = self.centipawns().clamp(-EVALUATION_CAP, EVALUATION_CAP);
+        if player == Player::White { centipawns } else { -centipawns }
+    }
+}
+
+/// One of the best lines of a position.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Line {
+    pub depth: u32,
+    pub evaluation: Evaluation,
+    pub moves: Vec<Move>,
+    pub san: Vec<String>,
+}
+
+/// The best `count` lines of `state`, best first.
+pub fn analyze_position(state: &State, limits: &SearchLimits, count: usize) -> Vec<Line> {
+    let player = state.get_current_player();
+    search::search_lines(state, limits, count, &mut |_| ()).into_iter()
+        .map(|info| Line {
+            depth: info.depth,
+            evaluation: Evaluation { score: info.score, player },
+            san: state.line_to_san(&info.pv),
+            moves: info.pv,
+        })
+        .collect()
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Classification {
+    Inaccuracy,
+    Mistake,
+    Blunder,
+}
+
+impl Classification {
+    /// The classification of a move that loses `loss` centipawns, if it is bad enough for one.
+    pub fn from_loss(loss: i32) -> Option<Classification> {
+        if loss >= BLUNDER_LOSS {
+            Some(Classification::Blunder)
+        } else if loss >= MISTAKE_LOSS {
+            Some(Classification::Mistake)
+        } else if loss >= INACCURACY_LOSS {
+            Some(Classification::Inaccuracy)
+        } else {
+            None
+        }
+    }
+
+    pub fn to_string(&self) -> &str {
+        match self {
+            Self::Inaccuracy => "inaccuracy",
+            Self::Mistake => "mistake",
+            Self::Blunder => "blunder",
+        }
+    }
+
+    /// The annotation symbol used in PGN: `?!`, `?` or `??`.
+    pub fn symbol(&self) -> &str {
+        match self {
+            Self::Inaccuracy => "?!",
+            Self::Mistake => "?",
+            Self::Blunder => "??",
+        }
+    }
+}
+
+/// A move of the game compared with what the search would have played.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MoveAnalysis {
+    pub mv: Move,
+    pub san: String,
+    pub player: Player,
+    pub best_move: Option<Move>,
+    pub best_san: Option<String>,
+    /// Centipawns the move gave away compared with the best move.
+    pub loss: i32,
+    pub classification: Option<Classification>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct GameAnalysis {
+    /// The evaluation of the start position and after each move.
+    pub evaluations: Vec<Evaluation>,
+    pub moves: Vec<MoveAnalysis>,
+}
+
+impl GameAnalysis {
+    pub fn count(&self, player: Player, classification: Classification) -> usize {
+        self.moves.iter().filter(|analysis| analysis.player == player && analysis.classification == Some(classification)).count()
+    }
+
+    /// The centipawns `player` lost on average per move.
+    pub fn average_loss(&self, player: Player) -> f32 {
+        let losses: Vec<i32> = self.moves.iter().filter(|analysis| analysis.player == player).map(|analysis| analysis.loss).collect();
+        if losses.is_empty() {
+            0.0
+        } else {
+            losses.iter().sum::<i32>() as f32 / losses.len() as f32
+        }
+    }
+}
+
+/// Evaluates every position of the game in `game`, which started from
+/// `start`, with the given limits for each position. `on_position` is called
+/// with the number of positions done and the total.
+pub fn analyze_game(start: &State, game: &State, limits: &SearchLimits,
+                    on_position: &mut dyn FnMut(usize, usize)) -> GameAnalysis {
+    let history = game.get_history();
+    let mut position = start.clone();
+    let mut evaluations = Vec::new();
+    let mut moves = Vec::new();
+
+    for index in 0..=history.len() {
+        let player = position.get_current_player();
+        let result = search::search(&position, limits, &mut |_| ());
+        evaluations.push(Evaluation { score: result.score, player });
+        on_position(index + 1, history.len() + 1);
+
+        if let Some(entry) = history.get(index) {
+            let best_san = result.best_move().and_then(|mv| position.move_to_san(mv));
+            if position.play_move(entry.mv).is_err() {
+                break;
+            }
+            moves.push(MoveAnalysis {
+                mv: entry.mv,
+                san: entry.san.clone(),
+                player,
+                best_move: result.best_move(),
+                best_san,
+                loss: 0,
+                classification: None,
+            });
+        }
+    }
+
+    for (index, analysis) in moves.iter_mut().enumerate() {
+        // Playing the best move loses nothing, whatever the next search thinks of it
+        if analysis.best_move == Some(analysis.mv) {
+            continue;
+        }
+        let before = evaluations[index].capped_for(analysis.player);
+        let after = evaluations[index + 1].capped_for(analysis.player);
+        analysis.loss = (before - after).max(0);
+        analysis.classification = Classification::from_loss(analysis.loss);
+    }
+    GameAnalysis { evaluations, moves }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn evaluation_test() {
+        let evaluation = Evaluation { score: Score::Centipawns(125), player: Player::Black };
+        assert_eq!(evaluation.centipawns(), -125);
+        assert_eq!(evaluation.format(), "-1.25");
+        assert!(evaluation.white_share() < 0.5);
+        assert_eq!(Evaluation { score: Score::Centipawns(0), player: Player::White }.white_share(), 0.5);
+        assert_eq!(Evaluation { score: Score::Mate(3), player: Player::Black }.format(), "#-3");
+        assert_eq!(Evaluation { score: Score::Mate(-2), player: Player::Black }.format(), "#2");
+        assert_eq!(Evaluation { score: Score::Mate(0), player: Player::Black }.format(), "1-0");
+        assert!(Evaluation { score: Score::Mate(0), player: Player::Black }.white_share() > 0.99);
+
+        assert_eq!(Classification::from_loss(30), None);
+        assert_eq!(Classification::from_loss(50), Some(Classification::Inaccuracy));
+        assert_eq!(Classification::from_loss(150), Some(Classification::Mistake));
+        assert_eq!(Classification::from_loss(900), Some(Classification::Blunder));
+    }
+
+    #[test]
+    fn position_test() {
+        // The knight takes the queen
+        let state = State::from_fen("4k3/8/3q1p2/8/4N3/8/8/4K3 w - - 0 1").unwrap();
+        let lines = analyze_position(&state, &SearchLimits::depth(2), 2);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].san[0], "Nxd6+");
+        assert!(lines[0].evaluation.centipawns() > lines[1].evaluation.centipawns());
+    }
+
+    #[test]
+    fn game_test() {
+        // 1. e4 e5 2. Qh5 Nc6 3. Bc4 Nf6?? 4. Qxf7#
+        let start = State::new();
+        let mut game = start.clone();
+        for san in ["e4", "e5", "Qh5", "Nc6", "Bc4", "Nf6", "Qxf7#"] {
+            let mv = game.move_from_san(san).unwrap();
+            game.play_move(mv).unwrap();
+        }
+
+        let mut progress = Vec::new();
+        let analysis = analyze_game(&start, &game, &SearchLimits::depth(2), &mut |done, total| progress.push((done, total)));
+        assert_eq!(progress.len(), 8);
+        assert_eq!(progress.last(), Some(&(8, 8)));
+        assert_eq!(analysis.evaluations.len(), 8);
+        assert_eq!(analysis.evaluations[7].format(), "1-0");
+
+        let blunder = &analysis.moves[5];
+        assert_eq!(blunder.san, "Nf6");
+        assert_eq!(blunder.classification, Some(Classification::Blunder));
+        assert!(blunder.loss >= BLUNDER_LOSS);
+        assert!(blunder.best_san.is_some());
+        assert_eq!(analysis.moves[6].loss, 0);
+        assert_eq!(analysis.count(Player::Black, Classification::Blunder), 1);
+        assert!(analysis.average_loss(Player::Black) > analysis.average_loss(Player::White));
+    }
+}
@@ -0,0 +1,51 @@
+use std::fs;
+use std::time::Duration;
+
+use chess::epd::{self, Epd, EpdResult};
+use chess::search::{Score, SearchLimits};
+
+fn get_arg<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1)).map(|arg| arg.as_str())
+}
+
+fn format_score(score: Score) -> String {
+    match score {
+        Score::Centipawns(centipawns) => format!("cp {}", centipawns),
+        Score::Mate(moves) => format!("mate {}", moves),
+    }
+}
+
+fn print_result(number: usize, epd: &Epd, result: &EpdResult) {
+    let id = epd.id.clone().unwrap_or_else(|| format!("#{}", number));
+    let san = result.best_move.and_then(|mv| epd.state.move_to_san(mv)).unwrap_or_else(|| String::from("-"));
+    let expected = epd.best_moves.iter().chain(&epd.avoid_moves).filter_map(|&mv| epd.state.move_to_san(mv)).collect::<Vec<String>>().join(" ");
+    let time = result.time_to_solution.map_or(String::from("-"), |time| format!("{} ms", time.as_millis()));
+    println!("{:30} {:8} {:8} depth {:2}  {:10}  {:3} points  {} (expected {})", id, if result.solved { "solved" } else { "unsolved" },
+             san, result.depth, format_score(result.score), result.points, time, expected);
+}
+
+fn main() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().collect();
+    let file = match args.last() {
+        Some(file) if args.len() > 1 && !args[args.len() - 2].starts_with("--") => file,
+        _ => return Err(String::from("Usage: epd [--depth plies | --time ms] suite.epd")),
+    };
+    let text = fs::read_to_string(file).map_err(|e| format!("Could not read {}: {}", file, e))?;
+    let suite = epd::parse_epd(&text).map_err(|e| format!("{}: {}", file, e.to_string()))?;
+
+    let limits = match (get_arg(&args, "--depth"), get_arg(&args, "--time")) {
+        (Some(depth), _) => SearchLimits::depth(depth.parse().map_err(|_| format!("Invalid depth: {}", depth))?),
+        (None, Some(time)) => SearchLimits::time(Duration::from_millis(time.parse().map_err(|_| format!("Invalid time: {}", time))?)),
+        (None, None) => SearchLimits::time(Duration::from_secs(1)),
+    };
+
+    let mut number = 0;
+    let report = epd::run_suite(&suite, &limits, &mut |epd, result| {
+        number += 1;
+        print_result(number, epd, result);
+    });
+    let average = report.average_time_to_solution().map_or(String::from("-"), |time| format!("{} ms", time.as_millis()));
+    println!("Solved {} of {}, {} of {} points, average time to solution {}", report.solved(), report.results.len(),
+             report.points(), report.total_max_points(), average);
+    Ok(())
+}
@@ -0,0 +1,59 @@
+use std::fs;
+use std::path::Path;
+
+use chess::explorer::{self, ExplorerBuilder, ExplorerDb};
+use chess::pgn::parse_pgn;
+use chess::State;
+
+fn get_arg<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1)).map(|arg| arg.as_str())
+}
+
+// Prints the moves played from a position
+fn probe(db: &mut ExplorerDb, fen: &str) -> Result<(), String> {
+    let state = State::from_fen(fen).map_err(|e| e.to_string().to_string())?;
+    let moves = db.query(&state).map_err(|e| e.to_string())?;
+    let total: u32 = moves.iter().map(|stats| stats.games()).sum();
+    for stats in moves {
+        let san = state.move_to_san(stats.mv).unwrap_or_else(|| stats.mv.to_uci());
+        let rating = stats.average_rating().map_or(String::from("-"), |rating| rating.to_string());
+        println!("{:8} {:6} games {:3.0}%  {:3.0}% white  {:3} {:3} {:3}  rating {}", san, stats.games(),
+                 stats.games() as f32 * 100.0 / total as f32, stats.white_score() * 100.0,
+                 stats.white_wins, stats.draws, stats.black_wins, rating);
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().collect();
+    let path = Path::new(get_arg(&args, "--db").unwrap_or("openings.db"));
+
+    if let Some(fen) = get_arg(&args, "--probe") {
+        let mut db = ExplorerDb::open(path).map_err(|e| format!("Could not open {}: {}", path.display(), e))?;
+        return probe(&mut db, fen);
+    }
+
+    // Every argument that is not an option or its value is a PGN file
+    let files: Vec<&String> = args.iter().enumerate().skip(1)
+        .filter(|(i, arg)| !arg.starts_with("--") && !args[i - 1].starts_with("--"))
+        .map(|(_, arg)| arg)
+        .collect();
+    if files.is_empty() {
+        return Err(String::from("Usage: explorer [--db openings.db] [--depth plies] games.pgn ... | explorer [--db openings.db] --probe FEN"));
+    }
+
+    let depth = match get_arg(&args, "--depth") {
+        Some(depth) => depth.parse().map_err(|_| format!("Invalid depth: {}", depth))?,
+        None => explorer::DEFAULT_DEPTH,
+    };
+    let mut builder = ExplorerBuilder::new(depth);
+    for file in files {
+        let text = fs::read_to_string(file).map_err(|e| format!("Could not read {}: {}", file, e))?;
+        let games = parse_pgn(&text).map_err(|e| format!("{}: {}", file, e.to_string()))?;
+        let added = games.iter().filter(|game| builder.add_game(game)).count();
+        println!("{}: {} of {} games added", file, added, games.len());
+    }
+    builder.write(path).map_err(|e| format!("Could not write {}: {}", path.display(), e))?;
+    println!("Wrote {} games to {}", builder.games(), path.display());
+    Ok(())
+}
@@ -0,0 +1,69 @@
+use std::fs;
+use std::path::Path;
+
+use chess::database::{self, GameDatabase, GameQuery, PawnStructure};
+use chess::State;
+
+fn get_arg<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1)).map(|arg| arg.as_str())
+}
+
+fn parse_fen(fen: &str) -> Result<State, String> {
+    State::from_fen(fen).map_err(|e| format!("Invalid FEN {}: {}", fen, e.to_string()))
+}
+
+fn create_query(args: &[String]) -> Result<GameQuery, String> {
+    let text = |name| get_arg(args, name).map(String::from);
+    let material = match get_arg(args, "--material") {
+        Some(material) => Some(database::parse_material(material).ok_or(format!("Invalid material: {}", material))?),
+        None => None,
+    };
+    Ok(GameQuery {
+        player: text("--player"),
+        white: text("--white"),
+        black: text("--black"),
+        date_from: text("--from"),
+        date_to: text("--to"),
+        result: text("--result"),
+        eco: text("--eco"),
+        position: get_arg(args, "--fen").map(parse_fen).transpose()?.map(|state| state.get_hash()),
+        material,
+        pawns: get_arg(args, "--pawns").map(parse_fen).transpose()?.map(|state| PawnStructure::of(&state)),
+    })
+}
+
+fn main() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().collect();
+    let path = Path::new(get_arg(&args, "--db").unwrap_or("games.pgn"));
+    let mut database = GameDatabase::open(path).map_err(|e| e.to_string().to_string())?;
+
+    if let Some(file) = get_arg(&args, "--import") {
+        let text = fs::read_to_string(file).map_err(|e| format!("Could not read {}: {}", file, e))?;
+        let count = database.import(&text).map_err(|e| format!("{}: {}", file, e.to_string()))?;
+        println!("Imported {} games, {} in {}", count, database.len(), path.display());
+        return Ok(());
+    }
+
+    // The positions of one game as FEN
+    if let Some(id) = get_arg(&args, "--positions") {
+        let id: usize = id.parse().map_err(|_| format!("Invalid game number: {}", id))?;
+        for state in database.positions(id) {
+            println!("{}", state.to_fen());
+        }
+        return Ok(());
+    }
+
+    let ids = database.search(&create_query(&args)?);
+    if let Some(file) = get_arg(&args, "--export") {
+        fs::write(file, database.export(&ids)).map_err(|e| format!("Could not write {}: {}", file, e))?;
+        println!("Exported {} games to {}", ids.len(), file);
+        return Ok(());
+    }
+    for &id in &ids {
+        let game = database.get(id).unwrap();
+        let tag = |name| game.tag(name).unwrap_or("?");
+        println!("{:5}  {} - {}  {}  {}  {}  {}", id, tag("White"), tag("Black"), tag("Result"), tag("Date"), tag("ECO"), tag("Event"));
+    }
+    println!("{} of {} games", ids.len(), database.len());
+    Ok(())
+}
@@ -0,0 +1,14 @@
+use chess::http::HttpServer;
+
+fn get_arg<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1)).map(|arg| arg.as_str())
+}
+
+fn main() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().collect();
+    let address = get_arg(&args, "--listen").unwrap_or("0.0.0.0:8080");
+    let server = HttpServer::bind(address).map_err(|e| format!("Could not listen on {}: {}", address, e))?;
+
+    println!("Listening on http://{}", server.local_addr().map_err(|e| e.to_string())?);
+    server.run().map_err(|e| e.to_string())
+}
@@ -0,0 +1,21 @@
+use std::sync::Arc;
+
+use chess::server::Server;
+
+fn get_arg<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1)).map(|arg| arg.as_str())
+}
+
+fn main() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().collect();
+    let address = get_arg(&args, "--listen").unwrap_or("0.0.0.0:7600");
+    let mut server = Server::bind(address).map_err(|e| format!("Could not listen on {}: {}", address, e))?;
+    if let Some(path) = get_arg(&args, "--pgn") {
+        let pgn_path = path.to_string();
+        server = server.with_pgn_file(path)
+            .with_save_error_handler(Arc::new(move |game, error| eprintln!("Could not save game {} to {}: {}", game, pgn_path, error)));
+    }
+
+    println!("Listening on {}", server.local_addr().map_err(|e| e.to_string())?);
+    server.run().map_err(|e| e.to_string())
+}
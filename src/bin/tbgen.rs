@@ -0,0 +1,55 @@
+use std::path::Path;
+
+use chess::retrograde::GeneratedTables;
+use chess::tablebase::{self, Tablebase};
+use chess::{Player, State};
+
+fn get_arg<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1)).map(|arg| arg.as_str())
+}
+
+// Prints what the tables say about every move of a position
+fn probe(tables: &GeneratedTables, fen: &str) -> Result<(), String> {
+    let state = State::from_fen(fen).map_err(|e| e.to_string().to_string())?;
+    match tables.probe_dtm(&state) {
+        Some(0) => println!("Draw"),
+        Some(plies) if plies > 0 => println!("Mate in {} moves", (plies + 1) / 2),
+        Some(plies) => println!("Mated in {} moves", -plies / 2),
+        None => return Err(format!("The position is not in the tables: {}", tablebase::material_key(&state))),
+    }
+    for root_move in tablebase::probe_root(tables, &state).unwrap_or_default() {
+        let san = state.move_to_san(root_move.mv).unwrap_or_default();
+        match root_move.dtm {
+            Some(plies) if plies != 0 => println!("{:8} {} in {} plies", san, root_move.wdl.to_string(), plies.abs()),
+            _ => println!("{:8} {}", san, root_move.wdl.to_string()),
+        }
+    }
+    Ok(())
+}
+
+fn main() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().collect();
+    let dir = Path::new(get_arg(&args, "--dir").unwrap_or("tables"));
+
+    if let Some(fen) = get_arg(&args, "--probe") {
+        let tables = GeneratedTables::load(dir).map_err(|e| format!("Could not read the tables in {}: {}", dir.display(), e))?;
+        return probe(&tables, fen);
+    }
+
+    // Every argument that is not an option or its value is a material
+    let materials: Vec<&String> = args.iter().enumerate().skip(1)
+        .filter(|(i, arg)| !arg.starts_with("--") && !args[i - 1].starts_with("--"))
+        .map(|(_, arg)| arg)
+        .collect();
+    if materials.is_empty() {
+        return Err(String::from("Usage: tbgen [--dir tables] KRvK KBNvK ... | tbgen [--dir tables] --probe FEN"));
+    }
+
+    let mut tables = GeneratedTables::load(dir).unwrap_or_default();
+    for material in materials {
+        let table = tables.generate(material).map_err(|e| format!("{}: {}", material, e.to_string()))?;
+        println!("{}: {} positions, longest mate {} plies with white to move, {} with black to move", table.key(),
+                 table.size(), table.longest_win(Player::White), table.longest_win(Player::Black));
+    }
+    tables.save(dir).map_err(|e| format!("Could not write the tables to {}: {}", dir.display(), e))
+}
@@ -0,0 +1,164 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use chess::clock::TimeControl;
+use chess::pgn;
+use chess::retrograde::GeneratedTables;
+use chess::tournament::{Adjudication, EngineConfig, Format, MatchSettings, Sprt, SprtResult, Tournament};
+use chess::variant::{self, Standard};
+
+fn get_arg<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1)).map(|arg| arg.as_str())
+}
+
+fn get_all_args<'a>(args: &'a [String], name: &str) -> Vec<&'a str> {
+    args.windows(2).filter(|pair| pair[0] == name).map(|pair| pair[1].as_str()).collect()
+}
+
+// Comma separated numbers, like `--sprt 0,5,0.05,0.05`
+fn parse_numbers(value: &str) -> Result<Vec<f64>, String> {
+    value.split(',').map(|number| number.trim().parse().map_err(|_| format!("Invalid number: {}", number))).collect()
+}
+
+fn parse_settings(args: &[String]) -> Result<MatchSettings, String> {
+    let control = get_arg(args, "--tc").unwrap_or("10+0.1");
+    let time_control = TimeControl::parse(control).ok_or(format!("Invalid time control: {}", control))?;
+
+    let mut adjudication = Adjudication::default();
+    if let Some(resign) = get_arg(args, "--resign") {
+        match parse_numbers(resign)?.as_slice() {
+            [score, moves] => {
+                adjudication.resign_score = Some(*score as i32);
+                adjudication.resign_moves = *moves as u32;
+            }
+            _ => return Err(String::from("--resign takes score,moves")),
+        }
+    }
+    if let Some(draw) = get_arg(args, "--draw") {
+        match parse_numbers(draw)?.as_slice() {
+            [score, moves, start] => {
+                adjudication.draw_score = Some(*score as i32);
+                adjudication.draw_moves = *moves as u32;
+                adjudication.draw_start = *start as u32;
+            }
+            _ => return Err(String::from("--draw takes score,moves,start")),
+        }
+    }
+    if let Some(max_moves) = get_arg(args, "--max-moves") {
+        adjudication.max_moves = Some(max_moves.parse().map_err(|_| format!("Invalid move count: {}", max_moves))?);
+    }
+    if let Some(dir) = get_arg(args, "--tb") {
+        let tables = GeneratedTables::load(Path::new(dir)).map_err(|e| format!("Could not read the tables in {}: {}", dir, e))?;
+        adjudication.tablebase = Some(Arc::new(tables));
+    }
+    Ok(MatchSettings { time_control, adjudication })
+}
+
+fn parse_sprt(args: &[String]) -> Result<Option<Sprt>, String> {
+    let sprt = match get_arg(args, "--sprt") {
+        Some(sprt) => sprt,
+        None => return Ok(None),
+    };
+    match parse_numbers(sprt)?.as_slice() {
+        [elo0, elo1] => Ok(Some(Sprt::new(*elo0, *elo1))),
+        [elo0, elo1, alpha, beta] => Ok(Some(Sprt { elo0: *elo0, elo1: *elo1, alpha: *alpha, beta: *beta })),
+        _ => Err(String::from("--sprt takes elo0,elo1 or elo0,elo1,alpha,beta")),
+    }
+}
+
+fn create_tournament(args: &[String]) -> Result<Tournament, String> {
+    let engines = get_all_args(args, "--engine").into_iter()
+        .map(|spec| EngineConfig::parse(spec).ok_or(format!("Invalid engine: {}", spec)))
+        .collect::<Result<Vec<_>, _>>()?;
+    if engines.len() < 2 {
+        return Err(String::from("At least two engines are needed"));
+    }
+
+    let format = match get_arg(args, "--format") {
+        None | Some("round-robin") => Format::RoundRobin,
+        Some("gauntlet") => Format::Gauntlet,
+        Some(format) => return Err(format!("Invalid format: {}", format)),
+    };
+    let games = match get_arg(args, "--games") {
+        Some(games) => games.parse().map_err(|_| format!("Invalid number of games: {}", games))?,
+        None => 2,
+    };
+    let variant = match get_arg(args, "--variant") {
+        Some(name) => variant::from_name(name).ok_or(format!("Unknown variant: {}", name))?,
+        None => Arc::new(Standard),
+    };
+    let openings = match get_arg(args, "--openings") {
+        Some(path) => {
+            let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+            chess::tournament::parse_openings(&text, variant.clone()).ok_or(format!("Invalid openings in {}", path))?
+        }
+        None => Vec::new(),
+    };
+
+    Ok(Tournament {
+        engines,
+        format,
+        games,
+        openings,
+        variant,
+        settings: parse_settings(args)?,
+        event: get_arg(args, "--event").unwrap_or("Engine match").to_string(),
+    })
+}
+
+fn main() -> Result<(), String> {
+    let args: Vec<String> = std::env::args().collect();
+    let tournament = create_tournament(&args)?;
+    let sprt = parse_sprt(&args)?;
+    if sprt.is_some() && tournament.engines.len() != 2 {
+        return Err(String::from("An SPRT needs exactly two engines"));
+    }
+    let mut pgn_file = match get_arg(&args, "--pgn") {
+        Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)
+            .map_err(|e| format!("Could not open {}: {}", path, e))?),
+        None => None,
+    };
+
+    let results = tournament.run(&mut |record, results| {
+        let termination = match record.state.get_termination() {
+            Some(termination) => termination.to_string().to_string(),
+            None => String::new(),
+        };
+        println!("Game {}: {} vs {} {} ({})", record.round, results.names[record.white], results.names[record.black],
+                 pgn::result_string(&record.state.get_game_status()), termination);
+        if let Some(file) = pgn_file.as_mut() {
+            if let Err(err) = writeln!(file, "{}", record.pgn) {
+                println!("Could not write the game: {}", err);
+            }
+        }
+
+        if results.names.len() != 2 {
+            return true;
+        }
+        let stats = results.stats(0, 1);
+        let (elo, margin) = stats.elo();
+        println!("Score of {} vs {}: {} - {} - {} [{:.3}] {}", results.names[0], results.names[1],
+                 stats.wins, stats.losses, stats.draws, stats.score(), stats.games());
+        println!("Elo difference: {:.1} +/- {:.1}", elo, margin);
+
+        match sprt {
+            Some(sprt) => {
+                let (lower, upper) = sprt.bounds();
+                println!("SPRT: llr {:.2} ({:.2}, {:.2})", sprt.llr(&stats), lower, upper);
+                match sprt.test(&stats) {
+                    SprtResult::AcceptH0 => println!("H0 was accepted"),
+                    SprtResult::AcceptH1 => println!("H1 was accepted"),
+                    SprtResult::Continue => return true,
+                }
+                false
+            }
+            None => true,
+        }
+    }).map_err(|e| format!("Could not start the engines: {}", e))?;
+
+    println!();
+    print!("{}", results.crosstable());
+    Ok(())
+}
@@ -0,0 +1,20 @@
+use std::io::{self, BufRead, Write};
+
+use chess::xboard::XBoard;
+
+fn main() -> io::Result<()> {
+    let mut xboard = XBoard::new();
+    let stdout = io::stdout();
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let mut out = stdout.lock();
+        for answer in xboard.handle(&line) {
+            writeln!(out, "{}", answer)?;
+        }
+        out.flush()?;
+        if xboard.has_quit() {
+            break;
+        }
+    }
+    Ok(())
+}
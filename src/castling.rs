@@ -0,0 +1,233 @@
+use crate::{Move, MoveError, Piece, PieceType, Player, Pos, State};
+
+pub const KING_SIDE: usize = 0;
+pub const QUEEN_SIDE: usize = 1;
+
+// Files the king and rook end up on, the same in classical chess and Chess960
+fn king_destination_file(side: usize) -> i32 {
+    if side == KING_SIDE { 6 } else { 2 }
+}
+
+fn rook_destination_file(side: usize) -> i32 {
+    if side == KING_SIDE { 5 } else { 3 }
+}
+
+pub(crate) fn player_index(player: Player) -> usize {
+    match player {
+        Player::White => 0,
+        Player::Black => 1,
+    }
+}
+
+pub(crate) fn back_rank(player: Player) -> i32 {
+    match player {
+        Player::White => 0,
+        Player::Black => 7,
+    }
+}
+
+fn span(a: i32, b: i32) -> std::ops::RangeInclusive<i32> {
+    a.min(b)..=a.max(b)
+}
+
+impl State {
+    /// File of the rook `player` may still castle with on `side`, if any.
+    pub fn get_castling_rook(&self, player: Player, side: usize) -> Option<i32> {
+        self.castling_rights[player_index(player)][side]
+    }
+
+    pub(crate) fn set_castling_rook(&mut self, player: Player, side: usize, file: Option<i32>) {
+        self.castling_rights[player_index(player)][side] = file;
+    }
+
+    /// The castling side if moving the piece at `from` to `to` means castling.
+    /// In classical chess the king is moved two squares, in Chess960 the king is
+    /// moved onto its own rook, since the destination can be a normal king move.
+    pub(crate) fn get_castling_side(&self, from: Pos, to: Pos) -> Option<usize> {
+        let piece = self.get(from)?;
+        let rank = back_rank(piece.player);
+        if piece.piece_type != PieceType::King || piece.player != self.current_player ||
+           from.y != rank || to.y != rank
+        {
+            return None;
+        }
+
+        [KING_SIDE, QUEEN_SIDE].iter().copied().find(|&side| {
+            match self.get_castling_rook(piece.player, side) {
+                Some(rook_file) if self.chess960 => to.x == rook_file,
+                Some(_) => to.x == king_destination_file(side) && (to.x - from.x).abs() == 2,
+                None => false,
+            }
+        })
+    }
+
+    /// Castling moves of the king on `from` that the castling rights allow,
+    /// whether or not the way is clear.
+    pub(crate) fn get_castling_moves(&self, from: Pos) -> Vec<Move> {
+        [KING_SIDE, QUEEN_SIDE].iter()
+            .copied()
+            .filter_map(|side| {
+                let rook_file = self.get_castling_rook(self.current_player, side)?;
+                let to_file = if self.chess960 { rook_file } else { king_destination_file(side) };
+                let to = Pos::new(to_file, from.y);
+                if self.get_castling_side(from, to) == Some(side) {
+                    Some(Move::new(from, to))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub(crate) fn check_castling(&self, from: Pos, side: usize) -> Result<(), MoveError> {
+        let player = self.current_player;
+        let rank = from.y;
+        let rook_pos = Pos::new(self.get_castling_rook(player, side).unwrap(), rank);
+        if self.get(rook_pos) != Some(Piece::new(PieceType::Rook, player)) {
+            return Err(MoveError::InvalidMove);
+        }
+
+        let king_to = king_destination_file(side);
+        let rook_to = rook_destination_file(side);
+
+        // Everything the king and rook pass over or land on must be empty, apart from themselves
+        for x in span(from.x, king_to).chain(span(rook_pos.x, rook_to)) {
+            let pos = Pos::new(x, rank);
+            if pos != from && pos != rook_pos && self.get(pos).is_some() {
+                return Err(MoveError::InvalidMove);
+            }
+        }
+
+        if self.is_player_check(player) {
+            return Err(MoveError::CastlingThroughCheck);
+        }
+
+        for x in span(from.x, king_to) {
+            let mut state_copy = self.clone_position();
+            state_copy.set(from, None);
+            state_copy.set(rook_pos, None);
+            state_copy.set(Pos::new(x, rank), Some(Piece::new(PieceType::King, player)));
+            if state_copy.is_player_check(player) {
+                return Err(MoveError::CastlingThroughCheck);
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn perform_castling(&mut self, from: Pos, side: usize) {
+        let player = self.current_player;
+        let rook_pos = Pos::new(self.get_castling_rook(player, side).unwrap(), from.y);
+        self.set(from, None);
+        self.set(rook_pos, None);
+        self.set(Pos::new(king_destination_file(side), from.y), Some(Piece::new(PieceType::King, player)));
+        self.set(Pos::new(rook_destination_file(side), from.y), Some(Piece::new(PieceType::Rook, player)));
+        self.set_castling_rook(player, KING_SIDE, None);
+        self.set_castling_rook(player, QUEEN_SIDE, None);
+    }
+
+    /// Removes the rights lost by moving from `from` to `to`: moving the king or
+    /// a castling rook, or capturing one of the opponent's castling rooks.
+    pub(crate) fn update_castling_rights(&mut self, from: Pos, to: Pos) {
+        let piece = self.get(from).unwrap();
+        if piece.piece_type == PieceType::King {
+            self.set_castling_rook(piece.player, KING_SIDE, None);
+            self.set_castling_rook(piece.player, QUEEN_SIDE, None);
+        }
+
+        for player in [Player::White, Player::Black].iter().copied() {
+            for side in [KING_SIDE, QUEEN_SIDE].iter().copied() {
+                if let Some(file) = self.get_castling_rook(player, side) {
+                    let rook_pos = Pos::new(file, back_rank(player));
+                    if rook_pos == from || rook_pos == to {
+                        self.set_castling_rook(player, side, None);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Removes the rights whose king or rook has left the back rank some other
+    /// way than by moving, like being blown up in Atomic chess.
+    pub(crate) fn remove_lost_castling_rights(&mut self) {
+        for player in [Player::White, Player::Black].iter().copied() {
+            let rank = back_rank(player);
+            let king_home = self.get_king_pos(player).is_some_and(|pos| pos.y == rank);
+            for side in [KING_SIDE, QUEEN_SIDE].iter().copied() {
+                if let Some(file) = self.get_castling_rook(player, side) {
+                    if !king_home || self.get(Pos::new(file, rank)) != Some(Piece::new(PieceType::Rook, player)) {
+                        self.set_castling_rook(player, side, None);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(str: &str) -> Pos {
+        Pos::from_notation(str).unwrap()
+    }
+
+    fn clear_between(state: &mut State, squares: &[&str]) {
+        for square in squares {
+            state.set(pos(square), None);
+        }
+    }
+
+    #[test]
+    fn castle_king_side_test() {
+        let mut state = State::new();
+        clear_between(&mut state, &["f1", "g1"]);
+        assert!(state.move_piece(pos("e1"), pos("g1")).is_ok());
+        assert_eq!(state.get(pos("g1")), Some(Piece::new(PieceType::King, Player::White)));
+        assert_eq!(state.get(pos("f1")), Some(Piece::new(PieceType::Rook, Player::White)));
+        assert_eq!(state.get(pos("h1")), None);
+        assert_eq!(state.get_history()[0].san, "O-O");
+        assert_eq!(state.get_castling_rook(Player::White, QUEEN_SIDE), None);
+    }
+
+    #[test]
+    fn castle_queen_side_test() {
+        let mut state = State::new();
+        state.swap_current_player();
+        clear_between(&mut state, &["b8", "c8", "d8"]);
+        assert!(state.move_piece(pos("e8"), pos("c8")).is_ok());
+        assert_eq!(state.get(pos("c8")), Some(Piece::new(PieceType::King, Player::Black)));
+        assert_eq!(state.get(pos("d8")), Some(Piece::new(PieceType::Rook, Player::Black)));
+        assert_eq!(state.get_history()[0].san, "O-O-O");
+    }
+
+    #[test]
+    fn castle_blocked_test() {
+        let mut state = State::new();
+        clear_between(&mut state, &["f1"]);
+        assert!(state.move_piece(pos("e1"), pos("g1")).is_err());
+        // Moving onto the own rook is only castling in Chess960
+        clear_between(&mut state, &["g1"]);
+        assert!(state.move_piece(pos("e1"), pos("h1")).is_err());
+    }
+
+    #[test]
+    fn castle_through_check_test() {
+        let mut state = State::new();
+        clear_between(&mut state, &["f1", "g1", "f2"]);
+        state.set(pos("f5"), Some(Piece::new(PieceType::Rook, Player::Black)));
+        assert!(matches!(state.move_piece(pos("e1"), pos("g1")), Err(MoveError::CastlingThroughCheck)));
+    }
+
+    #[test]
+    fn castling_rights_lost_test() {
+        let mut state = State::new();
+        clear_between(&mut state, &["f1", "g1", "h2"]);
+        assert!(state.move_piece(pos("h1"), pos("h2")).is_ok());
+        assert!(state.move_piece(pos("a7"), pos("a6")).is_ok());
+        assert!(state.move_piece(pos("h2"), pos("h1")).is_ok());
+        assert!(state.move_piece(pos("a6"), pos("a5")).is_ok());
+        assert!(state.move_piece(pos("e1"), pos("g1")).is_err());
+        assert_eq!(state.get_castling_rook(Player::White, KING_SIDE), None);
+        assert_eq!(state.get_castling_rook(Player::White, QUEEN_SIDE), Some(0));
+    }
+}
@@ -0,0 +1,123 @@
+use crate::castling::{KING_SIDE, QUEEN_SIDE};
+use crate::{Piece, PieceType, Player, Pos, State};
+
+// Knight placements on the five squares left after the bishops and the queen
+const KNIGHT_TABLE: [(usize, usize); 10] = [
+    (0, 1), (0, 2), (0, 3), (0, 4), (1, 2), (1, 3), (1, 4), (2, 3), (2, 4), (3, 4),
+];
+
+/// The white back rank of Chess960 start position `id`, from the a-file to the
+/// h-file, following Scharnagl's numbering. Position 518 is the classical setup.
+pub fn chess960_back_rank(id: u16) -> Option<[PieceType; 8]> {
+    if id >= 960 {
+        return None;
+    }
+
+    let mut rank: [Option<PieceType>; 8] = [None; 8];
+    let mut n = id as usize;
+
+    rank[(n % 4) * 2 + 1] = Some(PieceType::Bishop);
+    n /= 4;
+    rank[(n % 4) * 2] = Some(PieceType::Bishop);
+    n /= 4;
+
+    let place_on_empty = |rank: &mut [Option<PieceType>; 8], nth: usize, piece_type: PieceType| {
+        let file = (0..8).filter(|&file| rank[file].is_none()).nth(nth).unwrap();
+        rank[file] = Some(piece_type);
+    };
+
+    place_on_empty(&mut rank, n % 6, PieceType::Queen);
+    n /= 6;
+
+    // The second knight is counted before the first is placed, so take it first
+    let (first, second) = KNIGHT_TABLE[n];
+    place_on_empty(&mut rank, second, PieceType::Knight);
+    place_on_empty(&mut rank, first, PieceType::Knight);
+
+    for piece_type in [PieceType::Rook, PieceType::King, PieceType::Rook].iter() {
+        place_on_empty(&mut rank, 0, *piece_type);
+    }
+
+    let mut result = [PieceType::Pawn; 8];
+    for (file, piece_type) in rank.iter().enumerate() {
+        result[file] = piece_type.unwrap();
+    }
+    Some(result)
+}
+
+impl State {
+    /// A Chess960 game from start position `id` (0 to 959). Castling follows the
+    /// Chess960 rules, so the king is moved onto the rook to castle.
+    pub fn new_chess960(id: u16) -> Option<State> {
+        let back_rank = chess960_back_rank(id)?;
+        let mut state = State::new();
+        state.board = [None; 64];
+
+        for (file, piece_type) in back_rank.iter().enumerate() {
+            let x = file as i32;
+            state.set(Pos::new(x, 0), Some(Piece::new(*piece_type, Player::White)));
+            state.set(Pos::new(x, 1), Some(Piece::new(PieceType::Pawn, Player::White)));
+            state.set(Pos::new(x, 6), Some(Piece::new(PieceType::Pawn, Player::Black)));
+            state.set(Pos::new(x, 7), Some(Piece::new(*piece_type, Player::Black)));
+        }
+
+        let king_file = back_rank.iter().position(|&p| p == PieceType::King).unwrap() as i32;
+        let rook_files: Vec<i32> = (0..8).filter(|&file| back_rank[file as usize] == PieceType::Rook).collect();
+        for player in [Player::White, Player::Black].iter().copied() {
+            state.set_castling_rook(player, QUEEN_SIDE, Some(rook_files[0]));
+            state.set_castling_rook(player, KING_SIDE, Some(rook_files[1]));
+        }
+        debug_assert!(rook_files[0] < king_file && king_file < rook_files[1]);
+
+        state.chess960 = true;
+        state.chess960_id = Some(id);
+        state.position_hashes = vec![state.get_hash()];
+        Some(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use PieceType::*;
+
+    #[test]
+    fn back_rank_test() {
+        assert_eq!(chess960_back_rank(518).unwrap(), [Rook, Knight, Bishop, Queen, King, Bishop, Knight, Rook]);
+        assert_eq!(chess960_back_rank(0).unwrap(), [Bishop, Bishop, Queen, Knight, Knight, Rook, King, Rook]);
+        assert_eq!(chess960_back_rank(959).unwrap(), [Rook, King, Rook, Knight, Knight, Queen, Bishop, Bishop]);
+        assert!(chess960_back_rank(960).is_none());
+    }
+
+    #[test]
+    fn all_positions_valid_test() {
+        let mut seen = std::collections::HashSet::new();
+        for id in 0..960 {
+            let rank = chess960_back_rank(id).unwrap();
+            let bishops: Vec<usize> = (0..8).filter(|&f| rank[f] == Bishop).collect();
+            assert_eq!(bishops.len(), 2);
+            assert_ne!(bishops[0] % 2, bishops[1] % 2);
+            let king = rank.iter().position(|&p| p == King).unwrap();
+            let rooks: Vec<usize> = (0..8).filter(|&f| rank[f] == Rook).collect();
+            assert!(rooks[0] < king && king < rooks[1]);
+            assert!(seen.insert(rank.iter().map(|p| p.to_letter()).collect::<String>()));
+        }
+    }
+
+    #[test]
+    fn chess960_castling_test() {
+        // BBQNNRKR: king on g1 with the rooks on f1 and h1
+        let mut state = State::new_chess960(0).unwrap();
+        assert_eq!(state.get_legal_moves().len(), 20);
+
+        let pos = |str: &str| Pos::from_notation(str).unwrap();
+        for square in ["e1", "d1", "c1", "b1", "a1"].iter() {
+            state.set(pos(square), None);
+        }
+        state.set_castling_rook(Player::White, QUEEN_SIDE, Some(5));
+        assert!(state.move_piece(pos("g1"), pos("f1")).is_ok());
+        assert_eq!(state.get(pos("c1")), Some(Piece::new(King, Player::White)));
+        assert_eq!(state.get(pos("d1")), Some(Piece::new(Rook, Player::White)));
+        assert_eq!(state.get_history()[0].san, "O-O-O");
+    }
+}
@@ -0,0 +1,441 @@
+use crate::Player;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Where the clock gets the current time from. Only differences between
+/// two readings matter, so the origin can be anything.
+pub trait TimeSource {
+    fn now(&self) -> Duration;
+}
+
+/// Wall clock time, measured from when the source was created.
+pub struct SystemTimeSource {
+    start: Instant,
+}
+
+impl SystemTimeSource {
+    pub fn new() -> SystemTimeSource {
+        SystemTimeSource { start: Instant::now() }
+    }
+}
+
+impl Default for SystemTimeSource {
+    fn default() -> SystemTimeSource {
+        SystemTimeSource::new()
+    }
+}
+
+impl TimeSource for SystemTimeSource {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+}
+
+/// Time that only moves when told to, for tests. Clones share the same time.
+#[derive(Clone, Default)]
+pub struct ManualTimeSource {
+    millis: Arc<AtomicU64>,
+}
+
+impl ManualTimeSource {
+    pub fn new() -> ManualTimeSource {
+        ManualTimeSource::default()
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        self.millis.fetch_add(duration.as_millis() as u64, Ordering::SeqCst);
+    }
+}
+
+impl TimeSource for ManualTimeSource {
+    fn now(&self) -> Duration {
+        Duration::from_millis(self.millis.load(Ordering::SeqCst))
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Delay {
+    /// Fischer: the increment is added after every move.
+    Increment(Duration),
+    /// Bronstein: the time spent on a move is given back, at most the delay.
+    Bronstein(Duration),
+    /// Simple (US) delay: the clock waits for the delay before counting down.
+    Simple(Duration),
+    None,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct TimeControlStage {
+    /// Number of moves to make in this stage, `None` for the rest of the game.
+    pub moves: Option<u32>,
+    pub time: Duration,
+    pub delay: Delay,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TimeControl {
+    pub stages: Vec<TimeControlStage>,
+}
+
+impl TimeControl {
+    pub fn new(stages: Vec<TimeControlStage>) -> TimeControl {
+        assert!(!stages.is_empty(), "a time control needs at least one stage");
+        TimeControl { stages }
+    }
+
+    pub fn fischer(time: Duration, increment: Duration) -> TimeControl {
+        TimeControl::new(vec![TimeControlStage { moves: None, time, delay: Delay::Increment(increment) }])
+    }
+
+    pub fn bronstein(time: Duration, delay: Duration) -> TimeControl {
+        TimeControl::new(vec![TimeControlStage { moves: None, time, delay: Delay::Bronstein(delay) }])
+    }
+
+    pub fn simple_delay(time: Duration, delay: Duration) -> TimeControl {
+        TimeControl::new(vec![TimeControlStage { moves: None, time, delay: Delay::Simple(delay) }])
+    }
+
+    /// Parses a time control in seconds, with stages separated by `:` like the PGN
+    /// `TimeControl` tag. Each stage is `[moves/]time` followed by an optional
+    /// `+increment`, `d` and a simple delay or `b` and a Bronstein delay.
+    ///
+    /// `40/5400+30:1800+30` is 90 minutes for 40 moves and then 30 minutes for the
+    /// rest of the game, with 30 seconds added per move from the start.
+    pub fn parse(str: &str) -> Option<TimeControl> {
+        let mut stages = Vec::new();
+        for stage in str.trim().split(':') {
+            let (moves, rest) = match stage.find('/') {
+                Some(index) => (Some(stage[..index].parse().ok().filter(|&moves: &u32| moves > 0)?), &stage[index + 1..]),
+                None => (None, stage),
+            };
+
+            let (time, delay) = if let Some(index) = rest.find('+') {
+                (&rest[..index], Delay::Increment(parse_seconds(&rest[index + 1..])?))
+            } else if let Some(index) = rest.find('d') {
+                (&rest[..index], Delay::Simple(parse_seconds(&rest[index + 1..])?))
+            } else if let Some(index) = rest.find('b') {
+                (&rest[..index], Delay::Bronstein(parse_seconds(&rest[index + 1..])?))
+            } else {
+                (rest, Delay::None)
+            };
+
+            stages.push(TimeControlStage { moves, time: parse_seconds(time)?, delay });
+        }
+        Some(TimeControl::new(stages))
+    }
+
+    /// Writes the time control in the form `parse` reads, as in the PGN `TimeControl` tag.
+    pub fn to_tag(&self) -> String {
+        let stages: Vec<String> = self.stages.iter().map(|stage| {
+            let mut tag = match stage.moves {
+                Some(moves) => format!("{}/{}", moves, format_seconds(stage.time)),
+                None => format_seconds(stage.time),
+            };
+            match stage.delay {
+                Delay::Increment(increment) => tag.push_str(&format!("+{}", format_seconds(increment))),
+                Delay::Simple(delay) => tag.push_str(&format!("d{}", format_seconds(delay))),
+                Delay::Bronstein(delay) => tag.push_str(&format!("b{}", format_seconds(delay))),
+                Delay::None => (),
+            }
+            tag
+        }).collect();
+        stages.join(":")
+    }
+}
+
+fn format_seconds(duration: Duration) -> String {
+    if duration.subsec_nanos() == 0 {
+        duration.as_secs().to_string()
+    } else {
+        duration.as_secs_f64().to_string()
+    }
+}
+
+fn parse_seconds(str: &str) -> Option<Duration> {
+    let seconds: f64 = str.trim().parse().ok()?;
+    if seconds.is_finite() && seconds >= 0.0 {
+        Some(Duration::from_secs_f64(seconds))
+    } else {
+        None
+    }
+}
+
+fn player_index(player: Player) -> usize {
+    match player {
+        Player::White => 0,
+        Player::Black => 1,
+    }
+}
+
+/// A chess clock for both players following a `TimeControl`.
+pub struct Clock {
+    control: TimeControl,
+    remaining: [Duration; 2],
+    stage: [usize; 2],
+    stage_moves: [u32; 2],
+    running: Option<Player>,
+    turn_started: Duration,
+    flagged: Option<Player>,
+    source: Box<dyn TimeSource + Send>,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl, source: Box<dyn TimeSource + Send>) -> Clock {
+        let time = control.stages[0].time;
+        Clock {
+            control,
+            remaining: [time, time],
+            stage: [0, 0],
+            stage_moves: [0, 0],
+            running: None,
+            turn_started: Duration::from_secs(0),
+            flagged: None,
+            source,
+        }
+    }
+
+    pub fn get_time_control(&self) -> &TimeControl {
+        &self.control
+    }
+
+    pub fn get_running(&self) -> Option<Player> {
+        self.running
+    }
+
+    pub fn get_flagged(&self) -> Option<Player> {
+        self.flagged
+    }
+
+    /// The increment `player` gets for each move in the current stage.
+    pub fn increment(&self, player: Player) -> Duration {
+        match self.current_stage(player).delay {
+            Delay::Increment(increment) => increment,
+            _ => Duration::from_secs(0),
+        }
+    }
+
+    /// Moves `player` has left until the next time control, `None` if the
+    /// current time has to last for the rest of the game.
+    pub fn moves_to_go(&self, player: Player) -> Option<u32> {
+        let index = player_index(player);
+        self.current_stage(player).moves.map(|moves| moves.saturating_sub(self.stage_moves[index]))
+    }
+
+    /// Starts counting down for `player`, e.g. for white at the start of the game.
+    pub fn start(&mut self, player: Player) {
+        if self.flagged.is_none() {
+            self.running = Some(player);
+            self.turn_started = self.source.now();
+        }
+    }
+
+    /// Stops the clock without ending the turn, e.g. when the game is over.
+    pub fn stop(&mut self) {
+        if let Some(player) = self.running {
+            let used = self.charged_time(player);
+            let index = player_index(player);
+            self.remaining[index] = self.remaining[index].saturating_sub(used);
+            self.running = None;
+        }
+    }
+
+    fn elapsed(&self) -> Duration {
+        self.source.now().saturating_sub(self.turn_started)
+    }
+
+    fn current_stage(&self, player: Player) -> &TimeControlStage {
+        &self.control.stages[self.stage[player_index(player)]]
+    }
+
+    // Time taken from the remaining time during the current turn
+    fn charged_time(&self, player: Player) -> Duration {
+        let elapsed = self.elapsed();
+        match self.current_stage(player).delay {
+            Delay::Simple(delay) => elapsed.saturating_sub(delay),
+            _ => elapsed,
+        }
+    }
+
+    /// Remaining time for `player`, counting the running turn.
+    pub fn remaining(&self, player: Player) -> Duration {
+        let remaining = self.remaining[player_index(player)];
+        if self.running == Some(player) {
+            remaining.saturating_sub(self.charged_time(player))
+        } else {
+            remaining
+        }
+    }
+
+    /// Marks the running player as flagged if their time is up.
+    pub fn check_flag(&mut self) -> Option<Player> {
+        if let Some(player) = self.running {
+            if self.charged_time(player) > self.remaining[player_index(player)] {
+                self.stop();
+                self.remaining[player_index(player)] = Duration::from_secs(0);
+                self.flagged = Some(player);
+            }
+        }
+        self.flagged
+    }
+
+    /// Ends the turn of the running player and starts the opponent's clock.
+    /// Returns the flagged player if the move was made too late.
+    pub fn press(&mut self) -> Option<Player> {
+        let player = match self.running {
+            Some(player) => player,
+            None => return self.flagged,
+        };
+        if self.check_flag().is_some() {
+            return self.flagged;
+        }
+
+        let index = player_index(player);
+        let elapsed = self.elapsed();
+        let stage = *self.current_stage(player);
+
+        self.remaining[index] = self.remaining[index].saturating_sub(self.charged_time(player));
+        match stage.delay {
+            Delay::Increment(increment) => self.remaining[index] += increment,
+            Delay::Bronstein(delay) => self.remaining[index] += elapsed.min(delay),
+            Delay::Simple(_) | Delay::None => (),
+        }
+
+        self.stage_moves[index] += 1;
+        if stage.moves == Some(self.stage_moves[index]) {
+            // The last stage starts over if it is also limited to a number of moves
+            let next = (self.stage[index] + 1).min(self.control.stages.len() - 1);
+            self.stage[index] = next;
+            self.stage_moves[index] = 0;
+            self.remaining[index] += self.control.stages[next].time;
+        }
+
+        let other = match player {
+            Player::White => Player::Black,
+            Player::Black => Player::White,
+        };
+        self.start(other);
+        None
+    }
+}
+
+/// Formats a clock time as `h:mm:ss`, `m:ss`, or `s.t` in the last ten seconds.
+pub fn format_duration(duration: Duration) -> String {
+    let total = duration.as_secs();
+    if total >= 3600 {
+        format!("{}:{:02}:{:02}", total / 3600, (total / 60) % 60, total % 60)
+    } else if total >= 10 {
+        format!("{}:{:02}", total / 60, total % 60)
+    } else {
+        format!("0:{:02}.{}", total, duration.subsec_millis() / 100)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn secs(seconds: u64) -> Duration {
+        Duration::from_secs(seconds)
+    }
+
+    fn clock(control: TimeControl) -> (Clock, ManualTimeSource) {
+        let time = ManualTimeSource::new();
+        let mut clock = Clock::new(control, Box::new(time.clone()));
+        clock.start(Player::White);
+        (clock, time)
+    }
+
+    #[test]
+    fn parse_test() {
+        let control = TimeControl::parse("40/5400+30:1800+30").unwrap();
+        assert_eq!(control.stages.len(), 2);
+        assert_eq!(control.to_tag(), "40/5400+30:1800+30");
+        assert_eq!(TimeControl::parse("10+0.1").unwrap().to_tag(), "10+0.1");
+        assert_eq!(control.stages[0], TimeControlStage { moves: Some(40), time: secs(5400), delay: Delay::Increment(secs(30)) });
+        assert_eq!(control.stages[1], TimeControlStage { moves: None, time: secs(1800), delay: Delay::Increment(secs(30)) });
+
+        assert_eq!(TimeControl::parse("300d5").unwrap(), TimeControl::simple_delay(secs(300), secs(5)));
+        assert_eq!(TimeControl::parse("300b5").unwrap(), TimeControl::bronstein(secs(300), secs(5)));
+        assert_eq!(TimeControl::parse("180").unwrap().stages[0].delay, Delay::None);
+        assert!(TimeControl::parse("x/300").is_none());
+        assert!(TimeControl::parse("0/60").is_none());
+        assert!(TimeControl::parse("").is_none());
+    }
+
+    #[test]
+    fn fischer_test() {
+        let (mut clock, time) = clock(TimeControl::fischer(secs(60), secs(2)));
+        time.advance(secs(10));
+        assert_eq!(clock.remaining(Player::White), secs(50));
+        assert_eq!(clock.press(), None);
+        assert_eq!(clock.remaining(Player::White), secs(52));
+        assert_eq!(clock.get_running(), Some(Player::Black));
+        time.advance(secs(1));
+        assert_eq!(clock.remaining(Player::Black), secs(59));
+        assert_eq!(clock.remaining(Player::White), secs(52));
+    }
+
+    #[test]
+    fn bronstein_test() {
+        let (mut clock, time) = clock(TimeControl::bronstein(secs(60), secs(5)));
+        time.advance(secs(3));
+        assert_eq!(clock.remaining(Player::White), secs(57));
+        clock.press();
+        assert_eq!(clock.remaining(Player::White), secs(60));
+        time.advance(secs(8));
+        clock.press();
+        assert_eq!(clock.remaining(Player::Black), secs(57));
+    }
+
+    #[test]
+    fn simple_delay_test() {
+        let (mut clock, time) = clock(TimeControl::simple_delay(secs(60), secs(5)));
+        time.advance(secs(3));
+        assert_eq!(clock.remaining(Player::White), secs(60));
+        time.advance(secs(4));
+        assert_eq!(clock.remaining(Player::White), secs(58));
+        clock.press();
+        assert_eq!(clock.remaining(Player::White), secs(58));
+    }
+
+    #[test]
+    fn stages_test() {
+        let (mut clock, time) = clock(TimeControl::parse("2/60:30+1").unwrap());
+        assert_eq!(clock.moves_to_go(Player::White), Some(2));
+        assert_eq!(clock.increment(Player::White), secs(0));
+        for _ in 0..2 {
+            time.advance(secs(10));
+            clock.press();
+            time.advance(secs(1));
+            clock.press();
+        }
+        assert_eq!(clock.remaining(Player::White), secs(70));
+        assert_eq!(clock.remaining(Player::Black), secs(88));
+        assert_eq!(clock.moves_to_go(Player::White), None);
+        assert_eq!(clock.increment(Player::White), secs(1));
+
+        time.advance(secs(10));
+        clock.press();
+        assert_eq!(clock.remaining(Player::White), secs(61));
+    }
+
+    #[test]
+    fn flag_test() {
+        let (mut clock, time) = clock(TimeControl::fischer(secs(5), secs(0)));
+        time.advance(secs(4));
+        assert_eq!(clock.check_flag(), None);
+        time.advance(secs(2));
+        assert_eq!(clock.check_flag(), Some(Player::White));
+        assert_eq!(clock.remaining(Player::White), secs(0));
+        assert_eq!(clock.get_running(), None);
+        assert_eq!(clock.press(), Some(Player::White));
+    }
+
+    #[test]
+    fn format_duration_test() {
+        assert_eq!(format_duration(secs(5400)), "1:30:00");
+        assert_eq!(format_duration(secs(65)), "1:05");
+        assert_eq!(format_duration(Duration::from_millis(9_450)), "0:09.4");
+    }
+}
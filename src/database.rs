@@ -0,0 +1,313 @@
+//! A local game database: games are kept in a PGN file and searched by their
+//! tags or by the positions they went through, with the exact position, the
+//! material or the pawn structure.
+
+use std::fs::{self, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::{Path, PathBuf};
+
+use crate::eco;
+use crate::pgn::{parse_pgn, PgnError, PgnGame};
+use crate::tablebase::material_key;
+use crate::{PieceType, Player, State};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DatabaseError {
+    Io(String),
+    InvalidPgn(PgnError),
+}
+
+impl DatabaseError {
+    pub fn to_string(&self) -> &str {
+        match self {
+            Self::Io(message) => message,
+            Self::InvalidPgn(err) => err.to_string(),
+        }
+    }
+}
+
+/// The squares of white's and black's pawns, as bits numbered like `Pos::index`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PawnStructure {
+    pub white: u64,
+    pub black: u64,
+}
+
+impl PawnStructure {
+    pub fn of(state: &State) -> PawnStructure {
+        let mut structure = PawnStructure { white: 0, black: 0 };
+        for (index, square) in state.board.iter().enumerate() {
+            match square {
+                Some(piece) if piece.piece_type == PieceType::Pawn && piece.player == Player::White => structure.white |= 1 << index,
+                Some(piece) if piece.piece_type == PieceType::Pawn => structure.black |= 1 << index,
+                _ => {}
+            }
+        }
+        structure
+    }
+}
+
+/// Turns a material signature like `KRP vs KR` or `krpvkr` into the notation
+/// of `tablebase::material_key`, white first, if it is one.
+pub fn parse_material(text: &str) -> Option<String> {
+    let text = text.to_uppercase().replace("VS", "V").replace(char::is_whitespace, "");
+    let (white, black) = text.split_once('V')?;
+    let valid = |side: &str| side.chars().filter(|&letter| letter == 'K').count() == 1
+        && side.chars().all(|letter| PieceType::from_letter(letter).is_some());
+    if !valid(white) || !valid(black) {
+        return None;
+    }
+    // The pieces are ordered like in material_key
+    let order = |side: &str| -> String {
+        "KQRBNP".chars().flat_map(|letter| std::iter::repeat_n(letter, side.matches(letter).count())).collect()
+    };
+    Some(format!("{}v{}", order(white), order(black)))
+}
+
+/// What games to look for. Every condition that is given has to hold, and
+/// the position conditions have to hold in the same position.
+#[derive(Clone, Debug, Default)]
+pub struct GameQuery {
+    /// Part of the name of either player, ignoring case.
+    pub player: Option<String>,
+    pub white: Option<String>,
+    pub black: Option<String>,
+    /// Dates like `2021.03.14`, both included. A game with a partly known
+    /// date, like `2021.??.??`, matches if it could be in the range, and one
+    /// without a known year never does.
+    pub date_from: Option<String>,
+    pub date_to: Option<String>,
+    /// `1-0`, `0-1`, `1/2-1/2` or `*`.
+    pub result: Option<String>,
+    /// The start of an ECO code, like `B2` for B20 to B29.
+    pub eco: Option<String>,
+    /// The hash of a position the game went through, see `State::get_hash`.
+    pub position: Option<u64>,
+    /// Material like `parse_material` returns.
+    pub material: Option<String>,
+    pub pawns: Option<PawnStructure>,
+}
+
+impl GameQuery {
+    fn has_position_conditions(&self) -> bool {
+        self.position.is_some() || self.material.is_some() || self.pawns.is_some()
+    }
+
+    fn matches_position(&self, state: &State) -> bool {
+        self.position.is_none_or(|hash| state.get_hash() == hash)
+            && self.pawns.is_none_or(|pawns| PawnStructure::of(state) == pawns)
+            && self.material.as_ref().is_none_or(|material| material_key(state) == *material)
+    }
+
+    fn matches_tags(&self, game: &PgnGame) -> bool {
+        let tag = |name| game.tag(name).unwrap_or("");
+        let contains = |name, part: &Option<String>| part.as_ref().is_none_or(|part| tag(name).to_lowercase().contains(&part.to_lowercase()));
+        let date = tag("Date");
+        let known_year = date.len() >= 4 && date[..4].chars().all(|c| c.is_ascii_digit());
+        contains("White", &self.white)
+            && contains("Black", &self.black)
+            && (contains("White", &self.player) || contains("Black", &self.player))
+            && self.date_from.as_ref().is_none_or(|from| known_year && date.replace('?', "9") >= *from)
+            && self.date_to.as_ref().is_none_or(|to| known_year && date.replace('?', "0") <= *to)
+            && self.result.as_ref().is_none_or(|result| tag("Result") == result)
+            && self.eco.as_ref().is_none_or(|eco| tag("ECO").to_uppercase().starts_with(&eco.to_uppercase()))
+    }
+}
+
+// Only games from the standard start position can be classified
+fn tag_opening(game: &mut PgnGame) {
+    if ["ECO", "FEN", "Variant"].iter().any(|name| game.tag(name).is_some()) {
+        return;
+    }
+    let end = &game.tree.get(game.tree.main_line_end()).unwrap().position;
+    if let Some(opening) = eco::classify_game(end) {
+        game.tags.push((String::from("ECO"), opening.eco.to_string()));
+        if game.tag("Opening").is_none() {
+            game.tags.push((String::from("Opening"), opening.name.to_string()));
+        }
+    }
+}
+
+/// Games kept in a PGN file.
+pub struct GameDatabase {
+    path: PathBuf,
+    games: Vec<PgnGame>,
+}
+
+impl GameDatabase {
+    /// Reads the database in `path`, which is created on the first import if it does not exist.
+    pub fn open(path: &Path) -> Result<GameDatabase, DatabaseError> {
+        let games = match fs::read_to_string(path) {
+            Ok(text) => parse_pgn(&text).map_err(DatabaseError::InvalidPgn)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(DatabaseError::Io(format!("Could not read {}: {}", path.display(), err))),
+        };
+        Ok(GameDatabase { path: path.to_path_buf(), games })
+    }
+
+    pub fn len(&self) -> usize {
+        self.games.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.games.is_empty()
+    }
+
+    /// The game with the given number, counted from 0 in the order of import.
+    pub fn get(&self, id: usize) -> Option<&PgnGame> {
+        self.games.get(id)
+    }
+
+    /// Adds every game of a PGN text and returns how many there were. Games
+    /// without an `ECO` tag are tagged with their opening. Nothing is added if
+    /// any game cannot be read.
+    pub fn import(&mut self, pgn: &str) -> Result<usize, DatabaseError> {
+        let mut games = parse_pgn(pgn).map_err(DatabaseError::InvalidPgn)?;
+        games.iter_mut().for_each(tag_opening);
+        let text = self.export_games(&games);
+        OpenOptions::new().create(true).append(true).open(&self.path)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .map_err(|err| DatabaseError::Io(format!("Could not write {}: {}", self.path.display(), err)))?;
+        let count = games.len();
+        self.games.extend(games);
+        Ok(count)
+    }
+
+    /// The numbers of the games that match `query`.
+    pub fn search(&self, query: &GameQuery) -> Vec<usize> {
+        (0..self.games.len())
+            .filter(|&id| {
+                let game = &self.games[id];
+                query.matches_tags(game)
+                    && (!query.has_position_conditions() || self.positions(id).iter().any(|state| query.matches_position(state)))
+            })
+            .collect()
+    }
+
+    /// The position at the start of the game and after each move of its main line.
+    pub fn positions(&self, id: usize) -> Vec<State> {
+        match self.games.get(id) {
+            Some(game) => game.tree.main_line().iter().map(|&node| game.tree.get(node).unwrap().position.clone()).collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// The given games as PGN.
+    pub fn export(&self, ids: &[usize]) -> String {
+        let games: Vec<PgnGame> = ids.iter().filter_map(|&id| self.games.get(id).cloned()).collect();
+        self.export_games(&games)
+    }
+
+    fn export_games(&self, games: &[PgnGame]) -> String {
+        games.iter().map(|game| game.to_pgn() + "\n").collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAMES: &str = r#"[Event "Club"]
+[Date "2021.03.14"]
+[White "Smith, Ann"]
+[Black "Jones, Bo"]
+[Result "1-0"]
+[ECO "C40"]
+
+1. e4 e5 2. Nf3 d6 3. d4 exd4 1-0
+
+[Event "Club"]
+[Date "2022.??.??"]
+[White "Jones, Bo"]
+[Black "Lee, Cy"]
+[Result "1/2-1/2"]
+[ECO "D06"]
+
+1. d4 d5 2. c4 dxc4 1/2-1/2
+
+[FEN "8/8/4k3/8/8/3K4/3RP3/4r3 w - - 0 60"]
+[White "Smith, Ann"]
+[Black "Lee, Cy"]
+[Result "1-0"]
+
+60. Kd4 Ke7 1-0
+"#;
+
+    fn database() -> (GameDatabase, PathBuf) {
+        let path = std::env::temp_dir().join(format!("chess-games-{}-{:?}.pgn", std::process::id(), std::thread::current().id()));
+        let _res = fs::remove_file(&path);
+        let mut database = GameDatabase::open(&path).unwrap();
+        assert_eq!(database.import(GAMES), Ok(3));
+        (database, path)
+    }
+
+    #[test]
+    fn material_test() {
+        assert_eq!(parse_material("KRP vs KR"), Some(String::from("KRPvKR")));
+        assert_eq!(parse_material("kpr v kr"), Some(String::from("KRPvKR")));
+        assert_eq!(parse_material("KRP"), None);
+        assert_eq!(parse_material("KRX v K"), None);
+        assert_eq!(parse_material("KR v R"), None);
+    }
+
+    #[test]
+    fn tag_query_test() {
+        let (database, path) = database();
+        let search = |query: GameQuery| database.search(&query);
+        assert_eq!(search(GameQuery { player: Some(String::from("jones")), ..GameQuery::default() }), [0, 1]);
+        assert_eq!(search(GameQuery { white: Some(String::from("Smith")), ..GameQuery::default() }), [0, 2]);
+        assert_eq!(search(GameQuery { result: Some(String::from("1-0")), ..GameQuery::default() }), [0, 2]);
+        assert_eq!(search(GameQuery { eco: Some(String::from("d0")), ..GameQuery::default() }), [1]);
+        assert_eq!(search(GameQuery { date_from: Some(String::from("2022.01.01")), ..GameQuery::default() }), [1]);
+        assert_eq!(search(GameQuery { date_to: Some(String::from("2021.12.31")), ..GameQuery::default() }), [0]);
+
+        let reopened = GameDatabase::open(&path).unwrap();
+        let _res = fs::remove_file(&path);
+        assert_eq!(reopened.len(), 3);
+        assert_eq!(reopened.get(1).unwrap().tag("Black"), Some("Lee, Cy"));
+    }
+
+    #[test]
+    fn opening_tag_test() {
+        let (mut database, path) = database();
+        assert_eq!(database.import("1. e4 e5 2. Nf3 Nf6 *\n\n[Variant \"Atomic\"]\n\n1. e4 e5 *\n"), Ok(2));
+        let reopened = GameDatabase::open(&path).unwrap();
+        let _res = fs::remove_file(&path);
+
+        assert_eq!(database.get(0).unwrap().tag("ECO"), Some("C40"));
+        assert_eq!(database.get(2).unwrap().tag("Opening"), None);
+        assert_eq!(database.get(3).unwrap().tag("ECO"), Some("C42"));
+        assert_eq!(database.get(3).unwrap().tag("Opening"), Some("Petrov's Defence"));
+        assert_eq!(database.get(4).unwrap().tag("ECO"), None);
+        assert_eq!(reopened.search(&GameQuery { eco: Some(String::from("C42")), ..GameQuery::default() }), [3]);
+    }
+
+    #[test]
+    fn position_query_test() {
+        let (database, path) = database();
+        let _res = fs::remove_file(&path);
+
+        let mut state = State::new();
+        for san in ["d4", "d5", "c4"] {
+            let mv = state.move_from_san(san).unwrap();
+            state.play_move(mv).unwrap();
+        }
+        let query = GameQuery { position: Some(state.get_hash()), ..GameQuery::default() };
+        assert_eq!(database.search(&query), [1]);
+
+        let query = GameQuery { material: parse_material("KRP vs KR"), ..GameQuery::default() };
+        assert_eq!(database.search(&query), [2]);
+
+        // The pawns after 3. d4 exd4
+        let pawns = PawnStructure::of(&database.positions(0)[6]);
+        let query = GameQuery { pawns: Some(pawns), ..GameQuery::default() };
+        assert_eq!(database.search(&query), [0]);
+        assert_eq!(database.positions(1).len(), 5);
+        assert_eq!(database.positions(7).len(), 0);
+
+        let exported = database.export(&[2]);
+        assert!(exported.contains("[White \"Smith, Ann\"]"));
+        assert!(exported.contains("60. Kd4 Ke7 1-0"));
+        assert_eq!(parse_pgn(&exported).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,113 @@
+//! ECO opening classification. The bundled table lists openings by their
+//! moves, and they are looked up by the position those moves reach, so a game
+//! that gets there in another move order is classified the same.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use crate::{Move, State};
+
+// Lines of `eco<TAB>name<TAB>moves`, after a header line
+const TABLE: &str = include_str!("openings.tsv");
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Opening {
+    pub eco: &'static str,
+    pub name: &'static str,
+}
+
+// The openings by the hash of the position after their moves
+fn openings() -> &'static HashMap<u64, Opening> {
+    static OPENINGS: OnceLock<HashMap<u64, Opening>> = OnceLock::new();
+    OPENINGS.get_or_init(|| {
+        let mut openings = HashMap::new();
+        for line in TABLE.lines().skip(1) {
+            let mut fields = line.split('\t');
+            let (eco, name, moves) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(eco), Some(name), Some(moves)) => (eco, name, moves),
+                _ => continue,
+            };
+            let mut state = State::new();
+            // Move numbers like `1.` are skipped
+            for san in moves.split_whitespace().filter(|token| !token.ends_with('.')) {
+                let mv = state.move_from_san(san).expect("illegal move in the opening table");
+                state.play_move(mv).expect("illegal move in the opening table");
+            }
+            openings.entry(state.get_hash()).or_insert(Opening { eco, name });
+        }
+        openings
+    })
+}
+
+/// The opening of exactly this position, if it is in the table.
+pub fn lookup(state: &State) -> Option<&'static Opening> {
+    openings().get(&state.get_hash())
+}
+
+/// Whether `state` is the standard start position, where the openings of
+/// the table start from.
+pub fn is_standard_start(state: &State) -> bool {
+    state.get_variant().name() == "Standard" && !state.is_chess960() && state.get_hash() == State::new().get_hash()
+}
+
+/// The opening of a game with the given moves from the standard start
+/// position: the one of the last position that is in the table.
+pub fn classify_opening(moves: &[Move]) -> Option<&'static Opening> {
+    let openings = openings();
+    let mut state = State::new();
+    let mut opening = None;
+    for &mv in moves {
+        if state.play_move(mv).is_err() {
+            break;
+        }
+        opening = openings.get(&state.get_hash()).or(opening);
+    }
+    opening
+}
+
+/// The opening of the game played in `state`, which has to have started from
+/// the standard start position.
+pub fn classify_game(state: &State) -> Option<&'static Opening> {
+    let moves: Vec<Move> = state.get_history().iter().map(|entry| entry.mv).collect();
+    classify_opening(&moves)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn moves(sans: &[&str]) -> Vec<Move> {
+        let mut state = State::new();
+        sans.iter().map(|san| {
+            let mv = state.move_from_san(san).unwrap();
+            state.play_move(mv).unwrap();
+            mv
+        }).collect()
+    }
+
+    #[test]
+    fn table_test() {
+        // Every line is read, and lines that reach the same position are left out
+        assert!(openings().len() > 150);
+        assert!(openings().len() < TABLE.lines().count());
+    }
+
+    #[test]
+    fn classify_test() {
+        let petrov = classify_opening(&moves(&["e4", "e5", "Nf3", "Nf6", "Nxe5", "d6"])).unwrap();
+        assert_eq!((petrov.eco, petrov.name), ("C42", "Petrov's Defence"));
+        assert_eq!(classify_opening(&moves(&["e4", "c5", "Nf3", "d6", "d4", "cxd4", "Nxd4", "Nf6", "Nc3", "a6"])).unwrap().eco, "B90");
+        assert_eq!(classify_opening(&[]), None);
+        assert_eq!(classify_opening(&moves(&["a3"])), None);
+
+        // The Queen's Gambit Declined reached from the English
+        let transposed = classify_opening(&moves(&["c4", "e6", "Nc3", "d5", "d4"])).unwrap();
+        assert_eq!(transposed.eco, "D31");
+
+        let mut state = State::new();
+        for mv in moves(&["d4", "Nf6", "c4", "g6", "Nc3", "Bg7", "e4", "d6", "f3"]) {
+            state.play_move(mv).unwrap();
+        }
+        assert_eq!(classify_game(&state).unwrap().name, "King's Indian Defence: Sämisch Variation");
+    }
+}
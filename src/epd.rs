@@ -0,0 +1,412 @@
+//! EPD test suites, like WAC, STS or Bratko-Kopec: positions as the first
+//! four fields of a FEN followed by operations like `bm Qg6; id "WAC.001";`,
+//! and a runner that checks the moves of the built-in search against them.
+
+use std::time::Duration;
+
+use crate::search::{self, Score, SearchLimits};
+use crate::{Move, State};
+
+/// The points of the best move in STS-style scoring.
+pub const BEST_MOVE_POINTS: u32 = 10;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum EpdError {
+    MissingField,
+    InvalidFen,
+    InvalidMove,
+    InvalidOperation,
+}
+
+impl EpdError {
+    pub fn to_string(&self) -> &str {
+        match self {
+            Self::MissingField => "An EPD line needs the board, player, castling and en passant fields",
+            Self::InvalidFen => "The EPD position is not valid",
+            Self::InvalidMove => "A move of the EPD operations is not legal",
+            Self::InvalidOperation => "An EPD operation is not valid",
+        }
+    }
+}
+
+// Opcodes with a string operand, which is quoted
+fn is_string_opcode(opcode: &str) -> bool {
+    opcode == "id" || (opcode.len() == 2 && opcode.starts_with('c') && opcode.as_bytes()[1].is_ascii_digit())
+}
+
+// Splits the operations into their opcode and operands, with quoted operands
+// kept whole
+fn parse_operations(text: &str) -> Result<Vec<(String, Vec<String>)>, EpdError> {
+    let mut operations = Vec::new();
+    let mut tokens: Vec<String> = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ';' => {
+                if tokens.is_empty() {
+                    return Err(EpdError::InvalidOperation);
+                }
+                let opcode = tokens.remove(0);
+                operations.push((opcode, std::mem::take(&mut tokens)));
+            }
+            '"' => {
+                let mut operand = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some(c) => operand.push(c),
+                        None => return Err(EpdError::InvalidOperation),
+                    }
+                }
+                tokens.push(operand);
+            }
+            c if c.is_whitespace() => {}
+            c => {
+                let mut token = c.to_string();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || c == ';' || c == '"' {
+                        break;
+                    }
+                    token.push(c);
+                    chars.next();
+                }
+                tokens.push(token);
+            }
+        }
+    }
+    // The last semicolon may be missing
+    if !tokens.is_empty() {
+        let opcode = tokens.remove(0);
+        operations.push((opcode, tokens));
+    }
+    Ok(operations)
+}
+
+/// A position of a test suite.
+#[derive(Clone)]
+pub struct Epd {
+    pub state: State,
+    pub id: Option<String>,
+    /// `bm`: the search should play one of these.
+    pub best_moves: Vec<Move>,
+    /// `am`: the search should play none of these.
+    pub avoid_moves: Vec<Move>,
+    /// `ce`: the evaluation in centipawns for the player to move.
+    pub centipawns: Option<i32>,
+    /// `dm`: the player to move mates in this many moves.
+    pub mate: Option<i32>,
+    /// `c0`, which STS uses for the points of each move, like `Nf3=10, Nd2=5`.
+    pub comment: Option<String>,
+    /// Any other operations, with their operands as they were written.
+    pub other: Vec<(String, String)>,
+}
+
+impl Epd {
+    pub fn new(state: State) -> Epd {
+        Epd {
+            state,
+            id: None,
+            best_moves: Vec::new(),
+            avoid_moves: Vec::new(),
+            centipawns: None,
+            mate: None,
+            comment: None,
+            other: Vec::new(),
+        }
+    }
+
+    /// Reads an EPD line. The move counters are taken from the `hmvc` and
+    /// `fmvn` operations, or from the two fields after the four of the
+    /// position if a FEN line is given.
+    pub fn parse(line: &str) -> Result<Epd, EpdError> {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 {
+            return Err(EpdError::MissingField);
+        }
+        let (counters, rest) = match fields.get(4..6) {
+            Some([halfmoves, moves]) if halfmoves.parse::<u32>().is_ok() && moves.parse::<u32>().is_ok() => {
+                (Some(format!("{} {}", halfmoves, moves)), 6)
+            }
+            _ => (None, 4),
+        };
+        // The operations are the text after the position fields
+        let mut operations_text = line.trim_start();
+        for _ in 0..rest.min(fields.len()) {
+            operations_text = operations_text.trim_start();
+            let end = operations_text.find(char::is_whitespace).unwrap_or(operations_text.len());
+            operations_text = &operations_text[end..];
+        }
+        let operations = parse_operations(operations_text)?;
+
+        let operand = |opcode: &str| operations.iter().find(|(code, _)| code == opcode).and_then(|(_, operands)| operands.first());
+        let counters = counters.unwrap_or_else(|| {
+            format!("{} {}", operand("hmvc").map_or("0", String::as_str), operand("fmvn").map_or("1", String::as_str))
+        });
+        let state = State::from_fen(&format!("{} {}", fields[..4].join(" "), counters)).map_err(|_| EpdError::InvalidFen)?;
+
+        let mut epd = Epd::new(state);
+        for (opcode, operands) in operations {
+            let moves = || operands.iter()
+                .map(|san| epd.state.move_from_san(san).ok_or(EpdError::InvalidMove))
+                .collect::<Result<Vec<Move>, EpdError>>();
+            let number = || operands.first().and_then(|operand| operand.parse().ok()).ok_or(EpdError::InvalidOperation);
+            match opcode.as_str() {
+                "bm" => epd.best_moves = moves()?,
+                "am" => epd.avoid_moves = moves()?,
+                "ce" => epd.centipawns = Some(number()?),
+                "dm" => epd.mate = Some(number()?),
+                "id" => epd.id = operands.first().cloned(),
+                "c0" => epd.comment = operands.first().cloned(),
+                "hmvc" | "fmvn" => {}
+                _ => {
+                    let operands = if is_string_opcode(&opcode) {
+                        operands.iter().map(|operand| format!("\"{}\"", operand)).collect::<Vec<String>>().join(" ")
+                    } else {
+                        operands.join(" ")
+                    };
+                    epd.other.push((opcode, operands));
+                }
+            }
+        }
+        Ok(epd)
+    }
+
+    /// Writes the position with its operations. The move counters are
+    /// written as `hmvc` and `fmvn` unless they are 0 and 1.
+    pub fn to_epd(&self) -> String {
+        let sans = |moves: &[Move]| moves.iter().filter_map(|&mv| self.state.move_to_san(mv)).collect::<Vec<String>>().join(" ");
+        let mut operations: Vec<(String, String)> = Vec::new();
+        let fen = self.state.to_fen();
+        let counters: Vec<&str> = fen.split_whitespace().skip(4).collect();
+        if let [halfmoves, moves] = counters[..] {
+            if halfmoves != "0" {
+                operations.push((String::from("hmvc"), halfmoves.to_string()));
+            }
+            if moves != "1" {
+                operations.push((String::from("fmvn"), moves.to_string()));
+            }
+        }
+        if !self.best_moves.is_empty() {
+            operations.push((String::from("bm"), sans(&self.best_moves)));
+        }
+        if !self.avoid_moves.is_empty() {
+            operations.push((String::from("am"), sans(&self.avoid_moves)));
+        }
+        if let Some(centipawns) = self.centipawns {
+            operations.push((String::from("ce"), centipawns.to_string()));
+        }
+        if let Some(mate) = self.mate {
+            operations.push((String::from("dm"), mate.to_string()));
+        }
+        if let Some(id) = &self.id {
+            operations.push((String::from("id"), format!("\"{}\"", id)));
+        }
+        if let Some(comment) = &self.comment {
+            operations.push((String::from("c0"), format!("\"{}\"", comment)));
+        }
+        operations.extend(self.other.iter().cloned());
+
+        let mut epd = self.state.to_epd(&[]);
+        for (opcode, operands) in operations {
+            epd.push_str(&format!(" {} {};", opcode, operands));
+        }
+        epd
+    }
+
+    /// The STS-style points of each move given in the comment, like
+    /// `Nf3=10, Nd2=5`, or the full points for the best moves if it has none.
+    pub fn move_points(&self) -> Vec<(Move, u32)> {
+        let from_comment: Option<Vec<(Move, u32)>> = self.comment.as_ref().and_then(|comment| {
+            comment.split(',')
+                .map(|part| {
+                    let (san, points) = part.trim().split_once('=')?;
+                    Some((self.state.move_from_san(san)?, points.trim().parse().ok()?))
+                })
+                .collect()
+        });
+        from_comment.unwrap_or_else(|| self.best_moves.iter().map(|&mv| (mv, BEST_MOVE_POINTS)).collect())
+    }
+
+    /// Whether playing `mv` with the evaluation `score` solves the position.
+    /// Every condition of `bm`, `am` and `dm` that is given has to hold, and
+    /// positions without any cannot be solved.
+    pub fn is_solution(&self, mv: Move, score: Score) -> bool {
+        let has_condition = !self.best_moves.is_empty() || !self.avoid_moves.is_empty() || self.mate.is_some();
+        has_condition
+            && (self.best_moves.is_empty() || self.best_moves.contains(&mv))
+            && !self.avoid_moves.contains(&mv)
+            && self.mate.is_none_or(|mate| matches!(score, Score::Mate(moves) if moves > 0 && moves <= mate))
+    }
+}
+
+impl State {
+    /// The position as EPD, with the given operations written as
+    /// `opcode operand;` after it. String operands have to be quoted.
+    pub fn to_epd(&self, operations: &[(&str, &str)]) -> String {
+        let fen = self.to_fen();
+        let mut epd = fen.split_whitespace().take(4).collect::<Vec<&str>>().join(" ");
+        for (opcode, operand) in operations {
+            epd.push_str(&format!(" {} {};", opcode, operand));
+        }
+        epd
+    }
+}
+
+/// Reads every EPD line of a suite, skipping empty lines and lines starting with `#`.
+pub fn parse_epd(text: &str) -> Result<Vec<Epd>, EpdError> {
+    text.lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(Epd::parse)
+        .collect()
+}
+
+/// What the search made of one position.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EpdResult {
+    pub best_move: Option<Move>,
+    pub score: Score,
+    pub depth: u32,
+    pub solved: bool,
+    /// When the search found the solution and kept it until the end.
+    pub time_to_solution: Option<Duration>,
+    pub points: u32,
+}
+
+/// Searches the position of `epd` with `limits` and checks the move.
+pub fn run_position(epd: &Epd, limits: &SearchLimits) -> EpdResult {
+    let mut found: Option<Duration> = None;
+    let info = search::search(&epd.state, limits, &mut |info| {
+        let solved = info.best_move().is_some_and(|mv| epd.is_solution(mv, info.score));
+        found = if solved { found.or(Some(info.time)) } else { None };
+    });
+    let best_move = info.best_move();
+    let solved = best_move.is_some_and(|mv| epd.is_solution(mv, info.score));
+    let points = best_move.and_then(|mv| epd.move_points().iter().find(|(points_move, _)| *points_move == mv).map(|(_, points)| *points));
+    EpdResult {
+        best_move,
+        score: info.score,
+        depth: info.depth,
+        solved,
+        time_to_solution: found.filter(|_| solved),
+        points: points.unwrap_or(0),
+    }
+}
+
+/// The results of a whole suite.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SuiteReport {
+    pub results: Vec<EpdResult>,
+    /// The most points each position gives.
+    pub max_points: Vec<u32>,
+}
+
+impl SuiteReport {
+    pub fn solved(&self) -> usize {
+        self.results.iter().filter(|result| result.solved).count()
+    }
+
+    pub fn points(&self) -> u32 {
+        self.results.iter().map(|result| result.points).sum()
+    }
+
+    pub fn total_max_points(&self) -> u32 {
+        self.max_points.iter().sum()
+    }
+
+    /// The average time to the solution of the solved positions.
+    pub fn average_time_to_solution(&self) -> Option<Duration> {
+        let times: Vec<Duration> = self.results.iter().filter_map(|result| result.time_to_solution).collect();
+        if times.is_empty() {
+            None
+        } else {
+            Some(times.iter().sum::<Duration>() / times.len() as u32)
+        }
+    }
+}
+
+/// Runs every position of a suite. `on_result` is called after each one.
+pub fn run_suite(suite: &[Epd], limits: &SearchLimits, on_result: &mut dyn FnMut(&Epd, &EpdResult)) -> SuiteReport {
+    let mut report = SuiteReport::default();
+    for epd in suite {
+        let result = run_position(epd, limits);
+        on_result(epd, &result);
+        report.results.push(result);
+        report.max_points.push(epd.move_points().iter().map(|(_, points)| *points).max().unwrap_or(0));
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // WAC.001 and WAC.002, an STS-style line and a mate
+    const SUITE: &str = "# A few test positions
+2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id \"WAC.001\";
+8/7p/5k2/5p2/p1p2P2/Pr1pPK2/1P1R3P/8 b - - bm Rxb2; id \"WAC.002\";
+1kr5/3n4/q3p2p/p2n2p1/PppB1P2/5BP1/1P2Q2P/3R2K1 w - - bm f5; id \"STS(v1.0) Undermining.001\"; c0 \"f5=10, Be5+=2, Bf2=3, Bg4=2\";
+6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1 dm 1; am Kf2; id \"mate\";
+";
+
+    #[test]
+    fn parse_test() {
+        let suite = parse_epd(SUITE).unwrap();
+        assert_eq!(suite.len(), 4);
+        assert_eq!(suite[0].id.as_deref(), Some("WAC.001"));
+        assert_eq!(suite[0].best_moves, [suite[0].state.move_from_san("Qg6").unwrap()]);
+        assert_eq!(suite[2].move_points().len(), 4);
+        assert_eq!(suite[2].move_points()[1].1, 2);
+        assert_eq!(suite[1].move_points()[0].1, BEST_MOVE_POINTS);
+        assert_eq!(suite[3].mate, Some(1));
+        assert_eq!(suite[3].avoid_moves.len(), 1);
+
+        assert_eq!(Epd::parse("8/8/8 w -").err(), Some(EpdError::MissingField));
+        assert_eq!(Epd::parse("4k3/8/8/8/8/8/8/4K3 w - - bm Qh5;").err(), Some(EpdError::InvalidMove));
+        assert_eq!(Epd::parse("4k3/8/8/8/8/8/8/4K3 w - - id \"open;").err(), Some(EpdError::InvalidOperation));
+
+        let epd = Epd::parse("4k3/8/8/8/8/8/8/4K3 w - - hmvc 12; fmvn 40; acd 20; c1 \"two words\";").unwrap();
+        assert!(epd.state.to_fen().ends_with(" 12 40"));
+        assert_eq!(epd.other, [(String::from("acd"), String::from("20")), (String::from("c1"), String::from("\"two words\""))]);
+    }
+
+    #[test]
+    fn write_test() {
+        let suite = parse_epd(SUITE).unwrap();
+        let text = suite[2].to_epd();
+        assert_eq!(text, SUITE.lines().nth(3).unwrap());
+        let epd = Epd::parse(&text).unwrap();
+        assert_eq!(epd.to_epd(), text);
+
+        let text = "4k3/8/8/8/8/8/8/4K3 w - - hmvc 12; fmvn 40; acd 20;";
+        assert_eq!(Epd::parse(text).unwrap().to_epd(), text);
+        let epd = Epd::parse("4k3/8/8/8/8/8/8/4K3 w - - 0 7 id \"counters\";").unwrap();
+        assert_eq!(epd.to_epd(), "4k3/8/8/8/8/8/8/4K3 w - - fmvn 7; id \"counters\";");
+
+        let state = State::new();
+        assert_eq!(state.to_epd(&[]), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq -");
+        assert_eq!(state.to_epd(&[("bm", "e4"), ("id", "\"start\"")]),
+                   "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - bm e4; id \"start\";");
+    }
+
+    #[test]
+    fn run_test() {
+        let suite = parse_epd(SUITE).unwrap();
+        let mut seen = Vec::new();
+        let report = run_suite(&suite[3..], &SearchLimits::depth(3), &mut |epd, _| seen.push(epd.id.clone()));
+        assert_eq!(seen, [Some(String::from("mate"))]);
+        assert_eq!(report.solved(), 1);
+        assert!(report.results[0].time_to_solution.is_some());
+        assert!(report.average_time_to_solution().is_some());
+
+        // Taking the hanging queen
+        let epd = Epd::parse("4k3/8/8/3q4/8/8/8/3RK3 w - - bm Rxd5;").unwrap();
+        let result = run_position(&epd, &SearchLimits::depth(3));
+        assert!(result.solved);
+        assert_eq!(result.points, BEST_MOVE_POINTS);
+
+        let mut epd = epd.clone();
+        epd.best_moves.clear();
+        assert!(!epd.is_solution(result.best_move.unwrap(), result.score));
+    }
+}
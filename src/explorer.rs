@@ -0,0 +1,302 @@
+//! An opening explorer: every move played from a position in a collection of
+//! games, with how often it was played, how the games ended and the average
+//! rating of the players. The database file holds fixed-size records sorted
+//! by position hash and move, so a position is found by a binary search
+//! without reading the whole file.
+
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs::File;
+use std::io::{self, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::pgn::PgnGame;
+use crate::{Move, PieceType, Pos, State};
+
+const MAGIC: &[u8; 8] = b"CHESSEXP";
+const HEADER_SIZE: u64 = 16;
+// Hash, move, white wins, draws, black wins, rated games and rating sum
+const RECORD_SIZE: usize = 8 + 2 + 4 * 4 + 8;
+
+/// The number of plies of each game that are indexed unless told otherwise.
+pub const DEFAULT_DEPTH: usize = 40;
+
+/// A move played from a position, with the results of the games it was played in.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ExplorerMove {
+    pub mv: Move,
+    pub white_wins: u32,
+    pub draws: u32,
+    pub black_wins: u32,
+    // Games with at least one rated player, and the sum of their average ratings
+    rated: u32,
+    rating_sum: u64,
+}
+
+impl ExplorerMove {
+    fn new(mv: Move) -> ExplorerMove {
+        ExplorerMove { mv, white_wins: 0, draws: 0, black_wins: 0, rated: 0, rating_sum: 0 }
+    }
+
+    pub fn games(&self) -> u32 {
+        self.white_wins + self.draws + self.black_wins
+    }
+
+    /// White's score in these games, from 0 to 1.
+    pub fn white_score(&self) -> f32 {
+        (self.white_wins as f32 + self.draws as f32 / 2.0) / self.games().max(1) as f32
+    }
+
+    /// The average rating of the players, if any of them had one.
+    pub fn average_rating(&self) -> Option<u32> {
+        if self.rated == 0 {
+            None
+        } else {
+            Some((self.rating_sum / self.rated as u64) as u32)
+        }
+    }
+
+    fn add(&mut self, other: &ExplorerMove) {
+        self.white_wins += other.white_wins;
+        self.draws += other.draws;
+        self.black_wins += other.black_wins;
+        self.rated += other.rated;
+        self.rating_sum += other.rating_sum;
+    }
+}
+
+// Squares take six bits each, then the promotion piece plus one. Drops have
+// the top bit set and the dropped piece in place of the square it comes from.
+fn encode_move(mv: Move) -> u16 {
+    let to = mv.to.index() as u16;
+    match mv.drop {
+        Some(piece_type) => 1 << 15 | (piece_type as u16) << 6 | to,
+        None => {
+            let promotion = mv.promotion.map_or(0, |piece_type| piece_type as u16 + 1);
+            promotion << 12 | (mv.from.index() as u16) << 6 | to
+        }
+    }
+}
+
+fn piece_type_from_index(index: u16) -> Option<PieceType> {
+    [PieceType::King, PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::Pawn]
+        .get(index as usize).copied()
+}
+
+fn decode_move(code: u16) -> Option<Move> {
+    let square = |index: u16| Pos::new((index % 8) as i32, (index / 8) as i32);
+    let to = square(code & 63);
+    if code & 1 << 15 != 0 {
+        let piece_type = piece_type_from_index(code >> 6 & 63)?;
+        return Some(Move { from: to, to, promotion: None, drop: Some(piece_type) });
+    }
+    let from = square(code >> 6 & 63);
+    match code >> 12 {
+        0 => Some(Move::new(from, to)),
+        promotion => Some(Move::with_promotion(from, to, piece_type_from_index(promotion - 1)?)),
+    }
+}
+
+/// Collects the moves of games before they are written to a database file.
+pub struct ExplorerBuilder {
+    depth: usize,
+    moves: BTreeMap<(u64, u16), ExplorerMove>,
+    games: usize,
+}
+
+impl ExplorerBuilder {
+    /// A builder that indexes the first `depth` plies of every game.
+    pub fn new(depth: usize) -> ExplorerBuilder {
+        ExplorerBuilder { depth, moves: BTreeMap::new(), games: 0 }
+    }
+
+    /// The number of games added so far.
+    pub fn games(&self) -> usize {
+        self.games
+    }
+
+    /// Adds the main line of a game. Games without a result are left out,
+    /// and the returned value says whether the game was added.
+    pub fn add_game(&mut self, game: &PgnGame) -> bool {
+        let mut counts = ExplorerMove::new(Move::new(Pos::new(0, 0), Pos::new(0, 0)));
+        match game.tag("Result") {
+            Some("1-0") => counts.white_wins = 1,
+            Some("0-1") => counts.black_wins = 1,
+            Some("1/2-1/2") => counts.draws = 1,
+            _ => return false,
+        }
+        let ratings: Vec<u64> = ["WhiteElo", "BlackElo"].iter()
+            .filter_map(|tag| game.tag(tag).and_then(|rating| rating.parse().ok()))
+            .collect();
+        if !ratings.is_empty() {
+            counts.rated = 1;
+            counts.rating_sum = ratings.iter().sum::<u64>() / ratings.len() as u64;
+        }
+
+        let tree = &game.tree;
+        let line = tree.main_line();
+        for pair in line.windows(2).take(self.depth) {
+            let hash = tree.get(pair[0]).unwrap().position.get_hash();
+            let mv = match tree.get(pair[1]).unwrap().mv {
+                Some(mv) => mv,
+                None => continue,
+            };
+            self.moves.entry((hash, encode_move(mv))).or_insert_with(|| ExplorerMove::new(mv)).add(&counts);
+        }
+        self.games += 1;
+        true
+    }
+
+    /// Writes the database file, which `ExplorerDb::open` reads.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(self.moves.len() as u64).to_be_bytes())?;
+        for ((hash, code), stats) in self.moves.iter() {
+            writer.write_all(&hash.to_be_bytes())?;
+            writer.write_all(&code.to_be_bytes())?;
+            for count in [stats.white_wins, stats.draws, stats.black_wins, stats.rated] {
+                writer.write_all(&count.to_be_bytes())?;
+            }
+            writer.write_all(&stats.rating_sum.to_be_bytes())?;
+        }
+        writer.flush()
+    }
+}
+
+/// A database file opened for queries.
+pub struct ExplorerDb {
+    file: File,
+    records: u64,
+}
+
+impl ExplorerDb {
+    pub fn open(path: &Path) -> io::Result<ExplorerDb> {
+        let mut file = File::open(path)?;
+        let mut header = [0; HEADER_SIZE as usize];
+        file.read_exact(&mut header)?;
+        if &header[..8] != MAGIC {
+            return Err(io::Error::new(ErrorKind::InvalidData, "Not an opening explorer database"));
+        }
+        let records = u64::from_be_bytes(header[8..].try_into().unwrap());
+        Ok(ExplorerDb { file, records })
+    }
+
+    // The hash of the record at `index`, with the move code and stats
+    fn read_record(&mut self, index: u64) -> io::Result<(u64, u16, [u8; RECORD_SIZE])> {
+        let mut record = [0; RECORD_SIZE];
+        self.file.seek(SeekFrom::Start(HEADER_SIZE + index * RECORD_SIZE as u64))?;
+        self.file.read_exact(&mut record)?;
+        let hash = u64::from_be_bytes(record[..8].try_into().unwrap());
+        let code = u16::from_be_bytes(record[8..10].try_into().unwrap());
+        Ok((hash, code, record))
+    }
+
+    /// The moves played from the position of `state`, the most played first.
+    pub fn query(&mut self, state: &State) -> io::Result<Vec<ExplorerMove>> {
+        let hash = state.get_hash();
+        let (mut low, mut high) = (0, self.records);
+        while low < high {
+            let middle = (low + high) / 2;
+            if self.read_record(middle)?.0 < hash {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+
+        let mut moves = Vec::new();
+        for index in low..self.records {
+            let (record_hash, code, record) = self.read_record(index)?;
+            if record_hash != hash {
+                break;
+            }
+            let count = |offset: usize| u32::from_be_bytes(record[offset..offset + 4].try_into().unwrap());
+            if let Some(mv) = decode_move(code) {
+                moves.push(ExplorerMove {
+                    mv,
+                    white_wins: count(10),
+                    draws: count(14),
+                    black_wins: count(18),
+                    rated: count(22),
+                    rating_sum: u64::from_be_bytes(record[26..34].try_into().unwrap()),
+                });
+            }
+        }
+        moves.sort_by_key(|stats| std::cmp::Reverse(stats.games()));
+        Ok(moves)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pgn::parse_pgn;
+
+    const GAMES: &str = r#"[White "a"]
+[Black "b"]
+[Result "1-0"]
+[WhiteElo "2000"]
+[BlackElo "1800"]
+
+1. e4 e5 2. Nf3 Nc6 1-0
+
+[White "c"]
+[Black "d"]
+[Result "1/2-1/2"]
+[WhiteElo "2200"]
+
+1. e4 c5 1/2-1/2
+
+[White "e"]
+[Black "f"]
+[Result "0-1"]
+
+1. d4 d5 2. c4 0-1
+
+[Result "*"]
+
+1. e4 e6 *
+"#;
+
+    #[test]
+    fn move_code_test() {
+        for uci in ["e2e4", "g1f3", "a7a8q", "h2g1n", "e1g1"] {
+            let mv = Move::from_uci(uci).unwrap();
+            assert_eq!(decode_move(encode_move(mv)), Some(mv));
+        }
+        let drop = Move { from: Pos::new(4, 3), to: Pos::new(4, 3), promotion: None, drop: Some(PieceType::Knight) };
+        assert_eq!(decode_move(encode_move(drop)), Some(drop));
+    }
+
+    #[test]
+    fn explorer_test() {
+        let mut builder = ExplorerBuilder::new(DEFAULT_DEPTH);
+        let added = parse_pgn(GAMES).unwrap().iter().filter(|game| builder.add_game(game)).count();
+        assert_eq!(added, 3);
+        assert_eq!(builder.games(), 3);
+
+        let path = std::env::temp_dir().join(format!("chess-explorer-{}", std::process::id()));
+        builder.write(&path).unwrap();
+        let mut db = ExplorerDb::open(&path).unwrap();
+
+        let mut state = State::new();
+        let moves = db.query(&state).unwrap();
+        assert_eq!(moves.len(), 2);
+        assert_eq!(moves[0].mv, Move::from_uci("e2e4").unwrap());
+        assert_eq!((moves[0].white_wins, moves[0].draws, moves[0].black_wins), (1, 1, 0));
+        assert_eq!(moves[0].white_score(), 0.75);
+        assert_eq!(moves[0].average_rating(), Some(2050));
+        assert_eq!(moves[1].average_rating(), None);
+
+        state.play_move(Move::from_uci("e2e4").unwrap()).unwrap();
+        let moves = db.query(&state).unwrap();
+        assert_eq!(moves.iter().map(ExplorerMove::games).sum::<u32>(), 2);
+        state.play_move(Move::from_uci("e7e6").unwrap()).unwrap();
+        assert!(db.query(&state).unwrap().is_empty());
+
+        std::fs::write(&path, b"something else entirely").unwrap();
+        assert!(ExplorerDb::open(&path).is_err());
+        let _res = std::fs::remove_file(&path);
+    }
+}
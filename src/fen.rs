@@ -0,0 +1,382 @@
+use std::sync::Arc;
+
+use crate::castling::{back_rank, KING_SIDE, QUEEN_SIDE};
+use crate::variant::{Standard, Variant};
+use crate::{Piece, PieceType, Player, Pos, State};
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FenError {
+    MissingField,
+    InvalidBoard,
+    InvalidPlayer,
+    InvalidCastling,
+    InvalidEnPassant,
+    InvalidCounter,
+    InvalidKings,
+    InvalidPockets,
+}
+
+impl FenError {
+    pub fn to_string(&self) -> &str {
+        match self {
+            Self::MissingField =>     "The FEN string has too few fields",
+            Self::InvalidBoard =>     "The piece placement is invalid",
+            Self::InvalidPlayer =>    "The player to move must be w or b",
+            Self::InvalidCastling =>  "The castling rights do not match the position",
+            Self::InvalidEnPassant => "The en passant square is invalid",
+            Self::InvalidCounter =>   "The move counters are invalid",
+            Self::InvalidKings =>     "Each player must have exactly one king",
+            Self::InvalidPockets =>   "The pieces in hand are invalid",
+        }
+    }
+}
+
+fn piece_to_char(piece: Piece) -> char {
+    let letter = piece.piece_type.to_letter();
+    match piece.player {
+        Player::White => letter,
+        Player::Black => letter.to_ascii_lowercase(),
+    }
+}
+
+fn piece_from_char(c: char) -> Option<Piece> {
+    let piece_type = PieceType::from_letter(c.to_ascii_uppercase())?;
+    let player = if c.is_ascii_uppercase() { Player::White } else { Player::Black };
+    Some(Piece::new(piece_type, player))
+}
+
+// The board and the pockets, which are either in brackets after the board,
+// `...RNBQKBNR[Qp]`, or given as a ninth rank, `...RNBQKBNR/Qp`
+fn split_pockets(field: &str) -> Result<(&str, Option<&str>), FenError> {
+    if let Some(start) = field.find('[') {
+        if !field.ends_with(']') {
+            return Err(FenError::InvalidPockets);
+        }
+        return Ok((&field[..start], Some(&field[start + 1..field.len() - 1])));
+    }
+    if field.split('/').count() == 9 {
+        let end = field.rfind('/').unwrap();
+        return Ok((&field[..end], Some(&field[end + 1..])));
+    }
+    Ok((field, None))
+}
+
+// A `~` after a piece marks it as a promoted pawn
+fn parse_board(field: &str) -> Result<([Option<Piece>; 64], u64), FenError> {
+    let mut board = [None; 64];
+    let mut promoted = 0;
+    let ranks: Vec<&str> = field.split('/').collect();
+    if ranks.len() != 8 {
+        return Err(FenError::InvalidBoard);
+    }
+
+    for (row, rank) in ranks.iter().enumerate() {
+        let y = 7 - row as i32;
+        let mut x = 0;
+        for c in rank.chars() {
+            if let Some(skip) = c.to_digit(10) {
+                x += skip as i32;
+            } else if c == '~' {
+                if x == 0 || x > 8 {
+                    return Err(FenError::InvalidBoard);
+                }
+                promoted |= 1 << Pos::new(x - 1, y).index();
+            } else {
+                let piece = piece_from_char(c).ok_or(FenError::InvalidBoard)?;
+                if x >= 8 {
+                    return Err(FenError::InvalidBoard);
+                }
+                board[Pos::new(x, y).index()] = Some(piece);
+                x += 1;
+            }
+        }
+        if x != 8 {
+            return Err(FenError::InvalidBoard);
+        }
+    }
+    Ok((board, promoted))
+}
+
+impl State {
+    /// Reads a position in FEN. The castling field may be classical (`KQkq`),
+    /// X-FEN, where an inner rook is named by its file, or Shredder-FEN, where
+    /// every rook is named by its file (`HAha`). Castling rights that need
+    /// Chess960 rules make the game a Chess960 game.
+    pub fn from_fen(fen: &str) -> Result<State, FenError> {
+        State::from_fen_with_variant(fen, Arc::new(Standard))
+    }
+
+    /// Reads a position in FEN for a game of `variant`. Pieces in hand follow the
+    /// board in brackets, as in `[Qp]`, and promoted pieces are marked with `~`.
+    pub fn from_fen_with_variant(fen: &str, variant: Arc<dyn Variant>) -> Result<State, FenError> {
+        let fields: Vec<&str> = fen.split_whitespace().collect();
+        if fields.len() < 4 {
+            return Err(FenError::MissingField);
+        }
+
+        let mut state = State::new();
+        state.variant = variant;
+        let (board, pockets) = split_pockets(fields[0])?;
+        let (board, promoted) = parse_board(board)?;
+        state.board = board;
+        state.promoted = promoted;
+        if let Some(pockets) = pockets {
+            for c in pockets.chars() {
+                match piece_from_char(c) {
+                    Some(piece) if piece.piece_type != PieceType::King => state.add_to_pocket(piece.player, piece.piece_type),
+                    _ => return Err(FenError::InvalidPockets),
+                }
+            }
+        }
+
+        for player in [Player::White, Player::Black].iter() {
+            let kings = state.board.iter()
+                .filter(|square| **square == Some(Piece::new(PieceType::King, *player)))
+                .count();
+            if kings != 1 && state.variant.has_royal_king() {
+                return Err(FenError::InvalidKings);
+            }
+        }
+
+        state.current_player = match fields[1] {
+            "w" => Player::White,
+            "b" => Player::Black,
+            _ => return Err(FenError::InvalidPlayer),
+        };
+
+        state.castling_rights = [[None; 2]; 2];
+        state.chess960 = false;
+        if fields[2] != "-" {
+            if !state.variant.allows_castling() {
+                return Err(FenError::InvalidCastling);
+            }
+            for c in fields[2].chars() {
+                state.parse_castling_char(c)?;
+            }
+        }
+
+        state.en_passant = None;
+        if fields[3] != "-" {
+            let rank = if state.current_player == Player::White { 5 } else { 2 };
+            match Pos::from_notation(fields[3]) {
+                Some(pos) if pos.y == rank => state.en_passant = Some(pos),
+                _ => return Err(FenError::InvalidEnPassant),
+            }
+        }
+
+        let halfmove_clock: u32 = match fields.get(4) {
+            Some(field) => field.parse().map_err(|_| FenError::InvalidCounter)?,
+            None => 0,
+        };
+        let fullmove: u32 = match fields.get(5) {
+            Some(field) => field.parse().map_err(|_| FenError::InvalidCounter)?,
+            None => 1,
+        };
+        if fullmove == 0 {
+            return Err(FenError::InvalidCounter);
+        }
+
+        state.halfmove_clock = halfmove_clock;
+        state.total_steps = (fullmove - 1) * 2 + if state.current_player == Player::Black { 1 } else { 0 };
+        state.position_hashes = vec![state.get_hash()];
+
+        // The position may already be decided
+        let _res = state.handle_post_move();
+        Ok(state)
+    }
+
+    fn parse_castling_char(&mut self, c: char) -> Result<(), FenError> {
+        let player = if c.is_ascii_uppercase() { Player::White } else { Player::Black };
+        let rank = back_rank(player);
+        let king_file = (0..8)
+            .find(|&x| self.get(Pos::new(x, rank)) == Some(Piece::new(PieceType::King, player)))
+            .ok_or(FenError::InvalidCastling)?;
+        let is_rook = |x: i32| self.get(Pos::new(x, rank)) == Some(Piece::new(PieceType::Rook, player));
+
+        let (side, rook_file) = match c.to_ascii_uppercase() {
+            'K' => (KING_SIDE, (king_file + 1..8).rev().find(|&x| is_rook(x))),
+            'Q' => (QUEEN_SIDE, (0..king_file).find(|&x| is_rook(x))),
+            'A'..='H' => {
+                let file = (c.to_ascii_uppercase() as u8 - b'A') as i32;
+                let side = if file > king_file { KING_SIDE } else { QUEEN_SIDE };
+                (side, if is_rook(file) { Some(file) } else { None })
+            }
+            _ => return Err(FenError::InvalidCastling),
+        };
+
+        let rook_file = rook_file.ok_or(FenError::InvalidCastling)?;
+        let classical_file = if side == KING_SIDE { 7 } else { 0 };
+        if king_file != 4 || rook_file != classical_file {
+            self.chess960 = true;
+        }
+        self.set_castling_rook(player, side, Some(rook_file));
+        Ok(())
+    }
+
+    /// The position in FEN, using X-FEN for castling rights.
+    pub fn to_fen(&self) -> String {
+        self.format_fen(false)
+    }
+
+    /// The position in Shredder-FEN, where castling rights are given as rook files.
+    pub fn to_shredder_fen(&self) -> String {
+        self.format_fen(true)
+    }
+
+    fn format_fen(&self, shredder: bool) -> String {
+        let mut fen = String::new();
+        for y in (0..8).rev() {
+            let mut empty = 0;
+            for x in 0..8 {
+                match self.get(Pos::new(x, y)) {
+                    Some(piece) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        fen.push(piece_to_char(piece));
+                        if self.is_promoted(Pos::new(x, y)) {
+                            fen.push('~');
+                        }
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if y > 0 {
+                fen.push('/');
+            }
+        }
+        if self.variant.has_drops() {
+            fen.push_str(&format!("[{}]", self.format_pockets()));
+        }
+
+        let player = match self.current_player {
+            Player::White => "w",
+            Player::Black => "b",
+        };
+        let castling = self.format_castling(shredder);
+        // Only given when a pawn can actually take, like X-FEN does
+        let en_passant = match self.get_capturable_en_passant() {
+            Some(pos) => pos.to_notation(),
+            None => String::from("-"),
+        };
+        let fullmove = self.total_steps / 2 + 1;
+        format!("{} {} {} {} {} {}", fen, player, castling, en_passant, self.halfmove_clock, fullmove)
+    }
+
+    fn format_pockets(&self) -> String {
+        const ORDER: [PieceType; 5] = [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::Pawn];
+        let mut pockets = String::new();
+        for player in [Player::White, Player::Black].iter().copied() {
+            for piece_type in ORDER.iter().copied() {
+                for _ in 0..self.get_pocket_count(player, piece_type) {
+                    pockets.push(piece_to_char(Piece::new(piece_type, player)));
+                }
+            }
+        }
+        pockets
+    }
+
+    fn format_castling(&self, shredder: bool) -> String {
+        let mut castling = String::new();
+        for player in [Player::White, Player::Black].iter().copied() {
+            let rank = back_rank(player);
+            for side in [KING_SIDE, QUEEN_SIDE].iter().copied() {
+                let file = match self.get_castling_rook(player, side) {
+                    Some(file) => file,
+                    None => continue,
+                };
+
+                // X-FEN only names the rook by file if another rook is further out
+                let further_out = if side == KING_SIDE { file + 1..8 } else { 0..file };
+                let outermost = !further_out
+                    .into_iter()
+                    .any(|x| self.get(Pos::new(x, rank)) == Some(Piece::new(PieceType::Rook, player)));
+
+                let c = if shredder || !outermost {
+                    (b'A' + file as u8) as char
+                } else if side == KING_SIDE {
+                    'K'
+                } else {
+                    'Q'
+                };
+                castling.push(if player == Player::White { c } else { c.to_ascii_lowercase() });
+            }
+        }
+        if castling.is_empty() {
+            castling.push('-');
+        }
+        castling
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn start_position_test() {
+        assert_eq!(State::new().to_fen(), START_FEN);
+        assert_eq!(State::new().to_shredder_fen(), "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w HAha - 0 1");
+
+        let state = State::from_fen(START_FEN).unwrap();
+        assert!(!state.is_chess960());
+        assert_eq!(state.get_hash(), State::new().get_hash());
+        assert_eq!(state.get_legal_moves().len(), 20);
+    }
+
+    #[test]
+    fn round_trip_test() {
+        let mut state = State::new();
+        assert!(state.move_piece(Pos::new(4, 1), Pos::new(4, 3)).is_ok());
+        assert!(state.move_piece(Pos::new(6, 7), Pos::new(5, 5)).is_ok());
+        let fen = state.to_fen();
+        assert_eq!(fen, "rnbqkb1r/pppppppp/5n2/8/4P3/8/PPPP1PPP/RNBQKBNR w KQkq - 1 2");
+        assert_eq!(State::from_fen(&fen).unwrap().to_fen(), fen);
+    }
+
+    #[test]
+    fn chess960_test() {
+        let state = State::new_chess960(0).unwrap();
+        assert_eq!(state.to_fen(), "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w KQkq - 0 1");
+        assert_eq!(state.to_shredder_fen(), "bbqnnrkr/pppppppp/8/8/8/8/PPPPPPPP/BBQNNRKR w HFhf - 0 1");
+
+        let parsed = State::from_fen(&state.to_shredder_fen()).unwrap();
+        assert!(parsed.is_chess960());
+        assert_eq!(parsed.get_castling_rook(Player::White, QUEEN_SIDE), Some(5));
+        assert_eq!(parsed.get_hash(), state.get_hash());
+    }
+
+    #[test]
+    fn x_fen_inner_rook_test() {
+        // Two rooks on the king side, castling with the inner one
+        let state = State::from_fen("4k3/8/8/8/8/8/8/R3KR1R w FA - 0 1").unwrap();
+        assert!(state.is_chess960());
+        assert_eq!(state.to_fen(), "4k3/8/8/8/8/8/8/R3KR1R w FQ - 0 1");
+        assert_eq!(State::from_fen("4k3/8/8/8/8/8/8/R3KR1R w K - 0 1").unwrap().get_castling_rook(Player::White, KING_SIDE), Some(7));
+    }
+
+    #[test]
+    fn invalid_fen_test() {
+        assert_eq!(State::from_fen("8/8/8 w - -").err(), Some(FenError::InvalidBoard));
+        assert_eq!(State::from_fen("9~/8/8/8/8/8/8/4K2k w - -").err(), Some(FenError::InvalidBoard));
+        assert_eq!(State::from_fen("~4k3/8/8/8/8/8/8/4K3 w - -").err(), Some(FenError::InvalidBoard));
+        assert_eq!(State::from_fen("8/8/8/8/8/8/8/8 w - -").err(), Some(FenError::InvalidKings));
+        assert_eq!(State::from_fen("4k3/8/8/8/8/8/8/4K3 x - -").err(), Some(FenError::InvalidPlayer));
+        assert_eq!(State::from_fen("4k3/8/8/8/8/8/8/4K3 w K -").err(), Some(FenError::InvalidCastling));
+        assert_eq!(State::from_fen("4k3/8/8/8/8/8/8/4K3 w - z9").err(), Some(FenError::InvalidEnPassant));
+        assert_eq!(State::from_fen("4k3/8/8/8/8/8/8/4K3 w - - x 1").err(), Some(FenError::InvalidCounter));
+        assert_eq!(State::from_fen("4k3/8/8/8/8/8/8/4K3 w").err(), Some(FenError::MissingField));
+    }
+
+    #[test]
+    fn decided_position_test() {
+        let state = State::from_fen("7k/5KQ1/8/8/8/8/8/8 b - - 0 1").unwrap();
+        assert!(!state.get_game_running());
+    }
+}
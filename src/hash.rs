@@ -0,0 +1,109 @@
+use crate::{Piece, Player, State};
+
+// SplitMix64. Every hashed feature gets a fixed pseudo random key from its
+// number, so no key table has to be stored.
+fn mix(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+fn piece_key(piece: Piece, index: usize) -> u64 {
+    let player = match piece.player {
+        Player::White => 0,
+        Player::Black => 1,
+    };
+    mix(((player * 6 + piece.piece_type as usize) * 64 + index) as u64)
+}
+
+const BLACK_TO_MOVE_KEY: u64 = 12 * 64;
+const CASTLING_KEYS: u64 = 12 * 64 + 1;
+const EN_PASSANT_KEYS: u64 = CASTLING_KEYS + 4 * 8;
+const PROMOTED_KEYS: u64 = EN_PASSANT_KEYS + 8;
+const POCKET_KEYS: u64 = PROMOTED_KEYS + 64;
+const CHECKS_KEYS: u64 = POCKET_KEYS + 2 * 6 * 64;
+
+impl State {
+    /// Zobrist hash of the position, covering the pieces, the player to move,
+    /// the castling rights, an en passant capture if one is possible, in
+    /// drop variants the pockets and promoted pieces, and in Three-check the
+    /// checks given.
+    pub fn get_hash(&self) -> u64 {
+        let mut hash = 0;
+        for (index, square) in self.board.iter().enumerate() {
+            if let Some(piece) = square {
+                hash ^= piece_key(*piece, index);
+            }
+        }
+        if self.current_player == Player::Black {
+            hash ^= mix(BLACK_TO_MOVE_KEY);
+        }
+        for (player, rights) in self.castling_rights.iter().enumerate() {
+            for (side, file) in rights.iter().enumerate() {
+                if let Some(file) = file {
+                    hash ^= mix(CASTLING_KEYS + ((player * 2 + side) * 8) as u64 + *file as u64);
+                }
+            }
+        }
+        if let Some(pos) = self.get_capturable_en_passant() {
+            hash ^= mix(EN_PASSANT_KEYS + pos.x as u64);
+        }
+        for index in 0..64 {
+            if self.promoted & (1 << index) != 0 {
+                hash ^= mix(PROMOTED_KEYS + index);
+            }
+        }
+        for (player, pocket) in self.pockets.iter().enumerate() {
+            for (piece_type, count) in pocket.iter().enumerate() {
+                if *count > 0 {
+                    hash ^= mix(POCKET_KEYS + ((player * 6 + piece_type) * 64) as u64 + *count as u64);
+                }
+            }
+        }
+        if self.variant.counts_checks() {
+            for (player, checks) in self.checks_given.iter().enumerate() {
+                hash ^= mix(CHECKS_KEYS + (player * 64) as u64 + (*checks).min(63) as u64);
+            }
+        }
+        hash
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::variant::ThreeCheck;
+    use crate::{Pos, State};
+
+    #[test]
+    fn hash_test() {
+        let mut state = State::new();
+        let start = state.get_hash();
+        assert_eq!(start, State::new().get_hash());
+
+        assert!(state.move_piece(Pos::new(6, 0), Pos::new(5, 2)).is_ok());
+        let after_one = state.get_hash();
+        assert_ne!(start, after_one);
+
+        assert!(state.move_piece(Pos::new(6, 7), Pos::new(5, 5)).is_ok());
+        assert!(state.move_piece(Pos::new(5, 2), Pos::new(6, 0)).is_ok());
+        assert_ne!(state.get_hash(), start);
+        assert!(state.move_piece(Pos::new(5, 5), Pos::new(6, 7)).is_ok());
+        assert_eq!(state.get_hash(), start);
+    }
+
+    #[test]
+    fn checks_test() {
+        let state = State::new_variant(Arc::new(ThreeCheck));
+        let mut checked = state.clone();
+        checked.checks_given[0] = 1;
+        assert_ne!(state.get_hash(), checked.get_hash());
+
+        // Elsewhere the checks given are not part of the position
+        let mut state = State::new();
+        let start = state.get_hash();
+        state.checks_given[1] = 2;
+        assert_eq!(state.get_hash(), start);
+    }
+}
@@ -0,0 +1,455 @@
+//! An HTTP API for web front ends and bots. Games live in memory and are
+//! addressed by id:
+//!
+//! ```text
+//! POST /games               create a game, optionally {"variant": "...", "fen": "..."}
+//! GET  /games/<id>          the position as FEN, the legal moves and the status
+//! POST /games/<id>/moves    play {"move": "e2e4"}, a UCI move
+//! GET  /games/<id>/pgn      the game so far as PGN
+//! GET  /games/<id>/events   a WebSocket that gets every move of the game
+//! ```
+//!
+//! Bodies are JSON. Errors are answered with a status code and
+//! `{"error": {"code": "...", "message": "..."}}`, where rejected moves use the
+//! codes of `MoveError`.
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use serde_json::{json, Value};
+use tungstenite::handshake::derive_accept_key;
+use tungstenite::protocol::Role;
+use tungstenite::{Message, WebSocket};
+
+use crate::pgn::result_string;
+use crate::variant::{self, Standard};
+use crate::{Move, MoveError, Player, State};
+
+const MAX_BODY_SIZE: usize = 64 * 1024;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ApiError {
+    NotFound,
+    MethodNotAllowed,
+    InvalidRequest,
+    UnknownVariant,
+    InvalidPosition,
+    Move(MoveError),
+}
+
+impl ApiError {
+    pub fn code(&self) -> &str {
+        match self {
+            Self::NotFound => "not_found",
+            Self::MethodNotAllowed => "method_not_allowed",
+            Self::InvalidRequest => "invalid_request",
+            Self::UnknownVariant => "unknown_variant",
+            Self::InvalidPosition => "invalid_position",
+            Self::Move(error) => error.code(),
+        }
+    }
+
+    pub fn to_string(&self) -> &str {
+        match self {
+            Self::NotFound =>         "There is nothing at this path",
+            Self::MethodNotAllowed => "This path does not support the method",
+            Self::InvalidRequest =>   "The request could not be understood",
+            Self::UnknownVariant =>   "There is no such variant",
+            Self::InvalidPosition =>  "The start position is invalid",
+            Self::Move(error) => error.to_string(),
+        }
+    }
+
+    pub fn status(&self) -> u16 {
+        match self {
+            Self::NotFound => 404,
+            Self::MethodNotAllowed => 405,
+            Self::InvalidRequest | Self::UnknownVariant | Self::InvalidPosition => 400,
+            Self::Move(_) => 422,
+        }
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(header, _)| header.eq_ignore_ascii_case(name)).map(|(_, value)| value.as_str())
+    }
+
+    fn json(&self) -> Result<Value, ApiError> {
+        if self.body.iter().all(u8::is_ascii_whitespace) {
+            return Ok(json!({}));
+        }
+        serde_json::from_slice(&self.body).map_err(|_| ApiError::InvalidRequest)
+    }
+}
+
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Option<Request>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid request");
+
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Err(invalid()),
+    };
+
+    let mut headers = Vec::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let index = header.find(':').ok_or_else(invalid)?;
+        headers.push((header[..index].trim().to_string(), header[index + 1..].trim().to_string()));
+    }
+
+    let mut request = Request { method, path, headers, body: Vec::new() };
+    let length: usize = match request.header("Content-Length") {
+        Some(length) => length.parse().map_err(|_| invalid())?,
+        None => 0,
+    };
+    if length > MAX_BODY_SIZE {
+        return Err(invalid());
+    }
+    request.body.resize(length, 0);
+    reader.read_exact(&mut request.body)?;
+    Ok(Some(request))
+}
+
+struct Response {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: &Value) -> Response {
+        Response { status, content_type: "application/json", body: body.to_string() }
+    }
+
+    fn error(error: ApiError) -> Response {
+        Response::json(error.status(), &json!({ "error": { "code": error.code(), "message": error.to_string() } }))
+    }
+
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let reason = match self.status {
+            200 => "OK",
+            201 => "Created",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            422 => "Unprocessable Entity",
+            _ => "",
+        };
+        write!(writer, "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status, reason, self.content_type, self.body.len(), self.body)?;
+        writer.flush()
+    }
+}
+
+fn player_name(player: Player) -> &'static str {
+    match player {
+        Player::White => "white",
+        Player::Black => "black",
+    }
+}
+
+struct Game {
+    start_fen: String,
+    state: State,
+    // Senders to the threads of the WebSockets watching the game
+    subscribers: Vec<Sender<String>>,
+}
+
+impl Game {
+    fn to_json(&self, id: u64) -> Value {
+        let status = self.state.get_game_status();
+        json!({
+            "id": id,
+            "variant": self.state.get_variant().name(),
+            "fen": self.state.to_fen(),
+            "turn": player_name(self.state.get_current_player()),
+            "status": status.code(),
+            "result": result_string(&status),
+            "termination": self.state.get_termination().map(|termination| termination.to_string().to_string()),
+            "moves": self.state.get_history().iter().map(|entry| entry.mv.to_uci()).collect::<Vec<_>>(),
+            "legal_moves": self.state.get_legal_moves().iter().map(Move::to_uci).collect::<Vec<_>>(),
+        })
+    }
+}
+
+#[derive(Default)]
+struct Games {
+    next_id: u64,
+    games: BTreeMap<u64, Game>,
+}
+
+impl Games {
+    fn get(&self, id: &str) -> Result<(u64, &Game), ApiError> {
+        let id: u64 = id.parse().map_err(|_| ApiError::NotFound)?;
+        self.games.get(&id).map(|game| (id, game)).ok_or(ApiError::NotFound)
+    }
+
+    fn create(&mut self, request: &Request) -> Result<Response, ApiError> {
+        let body = request.json()?;
+        let variant = match body["variant"].as_str() {
+            Some(name) => variant::from_name(name).ok_or(ApiError::UnknownVariant)?,
+            None => Arc::new(Standard),
+        };
+        let state = match body["fen"].as_str() {
+            Some(fen) => State::from_fen_with_variant(fen, variant).map_err(|_| ApiError::InvalidPosition)?,
+            None => State::new_variant(variant),
+        };
+
+        self.next_id += 1;
+        let id = self.next_id;
+        let game = Game { start_fen: state.to_fen(), state, subscribers: Vec::new() };
+        let response = Response::json(201, &game.to_json(id));
+        self.games.insert(id, game);
+        Ok(response)
+    }
+
+    fn play_move(&mut self, id: &str, request: &Request) -> Result<Response, ApiError> {
+        let body = request.json()?;
+        let mv = body["move"].as_str().and_then(Move::from_uci).ok_or(ApiError::InvalidRequest)?;
+        let (id, _) = self.get(id)?;
+        let game = self.games.get_mut(&id).unwrap();
+
+        let san = game.state.move_to_san(mv);
+        let success = game.state.play_move(mv).map_err(ApiError::Move)?;
+        let event = json!({
+            "move": mv.to_uci(),
+            "san": san,
+            "result": success.code(),
+            "game": game.to_json(id),
+        });
+
+        let mut message = event.clone();
+        message["type"] = json!("move");
+        let message = message.to_string();
+        game.subscribers.retain(|subscriber| subscriber.send(message.clone()).is_ok());
+        Ok(Response::json(200, &event))
+    }
+
+    fn pgn(&self, id: &str) -> Result<Response, ApiError> {
+        let (_, game) = self.get(id)?;
+        let mut tags = vec![("Event", "Casual game")];
+        if game.start_fen != game.state.get_variant().start_fen() {
+            tags.push(("FEN", &game.start_fen));
+        }
+        Ok(Response { status: 200, content_type: "application/x-chess-pgn", body: game.state.to_pgn(&tags) })
+    }
+}
+
+pub struct HttpServer {
+    listener: TcpListener,
+    games: Arc<Mutex<Games>>,
+}
+
+impl HttpServer {
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<HttpServer> {
+        let listener = TcpListener::bind(address)?;
+        Ok(HttpServer { listener, games: Arc::new(Mutex::new(Games::default())) })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Answers requests until accepting a connection fails, one thread per connection.
+    pub fn run(self) -> io::Result<()> {
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            let games = Arc::clone(&self.games);
+            thread::spawn(move || serve_connection(stream, games));
+        }
+        Ok(())
+    }
+}
+
+fn serve_connection(mut stream: TcpStream, games: Arc<Mutex<Games>>) -> io::Result<()> {
+    let request = match read_request(&mut BufReader::new(stream.try_clone()?)) {
+        Ok(Some(request)) => request,
+        Ok(None) => return Ok(()),
+        Err(_) => return Response::error(ApiError::InvalidRequest).write(&mut stream),
+    };
+
+    let path = request.path.split('?').next().unwrap_or("");
+    let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+    let method = request.method.as_str();
+
+    let response = match segments.as_slice() {
+        ["games"] if method == "POST" => games.lock().unwrap().create(&request),
+        ["games", id] if method == "GET" => {
+            games.lock().unwrap().get(id).map(|(id, game)| Response::json(200, &game.to_json(id)))
+        }
+        ["games", id, "moves"] if method == "POST" => games.lock().unwrap().play_move(id, &request),
+        ["games", id, "pgn"] if method == "GET" => games.lock().unwrap().pgn(id),
+        ["games", id, "events"] if method == "GET" => return serve_events(stream, &request, id, games),
+        ["games"] | ["games", _] | ["games", _, "moves"] | ["games", _, "pgn"] | ["games", _, "events"] =>
+            Err(ApiError::MethodNotAllowed),
+        _ => Err(ApiError::NotFound),
+    };
+
+    match response {
+        Ok(response) => response.write(&mut stream),
+        Err(error) => Response::error(error).write(&mut stream),
+    }
+}
+
+// Upgrades the connection to a WebSocket, sends the game and then every move
+// until either side closes it.
+fn serve_events(mut stream: TcpStream, request: &Request, id: &str, games: Arc<Mutex<Games>>) -> io::Result<()> {
+    let key = match request.header("Sec-WebSocket-Key") {
+        Some(key) => key,
+        None => return Response::error(ApiError::InvalidRequest).write(&mut stream),
+    };
+
+    let (receiver, snapshot) = {
+        let mut games = games.lock().unwrap();
+        let id = match games.get(id) {
+            Ok((id, _)) => id,
+            Err(error) => return Response::error(error).write(&mut stream),
+        };
+        let game = games.games.get_mut(&id).unwrap();
+        let (sender, receiver) = mpsc::channel();
+        game.subscribers.push(sender);
+        (receiver, json!({ "type": "game", "game": game.to_json(id) }).to_string())
+    };
+
+    write!(stream, "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        derive_accept_key(key.as_bytes()))?;
+    let mut socket = WebSocket::from_raw_socket(stream, Role::Server, None);
+    let closed = |error: tungstenite::Error| io::Error::new(io::ErrorKind::ConnectionAborted, error.to_string());
+
+    socket.send(Message::Text(snapshot)).map_err(closed)?;
+    for message in receiver {
+        socket.send(Message::Text(message)).map_err(closed)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::time::Duration;
+
+    fn start_server() -> SocketAddr {
+        let server = HttpServer::bind("127.0.0.1:0").unwrap();
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        address
+    }
+
+    fn request(address: SocketAddr, method: &str, path: &str, body: &str) -> (u16, String) {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "{} {} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}", method, path, body.len(), body).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        let status = response.split_whitespace().nth(1).unwrap().parse().unwrap();
+        let body = &response[response.find("\r\n\r\n").unwrap() + 4..];
+        (status, body.to_string())
+    }
+
+    fn request_json(address: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let (status, body) = request(address, method, path, body);
+        (status, serde_json::from_str(&body).unwrap())
+    }
+
+    #[test]
+    fn game_test() {
+        let address = start_server();
+        let (status, game) = request_json(address, "POST", "/games", "");
+        assert_eq!(status, 201);
+        assert_eq!(game["fen"], "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        assert_eq!(game["legal_moves"].as_array().unwrap().len(), 20);
+        let path = format!("/games/{}", game["id"]);
+
+        for uci in ["f2f3", "e7e5", "g2g4"].iter() {
+            let (status, event) = request_json(address, "POST", &format!("{}/moves", path), &format!("{{\"move\":\"{}\"}}", uci));
+            assert_eq!(status, 200);
+            assert_eq!(event["result"], "ok");
+        }
+        let (_, event) = request_json(address, "POST", &format!("{}/moves", path), "{\"move\":\"d8h4\"}");
+        assert_eq!(event["san"], "Qh4#");
+        assert_eq!(event["result"], "game_won_by_black");
+
+        let (status, game) = request_json(address, "GET", &path, "");
+        assert_eq!(status, 200);
+        assert_eq!(game["status"], "game_won_by_black");
+        assert_eq!(game["termination"], "checkmate");
+        assert_eq!(game["legal_moves"], json!([]));
+
+        let (status, pgn) = request(address, "GET", &format!("{}/pgn", path), "");
+        assert_eq!(status, 200);
+        assert!(pgn.contains("[Result \"0-1\"]"));
+        assert!(pgn.ends_with("1. f3 e5 2. g4 Qh4# 0-1\n"));
+    }
+
+    #[test]
+    fn error_test() {
+        let address = start_server();
+        let (_, game) = request_json(address, "POST", "/games", "{\"variant\":\"antichess\"}");
+        assert_eq!(game["variant"], "Antichess");
+        let path = format!("/games/{}", game["id"]);
+
+        request_json(address, "POST", &format!("{}/moves", path), "{\"move\":\"e2e4\"}");
+        request_json(address, "POST", &format!("{}/moves", path), "{\"move\":\"d7d5\"}");
+        let (status, error) = request_json(address, "POST", &format!("{}/moves", path), "{\"move\":\"a2a3\"}");
+        assert_eq!(status, 422);
+        assert_eq!(error["error"]["code"], "must_capture");
+        assert_eq!(error["error"]["message"], MoveError::MustCapture.to_string());
+
+        let (status, error) = request_json(address, "POST", &format!("{}/moves", path), "{\"move\":\"e4\"}");
+        assert_eq!((status, error["error"]["code"].as_str()), (400, Some("invalid_request")));
+        let (status, error) = request_json(address, "POST", &format!("{}/moves", path), "not json");
+        assert_eq!((status, error["error"]["code"].as_str()), (400, Some("invalid_request")));
+        let (status, error) = request_json(address, "POST", "/games", "{\"fen\":\"8/8/8 w - - 0 1\"}");
+        assert_eq!((status, error["error"]["code"].as_str()), (400, Some("invalid_position")));
+        let (status, error) = request_json(address, "GET", "/games/99", "");
+        assert_eq!((status, error["error"]["code"].as_str()), (404, Some("not_found")));
+        let (status, error) = request_json(address, "DELETE", &path, "");
+        assert_eq!((status, error["error"]["code"].as_str()), (405, Some("method_not_allowed")));
+    }
+
+    #[test]
+    fn events_test() {
+        let address = start_server();
+        let (_, game) = request_json(address, "POST", "/games", "");
+        let id = game["id"].clone();
+
+        let stream = TcpStream::connect(address).unwrap();
+        stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let url = format!("ws://{}/games/{}/events", address, id);
+        let (mut socket, _) = tungstenite::client(url.as_str(), stream).unwrap();
+        let mut receive = || -> Value { serde_json::from_str(socket.read().unwrap().to_text().unwrap()).unwrap() };
+
+        let snapshot = receive();
+        assert_eq!(snapshot["type"], "game");
+        assert_eq!(snapshot["game"]["id"], id);
+
+        request_json(address, "POST", &format!("/games/{}/moves", id), "{\"move\":\"g1f3\"}");
+        let event = receive();
+        assert_eq!(event["type"], "move");
+        assert_eq!(event["san"], "Nf3");
+        assert_eq!(event["game"]["turn"], "black");
+    }
+}
@@ -1,6 +1,39 @@
-use std::{convert::TryInto};
-
-#[derive(Copy, Clone, Debug, PartialEq)]
+use std::{convert::TryInto, sync::Arc};
+
+use variant::{Standard, Variant};
+
+pub mod analysis;
+pub mod castling;
+pub mod chess960;
+pub mod clock;
+pub mod database;
+pub mod eco;
+pub mod epd;
+pub mod explorer;
+pub mod fen;
+mod hash;
+pub mod http;
+pub mod movegen;
+pub mod net;
+pub mod pgn;
+pub mod puzzle;
+#[cfg(feature = "serde")]
+pub mod record;
+pub mod retrograde;
+pub mod san;
+pub mod search;
+pub mod server;
+mod syzygy;
+pub mod tablebase;
+pub mod tournament;
+pub mod tree;
+pub mod uci;
+pub mod variant;
+pub mod xboard;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum PieceType {
     King = 0,
     Queen = 1,
@@ -21,9 +54,46 @@ impl PieceType {
             &Self::Pawn => "pawn",
         }
     }
+
+    pub fn value(&self) -> i32 {
+        match self {
+            Self::King => 0,
+            Self::Queen => 9,
+            Self::Rook => 5,
+            Self::Bishop => 3,
+            Self::Knight => 3,
+            Self::Pawn => 1,
+        }
+    }
+
+    /// The piece type for an uppercase letter as used by `to_letter`.
+    pub fn from_letter(letter: char) -> Option<PieceType> {
+        match letter {
+            'K' => Some(PieceType::King),
+            'Q' => Some(PieceType::Queen),
+            'R' => Some(PieceType::Rook),
+            'B' => Some(PieceType::Bishop),
+            'N' => Some(PieceType::Knight),
+            'P' => Some(PieceType::Pawn),
+            _ => None,
+        }
+    }
+
+    pub fn to_letter(&self) -> char {
+        match self {
+            Self::King => 'K',
+            Self::Queen => 'Q',
+            Self::Rook => 'R',
+            Self::Bishop => 'B',
+            Self::Knight => 'N',
+            Self::Pawn => 'P',
+        }
+    }
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum MoveError {
     GameDone,
     NoSourcePiece,
@@ -33,13 +103,95 @@ pub enum MoveError {
     MoveToSameColor,
     InvalidMove,
     ResultsInCheck,
+    CastlingThroughCheck,
+    InvalidPromotion,
+    MustCapture,
+    GivesCheck,
+    EmptyPocket,
+    InvalidDrop,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum MoveSuccess {
     Ok,
     GameWonByWhite,
     GameWonByBlack,
+    Draw,
+}
+
+/// Errors for actions other than moves, like resigning or offering a draw.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ActionError {
+    GameDone,
+    NotPlayersTurn,
+    NoDrawOffer,
+    DrawNotClaimable,
+}
+
+impl ActionError {
+    pub fn to_string(&self) -> &str {
+        match self {
+            Self::GameDone =>         "The game has finished",
+            Self::NotPlayersTurn =>   "Only the player to move can do that",
+            Self::NoDrawOffer =>      "There is no draw offer to answer",
+            Self::DrawNotClaimable => "A draw cannot be claimed in this position",
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        match self {
+            Self::GameDone => "game_done",
+            Self::NotPlayersTurn => "not_players_turn",
+            Self::NoDrawOffer => "no_draw_offer",
+            Self::DrawNotClaimable => "draw_not_claimable",
+        }
+    }
+}
+
+/// Why a game ended.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
+pub enum Termination {
+    Checkmate,
+    Stalemate,
+    Resignation,
+    Timeout,
+    TimeoutVsInsufficientMaterial,
+    Agreement,
+    FiftyMoveRule,
+    ThreefoldRepetition,
+    KingOfTheHill,
+    ThreeChecks,
+    KingExploded,
+    NoMovesLeft,
+    RaceWon,
+    RaceDrawn,
+    Adjudication,
+}
+
+impl Termination {
+    pub fn to_string(&self) -> &str {
+        match self {
+            Self::Checkmate => "checkmate",
+            Self::Stalemate => "stalemate",
+            Self::Resignation => "resignation",
+            Self::Timeout => "time forfeit",
+            Self::TimeoutVsInsufficientMaterial => "timeout vs insufficient material",
+            Self::Agreement => "agreement",
+            Self::FiftyMoveRule => "fifty-move rule",
+            Self::ThreefoldRepetition => "threefold repetition",
+            Self::KingOfTheHill => "king reached the centre",
+            Self::ThreeChecks => "third check",
+            Self::KingExploded => "king exploded",
+            Self::NoMovesLeft => "no pieces or moves left",
+            Self::RaceWon => "king reached the last rank",
+            Self::RaceDrawn => "both kings reached the last rank",
+            Self::Adjudication => "adjudication",
+        }
+    }
 }
 
 impl MoveError {
@@ -53,6 +205,32 @@ impl MoveError {
             &Self::MoveToSameColor =>       "You cannot move to your own pieces",
             &Self::InvalidMove =>           "Invalid move for selected piece",
             &Self::ResultsInCheck =>        "This move places you in check",
+            &Self::CastlingThroughCheck =>  "You cannot castle out of or through check",
+            &Self::InvalidPromotion =>      "You cannot promote to that piece",
+            &Self::MustCapture =>           "You must capture when you can",
+            &Self::GivesCheck =>            "You cannot give check in this variant",
+            &Self::EmptyPocket =>           "You have no such piece to drop",
+            &Self::InvalidDrop =>           "You cannot drop that piece there",
+        }
+    }
+
+    /// A stable name for the error, for clients that should not depend on the message.
+    pub fn code(&self) -> &str {
+        match self {
+            Self::GameDone => "game_done",
+            Self::NoSourcePiece => "no_source_piece",
+            Self::IncorrectSourceColor => "incorrect_source_color",
+            Self::MoveToSamePos => "move_to_same_pos",
+            Self::InvalidTargetPosition => "invalid_target_position",
+            Self::MoveToSameColor => "move_to_same_color",
+            Self::InvalidMove => "invalid_move",
+            Self::ResultsInCheck => "results_in_check",
+            Self::CastlingThroughCheck => "castling_through_check",
+            Self::InvalidPromotion => "invalid_promotion",
+            Self::MustCapture => "must_capture",
+            Self::GivesCheck => "gives_check",
+            Self::EmptyPocket => "empty_pocket",
+            Self::InvalidDrop => "invalid_drop",
         }
     }
 }
@@ -63,6 +241,16 @@ impl MoveSuccess {
             &Self::Ok => "Ok",
             &Self::GameWonByWhite => "White has won",
             &Self::GameWonByBlack => "Black has won",
+            &Self::Draw => "The game is drawn",
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        match self {
+            Self::Ok => "ok",
+            Self::GameWonByWhite => "game_won_by_white",
+            Self::GameWonByBlack => "game_won_by_black",
+            Self::Draw => "draw",
         }
     }
 
@@ -74,11 +262,15 @@ impl MoveSuccess {
     }
 }
 
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "snake_case"))]
 pub enum GameStatus {
     WhiteTurn,
     BlackTurn,
     GameWonByWhite,
     GameWonByBlack,
+    Draw,
 }
 
 impl GameStatus {
@@ -88,17 +280,42 @@ impl GameStatus {
             &Self::BlackTurn => "Black's turn",
             &Self::GameWonByWhite => "Game won by white",
             &Self::GameWonByBlack => "Game won by black",
+            &Self::Draw => "Game drawn",
+        }
+    }
+
+    pub fn code(&self) -> &str {
+        match self {
+            Self::WhiteTurn => "white_turn",
+            Self::BlackTurn => "black_turn",
+            Self::GameWonByWhite => "game_won_by_white",
+            Self::GameWonByBlack => "game_won_by_black",
+            Self::Draw => "draw",
         }
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Debug)]
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(rename_all = "lowercase"))]
 pub enum Player {
     White,
     Black,
 }
 
-#[derive(Copy, Clone, PartialEq, Debug)]
+impl Player {
+    pub fn opponent(&self) -> Player {
+        match self {
+            Player::White => Player::Black,
+            Player::Black => Player::White,
+        }
+    }
+}
+
+/// Serialized as its square name, like `"e4"`.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(into = "String", try_from = "String"))]
 pub struct Pos {
     pub x: i32,
     pub y: i32,
@@ -112,9 +329,103 @@ impl Pos {
     pub fn index(&self) -> usize {
         (self.y * 8 + self.x).try_into().unwrap()
     }
+
+    /// Square name in algebraic notation, e.g. `Pos::new(4, 3)` is "e4".
+    pub fn to_notation(&self) -> String {
+        format!("{}{}", (b'a' + self.x as u8) as char, self.y + 1)
+    }
+
+    pub fn from_notation(str: &str) -> Option<Pos> {
+        let bytes = str.as_bytes();
+        if bytes.len() != 2 {
+            return None;
+        }
+        let x = bytes[0] as i32 - b'a' as i32;
+        let y = bytes[1] as i32 - b'1' as i32;
+        if (0..8).contains(&x) && (0..8).contains(&y) {
+            Some(Pos::new(x, y))
+        } else {
+            None
+        }
+    }
+}
+
+/// A move of a piece on the board, or a drop of a piece from the pocket in
+/// variants like Crazyhouse, in which case `from` is the same as `to`.
+///
+/// Serialized in UCI notation.
+#[derive(Copy, Clone, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(into = "String", try_from = "String"))]
+pub struct Move {
+    pub from: Pos,
+    pub to: Pos,
+    pub promotion: Option<PieceType>,
+    pub drop: Option<PieceType>,
+}
+
+impl Move {
+    pub fn new(from: Pos, to: Pos) -> Move {
+        Move { from, to, promotion: None, drop: None }
+    }
+
+    pub fn with_promotion(from: Pos, to: Pos, promotion: PieceType) -> Move {
+        Move { from, to, promotion: Some(promotion), drop: None }
+    }
+
+    pub fn new_drop(piece_type: PieceType, to: Pos) -> Move {
+        Move { from: to, to, promotion: None, drop: Some(piece_type) }
+    }
+
+    /// The move in UCI notation, e.g. "e2e4", "e7e8q" or "N@f3". Castling is
+    /// written as the king's move, which in Chess960 is onto the rook.
+    pub fn to_uci(&self) -> String {
+        match (self.drop, self.promotion) {
+            (Some(piece_type), _) => format!("{}@{}", piece_type.to_letter(), self.to.to_notation()),
+            (None, Some(promotion)) => format!("{}{}{}", self.from.to_notation(), self.to.to_notation(),
+                                               promotion.to_letter().to_ascii_lowercase()),
+            (None, None) => format!("{}{}", self.from.to_notation(), self.to.to_notation()),
+        }
+    }
+
+    pub fn from_uci(str: &str) -> Option<Move> {
+        if !str.is_ascii() {
+            return None;
+        }
+        if let Some(square) = str.strip_prefix(|c: char| c.is_ascii_uppercase()).and_then(|rest| rest.strip_prefix('@')) {
+            let piece_type = PieceType::from_letter(str.chars().next()?)?;
+            return Some(Move::new_drop(piece_type, Pos::from_notation(square)?));
+        }
+
+        let from = Pos::from_notation(str.get(0..2)?)?;
+        let to = Pos::from_notation(str.get(2..4)?)?;
+        match str.len() {
+            4 => Some(Move::new(from, to)),
+            5 => {
+                let promotion = PieceType::from_letter(str.chars().nth(4)?.to_ascii_uppercase())?;
+                Some(Move::with_promotion(from, to, promotion))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// How a game ended: the winner, if any, and the reason.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Outcome {
+    pub winner: Option<Player>,
+    pub termination: Termination,
+}
+
+/// A move that has been played, together with its SAN string.
+#[derive(Clone, Debug)]
+pub struct HistoryEntry {
+    pub mv: Move,
+    pub san: String,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Piece {
     pub piece_type: PieceType,
     pub player: Player,
@@ -143,11 +454,24 @@ pub struct State {
     black_eliminated: Vec<PieceType>,
     game_running: bool,
     game_won_by: Option<Player>,
+    termination: Option<Termination>,
+    draw_offered_by: Option<Player>,
+    halfmove_clock: u32,
+    position_hashes: Vec<u64>,
+    history: Vec<HistoryEntry>,
+    castling_rights: [[Option<i32>; 2]; 2],
+    chess960: bool,
+    chess960_id: Option<u16>,
+    en_passant: Option<Pos>,
+    checks_given: [u32; 2],
+    pockets: [[u32; 6]; 2],
+    promoted: u64,
+    variant: Arc<dyn Variant>,
 }
 
 impl State {
     pub fn new() -> State {
-        State {
+        let mut state = State {
             board: State::init_board(),
             current_player: Player::White,
             total_steps: 0,
@@ -155,9 +479,82 @@ impl State {
             black_eliminated: Vec::new(),
             game_running: true,
             game_won_by: None,
+            termination: None,
+            draw_offered_by: None,
+            halfmove_clock: 0,
+            position_hashes: Vec::new(),
+            history: Vec::new(),
+            castling_rights: [[Some(7), Some(0)]; 2],
+            chess960: false,
+            chess960_id: None,
+            en_passant: None,
+            checks_given: [0; 2],
+            pockets: [[0; 6]; 2],
+            promoted: 0,
+            variant: Arc::new(Standard),
+        };
+        state.position_hashes.push(state.get_hash());
+        state
+    }
+
+    /// A new game of `variant` from its start position.
+    pub fn new_variant(variant: Arc<dyn Variant>) -> State {
+        let fen = variant.start_fen().to_string();
+        State::from_fen_with_variant(&fen, variant).expect("variant start position is invalid")
+    }
+
+    pub fn get_variant(&self) -> &dyn Variant {
+        self.variant.as_ref()
+    }
+
+    /// Number of checks `player` has given so far, which decides Three-check games.
+    pub fn get_checks_given(&self, player: Player) -> u32 {
+        self.checks_given[castling::player_index(player)]
+    }
+
+    /// Number of pieces of `piece_type` that `player` has in hand to drop.
+    pub fn get_pocket_count(&self, player: Player, piece_type: PieceType) -> u32 {
+        self.pockets[castling::player_index(player)][piece_type as usize]
+    }
+
+    /// Puts a piece in the pocket of `player`, e.g. one passed on by the partner in Bughouse.
+    pub fn add_to_pocket(&mut self, player: Player, piece_type: PieceType) {
+        self.pockets[castling::player_index(player)][piece_type as usize] += 1;
+    }
+
+    /// Whether the piece on `pos` is a promoted pawn, which goes back to the
+    /// pocket as a pawn when captured.
+    pub fn is_promoted(&self, pos: Pos) -> bool {
+        self.promoted & (1 << pos.index()) != 0
+    }
+
+    fn set_promoted(&mut self, pos: Pos, promoted: bool) {
+        if promoted {
+            self.promoted |= 1 << pos.index();
+        } else {
+            self.promoted &= !(1 << pos.index());
         }
     }
 
+    /// The piece taken by `mv` as it goes into a pocket, where promoted pieces
+    /// count as pawns.
+    pub fn get_pocket_capture(&self, mv: Move) -> Option<PieceType> {
+        if self.is_en_passant(mv) {
+            Some(PieceType::Pawn)
+        } else if !self.is_capture(mv) {
+            None
+        } else if self.is_promoted(mv.to) {
+            Some(PieceType::Pawn)
+        } else {
+            self.get(mv.to).map(|piece| piece.piece_type)
+        }
+    }
+
+    /// The square a pawn skipped with a double step on the last move, if any.
+    pub fn get_en_passant(&self) -> Option<Pos> {
+        self.en_passant
+    }
+
     pub fn get_current_player(&self) -> Player {
         self.current_player
     }
@@ -166,6 +563,150 @@ impl State {
         self.game_running
     }
 
+    pub fn is_chess960(&self) -> bool {
+        self.chess960
+    }
+
+    /// Scharnagl number of the start position, for games from `new_chess960`.
+    pub fn get_chess960_id(&self) -> Option<u16> {
+        self.chess960_id
+    }
+
+    pub fn get_termination(&self) -> Option<Termination> {
+        self.termination
+    }
+
+    pub fn get_draw_offered_by(&self) -> Option<Player> {
+        self.draw_offered_by
+    }
+
+    /// Number of half moves since the last capture or pawn move.
+    pub fn get_halfmove_clock(&self) -> u32 {
+        self.halfmove_clock
+    }
+
+    /// How many times the current position has occurred, counting this time.
+    pub fn get_repetition_count(&self) -> usize {
+        let hash = self.get_hash();
+        self.position_hashes.iter().filter(|&&h| h == hash).count()
+    }
+
+    fn end_game(&mut self, winner: Option<Player>, termination: Termination) {
+        self.game_running = false;
+        self.game_won_by = winner;
+        self.termination = Some(termination);
+        self.draw_offered_by = None;
+    }
+
+    fn check_players_turn(&self, player: Player) -> Result<(), ActionError> {
+        if !self.game_running {
+            Err(ActionError::GameDone)
+        } else if player != self.current_player {
+            Err(ActionError::NotPlayersTurn)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn resign(&mut self, player: Player) -> Result<GameStatus, ActionError> {
+        if !self.game_running {
+            return Err(ActionError::GameDone);
+        }
+        self.end_game(Some(State::get_other_player(player)), Termination::Resignation);
+        Ok(self.get_game_status())
+    }
+
+    /// Offers a draw, which stands until the opponent accepts, declines or makes a move.
+    pub fn offer_draw(&mut self, player: Player) -> Result<(), ActionError> {
+        if !self.game_running {
+            return Err(ActionError::GameDone);
+        }
+        self.draw_offered_by = Some(player);
+        Ok(())
+    }
+
+    pub fn accept_draw(&mut self, player: Player) -> Result<GameStatus, ActionError> {
+        if !self.game_running {
+            return Err(ActionError::GameDone);
+        }
+        if self.draw_offered_by != Some(State::get_other_player(player)) {
+            return Err(ActionError::NoDrawOffer);
+        }
+        self.end_game(None, Termination::Agreement);
+        Ok(self.get_game_status())
+    }
+
+    pub fn decline_draw(&mut self, player: Player) -> Result<(), ActionError> {
+        if !self.game_running {
+            return Err(ActionError::GameDone);
+        }
+        if self.draw_offered_by != Some(State::get_other_player(player)) {
+            return Err(ActionError::NoDrawOffer);
+        }
+        self.draw_offered_by = None;
+        Ok(())
+    }
+
+    /// The draw the player to move could claim, if any. Fifty moves by each
+    /// side without a capture or pawn move, or a position occurring for the
+    /// third time, both give the right to claim.
+    pub fn get_claimable_draw(&self) -> Option<Termination> {
+        if !self.game_running {
+            None
+        } else if self.get_repetition_count() >= 3 {
+            Some(Termination::ThreefoldRepetition)
+        } else if self.halfmove_clock >= 100 {
+            Some(Termination::FiftyMoveRule)
+        } else {
+            None
+        }
+    }
+
+    pub fn claim_draw(&mut self, player: Player) -> Result<GameStatus, ActionError> {
+        self.check_players_turn(player)?;
+        match self.get_claimable_draw() {
+            Some(termination) => {
+                self.end_game(None, termination);
+                Ok(self.get_game_status())
+            }
+            None => Err(ActionError::DrawNotClaimable),
+        }
+    }
+
+    pub fn get_history(&self) -> &[HistoryEntry] {
+        &self.history
+    }
+
+    /// The number of half moves played, counting those before the start position of a FEN.
+    pub fn get_ply(&self) -> usize {
+        self.total_steps as usize
+    }
+
+    pub fn get_white_eliminated(&self) -> &[PieceType] {
+        &self.white_eliminated
+    }
+
+    pub fn get_black_eliminated(&self) -> &[PieceType] {
+        &self.black_eliminated
+    }
+
+    /// Material difference from white's point of view, counted from the
+    /// pieces on the board and in the pockets, so promotions count too.
+    /// Positive means white is ahead.
+    pub fn get_material_balance(&self) -> i32 {
+        let material = |player: Player| -> i32 {
+            let on_board: i32 = self.get_all_pieces_for_player(player).into_iter()
+                .map(|pos| self.get(pos).unwrap().piece_type.value())
+                .sum();
+            let pocket = &self.pockets[castling::player_index(player)];
+            let in_pocket: i32 = [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::Pawn].iter()
+                .map(|&piece_type| pocket[piece_type as usize] as i32 * piece_type.value())
+                .sum();
+            on_board + in_pocket
+        };
+        material(Player::White) - material(Player::Black)
+    }
+
     pub fn get_game_status(&self) -> GameStatus {
         if self.game_running {
             match self.current_player {
@@ -173,11 +714,51 @@ impl State {
                 Player::Black => GameStatus::BlackTurn,
             }
         } else {
-            match self.game_won_by.unwrap() {
-                Player::White => GameStatus::GameWonByWhite,
-                Player::Black => GameStatus::GameWonByBlack,
+            match self.game_won_by {
+                Some(Player::White) => GameStatus::GameWonByWhite,
+                Some(Player::Black) => GameStatus::GameWonByBlack,
+                None => GameStatus::Draw,
+            }
+        }
+    }
+
+    /// Whether `player` still has the pieces to ever checkmate. A lone king, or a
+    /// king with a single bishop or knight, cannot. Pieces in the pocket count
+    /// as well.
+    pub fn has_mating_material(&self, player: Player) -> bool {
+        let pocket = &self.pockets[castling::player_index(player)];
+        let pieces: Vec<PieceType> = self.get_all_pieces_for_player(player)
+            .into_iter()
+            .map(|pos| self.get(pos).unwrap().piece_type)
+            .chain([PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::Pawn].iter()
+                .flat_map(|&piece_type| std::iter::repeat_n(piece_type, pocket[piece_type as usize] as usize)))
+            .filter(|&piece_type| piece_type != PieceType::King)
+            .collect();
+
+        !matches!(pieces.as_slice(), [] | [PieceType::Bishop] | [PieceType::Knight])
+    }
+
+    /// Ends the game with a result decided outside the rules, e.g. by a
+    /// tournament director or a match runner. `None` is a draw.
+    pub fn adjudicate(&mut self, winner: Option<Player>) -> GameStatus {
+        if self.game_running {
+            self.end_game(winner, Termination::Adjudication);
+        }
+        self.get_game_status()
+    }
+
+    /// Ends the game because `player` ran out of time. The opponent wins unless
+    /// they cannot mate, in which case the game is drawn.
+    pub fn lose_on_time(&mut self, player: Player) -> GameStatus {
+        if self.game_running {
+            let other = State::get_other_player(player);
+            if self.has_mating_material(other) {
+                self.end_game(Some(other), Termination::Timeout);
+            } else {
+                self.end_game(None, Termination::TimeoutVsInsufficientMaterial);
             }
         }
+        self.get_game_status()
     }
 
     fn init_board() -> [Option<Piece>; 64] {
@@ -280,7 +861,7 @@ impl State {
                 } else if (from.x - to.x).abs() == 1 && to.y == from.y + 1 {
                     match self.get(to) {
                         Some(to_piece) => to_piece.player == Player::Black,
-                        None => false 
+                        None => self.en_passant == Some(to)
                     }
                 } else {
                     false
@@ -293,7 +874,7 @@ impl State {
                 } else if (from.x - to.x).abs() == 1 && to.y == from.y - 1 {
                     match self.get(to) {
                         Some(to_piece) => to_piece.player == Player::White,
-                        None => false 
+                        None => self.en_passant == Some(to)
                     }
                 } else {
                     false
@@ -384,19 +965,28 @@ impl State {
     }
 
     fn eliminate_target(&mut self, to: Pos) {
-        if let Some(target_piece) = self.get(to) {
-            match self.current_player {
-                Player::White => self.black_eliminated.push(target_piece.piece_type),
-                Player::Black => self.white_eliminated.push(target_piece.piece_type),
-            }
-            self.set(to, None);
+        self.remove_piece(to);
+    }
+
+    /// Takes the piece on `pos` off the board, counting it as captured.
+    pub fn remove_piece(&mut self, pos: Pos) -> Option<Piece> {
+        let piece = self.get(pos)?;
+        match piece.player {
+            Player::White => self.white_eliminated.push(piece.piece_type),
+            Player::Black => self.black_eliminated.push(piece.piece_type),
         }
+        self.set(pos, None);
+        self.set_promoted(pos, false);
+        Some(piece)
     }
 
     fn perform_move(&mut self, from: Pos, to: Pos) {
         let piece = self.get(from).unwrap();
+        let promoted = self.is_promoted(from);
         self.set(to, Some(piece));
         self.set(from, None);
+        self.set_promoted(from, false);
+        self.set_promoted(to, promoted);
     }
 
     fn swap_current_player(&mut self) {
@@ -407,10 +997,7 @@ impl State {
     }
 
     fn get_other_player(player: Player) -> Player {
-        match player {
-            Player::White => Player::Black,
-            Player::Black => Player::White,
-        }
+        player.opponent()
     }
 
     fn get_all_pieces_for_player(&self, player: Player) -> Vec<Pos> {
@@ -428,7 +1015,7 @@ impl State {
         list
     }
 
-    fn get_king_pos(&self, player: Player) -> Option<Pos> {
+    pub fn get_king_pos(&self, player: Player) -> Option<Pos> {
         for x in 0..8 {
             for y in 0..8 {
                 let pos = Pos::new(x, y);
@@ -442,173 +1029,272 @@ impl State {
         None
     }
 
-    fn get_threatening_pieces(&self, player: Player) -> Vec<Pos> {
-        let mut list: Vec<Pos> = Vec::new();
-        let king_pos = self.get_king_pos(player).unwrap();
-        for pos in self.get_all_pieces_for_player(State::get_other_player(player)) {
-            if let Ok(()) = self.check_valid_move(pos, king_pos) {
-                list.push(pos);
-            }
-        }
-        list
+    /// Whether any piece of the opponent could capture the king of `player`.
+    /// Without a king there is nothing to attack.
+    pub fn is_king_attacked(&self, player: Player) -> bool {
+        let king_pos = match self.get_king_pos(player) {
+            Some(pos) => pos,
+            None => return false,
+        };
+        self.get_all_pieces_for_player(State::get_other_player(player))
+            .into_iter()
+            .any(|pos| self.check_valid_move(pos, king_pos).is_ok())
     }
 
-    fn check_if_move_results_in_check(&self, from: Pos, to: Pos) -> Result<(), MoveError> {
-        let mut state_copy = self.clone();
-        state_copy.perform_move(from, to);
-        if state_copy.is_player_check(self.current_player) {
-            Err(MoveError::ResultsInCheck)
-        } else {
-            Ok(())
+    fn is_player_check(&self, player: Player) -> bool {
+        self.variant.is_check(self, player)
+    }
+
+    /// Whether `player` is in check by the rules of the variant.
+    pub fn is_in_check(&self, player: Player) -> bool {
+        self.is_player_check(player)
+    }
+
+    /// Whether `mv` takes a piece, counting en passant.
+    pub fn is_capture(&self, mv: Move) -> bool {
+        if mv.drop.is_some() || self.get_castling_side(mv.from, mv.to).is_some() {
+            return false;
         }
+        self.get(mv.to).is_some() || self.is_en_passant(mv)
     }
 
-    fn is_player_check(&self, player: Player) -> bool {
-        let list = self.get_threatening_pieces(player);
-        list.len() != 0
+    fn is_en_passant(&self, mv: Move) -> bool {
+        let is_pawn = self.get(mv.from).map(|piece| piece.piece_type) == Some(PieceType::Pawn);
+        is_pawn && mv.from.x != mv.to.x && self.en_passant == Some(mv.to) && self.get(mv.to).is_none()
     }
 
-    fn is_player_check_mate(&self, player: Player) -> bool {
-        let threatening_pieces = self.get_threatening_pieces(player);
+    // A copy of the position only, without the history, which is what the
+    // legality checks need
+    fn clone_position(&self) -> State {
+        State {
+            white_eliminated: Vec::new(),
+            black_eliminated: Vec::new(),
+            position_hashes: Vec::new(),
+            history: Vec::new(),
+            variant: self.variant.clone(),
+            ..*self
+        }
+    }
 
-        //println!("begin------");
-        //println!("{:?}", threatening_pieces);
+    /// The position after `mv`, which is not validated. Only the position is
+    /// copied, the history of the game is left out.
+    pub fn preview_move(&self, mv: Move) -> State {
+        let mut state_copy = self.clone_position();
+        state_copy.make_move(mv);
+        state_copy
+    }
 
-        // inga spelare chackar spelare A
-        if threatening_pieces.len() == 0 {
-            false
+    // Changes the board for a move that is assumed to be legal and returns
+    // whether something was captured
+    fn perform(&mut self, mv: Move) -> bool {
+        if let Some(piece_type) = mv.drop {
+            self.pockets[castling::player_index(self.current_player)][piece_type as usize] -= 1;
+            self.set(mv.to, Some(Piece::new(piece_type, self.current_player)));
+            self.en_passant = None;
+            return false;
         }
-        else if self.can_avoid_by_moving_king(player) {
-            //println!("can avoid by moving king");
-            false
+
+        if let Some(side) = self.get_castling_side(mv.from, mv.to) {
+            self.perform_castling(mv.from, side);
+            self.en_passant = None;
+            return false;
         }
-        // Det finns 2 spelare som chackar, med andra ord är det kört:
-        else if threatening_pieces.len() > 1 {
-            true
+
+        let piece = self.get(mv.from).unwrap();
+        if self.variant.pockets_captures() {
+            if let Some(piece_type) = self.get_pocket_capture(mv) {
+                self.add_to_pocket(piece.player, piece_type);
+            }
         }
-        else if self.can_avoid_by_attack(player, threatening_pieces[0]) {
-            //println!("can avoid by attack");
-            false
+
+        let mut capture = self.get(mv.to).is_some();
+        if self.is_en_passant(mv) {
+            self.eliminate_target(Pos::new(mv.to.x, mv.from.y));
+            capture = true;
         }
-        else if self.can_avoid_by_block(player, threatening_pieces[0]) {
-            //println!("can avoid by block");
-            false
+
+        self.en_passant = if piece.piece_type == PieceType::Pawn && (mv.to.y - mv.from.y).abs() == 2 {
+            Some(Pos::new(mv.from.x, (mv.from.y + mv.to.y) / 2))
+        } else {
+            None
+        };
+
+        self.update_castling_rights(mv.from, mv.to);
+        self.eliminate_target(mv.to);
+        self.perform_move(mv.from, mv.to);
+        if let Some(promotion) = mv.promotion {
+            self.set(mv.to, Some(Piece::new(promotion, piece.player)));
+            self.set_promoted(mv.to, true);
         }
-        else {
-            true
+
+        if capture {
+            let variant = self.variant.clone();
+            variant.after_capture(self, mv.to);
+            self.remove_lost_castling_rights();
         }
+        capture
     }
 
-    fn get_positions_around(pos: Pos) -> Vec<Pos> {
-        let mut list: Vec<Pos> = Vec::new();
-        for x in (pos.x-1)..(pos.x+1) {
-            for y in (pos.y-1)..(pos.y+1) {
-                if x >= 0 && x < 8 && y >= 0 && y < 8 && (x != pos.x || y != pos.y) {
-                    list.push(Pos::new(x, y));
-                }
-            }    
+    // Plays a legal move without recording it in the history or deciding the game
+    fn make_move(&mut self, mv: Move) {
+        let is_castling = self.get_castling_side(mv.from, mv.to).is_some();
+        let is_pawn_move = mv.drop.is_none() && self.get(mv.from).unwrap().piece_type == PieceType::Pawn;
+        let capture = self.perform(mv);
+        if !is_castling && (is_pawn_move || capture) {
+            self.halfmove_clock = 0;
+        } else {
+            self.halfmove_clock += 1;
         }
-        list
-    }
 
-    fn pos_contains_player(&self, pos: Pos, player: Player) -> bool {
-        if let Some(piece) = self.get(pos) {
-            if piece.player == player {
-                return true;
-            }
+        let mover = self.current_player;
+        self.swap_current_player();
+        self.total_steps += 1;
+        if self.is_player_check(self.current_player) {
+            self.checks_given[castling::player_index(mover)] += 1;
         }
-        false
+        self.position_hashes.push(self.get_hash());
     }
 
-    fn can_avoid_by_moving_king(&self, player: Player) -> bool {
-        let king_position = self.get_king_pos(player).unwrap();
-        for pos in State::get_positions_around(king_position) {
-            if self.pos_contains_player(pos, player) {
-                continue;
-            }
-            let mut state_copy = self.clone();
-            state_copy.perform_move(king_position, pos);
-            if !state_copy.is_player_check(player) {
-                return true;
-            }
+    fn get_outcome(&self) -> Option<Outcome> {
+        if let Some(outcome) = self.variant.get_outcome(self) {
+            Some(outcome)
+        } else if self.get_legal_moves().is_empty() {
+            Some(self.variant.get_no_moves_outcome(self))
+        } else {
+            None
         }
-        false
     }
 
-    fn can_avoid_by_attack(&self, player: Player, threatening_player: Pos) -> bool {
-        for pos in self.get_all_pieces_for_player(player) {
-            if let Ok(()) = self.check_valid_move(pos, threatening_player) {
-                let mut state_copy = self.clone();
-                state_copy.perform_move(pos, threatening_player);
-                if !state_copy.is_player_check(player) {
-                    return true;
+    fn handle_post_move(&mut self) -> Result<MoveSuccess, MoveError> {
+        match self.get_outcome() {
+            Some(outcome) => {
+                self.end_game(outcome.winner, outcome.termination);
+                match outcome.winner {
+                    Some(player) => Ok(MoveSuccess::get_game_won_by_player(player)),
+                    None => Ok(MoveSuccess::Draw),
                 }
             }
+            None => Ok(MoveSuccess::Ok),
         }
-        false
     }
 
-    fn can_avoid_by_block(&self, player: Player, threatening_player: Pos) -> bool {
-        let threatening_piece = self.get(threatening_player).unwrap();
-        if threatening_piece.piece_type != PieceType::Queen  &&
-           threatening_piece.piece_type != PieceType::Rook   &&
-           threatening_piece.piece_type != PieceType::Bishop
-        {
-            return false;
+    fn check_promotion(&self, mv: Move) -> Result<(), MoveError> {
+        let piece = self.get(mv.from).unwrap();
+        let last_rank = castling::back_rank(piece.player.opponent());
+        if piece.piece_type == PieceType::Pawn && mv.to.y == last_rank {
+            match mv.promotion {
+                Some(promotion) if self.variant.promotion_pieces().contains(&promotion) => Ok(()),
+                _ => Err(MoveError::InvalidPromotion),
+            }
+        } else if mv.promotion.is_some() {
+            Err(MoveError::InvalidPromotion)
+        } else {
+            Ok(())
         }
+    }
 
-        let king_pos = self.get_king_pos(player).unwrap();
-
-        //println!("{:?}", self.get_all_pieces_for_player(player));
-
-        for between_pos in State::get_all_pos_between(threatening_player, king_pos) {
-            for piece_pos in self.get_all_pieces_for_player(player) {
-                if let Ok(()) = self.check_valid_move(piece_pos, between_pos) {
-
-                    //println!("this happens");
-                    //println!("{:?}, {:?}", piece_pos, between_pos);
-
-                    let mut state_copy = self.clone();
-                    state_copy.perform_move(piece_pos, between_pos);
-
-                    if !state_copy.is_player_check(player) {
-                        return true;
-                    }
-                }
-            }
+    // Legality of a move the piece can make, apart from the variant's
+    // restrictions on the whole set of moves
+    fn check_legal(&self, mv: Move) -> Result<(), MoveError> {
+        match self.get_castling_side(mv.from, mv.to) {
+            Some(side) if mv.drop.is_none() => self.check_castling(mv.from, side),
+            _ => self.variant.check_legal(self, mv),
         }
-        false
     }
 
-    fn handle_post_move(&mut self) -> Result<MoveSuccess, MoveError> {
-        if self.is_player_check_mate(self.current_player) {
-            //println!("is check mate");
-            self.game_running = false;
-            self.game_won_by = Some(State::get_other_player(self.current_player));
-            Ok(MoveSuccess::get_game_won_by_player(State::get_other_player(self.current_player)))
-        } else {
-            //println!("is not check mate");
-            Ok(MoveSuccess::Ok)
+    fn check_drop(&self, piece_type: PieceType, to: Pos) -> Result<(), MoveError> {
+        State::check_valid_bounds(to)?;
+        if !self.variant.has_drops() || self.get_pocket_count(self.current_player, piece_type) == 0 {
+            return Err(MoveError::EmptyPocket);
         }
+        // Pawns may not be dropped on the first or last rank
+        if self.get(to).is_some() || (piece_type == PieceType::Pawn && (to.y == 0 || to.y == 7)) {
+            return Err(MoveError::InvalidDrop);
+        }
+        Ok(())
     }
 
-    pub fn move_piece(&mut self, from: Pos, to: Pos) -> Result<MoveSuccess, MoveError> {
+    fn check_move(&self, mv: Move) -> Result<(), MoveError> {
+        let Move { from, to, .. } = mv;
         self.check_game_running()?;
+        if let Some(piece_type) = mv.drop {
+            self.check_drop(piece_type, to)?;
+            self.check_legal(mv)?;
+            return self.variant.check_restrictions(self, mv, &self.get_unrestricted_moves());
+        }
         State::check_valid_bounds(from)?;
         State::check_valid_bounds(to)?;
         State::check_not_same_position(from, to)?; // Check that we don't move to the same position
         self.check_piece_at_source(from)?;     // Check that we move something
         self.check_correct_color_at_source(from)?;
-        self.check_not_move_to_same_color(from, to)?;
-        self.check_valid_move(from, to)?;
-        self.check_if_move_results_in_check(from, to)?;
+        if self.get_castling_side(from, to).is_none() {
+            self.check_not_move_to_same_color(from, to)?;
+            self.check_valid_move(from, to)?;
+            self.check_promotion(mv)?;
+        }
+        self.check_legal(mv)?;
+        self.variant.check_restrictions(self, mv, &self.get_unrestricted_moves())
+    }
 
-        self.eliminate_target(to);
-        self.perform_move(from, to);
-        self.swap_current_player();
-        self.total_steps += 1;
+    // A pawn moved to the last rank without a choice becomes a queen
+    fn default_move(&self, from: Pos, to: Pos) -> Move {
+        match self.get(from) {
+            Some(piece) if piece.piece_type == PieceType::Pawn && State::check_valid_bounds(to).is_ok() &&
+                           to.y == castling::back_rank(piece.player.opponent()) =>
+                Move::with_promotion(from, to, PieceType::Queen),
+            _ => Move::new(from, to),
+        }
+    }
+
+    pub fn is_legal_move(&self, from: Pos, to: Pos) -> bool {
+        self.check_move(self.default_move(from, to)).is_ok()
+    }
+
+    fn get_unrestricted_moves(&self) -> Vec<Move> {
+        self.get_pseudo_legal_moves()
+            .into_iter()
+            .filter(|&mv| self.check_legal(mv).is_ok())
+            .collect()
+    }
+
+    pub fn get_legal_moves(&self) -> Vec<Move> {
+        if !self.game_running {
+            return Vec::new();
+        }
+        let moves = self.get_unrestricted_moves();
+        moves.iter()
+            .copied()
+            .filter(|&mv| self.variant.check_restrictions(self, mv, &moves).is_ok())
+            .collect()
+    }
+
+    pub fn move_piece(&mut self, from: Pos, to: Pos) -> Result<MoveSuccess, MoveError> {
+        self.play_move(self.default_move(from, to))
+    }
+
+    /// Plays `mv`, which unlike `move_piece` can choose the promotion piece.
+    pub fn play_move(&mut self, mv: Move) -> Result<MoveSuccess, MoveError> {
+        self.check_move(mv)?;
+
+        let mut san = self.get_san_base(mv);
+
+        // Moving instead of answering declines the opponent's draw offer
+        if self.draw_offered_by == Some(State::get_other_player(self.current_player)) {
+            self.draw_offered_by = None;
+        }
+
+        self.make_move(mv);
+
+        let res = self.handle_post_move();
+
+        if self.termination == Some(Termination::Checkmate) {
+            san.push('#');
+        } else if self.is_player_check(self.current_player) {
+            san.push('+');
+        }
+        self.history.push(HistoryEntry { mv, san });
 
-        self.handle_post_move()
+        res
     }
 }
 
@@ -828,6 +1514,159 @@ mod tests {
         assert_eq!(state.white_eliminated[0], PieceType::Pawn);
     }
 
+    #[test]
+    fn history_test() {
+        let mut state = State::new();
+        assert!(state.move_piece(Pos::new(4, 1), Pos::new(4, 3)).is_ok());
+        assert!(state.move_piece(Pos::new(4, 4), Pos::new(4, 3)).is_err());
+        assert!(state.move_piece(Pos::new(3, 6), Pos::new(3, 4)).is_ok());
+        assert_eq!(state.get_history().len(), 2);
+        assert_eq!(state.get_history()[1].mv, Move::new(Pos::new(3, 6), Pos::new(3, 4)));
+    }
+
+    #[test]
+    fn get_legal_moves_test() {
+        let state = State::new();
+        assert_eq!(state.get_legal_moves().len(), 20);
+    }
+
+    #[test]
+    fn uci_test() {
+        let mv = Move::new(Pos::new(4, 1), Pos::new(4, 3));
+        assert_eq!(mv.to_uci(), "e2e4");
+        assert_eq!(Move::from_uci("e2e4"), Some(mv));
+
+        let promotion = Move::with_promotion(Pos::new(0, 6), Pos::new(0, 7), PieceType::Knight);
+        assert_eq!(promotion.to_uci(), "a7a8n");
+        assert_eq!(Move::from_uci("a7a8n"), Some(promotion));
+
+        let drop = Move::new_drop(PieceType::Knight, Pos::new(5, 2));
+        assert_eq!(drop.to_uci(), "N@f3");
+        assert_eq!(Move::from_uci("N@f3"), Some(drop));
+
+        assert_eq!(Move::from_uci("e2e9"), None);
+        assert_eq!(Move::from_uci("e7e8x"), None);
+        assert_eq!(Move::from_uci("K@e4x"), None);
+    }
+
+    #[test]
+    fn notation_test() {
+        assert_eq!(Pos::new(4, 3).to_notation(), "e4");
+        assert_eq!(Pos::from_notation("h8"), Some(Pos::new(7, 7)));
+        assert_eq!(Pos::from_notation("i1"), None);
+        assert_eq!(Pos::from_notation("a9"), None);
+    }
+
+    #[test]
+    fn lose_on_time_test() {
+        let mut state = State::new();
+        assert!(matches!(state.lose_on_time(Player::White), GameStatus::GameWonByBlack));
+        assert!(state.move_piece(Pos::new(0, 1), Pos::new(0, 2)).is_err());
+
+        let mut state = State::new();
+        for pos in state.get_all_pieces_for_player(Player::Black) {
+            let piece = state.get(pos).unwrap();
+            if piece.piece_type != PieceType::King && pos != Pos::new(1, 7) {
+                state.set(pos, None);
+            }
+        }
+        assert!(!state.has_mating_material(Player::Black));
+        assert!(state.has_mating_material(Player::White));
+        assert!(matches!(state.lose_on_time(Player::White), GameStatus::Draw));
+
+        // A bare king with a queen to drop can still mate
+        let mut state = State::from_fen_with_variant("4k3/8/8/8/8/8/8/3QK3[q] w - - 0 1", Arc::new(variant::Crazyhouse)).unwrap();
+        assert!(state.has_mating_material(Player::Black));
+        assert!(matches!(state.lose_on_time(Player::White), GameStatus::GameWonByBlack));
+    }
+
+    #[test]
+    fn resign_test() {
+        let mut state = State::new();
+        assert!(matches!(state.resign(Player::White), Ok(GameStatus::GameWonByBlack)));
+        assert_eq!(state.get_termination(), Some(Termination::Resignation));
+        assert_eq!(state.resign(Player::Black).err(), Some(ActionError::GameDone));
+    }
+
+    #[test]
+    fn draw_offer_test() {
+        let mut state = State::new();
+        assert_eq!(state.accept_draw(Player::Black).err(), Some(ActionError::NoDrawOffer));
+        assert!(state.offer_draw(Player::White).is_ok());
+        assert_eq!(state.accept_draw(Player::White).err(), Some(ActionError::NoDrawOffer));
+        assert!(state.decline_draw(Player::Black).is_ok());
+        assert_eq!(state.get_draw_offered_by(), None);
+
+        assert!(state.move_piece(Pos::new(4, 1), Pos::new(4, 3)).is_ok());
+        assert!(state.offer_draw(Player::White).is_ok());
+        assert!(state.move_piece(Pos::new(4, 6), Pos::new(4, 4)).is_ok());
+        assert_eq!(state.get_draw_offered_by(), None);
+
+        assert!(state.offer_draw(Player::White).is_ok());
+        assert!(matches!(state.accept_draw(Player::Black), Ok(GameStatus::Draw)));
+        assert_eq!(state.get_termination(), Some(Termination::Agreement));
+    }
+
+    #[test]
+    fn claim_threefold_test() {
+        let mut state = State::new();
+        assert_eq!(state.claim_draw(Player::White).err(), Some(ActionError::DrawNotClaimable));
+        for _ in 0..2 {
+            assert!(state.move_piece(Pos::new(6, 0), Pos::new(5, 2)).is_ok());
+            assert!(state.move_piece(Pos::new(6, 7), Pos::new(5, 5)).is_ok());
+            assert!(state.move_piece(Pos::new(5, 2), Pos::new(6, 0)).is_ok());
+            assert!(state.move_piece(Pos::new(5, 5), Pos::new(6, 7)).is_ok());
+        }
+        assert_eq!(state.get_repetition_count(), 3);
+        assert_eq!(state.claim_draw(Player::Black).err(), Some(ActionError::NotPlayersTurn));
+        assert!(matches!(state.claim_draw(Player::White), Ok(GameStatus::Draw)));
+        assert_eq!(state.get_termination(), Some(Termination::ThreefoldRepetition));
+    }
+
+    #[test]
+    fn claim_fifty_moves_test() {
+        let mut state = State::new();
+        assert!(state.move_piece(Pos::new(4, 1), Pos::new(4, 3)).is_ok());
+        assert_eq!(state.get_halfmove_clock(), 0);
+        state.halfmove_clock = 99;
+        assert!(state.move_piece(Pos::new(6, 7), Pos::new(5, 5)).is_ok());
+        assert_eq!(state.get_claimable_draw(), Some(Termination::FiftyMoveRule));
+        assert!(state.claim_draw(Player::White).is_ok());
+        assert_eq!(state.get_termination(), Some(Termination::FiftyMoveRule));
+    }
+
+    #[test]
+    fn stalemate_test() {
+        let mut state = State::new();
+        state.board = [None; 64];
+        state.set(Pos::new(7, 7), Some(Piece::new(PieceType::King, Player::Black)));
+        state.set(Pos::new(5, 6), Some(Piece::new(PieceType::King, Player::White)));
+        state.set(Pos::new(0, 5), Some(Piece::new(PieceType::Queen, Player::White)));
+        assert!(matches!(state.move_piece(Pos::new(0, 5), Pos::new(6, 5)), Ok(MoveSuccess::Draw)));
+        assert_eq!(state.get_termination(), Some(Termination::Stalemate));
+        assert!(matches!(state.get_game_status(), GameStatus::Draw));
+    }
+
+    #[test]
+    fn material_balance_test() {
+        let mut state = State::new();
+        assert_eq!(state.get_material_balance(), 0);
+        state.eliminate_target(Pos::new(3, 7));
+        assert_eq!(state.get_black_eliminated(), &[PieceType::Queen]);
+        assert_eq!(state.get_material_balance(), 9);
+        state.swap_current_player();
+        state.eliminate_target(Pos::new(0, 0));
+        state.eliminate_target(Pos::new(1, 1));
+        assert_eq!(state.get_white_eliminated(), &[PieceType::Rook, PieceType::Pawn]);
+        assert_eq!(state.get_material_balance(), 3);
+
+        // A promotion adds the new piece and takes away the pawn
+        let mut state = State::from_fen("4k3/P7/8/8/8/8/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(state.get_material_balance(), 1);
+        state.play_move(Move::with_promotion(Pos::new(0, 6), Pos::new(0, 7), PieceType::Queen)).unwrap();
+        assert_eq!(state.get_material_balance(), 9);
+    }
+
     #[test]
     fn fools_mate_test() {
         let mut state = State::new();
@@ -1,8 +1,21 @@
 extern crate sdl2;
 
-use chess::{Pos, State, MoveSuccess, MoveError, Player, GameStatus};
+mod theme;
+
+use chess::{Pos, State, Move, MoveSuccess, MoveError, Piece, PieceType, Player, GameStatus, ActionError};
+use chess::analysis::{self, Classification, Evaluation, GameAnalysis};
+use chess::clock::{self, Clock, SystemTimeSource, TimeControl};
+use chess::eco::Opening;
+use chess::explorer::{ExplorerDb, ExplorerMove};
+use chess::net::{NetEvent, Session};
+use chess::pgn::parse_pgn;
+use chess::puzzle::{parse_puzzles, Attempt, Puzzle, PuzzleProfiles, PuzzleRun};
+use chess::search::SearchLimits;
+use chess::tree::{self, GameTree, Node, NodeId};
+use chess::uci::{EngineEvent, EngineInfo, GoLimits, UciEngine};
+use chess::variant::{self, Standard, Variant};
 use sdl2::Sdl;
-use sdl2::event::Event;
+use sdl2::event::{Event, WindowEvent};
 use sdl2::keyboard::Keycode;
 use sdl2::pixels::{self, Color};
 
@@ -11,35 +24,416 @@ use sdl2::rect::Rect;
 use sdl2::render::{Canvas, Texture, TextureQuery, TextureCreator};
 use sdl2::ttf::{Font};
 use sdl2::video::{Window, WindowContext};
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use theme::{PieceFormat, Theme, THEME_DIR};
 
 const SCREEN_WIDTH: u32 = 1200;
 const SCREEN_HEIGHT: u32 = 640;
+const PUZZLE_PROFILES: &str = "puzzle_profiles.tsv";
+
+// The offsets and sizes below are given for the default 80 px squares and are scaled with the board
+const BASE_SQUARE_SIZE: u32 = 80;
+const INFO_BOARD_MIN_SQUARES: u32 = 6;
+const OPENING_TOP: i32 = 62;
+const CLOCK_TOP: i32 = 80;
+const CAPTURED_TOP: i32 = 120;
+const POCKET_SLOT_WIDTH: i32 = 70;
+const ACTIONS_TOP: i32 = 230;
+const ACTION_BUTTON_WIDTH: i32 = 150;
+const ACTION_BUTTON_HEIGHT: i32 = 30;
+const ENGINE_TOP: i32 = 280;
+const ENGINE_LINE_MOVES: usize = 8;
+const GRAPH_TOP: i32 = 340;
+const GRAPH_HEIGHT: i32 = 70;
+const EVAL_BAR_WIDTH: i32 = 16;
+const MOVE_LIST_TOP: i32 = 420;
+const MOVE_LIST_ROW_HEIGHT: i32 = 24;
+const MOVE_LIST_WHITE_COLUMN: i32 = 60;
+const MOVE_LIST_BLACK_COLUMN: i32 = 180;
+const MOVE_LIST_COLUMN_WIDTH: i32 = 120;
+const VARIATION_INDENT: i32 = 20;
+const VARIATION_CELL_WIDTH: i32 = 110;
+const VARIATION_CELLS: usize = 4;
+const EXPLORER_GAMES_COLUMN: i32 = 90;
+const EXPLORER_RESULTS_COLUMN: i32 = 220;
+const EXPLORER_RATING_COLUMN: i32 = 400;
+const LIVE_BUTTON_HEIGHT: i32 = 30;
+
+const POCKET_PIECES: [PieceType; 5] = [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::Pawn];
 
 #[derive(Copy, Clone)]
 struct Layout {
     square_size: u32,
     top_left_coord: Pos,
+    info_coord: Pos,
+    info_width: u32,
+    info_height: u32,
+    // Beside the board on wide screens and below it on tall ones
+    eval_bar: Rect,
+    scale: f32,
 }
 
 impl Layout {
-    fn new() -> Layout {
+    /// Fits the board and the info board into a drawable area of the given size in pixels.
+    /// The info board goes to the side of the board on wide screens and below it on tall ones.
+    fn new(width: u32, height: u32) -> Layout {
+        let side_square = (height / 8).min(width / (8 + INFO_BOARD_MIN_SQUARES));
+        let below_square = (width / 8).min(height / (8 + INFO_BOARD_MIN_SQUARES));
+        let square_size = side_square.max(below_square).max(1);
+        let board_size = square_size * 8;
+        let scale = square_size as f32 / BASE_SQUARE_SIZE as f32;
+        let bar = ((EVAL_BAR_WIDTH as f32 * scale).round() as u32).max(1);
+
+        let (info_coord, info_width, info_height, eval_bar) = if side_square >= below_square {
+            (Pos::new((board_size + bar) as i32, 0), width.saturating_sub(board_size + bar), height,
+             Rect::new(board_size as i32, 0, bar, board_size))
+        } else {
+            (Pos::new(0, (board_size + bar) as i32), width, height.saturating_sub(board_size + bar),
+             Rect::new(0, board_size as i32, board_size, bar))
+        };
+
         Layout {
-            square_size: 80,
-            top_left_coord: Pos::new(0, 0)
+            square_size,
+            top_left_coord: Pos::new(0, 0),
+            info_coord,
+            info_width,
+            info_height,
+            eval_bar,
+            scale,
+        }
+    }
+
+    fn scaled(&self, value: i32) -> i32 {
+        (value as f32 * self.scale).round() as i32
+    }
+
+    fn board_contains(&self, x: i32, y: i32) -> bool {
+        let board_size = (self.square_size * 8) as i32;
+        x >= self.top_left_coord.x && x < self.top_left_coord.x + board_size &&
+        y >= self.top_left_coord.y && y < self.top_left_coord.y + board_size
+    }
+
+    fn info_board_contains(&self, x: i32, y: i32) -> bool {
+        x >= self.info_coord.x && x < self.info_coord.x + self.info_width as i32 &&
+        y >= self.info_coord.y && y < self.info_coord.y + self.info_height as i32
+    }
+
+    fn graph_rect(&self) -> Rect {
+        let width = (self.info_width as i32 - self.scaled(10)).max(1);
+        Rect::new(self.info_coord.x + self.scaled(5), self.info_coord.y + self.scaled(GRAPH_TOP), width as u32,
+                  self.scaled(GRAPH_HEIGHT).max(1) as u32)
+    }
+}
+
+/// One row of the move list: main line rows have a move number and up to two moves, variation rows
+/// are indented and have a few moves each. Cells hold the node, its text and its x offset and width
+/// for 80 px squares.
+struct MoveRow {
+    number: String,
+    cells: Vec<(NodeId, String, i32, i32)>,
+}
+
+fn move_text(node: &Node) -> String {
+    let glyphs: String = node.nags.iter().filter_map(|&nag| tree::nag_glyph(nag)).collect();
+    format!("{}{}", node.san, glyphs)
+}
+
+// The main line, with each variation on its own rows after the move it replaces
+fn move_rows(tree: &GameTree) -> Vec<MoveRow> {
+    let mut rows = Vec::new();
+    let mut row: Option<MoveRow> = None;
+    for &id in tree.main_line().iter().skip(1) {
+        let node = tree.get(id).unwrap();
+        let parent = tree.get(node.get_parent().unwrap()).unwrap();
+        let ply = parent.position.get_ply();
+        if ply.is_multiple_of(2) {
+            rows.extend(row.take());
+        }
+        let row = row.get_or_insert_with(|| MoveRow { number: format!("{}.", ply / 2 + 1), cells: Vec::new() });
+        let column = if ply.is_multiple_of(2) { MOVE_LIST_WHITE_COLUMN } else { MOVE_LIST_BLACK_COLUMN };
+        row.cells.push((id, move_text(node), column, MOVE_LIST_COLUMN_WIDTH));
+
+        if parent.get_children().len() > 1 {
+            rows.push(std::mem::replace(row, MoveRow { number: format!("{}.", ply / 2 + 1), cells: Vec::new() }));
+            for &variation in &parent.get_children()[1..] {
+                add_variation_rows(tree, variation, 1, &mut rows);
+            }
         }
     }
+    rows.extend(row.filter(|row| !row.cells.is_empty()));
+    rows
+}
+
+fn add_variation_rows(tree: &GameTree, first: NodeId, depth: i32, rows: &mut Vec<MoveRow>) {
+    let indent = MOVE_LIST_WHITE_COLUMN + VARIATION_INDENT * (depth - 1);
+    let mut row = MoveRow { number: String::new(), cells: Vec::new() };
+    let mut id = first;
+    loop {
+        if row.cells.len() == VARIATION_CELLS {
+            rows.push(std::mem::replace(&mut row, MoveRow { number: String::new(), cells: Vec::new() }));
+        }
+        let node = tree.get(id).unwrap();
+        let parent = tree.get(node.get_parent().unwrap()).unwrap();
+        let ply = parent.position.get_ply();
+        let text = if ply.is_multiple_of(2) {
+            format!("{}.{}", ply / 2 + 1, move_text(node))
+        } else if row.cells.is_empty() {
+            format!("{}...{}", ply / 2 + 1, move_text(node))
+        } else {
+            move_text(node)
+        };
+        let x = indent + VARIATION_CELL_WIDTH * row.cells.len() as i32;
+        row.cells.push((id, text, x, VARIATION_CELL_WIDTH));
+
+        if parent.get_children()[0] == id && parent.get_children().len() > 1 {
+            rows.push(std::mem::replace(&mut row, MoveRow { number: String::new(), cells: Vec::new() }));
+            for &variation in &parent.get_children()[1..] {
+                add_variation_rows(tree, variation, depth + 1, rows);
+            }
+        }
+        match node.get_children().first() {
+            Some(&child) => id = child,
+            None => break,
+        }
+    }
+    if !row.cells.is_empty() {
+        rows.push(row);
+    }
+}
+
+/// Scroll position of the move list and which position of the game tree is shown on the board.
+struct MoveList {
+    scroll: usize,
+    // None follows the end of the main line, which is the live position
+    viewing: Option<NodeId>,
+}
+
+impl MoveList {
+    fn new() -> MoveList {
+        MoveList {
+            scroll: 0,
+            viewing: None,
+        }
+    }
+
+    fn visible_rows(layout: &Layout) -> usize {
+        let available = layout.info_height as i32 - layout.scaled(MOVE_LIST_TOP + LIVE_BUTTON_HEIGHT + 10);
+        (available / layout.scaled(MOVE_LIST_ROW_HEIGHT).max(1)).max(1) as usize
+    }
+
+    fn shown(&self, tree: &GameTree) -> NodeId {
+        self.viewing.unwrap_or(tree.main_line_end())
+    }
+
+    fn scroll_by(&mut self, layout: &Layout, rows: i32, tree: &GameTree) {
+        let max_scroll = move_rows(tree).len().saturating_sub(MoveList::visible_rows(layout));
+        let scroll = (self.scroll as i32 + rows).max(0) as usize;
+        self.scroll = scroll.min(max_scroll);
+    }
+
+    // Makes sure the row of the given node is on screen
+    fn scroll_to(&mut self, layout: &Layout, tree: &GameTree, id: NodeId) {
+        let row = match move_rows(tree).iter().position(|row| row.cells.iter().any(|cell| cell.0 == id)) {
+            Some(row) => row,
+            None => return,
+        };
+        let visible_rows = MoveList::visible_rows(layout);
+        if row < self.scroll {
+            self.scroll = row;
+        } else if row >= self.scroll + visible_rows {
+            self.scroll = row + 1 - visible_rows;
+        }
+    }
+
+    fn view(&mut self, layout: &Layout, tree: &GameTree, id: NodeId) {
+        self.viewing = if id == tree.main_line_end() { None } else { Some(id) };
+        self.scroll_to(layout, tree, id);
+    }
+
+    // Forward follows the line the shown position is on
+    fn step(&mut self, layout: &Layout, tree: &GameTree, forward: bool) {
+        let node = tree.get(self.shown(tree)).unwrap();
+        let next = if forward { node.get_children().first().copied() } else { node.get_parent() };
+        if let Some(next) = next {
+            self.view(layout, tree, next);
+        }
+    }
+
+    fn back_to_live(&mut self, layout: &Layout, tree: &GameTree) {
+        self.view(layout, tree, tree.main_line_end());
+    }
+}
+
+// `analyzed` is the number of positions in the evaluation graph
+fn handle_info_board_click(layout: &Layout, move_list: &mut MoveList, tree: &GameTree, analyzed: usize, x: i32, y: i32) {
+    let graph = layout.graph_rect();
+    if analyzed > 1 && graph.contains_point((x, y)) {
+        let ply = ((x - graph.x()) as f32 / graph.width() as f32 * (analyzed - 1) as f32).round() as usize;
+        if let Some(&id) = tree.main_line().get(ply.min(analyzed - 1)) {
+            move_list.view(layout, tree, id);
+        }
+        return;
+    }
+
+    let x = x - layout.info_coord.x;
+    let y = y - layout.info_coord.y;
+
+    if move_list.viewing.is_some() && y >= layout.info_height as i32 - layout.scaled(LIVE_BUTTON_HEIGHT + 5) {
+        move_list.back_to_live(layout, tree);
+        return;
+    }
+
+    let top = layout.scaled(MOVE_LIST_TOP);
+    let row_height = layout.scaled(MOVE_LIST_ROW_HEIGHT).max(1);
+    let visible_height = (MoveList::visible_rows(layout) as i32) * row_height;
+    if y < top || y >= top + visible_height {
+        return;
+    }
+
+    let rows = move_rows(tree);
+    let row = match rows.get(move_list.scroll + ((y - top) / row_height) as usize) {
+        Some(row) => row,
+        None => return,
+    };
+    let cell = row.cells.iter().find(|(_, _, cell_x, width)| (layout.scaled(*cell_x)..layout.scaled(cell_x + width)).contains(&x));
+    if let Some(&(id, ..)) = cell {
+        move_list.view(layout, tree, id);
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Action {
+    Resign,
+    OfferDraw,
+    AcceptDraw,
+    DeclineDraw,
+    ClaimDraw,
+}
+
+impl Action {
+    fn label(&self) -> &str {
+        match self {
+            Action::Resign => "Resign",
+            Action::OfferDraw => "Offer draw",
+            Action::AcceptDraw => "Accept draw",
+            Action::DeclineDraw => "Decline draw",
+            Action::ClaimDraw => "Claim draw",
+        }
+    }
+
+    /// Actions open to the player to move, in the order the buttons are shown.
+    fn available(state: &State) -> Vec<Action> {
+        if !state.get_game_running() {
+            return Vec::new();
+        }
+
+        let player = state.get_current_player();
+        let mut list = Vec::new();
+        match state.get_draw_offered_by() {
+            Some(offered_by) if offered_by != player => {
+                list.push(Action::AcceptDraw);
+                list.push(Action::DeclineDraw);
+            }
+            Some(_) => (),
+            None => list.push(Action::OfferDraw),
+        }
+        if state.get_claimable_draw().is_some() {
+            list.push(Action::ClaimDraw);
+        }
+        list.push(Action::Resign);
+        list
+    }
+
+    fn perform(&self, state: &mut State) -> Result<(), ActionError> {
+        let player = state.get_current_player();
+        match self {
+            Action::Resign => state.resign(player).map(|_| ()),
+            Action::OfferDraw => state.offer_draw(player),
+            Action::AcceptDraw => state.accept_draw(player).map(|_| ()),
+            Action::DeclineDraw => state.decline_draw(player),
+            Action::ClaimDraw => state.claim_draw(player).map(|_| ()),
+        }
+    }
+}
+
+fn action_button_rect(layout: &Layout, index: usize) -> Rect {
+    let x = layout.info_coord.x + layout.scaled(5) + (index as i32) * layout.scaled(ACTION_BUTTON_WIDTH + 10);
+    let y = layout.info_coord.y + layout.scaled(ACTIONS_TOP);
+    Rect::new(x, y, layout.scaled(ACTION_BUTTON_WIDTH) as u32, layout.scaled(ACTION_BUTTON_HEIGHT) as u32)
+}
+
+fn find_action_button(layout: &Layout, state: &State, x: i32, y: i32) -> Option<Action> {
+    Action::available(state)
+        .into_iter()
+        .enumerate()
+        .find(|(index, _)| action_button_rect(layout, *index).contains_point((x, y)))
+        .map(|(_, action)| action)
+}
+
+fn board_square(layout: &Layout, x: i32, y: i32) -> Pos {
+    let x_pos = (x - layout.top_left_coord.x) / (layout.square_size as i32);
+    let y_pos = 7 - (y - layout.top_left_coord.y) / (layout.square_size as i32);
+    Pos::new(x_pos, y_pos)
+}
+
+/// A piece taken from the pocket in drop variants. It follows the mouse until
+/// it is released over the board, or stays selected until a square is clicked.
+#[derive(Copy, Clone)]
+struct PocketDrag {
+    piece_type: PieceType,
+    x: i32,
+    y: i32,
+}
+
+// The pockets are shown where the captured pieces are otherwise, white on the first row
+fn pocket_slot_rect(layout: &Layout, player: Player, slot: usize) -> Rect {
+    let icon_size = layout.square_size / 2;
+    let row = match player {
+        Player::White => 0,
+        Player::Black => 1,
+    };
+    let x = layout.info_coord.x + layout.scaled(5) + (slot as i32) * layout.scaled(POCKET_SLOT_WIDTH);
+    let y = layout.info_coord.y + layout.scaled(CAPTURED_TOP) + row * (icon_size as i32 + layout.scaled(10));
+    Rect::new(x, y, icon_size, icon_size)
+}
+
+fn find_pocket_piece(layout: &Layout, state: &State, x: i32, y: i32) -> Option<PieceType> {
+    if !state.get_variant().has_drops() || !state.get_game_running() {
+        return None;
+    }
+    let player = state.get_current_player();
+    POCKET_PIECES.iter()
+        .copied()
+        .enumerate()
+        .find(|(slot, piece_type)| {
+            state.get_pocket_count(player, *piece_type) > 0 &&
+            pocket_slot_rect(layout, player, *slot).contains_point((x, y))
+        })
+        .map(|(_, piece_type)| piece_type)
+}
+
+fn handle_drop(layout: &Layout, state: &mut State, drag: PocketDrag, x: i32, y: i32) -> Result<MoveSuccess, MoveError> {
+    let res = state.play_move(Move::new_drop(drag.piece_type, board_square(layout, x, y)));
+    match res {
+        Err(err) => println!("{:?}", err),
+        Ok(msg) => println!("{:?}", msg),
+    }
+    res
 }
 
 fn handle_mouse_click(layout: &Layout, state: &mut State, moving_from: &mut Option<Pos>, x: i32, y: i32)
         -> Option<Result<MoveSuccess, MoveError>>
     {
 
-    let x_pos = x / (layout.square_size as i32);
-    let y_pos = 7 - y / (layout.square_size as i32);
+    let to = board_square(layout, x, y);
 
     if let Some(pos_from) = moving_from {
-        let res = state.move_piece(*pos_from, Pos::new(x_pos, y_pos));
+        let res = state.move_piece(*pos_from, to);
 
         match res {
             Err(err) => println!("{:?}", err),
@@ -49,11 +443,580 @@ fn handle_mouse_click(layout: &Layout, state: &mut State, moving_from: &mut Opti
         *moving_from = None;
         Some(res)
     } else {
-        *moving_from = Some(Pos::new(x_pos, y_pos));
+        *moving_from = Some(to);
         None
     }
 }
 
+// Keeps the game tree, the move list and the clocks up to date after a move was tried,
+// and sends a local move to the other player in a network game
+fn record_move(res: &Result<MoveSuccess, MoveError>, state: &mut State, tree: &mut GameTree,
+               move_list: &mut MoveList, layout: &Layout, clock: Option<&mut Clock>, session: Option<&mut Session>) {
+    if res.is_err() {
+        return;
+    }
+    if let Some(entry) = state.get_history().last() {
+        let _res = tree.add_move(tree.main_line_end(), entry.mv);
+    }
+    move_list.back_to_live(layout, tree);
+
+    if let (Some(session), Some(entry)) = (session, state.get_history().last()) {
+        if let Err(err) = session.send_move(state, entry.mv) {
+            println!("{}", err.to_string());
+        }
+    }
+
+    if let Some(clock) = clock {
+        if let Some(flagged) = clock.press() {
+            state.lose_on_time(flagged);
+        }
+    }
+}
+
+fn handle_history_keydown(layout: &Layout, keycode: Keycode, move_list: &mut MoveList, tree: &GameTree) {
+    match keycode {
+        Keycode::Left => move_list.step(layout, tree, false),
+        Keycode::Right => move_list.step(layout, tree, true),
+        Keycode::Home => move_list.view(layout, tree, tree.root()),
+        Keycode::End => move_list.back_to_live(layout, tree),
+        _ => {}
+    }
+}
+
+// Changes the variations at the shown position: Up and Down move its variation among its siblings and
+// Delete removes it. Returns whether the tree changed.
+fn handle_variation_keydown(layout: &Layout, keycode: Keycode, move_list: &mut MoveList, tree: &mut GameTree) -> bool {
+    let shown = move_list.shown(tree);
+    let changed = match keycode {
+        Keycode::Up => tree.promote(shown),
+        Keycode::Down => tree.demote(shown),
+        Keycode::Delete => {
+            let parent = tree.get(shown).and_then(Node::get_parent);
+            let deleted = tree.delete(shown);
+            if let Some(parent) = parent {
+                move_list.view(layout, tree, parent);
+            }
+            return deleted;
+        }
+        _ => false,
+    };
+    if changed {
+        move_list.view(layout, tree, shown);
+    }
+    changed
+}
+
+/// Theme files found in the theme directory and the one currently in use.
+struct ThemeList {
+    paths: Vec<PathBuf>,
+    current: usize,
+}
+
+impl ThemeList {
+    fn new(requested: Option<&str>) -> ThemeList {
+        let paths = Theme::discover(Path::new(THEME_DIR));
+        let wanted = requested.unwrap_or("classic");
+        let current = paths.iter()
+            .position(|path| path.parent().and_then(|dir| dir.file_name()).is_some_and(|name| name == wanted))
+            .unwrap_or(0);
+        ThemeList { paths, current }
+    }
+
+    fn current_theme(&self) -> Theme {
+        match self.paths.get(self.current) {
+            Some(path) => Theme::load(path).unwrap_or_else(|err| {
+                println!("Failed to load theme: {}", err);
+                Theme::default()
+            }),
+            None => Theme::default(),
+        }
+    }
+
+    fn next_theme(&mut self) -> Option<Result<Theme, String>> {
+        if self.paths.is_empty() {
+            return None;
+        }
+        self.current = (self.current + 1) % self.paths.len();
+        Some(Theme::load(&self.paths[self.current]))
+    }
+}
+
+fn get_arg<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter().position(|arg| arg == name).and_then(|i| args.get(i + 1)).map(|arg| arg.as_str())
+}
+
+/// The start position from `--fen <fen>` or `--chess960 <id>`, where the id may be
+/// `random`. Without either the game starts from the classical setup.
+fn create_state(args: &[String]) -> Result<State, String> {
+    let variant = match get_arg(args, "--variant") {
+        Some(name) => variant::from_name(name).ok_or(format!("Unknown variant: {}", name))?,
+        None => Arc::new(Standard),
+    };
+
+    if let Some(fen) = get_arg(args, "--fen") {
+        return State::from_fen_with_variant(fen, variant).map_err(|e| e.to_string().to_string());
+    }
+
+    match get_arg(args, "--chess960") {
+        Some("random") => {
+            let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map_err(|e| e.to_string())?.subsec_nanos();
+            Ok(State::new_chess960((nanos % 960) as u16).unwrap())
+        }
+        Some(id) => id.parse().ok()
+            .and_then(State::new_chess960)
+            .ok_or(format!("Invalid Chess960 position: {}", id)),
+        None => Ok(State::new_variant(variant)),
+    }
+}
+
+fn load_pgn(path: &str) -> Result<GameTree, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let games = parse_pgn(&text).map_err(|e| format!("Invalid PGN in {}: {}", path, e.to_string()))?;
+    games.into_iter().next().map(|game| game.tree).ok_or(format!("No game in {}", path))
+}
+
+// Sets the live position to the end of the main line after the tree was edited
+fn follow_main_line(tree: &GameTree, state: &mut State) {
+    let end = &tree.get(tree.main_line_end()).unwrap().position;
+    if end.to_fen() != state.to_fen() || end.get_history().len() != state.get_history().len() {
+        *state = end.clone();
+    }
+}
+
+// `--host <port>` waits for the other player, who starts with `--join <address>:<port>`.
+// The host plays white unless `--color black` is given.
+fn create_session(args: &[String], state: &State) -> Result<Option<Session>, String> {
+    if let Some(port) = get_arg(args, "--host") {
+        let port: u16 = port.parse().map_err(|_| format!("Invalid port: {}", port))?;
+        let local = match get_arg(args, "--color") {
+            None | Some("white") => Player::White,
+            Some("black") => Player::Black,
+            Some(color) => return Err(format!("Invalid color: {}", color)),
+        };
+        let session = Session::host(("0.0.0.0", port), state, local).map_err(|e| e.to_string())?;
+        println!("Waiting for the other player on port {}", port);
+        Ok(Some(session))
+    } else if let Some(address) = get_arg(args, "--join") {
+        Session::join(address).map(Some).map_err(|e| e.to_string())
+    } else {
+        Ok(None)
+    }
+}
+
+// `--engine <path>` starts a UCI engine that plays `--engine-color` (black unless given,
+// `none` only analyses) with `--engine-time` milliseconds per move, or the clock's time.
+// Engine options are set with `--engine-option Name=Value`, which may be repeated.
+fn create_engine(args: &[String], state: &State) -> Result<Option<EngineSide>, String> {
+    let path = match get_arg(args, "--engine") {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let player = match get_arg(args, "--engine-color") {
+        None | Some("black") => Some(Player::Black),
+        Some("white") => Some(Player::White),
+        Some("none") => None,
+        Some(color) => return Err(format!("Invalid engine color: {}", color)),
+    };
+    let move_time = match get_arg(args, "--engine-time") {
+        Some(millis) => Duration::from_millis(millis.parse().map_err(|_| format!("Invalid engine time: {}", millis))?),
+        None => Duration::from_secs(1),
+    };
+
+    let mut engine = UciEngine::start(path, &[]).map_err(|e| format!("Failed to start {}: {}", path, e))?;
+    for (i, arg) in args.iter().enumerate() {
+        if arg != "--engine-option" {
+            continue;
+        }
+        let option = args.get(i + 1).ok_or("Missing engine option")?;
+        let (name, value) = option.split_once('=').ok_or(format!("Invalid engine option: {}", option))?;
+        engine.set_option(name, value).map_err(|e| e.to_string())?;
+    }
+    engine.new_game().map_err(|e| e.to_string())?;
+
+    Ok(Some(EngineSide {
+        engine,
+        player,
+        move_time,
+        start: state.clone(),
+        sent: None,
+        searched: state.clone(),
+        info: None,
+    }))
+}
+
+// `--puzzles <file>` trains with the puzzles of a CSV file in the Lichess puzzle database format. The
+// rating and record of `--user` (`player` by default) are kept in `--puzzle-profiles`.
+fn create_puzzle_trainer(args: &[String]) -> Result<Option<PuzzleTrainer>, String> {
+    let path = match get_arg(args, "--puzzles") {
+        Some(path) => path,
+        None => return Ok(None),
+    };
+    let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let puzzles = parse_puzzles(&text).map_err(|e| format!("Invalid puzzle in {}: {}", path, e.to_string()))?;
+    if puzzles.is_empty() {
+        return Err(format!("No puzzles in {}", path));
+    }
+    let profiles_path = PathBuf::from(get_arg(args, "--puzzle-profiles").unwrap_or(PUZZLE_PROFILES));
+    let profiles = PuzzleProfiles::load(&profiles_path).map_err(|e| e.to_string())?;
+    let user = get_arg(args, "--user").unwrap_or("player").to_string();
+    let mut trainer = PuzzleTrainer { puzzles, current: 0, run: None, feedback: None, user, profiles, profiles_path };
+    trainer.start(0);
+    Ok(Some(trainer))
+}
+
+// What the last move or the H key gave, shown in the info panel
+enum PuzzleFeedback {
+    Correct,
+    Solved,
+    Wrong(String),
+    Hint(String),
+    Error(String),
+}
+
+impl PuzzleFeedback {
+    fn text(&self) -> String {
+        match self {
+            Self::Correct => String::from("Correct, keep going"),
+            Self::Solved => String::from("Solved! N for the next puzzle"),
+            Self::Wrong(solution) => format!("Wrong, the solution was {}", solution),
+            Self::Hint(san) => format!("Hint: {}", san),
+            Self::Error(message) => message.clone(),
+        }
+    }
+
+    fn color(&self) -> Color {
+        match self {
+            Self::Correct | Self::Solved => Color::RGBA(0, 255, 0, 255),
+            Self::Wrong(_) | Self::Error(_) => Color::RGBA(255, 0, 0, 255),
+            Self::Hint(_) => Color::RGBA(255, 255, 0, 255),
+        }
+    }
+}
+
+struct PuzzleTrainer {
+    puzzles: Vec<Puzzle>,
+    current: usize,
+    run: Option<PuzzleRun>,
+    feedback: Option<PuzzleFeedback>,
+    user: String,
+    profiles: PuzzleProfiles,
+    profiles_path: PathBuf,
+}
+
+impl PuzzleTrainer {
+    // Starts the first puzzle from `index` on that can be set up
+    fn start(&mut self, index: usize) {
+        for offset in 0..self.puzzles.len() {
+            let current = (index + offset) % self.puzzles.len();
+            match PuzzleRun::new(self.puzzles[current].clone()) {
+                Ok(run) => {
+                    self.current = current;
+                    self.run = Some(run);
+                    self.feedback = None;
+                    return;
+                }
+                Err(err) => println!("Skipping puzzle {}: {}", self.puzzles[current].id, err.to_string()),
+            }
+        }
+    }
+
+    fn next(&mut self) {
+        self.start(self.current + 1);
+    }
+
+    // The live position and the moves of the puzzle so far
+    fn show(&self, state: &mut State, tree: &mut GameTree) {
+        if let Some(run) = self.run.as_ref() {
+            *state = run.get_position().clone();
+            if let Ok(start) = State::from_fen(&run.get_puzzle().fen) {
+                *tree = GameTree::from_game(&start, state);
+            }
+        }
+    }
+
+    fn try_move(&mut self, mv: Move) {
+        let run = match self.run.as_mut() {
+            Some(run) => run,
+            None => return,
+        };
+        let position = run.get_position().clone();
+        let hint = run.hint();
+        self.feedback = Some(match run.try_move(mv) {
+            Ok(Attempt::Correct(_)) => PuzzleFeedback::Correct,
+            Ok(Attempt::Solved) => PuzzleFeedback::Solved,
+            Ok(Attempt::Wrong) => {
+                let solution = hint.and_then(|mv| position.move_to_san(mv)).unwrap_or_default();
+                PuzzleFeedback::Wrong(solution)
+            }
+            Err(err) => PuzzleFeedback::Error(err.to_string().to_string()),
+        });
+        if let Some(solved) = run.get_result() {
+            self.profiles.user(&self.user).record(run.get_puzzle().rating, solved);
+            if let Err(err) = self.profiles.save(&self.profiles_path) {
+                println!("Failed to save the puzzle ratings: {}", err);
+            }
+        }
+    }
+
+    fn show_hint(&mut self) {
+        let run = match self.run.as_ref() {
+            Some(run) => run,
+            None => return,
+        };
+        if let Some(san) = run.hint().and_then(|mv| run.get_position().move_to_san(mv)) {
+            self.feedback = Some(PuzzleFeedback::Hint(san));
+        }
+    }
+
+    fn title(&self) -> String {
+        let stats = self.profiles.get(&self.user).copied().unwrap_or_default();
+        let record = format!("{} solved {} of {} ({:.0}%), rating {:.0}", self.user, stats.solved, stats.attempts,
+                             stats.success_rate() * 100.0, stats.rating);
+        let run = match self.run.as_ref() {
+            Some(run) => run,
+            None => return record,
+        };
+        let status = match run.get_result() {
+            None if run.get_position().get_current_player() == Player::White => "white to play",
+            None => "black to play",
+            Some(true) => "solved",
+            Some(false) => "failed",
+        };
+        format!("Puzzle {} ({}) - {} - {}", run.get_puzzle().id, run.get_puzzle().rating, status, record)
+    }
+
+    // A shorter line for the info panel: the puzzle, and the user's rating and record
+    fn summary(&self) -> String {
+        let stats = self.profiles.get(&self.user).copied().unwrap_or_default();
+        let record = format!("{} {:.0}, {} of {} solved", self.user, stats.rating, stats.solved, stats.attempts);
+        match self.run.as_ref() {
+            Some(run) => format!("Puzzle {} ({}) - {}", run.get_puzzle().id, run.get_puzzle().rating, record),
+            None => record,
+        }
+    }
+}
+
+// `--explorer <file>` opens a database built with the explorer command. E switches the move list to
+// the moves played from the shown position.
+fn create_explorer(args: &[String]) -> Result<Option<ExplorerView>, String> {
+    match get_arg(args, "--explorer") {
+        Some(path) => {
+            let db = ExplorerDb::open(Path::new(path)).map_err(|e| format!("Could not open {}: {}", path, e))?;
+            Ok(Some(ExplorerView { db, shown: true, hash: None, moves: Vec::new() }))
+        }
+        None => Ok(None),
+    }
+}
+
+struct ExplorerView {
+    db: ExplorerDb,
+    shown: bool,
+    // The position the moves are for
+    hash: Option<u64>,
+    moves: Vec<(String, ExplorerMove)>,
+}
+
+impl ExplorerView {
+    // Looks the position up unless it is the one already shown
+    fn update(&mut self, position: &State) {
+        let hash = position.get_hash();
+        if !self.shown || self.hash == Some(hash) {
+            return;
+        }
+        self.moves = match self.db.query(position) {
+            Ok(moves) => moves.into_iter().filter_map(|stats| Some((position.move_to_san(stats.mv)?, stats))).collect(),
+            Err(err) => {
+                println!("Failed to read the opening explorer: {}", err);
+                Vec::new()
+            }
+        };
+        self.hash = Some(hash);
+    }
+}
+
+struct EngineSide {
+    engine: UciEngine,
+    player: Option<Player>,
+    move_time: Duration,
+    start: State,
+    // Number of moves and whether the game was running when the position was last sent
+    sent: Option<(usize, bool)>,
+    searched: State,
+    info: Option<EngineInfo>,
+}
+
+impl EngineSide {
+    fn can_move(&self, state: &State) -> bool {
+        self.player != Some(state.get_current_player())
+    }
+
+    // Sends the position whenever a move was made: the engine searches for its own
+    // move on its turn and analyses the position on the other player's turn
+    fn sync(&mut self, state: &State, clock: Option<&Clock>) {
+        let key = (state.get_history().len(), state.get_game_running());
+        if self.sent == Some(key) {
+            return;
+        }
+        self.sent = Some(key);
+        self.info = None;
+        self.searched = state.clone();
+
+        let res = if !state.get_game_running() {
+            self.engine.stop()
+        } else if self.can_move(state) {
+            self.engine.go(&self.start, state, &GoLimits { infinite: true, ..GoLimits::default() })
+        } else {
+            let limits = match clock {
+                Some(clock) => GoLimits {
+                    time: Some([clock.remaining(Player::White), clock.remaining(Player::Black)]),
+                    ..GoLimits::default()
+                },
+                None => GoLimits::move_time(self.move_time),
+            };
+            self.engine.go(&self.start, state, &limits)
+        };
+        if let Err(err) = res {
+            println!("Engine error: {}", err);
+        }
+    }
+
+    // Returns the engine's move once it has found one
+    fn poll(&mut self, redraw: &mut bool) -> Option<Move> {
+        let mut best_move = None;
+        for event in self.engine.poll() {
+            match event {
+                EngineEvent::Info(info) if info.multipv.unwrap_or(1) == 1 => {
+                    self.info = Some(info);
+                    *redraw = true;
+                }
+                EngineEvent::BestMove(mv) if !self.can_move(&self.searched) => best_move = mv,
+                EngineEvent::Exited => {
+                    println!("The engine exited");
+                    self.player = None;
+                }
+                _ => {}
+            }
+        }
+        best_move
+    }
+
+    fn evaluation(&self) -> Option<Evaluation> {
+        let score = self.info.as_ref()?.score?;
+        Some(Evaluation { score, player: self.searched.get_current_player() })
+    }
+
+    // The engine's name, the evaluation from white's point of view and the principal variation
+    fn describe(&self) -> (String, String) {
+        let info = match &self.info {
+            Some(info) => info,
+            None => return (self.engine.get_name().to_string(), String::new()),
+        };
+        let mut summary = self.engine.get_name().to_string();
+        if let Some(evaluation) = self.evaluation() {
+            summary.push_str(&format!("  {}", evaluation.format()));
+        }
+        if let Some(depth) = info.depth {
+            summary.push_str(&format!("  depth {}", depth));
+        }
+
+        let line = &info.pv[..info.pv.len().min(ENGINE_LINE_MOVES)];
+        (summary, self.searched.line_to_san(line).join(" "))
+    }
+}
+
+enum AnalyzerEvent {
+    Progress(usize, usize),
+    Done(GameAnalysis),
+}
+
+/// An analysis of the game so far, run in the background when the A key is pressed.
+struct GameAnalyzer {
+    receiver: Receiver<AnalyzerEvent>,
+    // Positions done and the total
+    progress: (usize, usize),
+    analysis: Option<GameAnalysis>,
+}
+
+impl GameAnalyzer {
+    fn start(start: &State, state: &State, limits: SearchLimits) -> GameAnalyzer {
+        let (sender, receiver) = mpsc::channel();
+        let (start, game) = (start.clone(), state.clone());
+        thread::spawn(move || {
+            let analysis = analysis::analyze_game(&start, &game, &limits, &mut |done, total| {
+                let _res = sender.send(AnalyzerEvent::Progress(done, total));
+            });
+            let _res = sender.send(AnalyzerEvent::Done(analysis));
+        });
+        GameAnalyzer {
+            receiver,
+            progress: (0, state.get_history().len() + 1),
+            analysis: None,
+        }
+    }
+
+    fn poll(&mut self, redraw: &mut bool) {
+        for event in self.receiver.try_iter() {
+            match event {
+                AnalyzerEvent::Progress(done, total) => self.progress = (done, total),
+                AnalyzerEvent::Done(analysis) => {
+                    print_analysis(&analysis);
+                    self.analysis = Some(analysis);
+                }
+            }
+            *redraw = true;
+        }
+    }
+
+    // The number of positions in the evaluation graph
+    fn analyzed(&self) -> usize {
+        self.analysis.as_ref().map_or(0, |analysis| analysis.evaluations.len())
+    }
+
+    fn evaluation(&self, ply: usize) -> Option<Evaluation> {
+        self.analysis.as_ref().and_then(|analysis| analysis.evaluations.get(ply).copied())
+    }
+}
+
+fn print_analysis(analysis: &GameAnalysis) {
+    for (index, analysis) in analysis.moves.iter().enumerate() {
+        if let Some(classification) = analysis.classification {
+            let number = format!("{}{}", index / 2 + 1, if index % 2 == 0 { "." } else { "..." });
+            let best = analysis.best_san.as_deref().unwrap_or("?");
+            println!("{} {}{} is a {}, {} was better", number, analysis.san, classification.symbol(), classification.to_string(), best);
+        }
+    }
+    for player in [Player::White, Player::Black] {
+        let count = |classification| analysis.count(player, classification);
+        let name = if player == Player::White { "White" } else { "Black" };
+        println!("{}: {} inaccuracies, {} mistakes, {} blunders, {:.0} centipawns lost per move", name,
+                 count(Classification::Inaccuracy), count(Classification::Mistake), count(Classification::Blunder),
+                 analysis.average_loss(player));
+    }
+}
+
+fn session_title(state: &State, session: Option<&Session>) -> String {
+    match session {
+        Some(session) if session.is_connected() => {
+            let color = match session.get_local_player() {
+                Player::White => "white",
+                Player::Black => "black",
+            };
+            format!("{} - playing {}", window_title(state), color)
+        }
+        Some(_) => format!("{} - waiting for the other player", window_title(state)),
+        None => window_title(state),
+    }
+}
+
+fn window_title(state: &State) -> String {
+    match (state.is_chess960(), state.get_chess960_id()) {
+        (true, Some(id)) => format!("Chess960 #{}", id),
+        (true, None) => String::from("Chess960"),
+        (false, _) if state.get_variant().name() != Standard.name() => state.get_variant().name().to_string(),
+        (false, _) => String::from("Chess"),
+    }
+}
+
 fn handle_keydown(keycode: Keycode) -> bool {
     if keycode == Keycode::Escape {
         true
@@ -62,15 +1025,26 @@ fn handle_keydown(keycode: Keycode) -> bool {
     }
 }
 
+/// What the info board shows, gathered anew for every frame.
+struct InfoBoard<'a> {
+    state: &'a State,
+    tree: &'a GameTree,
+    move_list: &'a MoveList,
+    explorer: Option<&'a ExplorerView>,
+    clock: Option<&'a Clock>,
+    drag: Option<PocketDrag>,
+}
+
 struct Graphics {
     canvas: Canvas<Window>,
     texture_creator: TextureCreator<WindowContext>,
     textures: Vec<Texture>,
     layout: Layout,
+    theme: Theme,
 }
 
 impl Graphics {
-    fn new(canvas: Canvas<Window>, layout: Layout) -> Self {
+    fn new(canvas: Canvas<Window>, layout: Layout, theme: Theme) -> Self {
         let texture_creator = canvas.texture_creator();        
 
         let mut graphics = Graphics {
@@ -78,44 +1052,102 @@ impl Graphics {
             texture_creator: texture_creator,
             textures: Vec::new(),
             layout: layout,
+            theme,
         };
         
         graphics.canvas.set_draw_color(pixels::Color::RGB(0, 0, 0));
         graphics.canvas.clear();
-        graphics.load_textures();
+        if let Err(err) = graphics.load_textures() {
+            println!("Failed to load texture: {}", err);
+            std::process::exit(1);
+        }
         graphics
     }
 
-    fn load_textures(&mut self) {
+    fn set_title(&mut self, title: &str) {
+        let _res = self.canvas.window_mut().set_title(title);
+    }
+
+    fn set_layout(&mut self, layout: Layout) {
+        let resized = layout.square_size != self.layout.square_size;
+        self.layout = layout;
+
+        // Vector pieces are rasterized at the square size, so they have to be redone
+        if resized && self.theme.piece_format == PieceFormat::Svg {
+            if let Err(err) = self.load_textures() {
+                println!("Failed to load texture: {}", err);
+            }
+        }
+    }
+
+    /// Switches to another theme. The current one is kept if the pieces cannot be loaded.
+    fn set_theme(&mut self, theme: Theme) -> Result<(), String> {
+        let old_theme = std::mem::replace(&mut self.theme, theme);
+        if let Err(err) = self.load_textures() {
+            self.theme = old_theme;
+            return Err(err);
+        }
+        Ok(())
+    }
+
+    fn output_size(&self) -> (u32, u32) {
+        self.canvas.output_size().unwrap_or((SCREEN_WIDTH, SCREEN_HEIGHT))
+    }
+
+    /// Drawable pixels per window coordinate, which is above 1 on high DPI displays.
+    fn pixel_ratio(&self) -> f32 {
+        let (window_width, _) = self.canvas.window().size();
+        let (output_width, _) = self.output_size();
+        if window_width == 0 {
+            1.0
+        } else {
+            output_width as f32 / window_width as f32
+        }
+    }
+
+    fn load_textures(&mut self) -> Result<(), String> {
         let list = [
             "white_king", "white_queen", "white_rook", "white_bishop", "white_knight", "white_pawn",
             "black_king", "black_queen", "black_rook", "black_bishop", "black_knight", "black_pawn"
         ];
-    
+
+        let mut textures = Vec::new();
         for name in list {
-            let t = self.texture_creator.load_texture(Path::new("images").join(format!("{}{}", name, ".png")));
-            match t {
-                Ok(texture) => self.textures.push(texture),
-                Err(err) => {
-                    println!("Failed to load texture: {}", err);
-                    std::process::exit(1);
+            let path = self.theme.piece_path(name);
+            let texture = match self.theme.piece_format {
+                PieceFormat::Png => self.texture_creator.load_texture(&path),
+                PieceFormat::Svg => {
+                    let svg = fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+                    let svg = theme::resize_svg(&svg, self.layout.square_size);
+                    self.texture_creator.load_texture_bytes(svg.as_bytes())
                 }
             };
+            textures.push(texture.map_err(|e| format!("{}: {}", path.display(), e))?);
         };
+
+        for texture in std::mem::replace(&mut self.textures, textures) {
+            unsafe {
+                texture.destroy();
+            }
+        }
+        Ok(())
     }
 
     fn draw(&mut self, state: &State, moving_from: &Option<Pos>) {
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+
         for y in (0..8).rev() {
             for x in 0..8 {
                 let mut square_color = if (x + y) % 2 == 1 {
-                    Color::RGB(255, 206, 158)
+                    self.theme.light
                 } else {
-                    Color::RGB(209, 139, 71)
+                    self.theme.dark
                 };
     
                 if let Some(from_pos) = moving_from {
                     if from_pos.x == x && from_pos.y == y {
-                        square_color = Color::RGB(255, 0,0 );
+                        square_color = self.theme.highlight;
                     }
                 }
     
@@ -162,6 +1194,7 @@ impl Graphics {
     
         let TextureQuery { width, height, .. } = texture.query();
     
+        let size = ((size as f32) * self.layout.scale).round().max(1.0) as u32;
         let frac = width / height;
         let width = size * frac;
         
@@ -175,36 +1208,351 @@ impl Graphics {
         }
     }
 
-    fn draw_current_player(&mut self, font: &Font, game_status: GameStatus) {
-        let x_pos = self.layout.top_left_coord.x + (self.layout.square_size as i32) * 8 + 5;
-        let y_pos = self.layout.top_left_coord.y + 5;
-        let str = game_status.to_string();
-        self.draw_text(str, font, Pos::new(x_pos, y_pos), 20, Color::RGBA(255, 255, 255, 255));
+    fn draw_current_player(&mut self, font: &Font, game_status: GameStatus, state: &State) {
+        let x_pos = self.layout.info_coord.x + self.layout.scaled(5);
+        let y_pos = self.layout.info_coord.y + self.layout.scaled(5);
+        let str = match state.get_termination() {
+            Some(termination) => format!("{} ({})", game_status.to_string(), termination.to_string()),
+            None => game_status.to_string().to_string(),
+        };
+        self.draw_text(&str, font, Pos::new(x_pos, y_pos), 20, Color::RGBA(255, 255, 255, 255));
+    }
+
+    fn draw_move_message(&mut self, font: &Font, move_result: Option<Result<MoveSuccess, MoveError>>,
+                         action_error: Option<ActionError>, state: &State) {
+        let x_pos = self.layout.info_coord.x + self.layout.scaled(5);
+        let y_pos = self.layout.info_coord.y + self.layout.scaled(40);
+
+        if let Some(Err(msg)) = &move_result {
+            self.draw_text(msg.to_string(), font, Pos::new(x_pos, y_pos), 20, Color::RGBA(255, 0, 0, 255));
+        } else if let Some(err) = action_error {
+            self.draw_text(err.to_string(), font, Pos::new(x_pos, y_pos), 20, Color::RGBA(255, 0, 0, 255));
+        } else if let (true, Some(player)) = (state.get_game_running(), state.get_draw_offered_by()) {
+            let str = match player {
+                Player::White => "White offers a draw",
+                Player::Black => "Black offers a draw",
+            };
+            self.draw_text(str, font, Pos::new(x_pos, y_pos), 20, Color::RGBA(255, 255, 0, 255));
+        }
+    }
+
+    // The puzzle and the user's rating under the status line, and the feedback
+    // on the last move where the move errors go
+    fn draw_puzzle(&mut self, font: &Font, trainer: &PuzzleTrainer, action_error: Option<ActionError>) {
+        let x_pos = self.layout.info_coord.x + self.layout.scaled(5);
+        if let (Some(feedback), None) = (trainer.feedback.as_ref(), action_error) {
+            let pos = Pos::new(x_pos, self.layout.info_coord.y + self.layout.scaled(40));
+            self.draw_text(&feedback.text(), font, pos, 20, feedback.color());
+        }
+        let pos = Pos::new(x_pos, self.layout.info_coord.y + self.layout.scaled(OPENING_TOP));
+        self.draw_text(&trainer.summary(), font, pos, 16, Color::RGBA(160, 160, 160, 255));
+    }
+
+    fn draw_opening(&mut self, font: &Font, opening: Option<&Opening>) {
+        if let Some(opening) = opening {
+            let pos = Pos::new(self.layout.info_coord.x + self.layout.scaled(5), self.layout.info_coord.y + self.layout.scaled(OPENING_TOP));
+            self.draw_text(&format!("{} {}", opening.eco, opening.name), font, pos, 16, Color::RGBA(160, 160, 160, 255));
+        }
+    }
+
+    fn draw_action_buttons(&mut self, font: &Font, state: &State) {
+        let layout = self.layout;
+        for (index, action) in Action::available(state).iter().enumerate() {
+            let rect = action_button_rect(&layout, index);
+            self.canvas.set_draw_color(Color::RGB(60, 60, 120));
+            let _r = self.canvas.fill_rect(rect);
+            let text_pos = Pos::new(rect.x() + layout.scaled(5), rect.y() + layout.scaled(5));
+            self.draw_text(action.label(), font, text_pos, 20, Color::RGBA(255, 255, 255, 255));
+        }
+    }
+
+    fn draw_piece(&mut self, piece: Piece, rect: Rect) {
+        let index_offset: usize = match piece.player {
+            Player::White => 0,
+            Player::Black => 6,
+        };
+        let _res = self.canvas.copy(&self.textures[index_offset + (piece.piece_type as usize)], None, Some(rect));
+    }
+
+    fn draw_pockets(&mut self, font: &Font, state: &State, drag: Option<PocketDrag>) {
+        let layout = self.layout;
+        for player in [Player::White, Player::Black].iter().copied() {
+            for (slot, piece_type) in POCKET_PIECES.iter().copied().enumerate() {
+                let count = state.get_pocket_count(player, piece_type);
+                if count == 0 {
+                    continue;
+                }
+
+                let rect = pocket_slot_rect(&layout, player, slot);
+                let selected = drag.is_some_and(|drag| drag.piece_type == piece_type) &&
+                               player == state.get_current_player();
+                if selected {
+                    self.canvas.set_draw_color(self.theme.highlight);
+                    let _r = self.canvas.fill_rect(rect);
+                }
+                self.draw_piece(Piece { piece_type, player }, rect);
+
+                let text_x = rect.x() + rect.width() as i32;
+                let text_y = rect.y() + (rect.height() as i32 - layout.scaled(20)) / 2;
+                self.draw_text(&format!("{}", count), font, Pos::new(text_x, text_y), 20, Color::RGBA(255, 255, 255, 255));
+            }
+        }
+    }
+
+    /// Draws the piece being dragged from the pocket centered on the mouse.
+    fn draw_drag(&mut self, state: &State, drag: PocketDrag) {
+        let size = self.layout.square_size;
+        let rect = Rect::new(drag.x - size as i32 / 2, drag.y - size as i32 / 2, size, size);
+        self.draw_piece(Piece { piece_type: drag.piece_type, player: state.get_current_player() }, rect);
+        self.canvas.present();
+    }
+
+    fn draw_captured_pieces(&mut self, font: &Font, state: &State) {
+        let x_pos = self.layout.info_coord.x + self.layout.scaled(5);
+        let y_pos = self.layout.info_coord.y + self.layout.scaled(CAPTURED_TOP);
+        let icon_size = self.layout.square_size / 2;
+        let balance = state.get_material_balance();
+
+        // White captures black pieces and vice versa, so each row shows the opponent's pieces
+        let rows = [
+            (Player::White, state.get_black_eliminated().to_vec(), balance),
+            (Player::Black, state.get_white_eliminated().to_vec(), -balance),
+        ];
+
+        for (row, (player, pieces, advantage)) in rows.iter().enumerate() {
+            let row_y = y_pos + (row as i32) * (icon_size as i32 + self.layout.scaled(10));
+            let index_offset: usize = match player {
+                Player::White => 6,
+                Player::Black => 0,
+            };
+
+            let mut icon_x = x_pos;
+            for piece_type in pieces {
+                let index = index_offset + (*piece_type as usize);
+                let _res = self.canvas.copy(
+                    &self.textures[index],
+                    None,
+                    Some(Rect::new(icon_x, row_y, icon_size, icon_size))
+                );
+                icon_x += (icon_size as i32) * 2 / 3;
+            }
+
+            if *advantage > 0 {
+                let text = format!("+{}", advantage);
+                let text_y = row_y + (icon_size as i32 - self.layout.scaled(20)) / 2;
+                self.draw_text(&text, font, Pos::new(icon_x + self.layout.scaled(10), text_y), 20, Color::RGBA(255, 255, 255, 255));
+            }
+        }
+    }
+
+    fn draw_clocks(&mut self, font: &Font, clock: &Clock) {
+        let x_pos = self.layout.info_coord.x + self.layout.scaled(5);
+        let y_pos = self.layout.info_coord.y + self.layout.scaled(CLOCK_TOP);
+
+        for (column, player) in [Player::White, Player::Black].iter().enumerate() {
+            let name = match player {
+                Player::White => "White",
+                Player::Black => "Black",
+            };
+            let color = if clock.get_flagged() == Some(*player) {
+                Color::RGBA(255, 0, 0, 255)
+            } else if clock.get_running() == Some(*player) {
+                Color::RGBA(255, 255, 255, 255)
+            } else {
+                Color::RGBA(160, 160, 160, 255)
+            };
+
+            let text = format!("{} {}", name, clock::format_duration(clock.remaining(*player)));
+            let column_x = x_pos + self.layout.scaled(280) * (column as i32);
+            self.draw_text(&text, font, Pos::new(column_x, y_pos), 20, color);
+        }
+    }
+
+    fn draw_engine(&mut self, font: &Font, engine: &EngineSide) {
+        let x_pos = self.layout.info_coord.x + self.layout.scaled(5);
+        let y_pos = self.layout.info_coord.y + self.layout.scaled(ENGINE_TOP);
+        let (summary, line) = engine.describe();
+        self.draw_text(&summary, font, Pos::new(x_pos, y_pos), 20, Color::RGBA(255, 255, 255, 255));
+        if !line.is_empty() {
+            self.draw_text(&line, font, Pos::new(x_pos, y_pos + self.layout.scaled(25)), 20, Color::RGBA(160, 160, 160, 255));
+        }
     }
 
-    fn draw_move_message(&mut self, font: &Font, move_result: Result<MoveSuccess, MoveError>) {
-        let x_pos = self.layout.top_left_coord.x + (self.layout.square_size as i32) * 8 + 5;
-        let y_pos = self.layout.top_left_coord.y + 40;
+    // White's part grows from the bottom, or from the left below the board
+    fn draw_eval_bar(&mut self, evaluation: Option<Evaluation>) {
+        let bar = self.layout.eval_bar;
+        self.canvas.set_draw_color(Color::RGB(50, 50, 50));
+        let _r = self.canvas.fill_rect(bar);
+        if let Some(evaluation) = evaluation {
+            self.canvas.set_draw_color(Color::RGB(230, 230, 230));
+            let share = evaluation.white_share();
+            let white = if bar.height() > bar.width() {
+                let height = (bar.height() as f32 * share).round() as u32;
+                Rect::new(bar.x(), bar.bottom() - height as i32, bar.width(), height)
+            } else {
+                Rect::new(bar.x(), bar.y(), (bar.width() as f32 * share).round() as u32, bar.height())
+            };
+            if white.width() > 0 && white.height() > 0 {
+                let _r = self.canvas.fill_rect(white);
+            }
+        }
+        self.canvas.present();
+    }
 
-        let str = match &move_result {
-            Ok(_) => return,
-            Err(msg) => msg.to_string(),
+    // The evaluation after every move with white's part from the bottom, the
+    // bad moves marked and the position on the board as a line
+    fn draw_analysis(&mut self, font: &Font, analyzer: Option<&GameAnalyzer>, shown_ply: Option<usize>) {
+        let graph = self.layout.graph_rect();
+        let gray = Color::RGBA(160, 160, 160, 255);
+        let text_pos = Pos::new(graph.x(), graph.y() + self.layout.scaled(5));
+        let analysis = match analyzer {
+            None => return self.draw_text("Press A to analyze the game", font, text_pos, 20, gray),
+            Some(GameAnalyzer { analysis: None, progress: (done, total), .. }) => {
+                return self.draw_text(&format!("Analyzing {}/{}", done, total), font, text_pos, 20, gray);
+            }
+            Some(GameAnalyzer { analysis: Some(analysis), .. }) => analysis,
         };
 
-        self.draw_text(str, font, Pos::new(x_pos, y_pos), 20, Color::RGBA(255, 0, 0, 255));
+        self.canvas.set_draw_color(Color::RGB(50, 50, 50));
+        let _r = self.canvas.fill_rect(graph);
+        let last = analysis.evaluations.len().saturating_sub(1).max(1) as f32;
+        let x_of = |ply: usize| graph.x() + (ply as f32 / last * (graph.width() - 1) as f32).round() as i32;
+
+        self.canvas.set_draw_color(Color::RGB(230, 230, 230));
+        for column in 0..graph.width() as i32 {
+            let position = column as f32 / (graph.width() - 1).max(1) as f32 * last;
+            let before = analysis.evaluations[position.floor() as usize].white_share();
+            let after = analysis.evaluations.get(position.ceil() as usize).map_or(before, Evaluation::white_share);
+            let share = before + (after - before) * position.fract();
+            let height = (graph.height() as f32 * share).round() as i32;
+            let _r = self.canvas.draw_line((graph.x() + column, graph.bottom() - 1), (graph.x() + column, graph.bottom() - height));
+        }
+        self.canvas.set_draw_color(Color::RGB(120, 120, 120));
+        let middle = graph.y() + graph.height() as i32 / 2;
+        let _r = self.canvas.draw_line((graph.x(), middle), (graph.right() - 1, middle));
+
+        let marker = self.layout.scaled(3).max(1);
+        for (index, analysis) in analysis.moves.iter().enumerate() {
+            let color = match analysis.classification {
+                Some(Classification::Inaccuracy) => Color::RGB(230, 200, 40),
+                Some(Classification::Mistake) => Color::RGB(230, 130, 30),
+                Some(Classification::Blunder) => Color::RGB(210, 40, 40),
+                None => continue,
+            };
+            self.canvas.set_draw_color(color);
+            let _r = self.canvas.fill_rect(Rect::new(x_of(index + 1) - marker, middle - marker, marker as u32 * 2, marker as u32 * 2));
+        }
+
+        if let Some(shown_ply) = shown_ply.filter(|&ply| ply < analysis.evaluations.len()) {
+            self.canvas.set_draw_color(Color::RGB(80, 120, 220));
+            let x = x_of(shown_ply);
+            let _r = self.canvas.draw_line((x, graph.y()), (x, graph.bottom() - 1));
+        }
+        self.canvas.present();
+    }
+
+    fn draw_move_list(&mut self, font: &Font, tree: &GameTree, move_list: &MoveList) {
+        let layout = self.layout;
+        let x_pos = layout.info_coord.x;
+        let y_pos = layout.info_coord.y + layout.scaled(MOVE_LIST_TOP);
+        let row_height = layout.scaled(MOVE_LIST_ROW_HEIGHT);
+        let white = Color::RGBA(255, 255, 255, 255);
+        let gray = Color::RGBA(160, 160, 160, 255);
+        let selected = move_list.shown(tree);
+
+        let rows = move_rows(tree);
+        let visible = rows.iter().skip(move_list.scroll).take(MoveList::visible_rows(&layout));
+        for (index, row) in visible.enumerate() {
+            let row_y = y_pos + (index as i32) * row_height;
+            let text_y = row_y + layout.scaled(2);
+            if !row.number.is_empty() {
+                self.draw_text(&row.number, font, Pos::new(x_pos + layout.scaled(5), text_y), 20, gray);
+            }
+
+            for (id, text, cell_x, width) in &row.cells {
+                let cell_x = x_pos + layout.scaled(*cell_x);
+                if *id == selected {
+                    self.canvas.set_draw_color(Color::RGB(80, 80, 80));
+                    let _r = self.canvas.fill_rect(Rect::new(cell_x, row_y, layout.scaled(*width) as u32, row_height as u32));
+                }
+                let color = if tree.is_main_line(*id) { white } else { gray };
+                self.draw_text(text, font, Pos::new(cell_x + layout.scaled(5), text_y), 20, color);
+            }
+        }
+
+        if move_list.viewing.is_some() {
+            let button_height = layout.scaled(LIVE_BUTTON_HEIGHT);
+            let button_y = layout.info_coord.y + layout.info_height as i32 - layout.scaled(LIVE_BUTTON_HEIGHT + 5);
+            self.canvas.set_draw_color(Color::RGB(60, 60, 120));
+            let _r = self.canvas.fill_rect(Rect::new(x_pos + layout.scaled(5), button_y, layout.scaled(280) as u32, button_height as u32));
+            self.draw_text("Back to live position", font, Pos::new(x_pos + layout.scaled(10), button_y + layout.scaled(5)), 20, white);
+        }
     }
 
-    fn draw_info_board(&mut self, font: &Font, move_result: Result<MoveSuccess, MoveError>, game_status: GameStatus) {
-        let x_pos = self.layout.top_left_coord.x + (self.layout.square_size as i32) * 8;
-        let y_pos = self.layout.top_left_coord.y;
-        let width = SCREEN_WIDTH - (x_pos as u32);
-        let height = SCREEN_HEIGHT;
+    // Every move played from the shown position, with its share of the games, the results and the
+    // average rating of the players
+    fn draw_explorer(&mut self, font: &Font, explorer: &ExplorerView) {
+        let layout = self.layout;
+        let x_pos = layout.info_coord.x;
+        let y_pos = layout.info_coord.y + layout.scaled(MOVE_LIST_TOP);
+        let row_height = layout.scaled(MOVE_LIST_ROW_HEIGHT);
+        let white = Color::RGBA(255, 255, 255, 255);
+        let gray = Color::RGBA(160, 160, 160, 255);
+        let columns = [5, EXPLORER_GAMES_COLUMN, EXPLORER_RESULTS_COLUMN, EXPLORER_RATING_COLUMN];
+
+        let header = ["Move", "Games", "White / Draw / Black", "Rating"];
+        for (text, column) in header.iter().zip(columns.iter()) {
+            self.draw_text(text, font, Pos::new(x_pos + layout.scaled(*column), y_pos + layout.scaled(2)), 20, gray);
+        }
+        if explorer.moves.is_empty() {
+            self.draw_text("No games reached this position", font, Pos::new(x_pos + layout.scaled(5), y_pos + row_height), 20, gray);
+            return;
+        }
+
+        let total: u32 = explorer.moves.iter().map(|(_, stats)| stats.games()).sum();
+        let percent = |count: u32, games: u32| (count as f32 * 100.0 / games.max(1) as f32).round();
+        let rows = MoveList::visible_rows(&layout).saturating_sub(1);
+        for (index, (san, stats)) in explorer.moves.iter().take(rows).enumerate() {
+            let text_y = y_pos + (index as i32 + 1) * row_height + layout.scaled(2);
+            let games = stats.games();
+            let texts = [
+                san.clone(),
+                format!("{} ({}%)", games, percent(games, total)),
+                format!("{}% / {}% / {}%", percent(stats.white_wins, games), percent(stats.draws, games), percent(stats.black_wins, games)),
+                stats.average_rating().map_or(String::from("-"), |rating| rating.to_string()),
+            ];
+            for (text, column) in texts.iter().zip(columns.iter()) {
+                self.draw_text(text, font, Pos::new(x_pos + layout.scaled(*column), text_y), 20, white);
+            }
+        }
+    }
+
+    fn draw_info_board(&mut self, font: &Font, move_result: Option<Result<MoveSuccess, MoveError>>,
+                       action_error: Option<ActionError>, info: &InfoBoard) {
+        let state = info.state;
+        let x_pos = self.layout.info_coord.x;
+        let y_pos = self.layout.info_coord.y;
+        let width = self.layout.info_width.max(1);
+        let height = self.layout.info_height.max(1);
     
         self.canvas.set_draw_color(Color::RGBA(0, 0, 0, 255));
         let _r = self.canvas.fill_rect(Rect::new(x_pos, y_pos, width, height));
         
-        self.draw_current_player(font, game_status);
-        self.draw_move_message(font, move_result);
+        self.draw_current_player(font, state.get_game_status(), state);
+        self.draw_move_message(font, move_result, action_error, state);
+        if let Some(clock) = info.clock {
+            self.draw_clocks(font, clock);
+        }
+        if state.get_variant().has_drops() {
+            self.draw_pockets(font, state, info.drag);
+        } else {
+            self.draw_captured_pieces(font, state);
+        }
+        self.draw_action_buttons(font, state);
+        match info.explorer.filter(|explorer| explorer.shown) {
+            Some(explorer) => self.draw_explorer(font, explorer),
+            None => self.draw_move_list(font, info.tree, info.move_list),
+        }
     }
 }
 
@@ -219,6 +1567,8 @@ fn create_window() -> Result<(Window, Sdl), String> {
             SCREEN_HEIGHT,
         )
         .position_centered()
+        .resizable()
+        .allow_highdpi()
         .opengl()
         .build()
         .map_err(|e| e.to_string())?;
@@ -226,27 +1576,96 @@ fn create_window() -> Result<(Window, Sdl), String> {
 }
 
 fn main() -> Result<(), String> {
-    let layout = Layout::new();
     let (window, sdl_context) = create_window()?;
     let canvas = window.into_canvas().build().map_err(|e| e.to_string())?;
-    let mut graphics = Graphics::new(canvas, layout);
-    let mut state = State::new();
+    let (width, height) = canvas.output_size()?;
+    let mut layout = Layout::new(width, height);
+    let args: Vec<String> = std::env::args().collect();
+    let mut themes = ThemeList::new(get_arg(&args, "--theme"));
+    let mut graphics = Graphics::new(canvas, layout, themes.current_theme());
+    let mut state = create_state(&args)?;
+    // `--pgn <file>` opens the first game of a PGN file, with its variations
+    let mut tree = match get_arg(&args, "--pgn") {
+        Some(path) => load_pgn(path)?,
+        None => GameTree::new(state.clone()),
+    };
+    state = tree.get(tree.main_line_end()).unwrap().position.clone();
+    let mut session = create_session(&args, &state)?;
+    let mut engine = create_engine(&args, &state)?;
+    if session.is_some() && engine.is_some() {
+        return Err(String::from("An engine cannot play in a network game"));
+    }
+    let mut trainer = create_puzzle_trainer(&args)?;
+    let mut explorer = create_explorer(&args)?;
+    if trainer.is_some() && (session.is_some() || engine.is_some()) {
+        return Err(String::from("Puzzles cannot be solved in a network game or against an engine"));
+    }
+    graphics.set_title(&session_title(&state, session.as_ref()));
+    if let Some(trainer) = trainer.as_ref() {
+        trainer.show(&mut state, &mut tree);
+        graphics.set_title(&trainer.title());
+    }
     let mut moving_from: Option<Pos> = None;
+    let mut drag: Option<PocketDrag> = None;
+    let mut move_list = MoveList::new();
+    let mut move_result: Option<Result<MoveSuccess, MoveError>> = None;
+    let mut action_error: Option<ActionError> = None;
+
+    // The clocks are only shown when a time control is given, e.g. `--time 40/5400+30:1800+30`
+    let mut clock = match get_arg(&args, "--time") {
+        Some(control) => {
+            let control = TimeControl::parse(control).ok_or(format!("Invalid time control: {}", control))?;
+            let mut clock = Clock::new(control, Box::new(SystemTimeSource::new()));
+            clock.start(Player::White);
+            Some(clock)
+        }
+        None => None,
+    };
+    let mut last_clock_draw = Instant::now();
+
+    // The A key analyzes the game with `--analysis-time` milliseconds per position
+    let analysis_time = match get_arg(&args, "--analysis-time") {
+        Some(millis) => Duration::from_millis(millis.parse().map_err(|_| format!("Invalid analysis time: {}", millis))?),
+        None => Duration::from_millis(300),
+    };
+    let mut analyzer: Option<GameAnalyzer> = None;
 
-    let font_path = "ubuntu.ttf";
     let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string()).unwrap();
-    let mut font = ttf_context.load_font(font_path, 128).unwrap();
+    let mut font = ttf_context.load_font(&graphics.theme.font, 128)?;
     font.set_style(sdl2::ttf::FontStyle::BOLD);
 
+    if let Some(explorer) = explorer.as_mut() {
+        explorer.update(&state);
+    }
     graphics.draw(&state, &moving_from);
+    let info = InfoBoard { state: &state, tree: &tree, move_list: &move_list, explorer: explorer.as_ref(), clock: clock.as_ref(), drag };
+    graphics.draw_info_board(&font, move_result, action_error, &info);
+    graphics.draw_opening(&font, tree.get(tree.main_line_end()).unwrap().get_opening());
+    if let Some(trainer) = trainer.as_ref() {
+        graphics.draw_puzzle(&font, trainer, action_error);
+    }
+    graphics.draw_eval_bar(None);
+    graphics.draw_analysis(&font, None, None);
 
     let mut events = sdl_context.event_pump()?;
 
     'main: loop {
+        let mut redraw = false;
+
         for event in events.poll_iter() {
+            // Variations can only be added when the game is not played against someone or on the clock
+            let can_edit = session.is_none() && engine.is_none() && clock.is_none() && trainer.is_none();
+
             match event {
                 Event::Quit { .. } => break 'main,
 
+                Event::Window { win_event: WindowEvent::SizeChanged(..), .. } => {
+                    let (width, height) = graphics.output_size();
+                    layout = Layout::new(width, height);
+                    graphics.set_layout(layout);
+                    move_list.scroll_by(&layout, 0, &tree);
+                }
+
                 Event::KeyDown {
                     keycode: Some(keycode),
                     ..
@@ -254,21 +1673,245 @@ fn main() -> Result<(), String> {
                     if handle_keydown(keycode) {
                         break 'main;
                     }
+                    if keycode == Keycode::T {
+                        let switched = match themes.next_theme() {
+                            Some(theme) => theme.and_then(|theme| {
+                                let mut new_font = ttf_context.load_font(&theme.font, 128)?;
+                                new_font.set_style(sdl2::ttf::FontStyle::BOLD);
+                                graphics.set_theme(theme)?;
+                                font = new_font;
+                                Ok(())
+                            }),
+                            None => Ok(()),
+                        };
+                        if let Err(err) = switched {
+                            println!("Failed to switch theme: {}", err);
+                        }
+                    }
+                    if keycode == Keycode::A {
+                        let start = &tree.get(tree.root()).unwrap().position;
+                        analyzer = Some(GameAnalyzer::start(start, &state, SearchLimits::time(analysis_time)));
+                    }
+                    if keycode == Keycode::F {
+                        println!("{}", tree.get(move_list.shown(&tree)).unwrap().position.to_fen());
+                    }
+                    if keycode == Keycode::P {
+                        println!("{}", tree.to_pgn(&[]));
+                    }
+                    if let (Keycode::E, Some(explorer)) = (keycode, explorer.as_mut()) {
+                        explorer.shown = !explorer.shown;
+                    }
+                    if let Some(trainer) = trainer.as_mut() {
+                        match keycode {
+                            Keycode::N => {
+                                trainer.next();
+                                trainer.show(&mut state, &mut tree);
+                                moving_from = None;
+                                move_list.back_to_live(&layout, &tree);
+                            }
+                            Keycode::H => trainer.show_hint(),
+                            _ => {}
+                        }
+                        graphics.set_title(&trainer.title());
+                    }
+                    handle_history_keydown(&layout, keycode, &mut move_list, &tree);
+                    if can_edit && handle_variation_keydown(&layout, keycode, &mut move_list, &mut tree) {
+                        follow_main_line(&tree, &mut state);
+                    }
+                }
+
+                Event::MouseWheel { y, .. } => {
+                    move_list.scroll_by(&layout, -y, &tree);
                 }
 
                 Event::MouseButtonDown { x, y, .. } => {
-                    let res = handle_mouse_click(&layout, &mut state, &mut moving_from, x, y);
-                    graphics.draw(&state, &moving_from);
+                    // Mouse coordinates are in window units, which differ from pixels on high DPI displays
+                    let ratio = graphics.pixel_ratio();
+                    let x = (x as f32 * ratio) as i32;
+                    let y = (y as f32 * ratio) as i32;
 
-                    if let Some(res) = res {
-                        graphics.draw_info_board(&font, res, state.get_game_status());
+                    if let Some(action) = find_action_button(&layout, &state, x, y) {
+                        move_result = None;
+                        action_error = action.perform(&mut state).err();
+                    } else if let (None, Some(piece_type)) = (move_list.viewing, find_pocket_piece(&layout, &state, x, y)) {
+                        moving_from = None;
+                        drag = Some(PocketDrag { piece_type, x, y });
+                    } else if layout.info_board_contains(x, y) {
+                        drag = None;
+                        let analyzed = analyzer.as_ref().map_or(0, GameAnalyzer::analyzed);
+                        let on_explorer = explorer.as_ref().is_some_and(|explorer| explorer.shown)
+                            && y >= layout.info_coord.y + layout.scaled(MOVE_LIST_TOP);
+                        if !on_explorer {
+                            handle_info_board_click(&layout, &mut move_list, &tree, analyzed, x, y);
+                        }
+                    } else if !layout.board_contains(x, y) {
+                        continue;
+                    } else if let (Some(shown), true) = (move_list.viewing, can_edit) {
+                        // A move at an earlier position starts a variation there
+                        let mut position = tree.get(shown).unwrap().position.clone();
+                        if let Some(Ok(_)) = handle_mouse_click(&layout, &mut position, &mut moving_from, x, y) {
+                            let mv = position.get_history().last().unwrap().mv;
+                            if let Ok(id) = tree.add_move(shown, mv) {
+                                move_list.view(&layout, &tree, id);
+                            }
+                        }
+                    } else if move_list.viewing.is_some() {
+                        // The board is read only while looking at an earlier position
+                        moving_from = None;
+                    } else if let Some(trainer) = trainer.as_mut() {
+                        // The move is checked against the solution before it is played
+                        let mut position = state.clone();
+                        if let Some(Ok(_)) = handle_mouse_click(&layout, &mut position, &mut moving_from, x, y) {
+                            trainer.try_move(position.get_history().last().unwrap().mv);
+                            trainer.show(&mut state, &mut tree);
+                            move_list.back_to_live(&layout, &tree);
+                            graphics.set_title(&trainer.title());
+                        }
+                    } else if session.as_ref().is_some_and(|session| !session.can_move(&state))
+                        || engine.as_ref().is_some_and(|engine| !engine.can_move(&state)) {
+                        // Only the local player's pieces can be moved in a network game or against an engine
+                        moving_from = None;
+                        drag = None;
+                    } else if let Some(selected) = drag.take() {
+                        let res = handle_drop(&layout, &mut state, selected, x, y);
+                        record_move(&res, &mut state, &mut tree, &mut move_list, &layout, clock.as_mut(), session.as_mut());
+                        move_result = Some(res);
+                        action_error = None;
+                    } else {
+                        let res = handle_mouse_click(&layout, &mut state, &mut moving_from, x, y);
+                        if let Some(res) = res {
+                            record_move(&res, &mut state, &mut tree, &mut move_list, &layout, clock.as_mut(), session.as_mut());
+                            move_result = Some(res);
+                            action_error = None;
+                        }
                     }
                 }
 
-                _ => {}
+                Event::MouseMotion { x, y, .. } => {
+                    let ratio = graphics.pixel_ratio();
+                    match drag.as_mut() {
+                        Some(drag) => {
+                            drag.x = (x as f32 * ratio) as i32;
+                            drag.y = (y as f32 * ratio) as i32;
+                        }
+                        None => continue,
+                    }
+                }
+
+                Event::MouseButtonUp { x, y, .. } => {
+                    let ratio = graphics.pixel_ratio();
+                    let x = (x as f32 * ratio) as i32;
+                    let y = (y as f32 * ratio) as i32;
+
+                    // Releasing a dragged pocket piece over the board drops it, anywhere
+                    // else it stays selected so the square can be clicked instead
+                    let may_move = session.as_ref().is_none_or(|session| session.can_move(&state))
+                        && engine.as_ref().is_none_or(|engine| engine.can_move(&state));
+                    match drag {
+                        Some(selected) if layout.board_contains(x, y) && may_move => {
+                            drag = None;
+                            let res = handle_drop(&layout, &mut state, selected, x, y);
+                            record_move(&res, &mut state, &mut tree, &mut move_list, &layout, clock.as_mut(), session.as_mut());
+                            move_result = Some(res);
+                            action_error = None;
+                        }
+                        _ => continue,
+                    }
+                }
+
+                _ => continue,
+            }
+
+            redraw = true;
+        }
+
+        if let Some(session) = session.as_mut() {
+            for event in session.poll(&mut state) {
+                match event {
+                    NetEvent::Move(res) => {
+                        record_move(&Ok(res), &mut state, &mut tree, &mut move_list, &layout, clock.as_mut(), None);
+                        move_result = Some(Ok(res));
+                        action_error = None;
+                    }
+                    NetEvent::Synced => {
+                        if let Some(start) = session.get_start_position() {
+                            tree = GameTree::from_game(start, &state);
+                        }
+                        moving_from = None;
+                        drag = None;
+                        move_list.back_to_live(&layout, &tree);
+                    }
+                    NetEvent::Error(err) => println!("{}", err.to_string()),
+                    NetEvent::Connected | NetEvent::Disconnected => {}
+                }
+                graphics.set_title(&session_title(&state, Some(session)));
+                redraw = true;
             }
         }
+
+        if let Some(engine) = engine.as_mut() {
+            if let Some(mv) = engine.poll(&mut redraw) {
+                moving_from = None;
+                let res = state.play_move(mv);
+                if let Err(err) = res {
+                    println!("The engine played an illegal move {}: {}", mv.to_uci(), err.to_string());
+                }
+                record_move(&res, &mut state, &mut tree, &mut move_list, &layout, clock.as_mut(), None);
+                move_result = Some(res);
+                action_error = None;
+                redraw = true;
+            }
+            engine.sync(&state, clock.as_ref());
+        }
+
+        if let Some(analyzer) = analyzer.as_mut() {
+            analyzer.poll(&mut redraw);
+        }
+
+        if let Some(clock) = clock.as_mut() {
+            if !state.get_game_running() {
+                clock.stop();
+            } else if let Some(flagged) = clock.check_flag() {
+                state.lose_on_time(flagged);
+                redraw = true;
+            }
+
+            if clock.get_running().is_some() && last_clock_draw.elapsed() >= Duration::from_millis(100) {
+                redraw = true;
+            }
+        }
+
+        if redraw {
+            let shown = match move_list.viewing {
+                Some(id) => &tree.get(id).unwrap().position,
+                None => &state,
+            };
+            if let Some(explorer) = explorer.as_mut() {
+                explorer.update(shown);
+            }
+            graphics.draw(shown, &moving_from);
+            let info = InfoBoard { state: &state, tree: &tree, move_list: &move_list, explorer: explorer.as_ref(), clock: clock.as_ref(), drag };
+            graphics.draw_info_board(&font, move_result, action_error, &info);
+            graphics.draw_opening(&font, tree.get(move_list.shown(&tree)).unwrap().get_opening());
+            if let Some(trainer) = trainer.as_ref() {
+                graphics.draw_puzzle(&font, trainer, action_error);
+            }
+            if let Some(engine) = engine.as_ref() {
+                graphics.draw_engine(&font, engine);
+            }
+            let shown_ply = tree.main_line().iter().position(|&id| id == move_list.shown(&tree));
+            let evaluation = analyzer.as_ref().zip(shown_ply).and_then(|(analyzer, ply)| analyzer.evaluation(ply))
+                .or_else(|| engine.as_ref().filter(|_| move_list.viewing.is_none()).and_then(EngineSide::evaluation));
+            graphics.draw_eval_bar(evaluation);
+            graphics.draw_analysis(&font, analyzer.as_ref(), shown_ply);
+            if let Some(drag) = drag {
+                graphics.draw_drag(&state, drag);
+            }
+            last_clock_draw = Instant::now();
+        }
+
+        std::thread::sleep(Duration::from_millis(10));
     }
 
     Ok(())
-}
\ No newline at end of file
+}
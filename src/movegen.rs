@@ -0,0 +1,215 @@
+use crate::castling::back_rank;
+use crate::{Move, PieceType, Player, Pos, State};
+
+pub(crate) const KNIGHT_STEPS: [(i32, i32); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+pub(crate) const KING_STEPS: [(i32, i32); 8] = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+pub(crate) const ROOK_DIRECTIONS: [(i32, i32); 4] = [(1, 0), (0, 1), (-1, 0), (0, -1)];
+pub(crate) const BISHOP_DIRECTIONS: [(i32, i32); 4] = [(1, 1), (-1, 1), (-1, -1), (1, -1)];
+
+fn offset(pos: Pos, (dx, dy): (i32, i32)) -> Option<Pos> {
+    let to = Pos::new(pos.x + dx, pos.y + dy);
+    if (0..8).contains(&to.x) && (0..8).contains(&to.y) {
+        Some(to)
+    } else {
+        None
+    }
+}
+
+impl State {
+    /// Every move the pieces of the player to move can make, without checking
+    /// whether the king is left in check or any other rule of the variant.
+    pub fn get_pseudo_legal_moves(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        for from in self.get_all_pieces_for_player(self.current_player) {
+            match self.get(from).unwrap().piece_type {
+                PieceType::Pawn => self.add_pawn_moves(from, &mut moves),
+                PieceType::Knight => self.add_steps(from, &KNIGHT_STEPS, &mut moves),
+                PieceType::Bishop => self.add_rays(from, &BISHOP_DIRECTIONS, &mut moves),
+                PieceType::Rook => self.add_rays(from, &ROOK_DIRECTIONS, &mut moves),
+                PieceType::Queen => {
+                    self.add_rays(from, &ROOK_DIRECTIONS, &mut moves);
+                    self.add_rays(from, &BISHOP_DIRECTIONS, &mut moves);
+                }
+                PieceType::King => {
+                    self.add_steps(from, &KING_STEPS, &mut moves);
+                    moves.extend(self.get_castling_moves(from));
+                }
+            }
+        }
+        if self.variant.has_drops() {
+            self.add_drops(&mut moves);
+        }
+        moves
+    }
+
+    fn add_drops(&self, moves: &mut Vec<Move>) {
+        const DROPPABLE: [PieceType; 5] =
+            [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::Pawn];
+        for piece_type in DROPPABLE.iter().copied() {
+            if self.get_pocket_count(self.current_player, piece_type) == 0 {
+                continue;
+            }
+            let ranks = if piece_type == PieceType::Pawn { 1..7 } else { 0..8 };
+            for y in ranks {
+                for x in 0..8 {
+                    let to = Pos::new(x, y);
+                    if self.get(to).is_none() {
+                        moves.push(Move::new_drop(piece_type, to));
+                    }
+                }
+            }
+        }
+    }
+
+    fn is_own_piece(&self, pos: Pos) -> bool {
+        self.get(pos).map(|piece| piece.player) == Some(self.current_player)
+    }
+
+    fn add_steps(&self, from: Pos, steps: &[(i32, i32)], moves: &mut Vec<Move>) {
+        for to in steps.iter().filter_map(|&step| offset(from, step)) {
+            if !self.is_own_piece(to) {
+                moves.push(Move::new(from, to));
+            }
+        }
+    }
+
+    fn add_rays(&self, from: Pos, directions: &[(i32, i32)], moves: &mut Vec<Move>) {
+        for &(dx, dy) in directions {
+            let mut distance = 1;
+            while let Some(to) = offset(from, (dx * distance, dy * distance)) {
+                if self.is_own_piece(to) {
+                    break;
+                }
+                moves.push(Move::new(from, to));
+                if self.get(to).is_some() {
+                    break;
+                }
+                distance += 1;
+            }
+        }
+    }
+
+    fn add_pawn_moves(&self, from: Pos, moves: &mut Vec<Move>) {
+        let (direction, start_rank) = match self.current_player {
+            Player::White => (1, 1),
+            Player::Black => (-1, 6),
+        };
+
+        if let Some(to) = offset(from, (0, direction)) {
+            if self.get(to).is_none() {
+                self.add_pawn_move(from, to, moves);
+                let double = Pos::new(from.x, from.y + 2 * direction);
+                if from.y == start_rank && self.get(double).is_none() {
+                    moves.push(Move::new(from, double));
+                }
+            }
+        }
+
+        for dx in [-1, 1].iter() {
+            if let Some(to) = offset(from, (*dx, direction)) {
+                let is_enemy = self.get(to).map(|piece| piece.player) == Some(self.current_player.opponent());
+                if is_enemy || self.en_passant == Some(to) {
+                    self.add_pawn_move(from, to, moves);
+                }
+            }
+        }
+    }
+
+    fn add_pawn_move(&self, from: Pos, to: Pos, moves: &mut Vec<Move>) {
+        if to.y == back_rank(self.current_player.opponent()) {
+            for promotion in self.variant.promotion_pieces() {
+                moves.push(Move::with_promotion(from, to, *promotion));
+            }
+        } else {
+            moves.push(Move::new(from, to));
+        }
+    }
+
+    /// The en passant square if the player to move has a pawn that can take
+    /// on it, which is when it matters for FEN and repetitions.
+    pub(crate) fn get_capturable_en_passant(&self) -> Option<Pos> {
+        let target = self.en_passant?;
+        let rank = match self.current_player {
+            Player::White => target.y - 1,
+            Player::Black => target.y + 1,
+        };
+        let can_capture = [target.x - 1, target.x + 1].iter().any(|&x| {
+            (0..8).contains(&x) && self.get(Pos::new(x, rank)).is_some_and(|piece| {
+                piece.player == self.current_player && piece.piece_type == PieceType::Pawn
+            })
+        });
+        if can_capture { Some(target) } else { None }
+    }
+
+    /// Number of move sequences `depth` plies deep, for comparing the move
+    /// generator against known counts. A game that has ended has no moves.
+    pub fn perft(&self, depth: u32) -> u64 {
+        if depth == 0 {
+            return 1;
+        }
+        let moves = self.get_legal_moves();
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        moves.into_iter()
+            .map(|mv| {
+                let next = self.preview_move(mv);
+                if next.variant.get_outcome(&next).is_some() {
+                    0
+                } else {
+                    next.perft(depth - 1)
+                }
+            })
+            .sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::State;
+
+    fn perft(fen: &str, depth: u32) -> u64 {
+        State::from_fen(fen).unwrap().perft(depth)
+    }
+
+    #[test]
+    fn perft_start_test() {
+        let state = State::new();
+        assert_eq!(state.perft(1), 20);
+        assert_eq!(state.perft(2), 400);
+        assert_eq!(state.perft(3), 8902);
+    }
+
+    #[test]
+    fn perft_kiwipete_test() {
+        let fen = "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1";
+        assert_eq!(perft(fen, 1), 48);
+        assert_eq!(perft(fen, 2), 2039);
+    }
+
+    #[test]
+    fn perft_en_passant_test() {
+        let fen = "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1";
+        assert_eq!(perft(fen, 1), 14);
+        assert_eq!(perft(fen, 2), 191);
+        assert_eq!(perft(fen, 3), 2812);
+    }
+
+    #[test]
+    fn perft_promotion_test() {
+        let fen = "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1";
+        assert_eq!(perft(fen, 1), 6);
+        assert_eq!(perft(fen, 2), 264);
+
+        let fen = "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8";
+        assert_eq!(perft(fen, 1), 44);
+        assert_eq!(perft(fen, 2), 1486);
+    }
+
+    #[test]
+    fn perft_chess960_test() {
+        let fen = "bqnb1rkr/pp3ppp/3ppn2/2p5/5P2/P2P4/NPP1P1PP/BQ1BNRKR w HFhf - 2 9";
+        assert_eq!(perft(fen, 1), 21);
+        assert_eq!(perft(fen, 2), 528);
+    }
+}
@@ -0,0 +1,608 @@
+//! Playing a game between two instances over TCP. One side hosts and owns the
+//! game, the other joins and gets the position from the host. Both sides check
+//! every move themselves and compare position hashes, and the host sends the
+//! whole game again when the joiner reconnects or the positions differ.
+//!
+//! The protocol is one message per line:
+//!
+//! ```text
+//! HELLO <version>        sent by both sides after connecting
+//! VARIANT <name>         host: the variant, followed by
+//! POSITION <fen>         host: the start position,
+//! MOVES <uci> ...        host: the moves played so far and
+//! START <white|black>    host: the color of the joiner, which ends the setup
+//! MOVE <uci> <hash>      a move and the position hash after it, in hex
+//! RESYNC                 joiner: asks for the setup again
+//! ERROR <message>        the other side rejected something
+//! ```
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::{variant, Move, MoveSuccess, Player, State};
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NetError {
+    NotConnected,
+    VersionMismatch,
+    InvalidMessage,
+    IllegalMove,
+    NotPeersTurn,
+    Desync,
+    Rejected,
+}
+
+impl NetError {
+    pub fn to_string(&self) -> &str {
+        match self {
+            Self::NotConnected =>    "The other player is not connected",
+            Self::VersionMismatch => "The other player uses another protocol version",
+            Self::InvalidMessage =>  "The other player sent an invalid message",
+            Self::IllegalMove =>     "The other player sent an illegal move",
+            Self::NotPeersTurn =>    "The other player moved out of turn",
+            Self::Desync =>          "The positions differ, getting the game from the host",
+            Self::Rejected =>        "The other player rejected a message",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Message {
+    Hello(u32),
+    Variant(String),
+    Position(String),
+    Moves(Vec<String>),
+    Start(Player),
+    Move(String, u64),
+    Resync,
+    Error(String),
+}
+
+impl Message {
+    pub fn to_line(&self) -> String {
+        match self {
+            Message::Hello(version) => format!("HELLO {}", version),
+            Message::Variant(name) => format!("VARIANT {}", name),
+            Message::Position(fen) => format!("POSITION {}", fen),
+            Message::Moves(moves) => format!("MOVES {}", moves.join(" ")).trim_end().to_string(),
+            Message::Start(Player::White) => String::from("START white"),
+            Message::Start(Player::Black) => String::from("START black"),
+            Message::Move(uci, hash) => format!("MOVE {} {:016x}", uci, hash),
+            Message::Resync => String::from("RESYNC"),
+            Message::Error(message) => format!("ERROR {}", message),
+        }
+    }
+
+    pub fn parse(line: &str) -> Option<Message> {
+        let line = line.trim_end();
+        let (command, rest) = match line.find(' ') {
+            Some(index) => (&line[..index], &line[index + 1..]),
+            None => (line, ""),
+        };
+
+        match command {
+            "HELLO" => rest.parse().ok().map(Message::Hello),
+            "VARIANT" => Some(Message::Variant(rest.to_string())),
+            "POSITION" => Some(Message::Position(rest.to_string())),
+            "MOVES" => Some(Message::Moves(rest.split_whitespace().map(String::from).collect())),
+            "START" => match rest {
+                "white" => Some(Message::Start(Player::White)),
+                "black" => Some(Message::Start(Player::Black)),
+                _ => None,
+            },
+            "MOVE" => {
+                let fields: Vec<&str> = rest.split_whitespace().collect();
+                match fields.as_slice() {
+                    [uci, hash] => u64::from_str_radix(hash, 16).ok().map(|hash| Message::Move(uci.to_string(), hash)),
+                    _ => None,
+                }
+            }
+            "RESYNC" if rest.is_empty() => Some(Message::Resync),
+            "ERROR" => Some(Message::Error(rest.to_string())),
+            _ => None,
+        }
+    }
+}
+
+/// Something that happened on the connection, returned by `Session::poll`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum NetEvent {
+    Connected,
+    Disconnected,
+    /// The other player's move was played.
+    Move(MoveSuccess),
+    /// The position was replaced by the host's.
+    Synced,
+    Error(NetError),
+}
+
+// The connection to the other instance. Lines are read on a separate thread so
+// polling never blocks; `None` means the connection was closed.
+struct Peer {
+    stream: TcpStream,
+    lines: Receiver<Option<String>>,
+}
+
+impl Peer {
+    fn new(stream: TcpStream) -> io::Result<Peer> {
+        stream.set_nodelay(true)?;
+        let reader = BufReader::new(stream.try_clone()?);
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in reader.lines() {
+                match line {
+                    Ok(line) => if sender.send(Some(line)).is_err() { return; },
+                    Err(_) => break,
+                }
+            }
+            let _res = sender.send(None);
+        });
+        Ok(Peer { stream, lines })
+    }
+
+    fn send(&mut self, message: &Message) -> io::Result<()> {
+        let mut line = message.to_line();
+        line.push('\n');
+        self.stream.write_all(line.as_bytes())
+    }
+}
+
+impl Drop for Peer {
+    fn drop(&mut self) {
+        let _res = self.stream.shutdown(std::net::Shutdown::Both);
+    }
+}
+
+enum Role {
+    Host { listener: TcpListener, skipped_moves: usize },
+    Join { address: SocketAddr, last_attempt: Instant },
+}
+
+// What the joiner has received of the host's setup so far
+#[derive(Default)]
+struct Setup {
+    variant: Option<String>,
+    fen: Option<String>,
+    moves: Vec<String>,
+}
+
+/// One side of a network game. The caller owns the `State` and passes it in,
+/// so the same position can be drawn and played on as in a local game.
+pub struct Session {
+    role: Role,
+    local: Player,
+    start: Option<State>,
+    peer: Option<Peer>,
+    greeted: bool,
+    synced: bool,
+    setup: Setup,
+}
+
+impl Session {
+    /// Listens on `address` for the other player. `state` is the position the
+    /// game starts from, and the host plays `local`.
+    pub fn host<A: ToSocketAddrs>(address: A, state: &State, local: Player) -> io::Result<Session> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        let role = Role::Host { listener, skipped_moves: state.get_history().len() };
+        Ok(Session { role, local, start: Some(state.clone()), peer: None, greeted: false, synced: false, setup: Setup::default() })
+    }
+
+    /// Connects to a host. The color and the position come from the host once
+    /// the connection is set up, which `poll` reports with `NetEvent::Synced`.
+    pub fn join<A: ToSocketAddrs>(address: A) -> io::Result<Session> {
+        let address = address.to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to"))?;
+        let mut session = Session {
+            role: Role::Join { address, last_attempt: Instant::now() },
+            local: Player::Black,
+            start: None,
+            peer: None,
+            greeted: false,
+            synced: false,
+            setup: Setup::default(),
+        };
+        session.connect()?;
+        Ok(session)
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        match &self.role {
+            Role::Host { listener, .. } => listener.local_addr(),
+            Role::Join { .. } => match &self.peer {
+                Some(peer) => peer.stream.local_addr(),
+                None => Err(io::Error::new(io::ErrorKind::NotConnected, "not connected")),
+            },
+        }
+    }
+
+    pub fn is_host(&self) -> bool {
+        matches!(self.role, Role::Host { .. })
+    }
+
+    /// The color played on this side.
+    pub fn get_local_player(&self) -> Player {
+        self.local
+    }
+
+    /// The position the game started from, once known.
+    pub fn get_start_position(&self) -> Option<&State> {
+        self.start.as_ref()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.peer.is_some() && self.synced
+    }
+
+    /// Whether the local player may move in `state` now.
+    pub fn can_move(&self, state: &State) -> bool {
+        self.is_connected() && state.get_game_running() && state.get_current_player() == self.local
+    }
+
+    /// Tells the other player about `mv`, which has just been played on `state`.
+    pub fn send_move(&mut self, state: &State, mv: Move) -> Result<(), NetError> {
+        if !self.is_connected() {
+            return Err(NetError::NotConnected);
+        }
+        self.send(&Message::Move(mv.to_uci(), state.get_hash()));
+        Ok(())
+    }
+
+    fn connect(&mut self) -> io::Result<()> {
+        if let Role::Join { address, last_attempt } = &mut self.role {
+            *last_attempt = Instant::now();
+            let stream = TcpStream::connect_timeout(address, CONNECT_TIMEOUT)?;
+            let mut peer = Peer::new(stream)?;
+            peer.send(&Message::Hello(PROTOCOL_VERSION))?;
+            self.peer = Some(peer);
+            self.greeted = false;
+            self.synced = false;
+            self.setup = Setup::default();
+        }
+        Ok(())
+    }
+
+    fn disconnect(&mut self, events: &mut Vec<NetEvent>) {
+        if self.peer.take().is_some() {
+            events.push(NetEvent::Disconnected);
+        }
+        self.synced = false;
+    }
+
+    fn send(&mut self, message: &Message) {
+        let failed = match self.peer.as_mut() {
+            Some(peer) => peer.send(message).is_err(),
+            None => false,
+        };
+        if failed {
+            // The reader thread notices the closed connection as well
+            self.synced = false;
+        }
+    }
+
+    fn send_setup(&mut self, state: &State) {
+        let skipped_moves = match &self.role {
+            Role::Host { skipped_moves, .. } => *skipped_moves,
+            Role::Join { .. } => return,
+        };
+        let start = match &self.start {
+            Some(start) => start.clone(),
+            None => return,
+        };
+        let moves = state.get_history()[skipped_moves..].iter().map(|entry| entry.mv.to_uci()).collect();
+        self.send(&Message::Variant(start.get_variant().name().to_string()));
+        self.send(&Message::Position(start.to_fen()));
+        self.send(&Message::Moves(moves));
+        self.send(&Message::Start(self.local.opponent()));
+    }
+
+    /// Accepts or reconnects, and handles everything the other side sent since
+    /// the last call. Moves of the other player are played on `state`, and a
+    /// joiner's `state` is replaced when the host sends the game.
+    pub fn poll(&mut self, state: &mut State) -> Vec<NetEvent> {
+        let mut events = Vec::new();
+        self.accept_or_reconnect();
+
+        loop {
+            let line = match self.peer.as_ref().map(|peer| peer.lines.try_recv()) {
+                Some(Ok(Some(line))) => line,
+                Some(Ok(None)) | Some(Err(TryRecvError::Disconnected)) => {
+                    self.disconnect(&mut events);
+                    break;
+                }
+                Some(Err(TryRecvError::Empty)) | None => break,
+            };
+
+            match Message::parse(&line) {
+                Some(message) => self.handle_message(message, state, &mut events),
+                None => {
+                    self.send(&Message::Error(String::from("invalid message")));
+                    events.push(NetEvent::Error(NetError::InvalidMessage));
+                }
+            }
+        }
+        events
+    }
+
+    fn accept_or_reconnect(&mut self) {
+        if self.peer.is_some() {
+            return;
+        }
+        match &self.role {
+            Role::Host { listener, .. } => {
+                if let Ok((stream, _)) = listener.accept() {
+                    let peer = stream.set_nonblocking(false).and_then(|_| Peer::new(stream));
+                    if let Ok(mut peer) = peer {
+                        if peer.send(&Message::Hello(PROTOCOL_VERSION)).is_ok() {
+                            self.peer = Some(peer);
+                        }
+                    }
+                }
+            }
+            Role::Join { last_attempt, .. } => {
+                if last_attempt.elapsed() >= RECONNECT_INTERVAL {
+                    let _res = self.connect();
+                }
+            }
+        }
+    }
+
+    fn handle_message(&mut self, message: Message, state: &mut State, events: &mut Vec<NetEvent>) {
+        match (message, self.is_host()) {
+            (Message::Hello(version), is_host) => {
+                if version != PROTOCOL_VERSION {
+                    self.send(&Message::Error(format!("protocol version {} is not supported", version)));
+                    events.push(NetEvent::Error(NetError::VersionMismatch));
+                    self.disconnect(events);
+                } else if is_host {
+                    self.send_setup(state);
+                    self.synced = true;
+                    events.push(NetEvent::Connected);
+                }
+            }
+            (Message::Resync, true) => {
+                self.send_setup(state);
+                self.synced = true;
+            }
+            (Message::Variant(name), false) => self.setup.variant = Some(name),
+            (Message::Position(fen), false) => self.setup.fen = Some(fen),
+            (Message::Moves(moves), false) => self.setup.moves = moves,
+            (Message::Start(player), false) => self.apply_setup(player, state, events),
+            (Message::Move(uci, hash), _) if self.synced => self.handle_move(&uci, hash, state, events),
+            (Message::Error(_), _) => events.push(NetEvent::Error(NetError::Rejected)),
+            _ => {
+                self.send(&Message::Error(String::from("unexpected message")));
+                events.push(NetEvent::Error(NetError::InvalidMessage));
+            }
+        }
+    }
+
+    fn apply_setup(&mut self, player: Player, state: &mut State, events: &mut Vec<NetEvent>) {
+        let setup = std::mem::take(&mut self.setup);
+        let variant = setup.variant.as_deref().and_then(variant::from_name);
+        let start = match (variant, setup.fen) {
+            (Some(variant), Some(fen)) => State::from_fen_with_variant(&fen, variant).ok(),
+            _ => None,
+        };
+        let mut rebuilt = match start {
+            Some(start) => {
+                self.start = Some(start.clone());
+                start
+            }
+            None => {
+                self.send(&Message::Error(String::from("invalid setup")));
+                events.push(NetEvent::Error(NetError::InvalidMessage));
+                return;
+            }
+        };
+
+        for uci in setup.moves.iter() {
+            let played = Move::from_uci(uci).map(|mv| rebuilt.play_move(mv).is_ok());
+            if played != Some(true) {
+                self.send(&Message::Error(format!("illegal move {} in setup", uci)));
+                events.push(NetEvent::Error(NetError::IllegalMove));
+                return;
+            }
+        }
+
+        *state = rebuilt;
+        self.local = player;
+        self.synced = true;
+        if !self.greeted {
+            self.greeted = true;
+            events.push(NetEvent::Connected);
+        }
+        events.push(NetEvent::Synced);
+    }
+
+    fn handle_move(&mut self, uci: &str, hash: u64, state: &mut State, events: &mut Vec<NetEvent>) {
+        if state.get_current_player() == self.local {
+            self.send(&Message::Error(String::from("not your turn")));
+            events.push(NetEvent::Error(NetError::NotPeersTurn));
+            self.recover(state);
+            return;
+        }
+
+        let res = match Move::from_uci(uci) {
+            Some(mv) => state.play_move(mv).ok(),
+            None => None,
+        };
+        match res {
+            Some(res) if state.get_hash() == hash => events.push(NetEvent::Move(res)),
+            Some(res) => {
+                events.push(NetEvent::Move(res));
+                events.push(NetEvent::Error(NetError::Desync));
+                self.recover(state);
+            }
+            None => {
+                self.send(&Message::Error(format!("illegal move {}", uci)));
+                events.push(NetEvent::Error(NetError::IllegalMove));
+                self.recover(state);
+            }
+        }
+    }
+
+    // The host's position is the one that counts. A host that notices a
+    // difference sends its game, a joiner asks for it.
+    fn recover(&mut self, state: &State) {
+        if self.is_host() {
+            self.send_setup(state);
+        } else {
+            self.synced = false;
+            self.send(&Message::Resync);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Pos;
+
+    fn mv(uci: &str) -> Move {
+        Move::from_uci(uci).unwrap()
+    }
+
+    // Polls both sides until `done` holds for the collected events, or panics
+    fn poll_until<F>(host: &mut (Session, State), join: &mut (Session, State), done: F) -> (Vec<NetEvent>, Vec<NetEvent>)
+        where F: Fn(&[NetEvent], &[NetEvent]) -> bool
+    {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let (mut host_events, mut join_events) = (Vec::new(), Vec::new());
+        while !done(&host_events, &join_events) {
+            assert!(Instant::now() < deadline, "timed out: {:?} {:?}", host_events, join_events);
+            host_events.extend(host.0.poll(&mut host.1));
+            join_events.extend(join.0.poll(&mut join.1));
+            thread::sleep(Duration::from_millis(5));
+        }
+        (host_events, join_events)
+    }
+
+    fn connect(state: State) -> ((Session, State), (Session, State)) {
+        let host = Session::host("127.0.0.1:0", &state, Player::White).unwrap();
+        let address = host.local_addr().unwrap();
+        let mut host = (host, state);
+        let mut join = (Session::join(address).unwrap(), State::new());
+        poll_until(&mut host, &mut join, |h, j| h.contains(&NetEvent::Connected) && j.contains(&NetEvent::Synced));
+        (host, join)
+    }
+
+    fn play(side: &mut (Session, State), uci: &str) {
+        assert!(side.0.can_move(&side.1));
+        assert!(side.1.play_move(mv(uci)).is_ok());
+        assert!(side.0.send_move(&side.1, mv(uci)).is_ok());
+    }
+
+    #[test]
+    fn message_test() {
+        let messages = [
+            Message::Hello(1),
+            Message::Variant(String::from("King of the Hill")),
+            Message::Position(String::from(crate::fen::START_FEN)),
+            Message::Moves(vec![String::from("e2e4"), String::from("e7e5")]),
+            Message::Moves(Vec::new()),
+            Message::Start(Player::Black),
+            Message::Move(String::from("e7e8q"), 0x0123_4567_89ab_cdef),
+            Message::Resync,
+            Message::Error(String::from("illegal move")),
+        ];
+        for message in messages.iter() {
+            assert_eq!(Message::parse(&message.to_line()).as_ref(), Some(message));
+        }
+        assert_eq!(Message::parse("MOVE e2e4"), None);
+        assert_eq!(Message::parse("JUMP"), None);
+    }
+
+    #[test]
+    fn play_over_loopback_test() {
+        let (mut host, mut join) = connect(State::new());
+        assert_eq!(join.0.get_local_player(), Player::Black);
+        assert!(!join.0.can_move(&join.1));
+
+        play(&mut host, "e2e4");
+        poll_until(&mut host, &mut join, |_, j| j.contains(&NetEvent::Move(MoveSuccess::Ok)));
+        play(&mut join, "e7e5");
+        poll_until(&mut host, &mut join, |h, _| h.contains(&NetEvent::Move(MoveSuccess::Ok)));
+
+        assert_eq!(host.1.get_hash(), join.1.get_hash());
+        assert_eq!(join.1.get(Pos::new(4, 3)), host.1.get(Pos::new(4, 3)));
+    }
+
+    #[test]
+    fn variant_setup_test() {
+        let state = State::new_variant(variant::from_name("Crazyhouse").unwrap());
+        let (_host, join) = connect(state);
+        assert_eq!(join.1.get_variant().name(), "Crazyhouse");
+        assert!(join.1.to_fen().contains("[]"));
+    }
+
+    #[test]
+    fn desync_test() {
+        let (mut host, mut join) = connect(State::new());
+        // The joiner's position is changed behind the host's back
+        join.1 = State::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKB1R w KQkq - 0 1").unwrap();
+
+        play(&mut host, "e2e4");
+        let (_, events) = poll_until(&mut host, &mut join, |_, j| j.contains(&NetEvent::Synced));
+        assert!(events.contains(&NetEvent::Error(NetError::Desync)));
+        assert_eq!(join.1.get_hash(), host.1.get_hash());
+        assert_eq!(join.1.get_history().len(), 1);
+    }
+
+    #[test]
+    fn illegal_move_test() {
+        let (mut host, mut join) = connect(State::new());
+        play(&mut host, "e2e4");
+        poll_until(&mut host, &mut join, |_, j| j.contains(&NetEvent::Move(MoveSuccess::Ok)));
+
+        join.0.send(&Message::Move(String::from("e7e4"), 0));
+        let (events, _) = poll_until(&mut host, &mut join, |h, _| !h.is_empty());
+        assert_eq!(events, vec![NetEvent::Error(NetError::IllegalMove)]);
+        assert_eq!(host.1.get_history().len(), 1);
+    }
+
+    #[test]
+    fn version_mismatch_test() {
+        let state = State::new();
+        let mut host = Session::host("127.0.0.1:0", &state, Player::White).unwrap();
+        let mut stream = TcpStream::connect(host.local_addr().unwrap()).unwrap();
+        stream.write_all(b"HELLO 99\n").unwrap();
+
+        let mut state = state;
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut events = Vec::new();
+        while !events.contains(&NetEvent::Disconnected) {
+            assert!(Instant::now() < deadline);
+            events.extend(host.poll(&mut state));
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(events.contains(&NetEvent::Error(NetError::VersionMismatch)));
+    }
+
+    #[test]
+    fn reconnect_test() {
+        let (mut host, mut join) = connect(State::new());
+        play(&mut host, "d2d4");
+        poll_until(&mut host, &mut join, |_, j| j.contains(&NetEvent::Move(MoveSuccess::Ok)));
+
+        // The joiner goes away and comes back with a fresh instance
+        drop(join);
+        let address = host.0.local_addr().unwrap();
+        let mut rejoined = (Session::join(address).unwrap(), State::new());
+        let (host_events, _) = poll_until(&mut host, &mut rejoined, |h, j| {
+            h.contains(&NetEvent::Connected) && j.contains(&NetEvent::Synced)
+        });
+        assert!(host_events.contains(&NetEvent::Disconnected));
+        assert_eq!(rejoined.1.get_hash(), host.1.get_hash());
+        assert!(rejoined.0.can_move(&rejoined.1));
+    }
+}
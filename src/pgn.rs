@@ -0,0 +1,469 @@
+use std::sync::Arc;
+
+use crate::eco;
+use crate::tree::{nag_from_glyph, GameTree, NodeId};
+use crate::variant::{self, Standard};
+use crate::{GameStatus, Player, State};
+
+const SEVEN_TAG_ROSTER: [&str; 6] = ["Event", "Site", "Date", "Round", "White", "Black"];
+
+/// The PGN result of a game: `1-0`, `0-1`, `1/2-1/2` or `*` while it is still running.
+pub fn result_string(status: &GameStatus) -> &'static str {
+    match status {
+        GameStatus::WhiteTurn | GameStatus::BlackTurn => "*",
+        GameStatus::GameWonByWhite => "1-0",
+        GameStatus::GameWonByBlack => "0-1",
+        GameStatus::Draw => "1/2-1/2",
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Tags the writer adds from the game, so they are not copied from the given ones
+const GENERATED_TAGS: [&str; 4] = ["Result", "Variant", "Termination", "SetUp"];
+
+// `start_fen` is the start position when it is not the variant's usual one
+// and no `FEN` tag is given
+fn write_tags(pgn: &mut String, state: &State, start_fen: Option<&str>, result: &str, tags: &[(&str, &str)]) {
+    for name in SEVEN_TAG_ROSTER.iter() {
+        let missing = if *name == "Date" { "????.??.??" } else { "?" };
+        let value = tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| *value).unwrap_or(missing);
+        pgn.push_str(&format!("[{} \"{}\"]\n", name, escape(value)));
+    }
+    pgn.push_str(&format!("[Result \"{}\"]\n", result));
+    if state.variant.name() != "Standard" {
+        pgn.push_str(&format!("[Variant \"{}\"]\n", state.variant.name()));
+    } else if state.chess960 {
+        pgn.push_str("[Variant \"Chess960\"]\n");
+    }
+    if let Some(termination) = state.termination {
+        pgn.push_str(&format!("[Termination \"{}\"]\n", termination.to_string()));
+    }
+    // Standard games from the start position get their opening unless it is given
+    let has_tag = |name| tags.iter().any(|(tag, _)| *tag == name);
+    let start_fen = start_fen.filter(|_| !has_tag("FEN"));
+    let standard = state.variant.name() == "Standard" && !state.chess960 && !has_tag("FEN") && start_fen.is_none();
+    if let Some(opening) = eco::classify_game(state).filter(|_| standard) {
+        if !has_tag("ECO") {
+            pgn.push_str(&format!("[ECO \"{}\"]\n", opening.eco));
+        }
+        if !has_tag("Opening") {
+            pgn.push_str(&format!("[Opening \"{}\"]\n", escape(opening.name)));
+        }
+    }
+    for (name, value) in tags.iter().filter(|(tag, _)| !SEVEN_TAG_ROSTER.contains(tag) && !GENERATED_TAGS.contains(tag)) {
+        pgn.push_str(&format!("[{} \"{}\"]\n", name, escape(value)));
+    }
+    if let Some(fen) = start_fen {
+        pgn.push_str(&format!("[FEN \"{}\"]\n", fen));
+    }
+    if has_tag("FEN") || start_fen.is_some() {
+        pgn.push_str("[SetUp \"1\"]\n");
+    }
+    pgn.push('\n');
+}
+
+// Wraps the movetext at 80 columns
+fn write_movetext(pgn: &mut String, movetext: Vec<String>) {
+    let mut line = String::new();
+    for token in movetext {
+        if !line.is_empty() && line.len() + token.len() + 1 > 80 {
+            pgn.push_str(&line);
+            pgn.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&token);
+    }
+    pgn.push_str(&line);
+    pgn.push('\n');
+}
+
+impl State {
+    /// Writes the game as PGN. The seven tag roster is filled with `?` for any
+    /// tag missing from `tags`, and the result and variant tags are added from
+    /// the game. A Chess960 game from `new_chess960` gets its start position,
+    /// and a game from any other custom position should pass its `FEN` tag.
+    pub fn to_pgn(&self, tags: &[(&str, &str)]) -> String {
+        let result = result_string(&self.get_game_status());
+        let start_fen = self.chess960_id.and_then(State::new_chess960).map(|start| start.to_fen());
+        let mut pgn = String::new();
+        write_tags(&mut pgn, self, start_fen.as_deref(), result, tags);
+
+        // Ply number of the first move, counted like total_steps
+        let first = self.total_steps as usize - self.history.len();
+        let mut movetext: Vec<String> = Vec::new();
+        for (i, entry) in self.history.iter().enumerate() {
+            let ply = first + i;
+            let player = if ply.is_multiple_of(2) { Player::White } else { Player::Black };
+            if player == Player::White {
+                movetext.push(format!("{}.", ply / 2 + 1));
+            } else if i == 0 {
+                movetext.push(format!("{}...", ply / 2 + 1));
+            }
+            movetext.push(entry.san.clone());
+        }
+        movetext.push(result.to_string());
+        write_movetext(&mut pgn, movetext);
+        pgn
+    }
+}
+
+fn comment_token(comment: &str) -> String {
+    format!("{{{}}}", comment.replace('}', ")"))
+}
+
+// The movetext of the line starting at `id` with the variations inside it
+fn write_line(tree: &GameTree, id: NodeId, movetext: &mut Vec<String>) {
+    let mut id = id;
+    let mut number_needed = true;
+    loop {
+        let node = tree.get(id).unwrap();
+        let parent = tree.get(node.get_parent().unwrap()).unwrap();
+        if !node.comment_before.is_empty() {
+            movetext.push(comment_token(&node.comment_before));
+        }
+        let ply = parent.position.total_steps as usize;
+        if ply.is_multiple_of(2) {
+            movetext.push(format!("{}.", ply / 2 + 1));
+        } else if number_needed {
+            movetext.push(format!("{}...", ply / 2 + 1));
+        }
+        movetext.push(node.san.clone());
+        movetext.extend(node.nags.iter().map(|nag| format!("${}", nag)));
+        number_needed = !node.comment.is_empty();
+        if number_needed {
+            movetext.push(comment_token(&node.comment));
+        }
+
+        // The other moves from the same position follow the first one
+        if parent.get_children()[0] == id {
+            for &variation in &parent.get_children()[1..] {
+                let start = movetext.len();
+                write_line(tree, variation, movetext);
+                movetext[start].insert(0, '(');
+                movetext.last_mut().unwrap().push(')');
+                number_needed = true;
+            }
+        }
+        match node.get_children().first() {
+            Some(&child) => id = child,
+            None => break,
+        }
+    }
+}
+
+impl GameTree {
+    /// Writes the game with its comments, annotations and variations as PGN,
+    /// with the tags like `State::to_pgn`. The result is taken from a
+    /// `Result` tag, or else from the end of the main line.
+    pub fn to_pgn(&self, tags: &[(&str, &str)]) -> String {
+        let end = &self.get(self.main_line_end()).unwrap().position;
+        let result = tags.iter().find(|(tag, _)| *tag == "Result").map_or(result_string(&end.get_game_status()), |(_, value)| *value);
+        let start = &self.get(self.root()).unwrap().position;
+        let start_fen = Some(start.to_fen()).filter(|fen| start.chess960 || fen != start.variant.start_fen());
+        let mut pgn = String::new();
+        write_tags(&mut pgn, end, start_fen.as_deref(), result, tags);
+
+        let root = self.get(self.root()).unwrap();
+        let mut movetext = Vec::new();
+        if !root.comment.is_empty() {
+            movetext.push(comment_token(&root.comment));
+        }
+        if let Some(&first) = root.get_children().first() {
+            write_line(self, first, &mut movetext);
+        }
+        movetext.push(result.to_string());
+        write_movetext(&mut pgn, movetext);
+        pgn
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PgnError {
+    InvalidTag,
+    InvalidFen,
+    UnknownVariant,
+    IllegalMove,
+    UnbalancedVariation,
+}
+
+impl PgnError {
+    pub fn to_string(&self) -> &str {
+        match self {
+            Self::InvalidTag => "A tag is not of the form [Name \"Value\"]",
+            Self::InvalidFen => "The FEN tag is not a valid position",
+            Self::UnknownVariant => "The variant is not supported",
+            Self::IllegalMove => "A move is not legal in its position",
+            Self::UnbalancedVariation => "A variation is not opened or closed properly",
+        }
+    }
+}
+
+/// A game read from PGN.
+#[derive(Clone)]
+pub struct PgnGame {
+    pub tags: Vec<(String, String)>,
+    pub tree: GameTree,
+}
+
+impl PgnGame {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(tag, _)| tag == name).map(|(_, value)| value.as_str())
+    }
+
+    pub fn to_pgn(&self) -> String {
+        let tags: Vec<(&str, &str)> = self.tags.iter().map(|(name, value)| (name.as_str(), value.as_str())).collect();
+        self.tree.to_pgn(&tags)
+    }
+}
+
+fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.strip_suffix(']')?;
+    let (name, value) = inner.split_once(' ')?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    Some((name.to_string(), value.replace("\\\"", "\"").replace("\\\\", "\\")))
+}
+
+enum Token {
+    Comment(String),
+    Open,
+    Close,
+    Nag(u8),
+    Move(String),
+}
+
+fn tokenize(movetext: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chars = movetext.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' => tokens.push(Token::Comment(chars.by_ref().take_while(|&c| c != '}').collect::<String>().trim().to_string())),
+            ';' => tokens.push(Token::Comment(chars.by_ref().take_while(|&c| c != '\n').collect::<String>().trim().to_string())),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            c if c.is_whitespace() => {}
+            c => {
+                let mut word = c.to_string();
+                while let Some(&next) = chars.peek() {
+                    if next.is_whitespace() || "{;()".contains(next) {
+                        break;
+                    }
+                    word.push(next);
+                    chars.next();
+                }
+                if let Some(nag) = word.strip_prefix('$') {
+                    if let Ok(nag) = nag.parse() {
+                        tokens.push(Token::Nag(nag));
+                    }
+                    continue;
+                }
+                if ["1-0", "0-1", "1/2-1/2", "*"].contains(&word.as_str()) {
+                    continue;
+                }
+                // Move numbers like `12.` or `12...` may be stuck to the move
+                let word = match word.rfind('.') {
+                    Some(dot) if word[..dot].chars().all(|c| c.is_ascii_digit() || c == '.') => &word[dot + 1..],
+                    _ => &word[..],
+                };
+                if !word.is_empty() {
+                    tokens.push(Token::Move(word.to_string()));
+                }
+            }
+        }
+    }
+    tokens
+}
+
+fn parse_game(tags: Vec<(String, String)>, movetext: &str) -> Result<PgnGame, PgnError> {
+    let variant = match tags.iter().find(|(name, _)| name == "Variant").map(|(_, value)| value.as_str()) {
+        None | Some("Standard") | Some("Chess960") => Arc::new(Standard),
+        Some(name) => variant::from_name(name).ok_or(PgnError::UnknownVariant)?,
+    };
+    let chess960 = tags.iter().any(|(name, value)| name == "Variant" && value == "Chess960");
+    let mut start = match tags.iter().find(|(name, _)| name == "FEN") {
+        Some((_, fen)) => State::from_fen_with_variant(fen, variant).map_err(|_| PgnError::InvalidFen)?,
+        None => State::new_variant(variant),
+    };
+    // A Chess960 position may look like the classical one
+    start.chess960 |= chess960;
+
+    let mut tree = GameTree::new(start);
+    let mut current = tree.root();
+    // Where to go back to when a variation is closed
+    let mut variations: Vec<NodeId> = Vec::new();
+    let mut variation_start = false;
+    let mut comment_before = String::new();
+    let append = |text: &mut String, comment: &str| {
+        if !text.is_empty() {
+            text.push(' ');
+        }
+        text.push_str(comment);
+    };
+
+    for token in tokenize(movetext) {
+        match token {
+            Token::Comment(comment) if variation_start => append(&mut comment_before, &comment),
+            Token::Comment(comment) => append(&mut tree.get_mut(current).unwrap().comment, &comment),
+            Token::Open => {
+                let parent = tree.get(current).unwrap().get_parent().ok_or(PgnError::UnbalancedVariation)?;
+                variations.push(current);
+                current = parent;
+                variation_start = true;
+            }
+            Token::Close => {
+                current = variations.pop().ok_or(PgnError::UnbalancedVariation)?;
+                variation_start = false;
+            }
+            Token::Nag(nag) => tree.get_mut(current).unwrap().nags.push(nag),
+            Token::Move(word) => {
+                let san = word.trim_end_matches(['!', '?']);
+                let glyph = nag_from_glyph(&word[san.len()..]);
+                let mv = tree.get(current).unwrap().position.move_from_san(san).ok_or(PgnError::IllegalMove)?;
+                current = tree.add_move(current, mv).map_err(|_| PgnError::IllegalMove)?;
+                let node = tree.get_mut(current).unwrap();
+                node.nags.extend(glyph);
+                node.comment_before = std::mem::take(&mut comment_before);
+                variation_start = false;
+            }
+        }
+    }
+    if !variations.is_empty() {
+        return Err(PgnError::UnbalancedVariation);
+    }
+    Ok(PgnGame { tags, tree })
+}
+
+/// Reads every game in `text` with its comments, annotations and variations.
+pub fn parse_pgn(text: &str) -> Result<Vec<PgnGame>, PgnError> {
+    let mut games = Vec::new();
+    let mut tags = Vec::new();
+    let mut movetext = String::new();
+
+    for line in text.lines() {
+        let trimmed = line.trim();
+        // A tag after movetext starts the next game
+        if trimmed.starts_with('[') && !movetext.trim().is_empty() {
+            games.push(parse_game(std::mem::take(&mut tags), &movetext)?);
+            movetext.clear();
+        }
+        if trimmed.starts_with('[') && movetext.is_empty() {
+            tags.push(parse_tag(trimmed).ok_or(PgnError::InvalidTag)?);
+        } else {
+            movetext.push_str(line);
+            movetext.push('\n');
+        }
+    }
+    if !movetext.trim().is_empty() || !tags.is_empty() {
+        games.push(parse_game(tags, &movetext)?);
+    }
+    Ok(games)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Move, Pos};
+
+    fn play(state: &mut State, moves: &[&str]) {
+        for uci in moves {
+            state.play_move(Move::from_uci(uci).unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn pgn_test() {
+        let mut state = State::new();
+        play(&mut state, &["f2f3", "e7e5", "g2g4", "d8h4"]);
+        let pgn = state.to_pgn(&[("White", "Alice"), ("Event", "Club \"night\"")]);
+        assert!(pgn.starts_with("[Event \"Club \\\"night\\\"\"]\n[Site \"?\"]\n"));
+        assert!(pgn.contains("[White \"Alice\"]\n[Black \"?\"]\n[Result \"0-1\"]\n[Termination \"checkmate\"]\n"));
+        assert!(pgn.ends_with("\n\n1. f3 e5 2. g4 Qh4# 0-1\n"));
+        assert!(!pgn.contains("[ECO"));
+
+        let mut state = State::new();
+        play(&mut state, &["e2e4", "e7e5", "g1f3", "g8f6", "f3e5"]);
+        assert!(state.to_pgn(&[]).contains("[Result \"*\"]\n[ECO \"C42\"]\n[Opening \"Petrov's Defence\"]\n\n"));
+        assert!(state.to_pgn(&[("ECO", "C00")]).contains("[Opening \"Petrov's Defence\"]\n[ECO \"C00\"]\n"));
+    }
+
+    #[test]
+    fn tree_round_trip_test() {
+        let text = "[Event \"Analysis\"]\n[Site \"?\"]\n[Date \"2024.01.01\"]\n[Round \"?\"]\n[White \"?\"]\n[Black \"?\"]\n\
+                    [Result \"1-0\"]\n[ECO \"C70\"]\n[Opening \"Ruy Lopez: Morphy Defence\"]\n[Annotator \"Me\"]\n\n\
+                    {An open game} 1. e4 e5 2. Nf3 (2. f4 $3 {The King's Gambit} 2... exf4 (2... d5)\n\
+                    3. Nf3) 2... Nc6 3. Bb5 $1 ({Or} 3. Bc4 Bc5 $6) 3... a6 1-0\n";
+        let games = parse_pgn(text).unwrap();
+        assert_eq!(games.len(), 1);
+        let game = &games[0];
+        assert_eq!(game.tag("Annotator"), Some("Me"));
+        assert_eq!(game.to_pgn(), text);
+
+        let tree = &game.tree;
+        let root = tree.get(tree.root()).unwrap();
+        assert_eq!(root.comment, "An open game");
+        let e4 = root.get_children()[0];
+        let e5 = tree.get(e4).unwrap().get_children()[0];
+        let f4 = tree.get(e5).unwrap().get_children()[1];
+        assert_eq!(tree.get(f4).unwrap().nags, [3]);
+        assert_eq!(tree.get(f4).unwrap().comment, "The King's Gambit");
+        assert_eq!(tree.get(f4).unwrap().get_children().len(), 2);
+        assert_eq!(tree.main_line().len(), 7);
+    }
+
+    #[test]
+    fn parse_pgn_test() {
+        // Glyphs after moves, castling with zeros, a line comment and two games
+        let text = "[Event \"One\"]\n\n1.e4 e5?! 2.Nf3 Nc6 3.Bc4 Nf6 4.0-0!? ; the main line\nNxe4 *\n\n\
+                    [Event \"Two\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 12\"]\n\n12... Kd7 13. e4 *\n";
+        let games = parse_pgn(text).unwrap();
+        assert_eq!(games.len(), 2);
+        let tree = &games[0].tree;
+        let line = tree.main_line();
+        assert_eq!(tree.get(line[2]).unwrap().nags, [6]);
+        assert_eq!(tree.get(line[7]).unwrap().san, "O-O");
+        assert_eq!(tree.get(line[7]).unwrap().nags, [5]);
+        assert_eq!(tree.get(line[7]).unwrap().comment, "the main line");
+        assert_eq!(line.len(), 9);
+        assert!(games[1].to_pgn().ends_with("[SetUp \"1\"]\n\n12... Kd7 13. e4 *\n"));
+
+        assert_eq!(parse_pgn("1. e4 e5 2. Ke3").err(), Some(PgnError::IllegalMove));
+        assert_eq!(parse_pgn("1. e4 (1. d4").err(), Some(PgnError::UnbalancedVariation));
+        assert_eq!(parse_pgn("[Event]\n1. e4").err(), Some(PgnError::InvalidTag));
+    }
+
+    #[test]
+    fn pgn_from_position_test() {
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 b - - 0 12";
+        let mut state = State::from_fen(fen).unwrap();
+        play(&mut state, &["e8d7", "e2e4"]);
+        let pgn = state.to_pgn(&[("FEN", fen)]);
+        assert!(pgn.contains("[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 12\"]\n[SetUp \"1\"]\n"));
+        assert!(pgn.ends_with("\n12... Kd7 13. e4 *\n"));
+        assert!(state.get(Pos::new(4, 3)).is_some());
+    }
+
+    #[test]
+    fn chess960_round_trip_test() {
+        for id in [0, 518] {
+            let mut state = State::new_chess960(id).unwrap();
+            for _ in 0..6 {
+                let mv = state.get_legal_moves()[0];
+                state.play_move(mv).unwrap();
+            }
+            let pgn = state.to_pgn(&[]);
+            let start = State::new_chess960(id).unwrap().to_fen();
+            assert!(pgn.contains(&format!("[Variant \"Chess960\"]\n[FEN \"{}\"]\n[SetUp \"1\"]\n", start)));
+
+            let game = &parse_pgn(&pgn).unwrap()[0];
+            let end = &game.tree.get(game.tree.main_line_end()).unwrap().position;
+            assert!(end.is_chess960());
+            assert_eq!(end.to_fen(), state.to_fen());
+            assert_eq!(game.to_pgn(), pgn);
+        }
+    }
+}
@@ -0,0 +1,311 @@
+//! Tactics puzzles in the CSV format of the Lichess puzzle database, with
+//! lines like `PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,...`.
+//! The FEN is the position before the opponent's last move, so the moves start
+//! with that move and then alternate between the solution and the replies.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::Path;
+
+use crate::tournament::elo_to_score;
+use crate::{Move, MoveError, State, Termination};
+
+/// The rating of new users and of puzzles without one.
+pub const DEFAULT_RATING: f64 = 1500.0;
+// How far a rating moves after one puzzle
+const RATING_K: f64 = 32.0;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PuzzleError {
+    MissingField,
+    InvalidFen,
+    InvalidMove,
+    InvalidRating,
+}
+
+impl PuzzleError {
+    pub fn to_string(&self) -> &str {
+        match self {
+            Self::MissingField => "A puzzle needs an id, a FEN and at least two moves",
+            Self::InvalidFen => "The puzzle position is not a valid FEN",
+            Self::InvalidMove => "A move of the puzzle is not legal",
+            Self::InvalidRating => "The puzzle rating is not a number",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Puzzle {
+    pub id: String,
+    pub fen: String,
+    /// The opponent's move that sets up the puzzle, then the solution with the replies.
+    pub moves: Vec<Move>,
+    pub rating: u32,
+    pub themes: Vec<String>,
+}
+
+impl Puzzle {
+    /// Reads one line of the puzzle database.
+    pub fn from_csv(line: &str) -> Result<Puzzle, PuzzleError> {
+        let fields: Vec<&str> = line.trim().split(',').collect();
+        if fields.len() < 3 || fields[0].is_empty() {
+            return Err(PuzzleError::MissingField);
+        }
+        let moves = fields[2].split_whitespace()
+            .map(|uci| Move::from_uci(uci).ok_or(PuzzleError::InvalidMove))
+            .collect::<Result<Vec<Move>, PuzzleError>>()?;
+        if moves.len() < 2 {
+            return Err(PuzzleError::MissingField);
+        }
+        let rating = match fields.get(3) {
+            Some(rating) if !rating.is_empty() => rating.parse().map_err(|_| PuzzleError::InvalidRating)?,
+            _ => DEFAULT_RATING as u32,
+        };
+        Ok(Puzzle {
+            id: fields[0].to_string(),
+            fen: fields[1].to_string(),
+            moves,
+            rating,
+            themes: fields.get(7).map_or(Vec::new(), |themes| themes.split_whitespace().map(String::from).collect()),
+        })
+    }
+}
+
+/// Reads every puzzle of a CSV file, skipping the header line if there is one.
+pub fn parse_puzzles(text: &str) -> Result<Vec<Puzzle>, PuzzleError> {
+    text.lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with("PuzzleId"))
+        .map(Puzzle::from_csv)
+        .collect()
+}
+
+/// What became of a move tried in a puzzle.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Attempt {
+    /// The move was right and the opponent replied with the given move.
+    Correct(Move),
+    Solved,
+    Wrong,
+}
+
+/// A puzzle being solved. The opponent's moves are played automatically.
+pub struct PuzzleRun {
+    puzzle: Puzzle,
+    position: State,
+    // Index of the next move of the solution
+    next: usize,
+    result: Option<bool>,
+}
+
+impl PuzzleRun {
+    /// Sets up the puzzle by playing the opponent's first move.
+    pub fn new(puzzle: Puzzle) -> Result<PuzzleRun, PuzzleError> {
+        let mut position = State::from_fen(&puzzle.fen).map_err(|_| PuzzleError::InvalidFen)?;
+        position.play_move(puzzle.moves[0]).map_err(|_| PuzzleError::InvalidMove)?;
+        Ok(PuzzleRun { puzzle, position, next: 1, result: None })
+    }
+
+    pub fn get_puzzle(&self) -> &Puzzle {
+        &self.puzzle
+    }
+
+    pub fn get_position(&self) -> &State {
+        &self.position
+    }
+
+    /// Whether the puzzle was solved, once it is over.
+    pub fn get_result(&self) -> Option<bool> {
+        self.result
+    }
+
+    /// The next move of the solution.
+    pub fn hint(&self) -> Option<Move> {
+        self.puzzle.moves.get(self.next).copied().filter(|_| self.result.is_none())
+    }
+
+    /// Checks `mv` against the solution. Any move that mates is right too. A
+    /// wrong move ends the puzzle and is not played.
+    pub fn try_move(&mut self, mv: Move) -> Result<Attempt, MoveError> {
+        let expected = self.hint().ok_or(MoveError::GameDone)?;
+        let mut after = self.position.clone();
+        after.play_move(mv)?;
+        let mate = after.get_termination() == Some(Termination::Checkmate);
+        if mv != expected && !mate {
+            self.result = Some(false);
+            return Ok(Attempt::Wrong);
+        }
+
+        self.position = after;
+        self.next += 1;
+        let reply = match self.puzzle.moves.get(self.next) {
+            Some(&reply) if !mate => reply,
+            _ => {
+                self.result = Some(true);
+                return Ok(Attempt::Solved);
+            }
+        };
+        self.position.play_move(reply)?;
+        self.next += 1;
+        Ok(Attempt::Correct(reply))
+    }
+}
+
+/// A user's puzzle rating and record.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct PuzzleStats {
+    pub rating: f64,
+    pub attempts: u32,
+    pub solved: u32,
+}
+
+impl PuzzleStats {
+    pub fn new() -> PuzzleStats {
+        PuzzleStats { rating: DEFAULT_RATING, attempts: 0, solved: 0 }
+    }
+
+    /// The part of the puzzles solved, from 0 to 1.
+    pub fn success_rate(&self) -> f64 {
+        if self.attempts == 0 {
+            0.0
+        } else {
+            self.solved as f64 / self.attempts as f64
+        }
+    }
+
+    /// Updates the rating like an Elo rating, as if the puzzle was an opponent
+    /// with its rating that the user beat by solving it.
+    pub fn record(&mut self, puzzle_rating: u32, solved: bool) {
+        let expected = elo_to_score(self.rating - puzzle_rating as f64);
+        let score = if solved { 1.0 } else { 0.0 };
+        self.rating += RATING_K * (score - expected);
+        self.attempts += 1;
+        if solved {
+            self.solved += 1;
+        }
+    }
+}
+
+impl Default for PuzzleStats {
+    fn default() -> PuzzleStats {
+        PuzzleStats::new()
+    }
+}
+
+/// The puzzle stats of every user, kept in a file with a line like
+/// `name<TAB>rating<TAB>attempts<TAB>solved` for each user.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PuzzleProfiles {
+    users: BTreeMap<String, PuzzleStats>,
+}
+
+impl PuzzleProfiles {
+    /// Reads the profiles from `path`, which does not have to exist yet.
+    pub fn load(path: &Path) -> io::Result<PuzzleProfiles> {
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(PuzzleProfiles::default()),
+            Err(err) => return Err(err),
+        };
+        let mut profiles = PuzzleProfiles::default();
+        for line in text.lines().filter(|line| !line.is_empty()) {
+            let fields: Vec<&str> = line.split('\t').collect();
+            let stats = match fields[..] {
+                [_, rating, attempts, solved] => rating.parse().ok().zip(attempts.parse().ok()).zip(solved.parse().ok())
+                    .map(|((rating, attempts), solved)| PuzzleStats { rating, attempts, solved }),
+                _ => None,
+            };
+            let stats = stats.ok_or_else(|| io::Error::new(ErrorKind::InvalidData, format!("Invalid profile: {}", line)))?;
+            profiles.users.insert(fields[0].to_string(), stats);
+        }
+        Ok(profiles)
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let text: String = self.users.iter()
+            .map(|(name, stats)| format!("{}\t{:.1}\t{}\t{}\n", name, stats.rating, stats.attempts, stats.solved))
+            .collect();
+        fs::write(path, text)
+    }
+
+    pub fn get(&self, user: &str) -> Option<&PuzzleStats> {
+        self.users.get(user)
+    }
+
+    /// The stats of `user`, who starts with the default rating.
+    pub fn user(&mut self, user: &str) -> &mut PuzzleStats {
+        self.users.entry(user.to_string()).or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PUZZLES: &str = "PuzzleId,FEN,Moves,Rating,RatingDeviation,Popularity,NbPlays,Themes,GameUrl,OpeningTags
+00008,r6k/pp2r2p/4Rp1Q/3p4/8/1N1P2R1/PqP2bPP/7K b - - 0 24,f2g3 e6e7 b2b1 b3c1 b1c1 h6c1,1913,75,94,6230,crushing hangingPiece long middlegame,https://lichess.org/787zsVup/black#47,
+0000a,6k1/1p3ppp/8/8/8/8/5PPP/R3R1K1 b - - 0 1,b7b6 e1e8,600,80,90,100,mate mateIn1 backRankMate oneMove,,
+";
+
+    #[test]
+    fn parse_test() {
+        let puzzles = parse_puzzles(PUZZLES).unwrap();
+        assert_eq!(puzzles.len(), 2);
+        assert_eq!(puzzles[0].id, "00008");
+        assert_eq!(puzzles[0].moves.len(), 6);
+        assert_eq!(puzzles[0].rating, 1913);
+        assert_eq!(puzzles[1].themes, ["mate", "mateIn1", "backRankMate", "oneMove"]);
+
+        assert_eq!(Puzzle::from_csv("1,8/8/8/8/8/8/8/8 w - - 0 1,e2e4"), Err(PuzzleError::MissingField));
+        assert_eq!(Puzzle::from_csv("1,8/8/8/8/8/8/8/8 w - - 0 1,e2e4 x,1500"), Err(PuzzleError::InvalidMove));
+        let puzzle = Puzzle::from_csv("1,4k3/8/8/8/8/8/8/4K3 w - - 0 1,e2e4 e7e5").unwrap();
+        assert_eq!(PuzzleRun::new(puzzle).err(), Some(PuzzleError::InvalidMove));
+        let puzzle = Puzzle::from_csv("1,8/8/8/8/8/8/8/8 w - - 0 1,e2e4 e7e5").unwrap();
+        assert_eq!(PuzzleRun::new(puzzle).err(), Some(PuzzleError::InvalidFen));
+    }
+
+    #[test]
+    fn run_test() {
+        let puzzles = parse_puzzles(PUZZLES).unwrap();
+        let mv = |uci| Move::from_uci(uci).unwrap();
+
+        let mut run = PuzzleRun::new(puzzles[0].clone()).unwrap();
+        assert_eq!(run.hint(), Some(mv("e6e7")));
+        assert_eq!(run.try_move(mv("e6e7")), Ok(Attempt::Correct(mv("b2b1"))));
+        assert!(run.try_move(mv("b3b4")).is_err());
+        assert_eq!(run.try_move(mv("b3c1")), Ok(Attempt::Correct(mv("b1c1"))));
+        assert_eq!(run.try_move(mv("h6c1")), Ok(Attempt::Solved));
+        assert_eq!(run.get_result(), Some(true));
+        assert_eq!(run.try_move(mv("h1g1")), Err(MoveError::GameDone));
+
+        let mut run = PuzzleRun::new(puzzles[0].clone()).unwrap();
+        assert_eq!(run.try_move(mv("h6h7")), Ok(Attempt::Wrong));
+        assert_eq!(run.get_result(), Some(false));
+        assert_eq!(run.get_position().get_history().len(), 1);
+
+        // The other rook mates as well
+        let mut run = PuzzleRun::new(puzzles[1].clone()).unwrap();
+        assert_eq!(run.try_move(mv("a1a8")), Ok(Attempt::Solved));
+    }
+
+    #[test]
+    fn profiles_test() {
+        let mut stats = PuzzleStats::new();
+        stats.record(1500, true);
+        assert_eq!(stats.rating, 1516.0);
+        stats.record(1000, false);
+        assert!(stats.rating < 1500.0);
+        assert_eq!(stats.success_rate(), 0.5);
+
+        let path = std::env::temp_dir().join(format!("chess-puzzle-profiles-{}", std::process::id()));
+        let mut profiles = PuzzleProfiles::load(&path).unwrap();
+        assert_eq!(profiles.get("ann"), None);
+        *profiles.user("ann") = stats;
+        profiles.user("bo lee").record(1200, true);
+        profiles.save(&path).unwrap();
+        let loaded = PuzzleProfiles::load(&path).unwrap();
+        let _res = fs::remove_file(&path);
+        assert_eq!(loaded.get("ann").unwrap().attempts, 2);
+        assert_eq!(loaded.get("bo lee").unwrap().solved, 1);
+    }
+}
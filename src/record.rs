@@ -0,0 +1,352 @@
+//! Serde support, enabled with the `serde` feature, and a versioned JSON game
+//! record for saving games and sending them to other programs.
+//!
+//! Squares are written as their names, moves in UCI notation and enums in
+//! the snake case of their `code`, so the JSON stays the same if the types
+//! change inside. A `State` is written as its position, without the moves
+//! that led there; a `GameRecord` keeps the whole game.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::time::Duration;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::pgn;
+use crate::variant;
+use crate::{Move, Player, Pos, State, Termination};
+
+/// The version of the game record schema that is written. Records of a later
+/// version are refused, as they may mean something this version does not know.
+pub const RECORD_VERSION: u32 = 1;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RecordError {
+    InvalidJson,
+    UnsupportedVersion,
+    UnknownVariant,
+    InvalidFen,
+    IllegalMove,
+    InvalidResult,
+}
+
+impl RecordError {
+    pub fn to_string(&self) -> &str {
+        match self {
+            Self::InvalidJson => "The game record is not valid JSON of the game record schema",
+            Self::UnsupportedVersion => "The game record was written by a newer version",
+            Self::UnknownVariant => "The game record is of an unknown variant",
+            Self::InvalidFen => "The start position of the game record is not valid",
+            Self::IllegalMove => "The game record contains an illegal move",
+            Self::InvalidResult => "The result of the game record is not valid",
+        }
+    }
+}
+
+impl From<Pos> for String {
+    fn from(pos: Pos) -> String {
+        pos.to_notation()
+    }
+}
+
+impl TryFrom<String> for Pos {
+    type Error = &'static str;
+
+    fn try_from(notation: String) -> Result<Pos, Self::Error> {
+        Pos::from_notation(&notation).ok_or("invalid square")
+    }
+}
+
+impl From<Move> for String {
+    fn from(mv: Move) -> String {
+        mv.to_uci()
+    }
+}
+
+impl TryFrom<String> for Move {
+    type Error = &'static str;
+
+    fn try_from(uci: String) -> Result<Move, Self::Error> {
+        Move::from_uci(&uci).ok_or("invalid UCI move")
+    }
+}
+
+// How a `State` is written
+#[derive(Serialize, Deserialize)]
+struct Position {
+    variant: String,
+    #[serde(default)]
+    chess960: bool,
+    fen: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    termination: Option<Termination>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    winner: Option<Player>,
+}
+
+impl Serialize for State {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        Position {
+            variant: self.variant.name().to_string(),
+            chess960: self.chess960,
+            fen: self.to_fen(),
+            termination: self.termination,
+            winner: self.game_won_by,
+        }.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for State {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<State, D::Error> {
+        use serde::de::Error;
+
+        let position = Position::deserialize(deserializer)?;
+        let variant = variant::from_name(&position.variant).ok_or_else(|| D::Error::custom(RecordError::UnknownVariant.to_string()))?;
+        let mut state = State::from_fen_with_variant(&position.fen, variant)
+            .map_err(|_| D::Error::custom(RecordError::InvalidFen.to_string()))?;
+        state.chess960 |= position.chess960;
+        if let Some(termination) = position.termination {
+            state.end_game(position.winner, termination);
+        }
+        Ok(state)
+    }
+}
+
+/// A move of a game record with the time left on the mover's clock after it.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RecordedMove {
+    #[serde(rename = "move")]
+    pub mv: Move,
+    pub san: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub clock_ms: Option<u64>,
+}
+
+/// A complete game: its PGN tags, start position, moves with clock times,
+/// and result.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct GameRecord {
+    pub version: u32,
+    /// PGN tags like `Event` or `White`, except the ones kept in other fields.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    pub variant: String,
+    #[serde(default)]
+    pub chess960: bool,
+    pub start_fen: String,
+    /// In the form of the PGN `TimeControl` tag, like `300+2`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time_control: Option<String>,
+    pub moves: Vec<RecordedMove>,
+    /// `1-0`, `0-1`, `1/2-1/2` or `*`, as in PGN.
+    pub result: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub termination: Option<Termination>,
+}
+
+// Tags that have their own fields in a record
+const RECORD_TAGS: [&str; 6] = ["FEN", "SetUp", "Variant", "Result", "Termination", "TimeControl"];
+
+impl GameRecord {
+    /// The record of the game played in `state`. A game from a custom
+    /// position should pass its `FEN` tag, like for `to_pgn`, and the time
+    /// control is taken from the `TimeControl` tag. `clocks` holds the time
+    /// left after each move, as far as it is known.
+    pub fn from_state(state: &State, tags: &[(&str, &str)], clocks: &[Duration]) -> GameRecord {
+        let tag = |name| tags.iter().find(|(tag, _)| *tag == name).map(|(_, value)| value.to_string());
+        let start_fen = tag("FEN")
+            .or_else(|| state.chess960_id.and_then(State::new_chess960).map(|start| start.to_fen()))
+            .unwrap_or_else(|| state.variant.start_fen().to_string());
+        let moves = state.history.iter().enumerate().map(|(i, entry)| RecordedMove {
+            mv: entry.mv,
+            san: entry.san.clone(),
+            clock_ms: clocks.get(i).map(|clock| clock.as_millis() as u64),
+        }).collect();
+        GameRecord {
+            version: RECORD_VERSION,
+            tags: tags.iter()
+                .filter(|(name, _)| !RECORD_TAGS.contains(name))
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            variant: state.variant.name().to_string(),
+            chess960: state.chess960,
+            start_fen,
+            time_control: tag("TimeControl"),
+            moves,
+            result: pgn::result_string(&state.get_game_status()).to_string(),
+            termination: state.termination,
+        }
+    }
+
+    /// Replays the game. A result that the moves do not reach, like a
+    /// resignation, ends the game with the recorded termination.
+    pub fn to_state(&self) -> Result<State, RecordError> {
+        let variant = variant::from_name(&self.variant).ok_or(RecordError::UnknownVariant)?;
+        let mut state = State::from_fen_with_variant(&self.start_fen, variant).map_err(|_| RecordError::InvalidFen)?;
+        state.chess960 |= self.chess960;
+        for recorded in &self.moves {
+            state.play_move(recorded.mv).map_err(|_| RecordError::IllegalMove)?;
+        }
+        let winner = match self.result.as_str() {
+            "1-0" => Some(Player::White),
+            "0-1" => Some(Player::Black),
+            "1/2-1/2" | "*" => None,
+            _ => return Err(RecordError::InvalidResult),
+        };
+        if state.game_running && self.result != "*" {
+            state.end_game(winner, self.termination.unwrap_or(Termination::Adjudication));
+        }
+        Ok(state)
+    }
+
+    /// The time left after each move, up to the first move without one.
+    pub fn clocks(&self) -> Vec<Duration> {
+        self.moves.iter().map_while(|recorded| recorded.clock_ms.map(Duration::from_millis)).collect()
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("a game record is always valid JSON")
+    }
+
+    /// Reads a record of this version or an earlier one.
+    pub fn from_json(json: &str) -> Result<GameRecord, RecordError> {
+        let value: serde_json::Value = serde_json::from_str(json).map_err(|_| RecordError::InvalidJson)?;
+        match value.get("version").and_then(|version| version.as_u64()) {
+            Some(version) if version > RECORD_VERSION as u64 => return Err(RecordError::UnsupportedVersion),
+            Some(_) => {}
+            None => return Err(RecordError::InvalidJson),
+        }
+        serde_json::from_value(value).map_err(|_| RecordError::InvalidJson)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{GameStatus, MoveError, MoveSuccess, Piece, PieceType};
+
+    fn play(state: &mut State, sans: &[&str]) {
+        for san in sans {
+            let mv = state.move_from_san(san).unwrap();
+            state.play_move(mv).unwrap();
+        }
+    }
+
+    #[test]
+    fn types_test() {
+        assert_eq!(serde_json::to_string(&Pos::new(4, 3)).unwrap(), "\"e4\"");
+        assert_eq!(serde_json::from_str::<Pos>("\"h8\"").unwrap(), Pos::new(7, 7));
+        assert!(serde_json::from_str::<Pos>("\"i9\"").is_err());
+        let promotion = Move::with_promotion(Pos::new(0, 6), Pos::new(0, 7), PieceType::Queen);
+        assert_eq!(serde_json::to_string(&promotion).unwrap(), "\"a7a8q\"");
+        assert_eq!(serde_json::from_str::<Move>("\"N@f3\"").unwrap(), Move::new_drop(PieceType::Knight, Pos::new(5, 2)));
+
+        let piece = Piece::new(PieceType::Knight, Player::Black);
+        assert_eq!(serde_json::to_string(&piece).unwrap(), "{\"piece_type\":\"knight\",\"player\":\"black\"}");
+        assert_eq!(serde_json::from_str::<Piece>(&serde_json::to_string(&piece).unwrap()).unwrap(), piece);
+
+        // Enums are written as their codes
+        let error = MoveError::CastlingThroughCheck;
+        assert_eq!(serde_json::to_string(&error).unwrap(), format!("\"{}\"", error.code()));
+        let success = MoveSuccess::GameWonByWhite;
+        assert_eq!(serde_json::to_string(&success).unwrap(), format!("\"{}\"", success.code()));
+        let status = GameStatus::BlackTurn;
+        assert_eq!(serde_json::to_string(&status).unwrap(), format!("\"{}\"", status.code()));
+        assert_eq!(serde_json::from_str::<Termination>("\"threefold_repetition\"").unwrap(), Termination::ThreefoldRepetition);
+    }
+
+    #[test]
+    fn state_test() {
+        let mut state = State::new();
+        play(&mut state, &["e4", "c5", "Nf3"]);
+        let json = serde_json::to_string(&state).unwrap();
+        let parsed: State = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.to_fen(), state.to_fen());
+        assert!(parsed.get_game_running());
+
+        state.resign(Player::Black).unwrap();
+        let parsed: State = serde_json::from_str(&serde_json::to_string(&state).unwrap()).unwrap();
+        assert_eq!(parsed.get_termination(), Some(Termination::Resignation));
+        assert_eq!(parsed.get_game_status(), GameStatus::GameWonByWhite);
+
+        // The Chess960 start position that looks like the standard one
+        let chess960 = State::new_chess960(518).unwrap();
+        let parsed: State = serde_json::from_str(&serde_json::to_string(&chess960).unwrap()).unwrap();
+        assert!(parsed.is_chess960());
+
+        assert!(serde_json::from_str::<State>("{\"variant\":\"Shogi\",\"fen\":\"8/8/8/8/8/8/8/8 w - - 0 1\"}").is_err());
+    }
+
+    #[test]
+    fn record_test() {
+        let mut state = State::new();
+        play(&mut state, &["f3", "e5", "g4", "Qh4#"]);
+        let clocks = [Duration::from_millis(299_500), Duration::from_secs(298), Duration::from_secs(297)];
+        let record = GameRecord::from_state(&state, &[("White", "Ann"), ("TimeControl", "300+2"), ("Result", "0-1")], &clocks);
+
+        // The schema of version 1
+        let expected = r#"{
+  "version": 1,
+  "tags": {
+    "White": "Ann"
+  },
+  "variant": "Standard",
+  "chess960": false,
+  "start_fen": "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+  "time_control": "300+2",
+  "moves": [
+    {
+      "move": "f2f3",
+      "san": "f3",
+      "clock_ms": 299500
+    },
+    {
+      "move": "e7e5",
+      "san": "e5",
+      "clock_ms": 298000
+    },
+    {
+      "move": "g2g4",
+      "san": "g4",
+      "clock_ms": 297000
+    },
+    {
+      "move": "d8h4",
+      "san": "Qh4#"
+    }
+  ],
+  "result": "0-1",
+  "termination": "checkmate"
+}"#;
+        assert_eq!(record.to_json(), expected);
+        let parsed = GameRecord::from_json(expected).unwrap();
+        assert_eq!(parsed, record);
+        assert_eq!(parsed.clocks(), clocks);
+        let replayed = parsed.to_state().unwrap();
+        assert_eq!(replayed.to_fen(), state.to_fen());
+        assert_eq!(replayed.get_termination(), Some(Termination::Checkmate));
+    }
+
+    #[test]
+    fn record_result_test() {
+        // A resignation is not reached by the moves
+        let mut state = State::new_chess960(0).unwrap();
+        let mv = state.get_legal_moves().into_iter().next().unwrap();
+        state.play_move(mv).unwrap();
+        state.resign(Player::White).unwrap();
+        let record = GameRecord::from_state(&state, &[], &[]);
+        assert_eq!(record.start_fen, State::new_chess960(0).unwrap().to_fen());
+        let replayed = GameRecord::from_json(&record.to_json()).unwrap().to_state().unwrap();
+        assert_eq!(replayed.get_game_status(), GameStatus::GameWonByBlack);
+        assert_eq!(replayed.get_termination(), Some(Termination::Resignation));
+        assert!(replayed.is_chess960());
+
+        let mut newer = record.clone();
+        newer.version = RECORD_VERSION + 1;
+        assert_eq!(GameRecord::from_json(&newer.to_json()), Err(RecordError::UnsupportedVersion));
+        assert_eq!(GameRecord::from_json("{\"moves\": []}"), Err(RecordError::InvalidJson));
+        let mut illegal = record.clone();
+        illegal.moves.push(illegal.moves[0].clone());
+        assert_eq!(illegal.to_state().err(), Some(RecordError::IllegalMove));
+    }
+}
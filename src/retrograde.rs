@@ -0,0 +1,882 @@
+//! Endgame tables with the exact distance to mate, generated by retrograde
+//! analysis. Starting from the mates, the analysis walks moves backwards: a
+//! position is won in n plies if a move reaches a position lost in n - 1, and
+//! lost in n plies once every move reaches a position won for the opponent,
+//! the longest in n - 1.
+//!
+//! Positions are indexed by the squares of their pieces. Without pawns the
+//! board is turned and mirrored so the white king is in the a1-d1-d4
+//! triangle, with pawns it is only mirrored onto the a to d files. Castling
+//! and en passant are left out. Captures and promotions lead into smaller
+//! tables, which are generated first.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::castling::player_index;
+use crate::movegen::{BISHOP_DIRECTIONS, KING_STEPS, KNIGHT_STEPS, ROOK_DIRECTIONS};
+use crate::tablebase::{self, Tablebase, Wdl};
+use crate::{Piece, PieceType, Player, State};
+
+/// The most pieces, kings included, of a generated table.
+pub const MAX_PIECES: usize = 4;
+
+const MAGIC: [u8; 4] = *b"RTB1";
+const EXTENSION: &str = "rtb";
+const ILLEGAL: u8 = 255;
+const DRAW: u8 = 0;
+// Out of the count of moves left for a position that cannot be lost
+const CANNOT_LOSE: u8 = 255;
+const PIECE_ORDER: [PieceType; 5] = [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::Pawn];
+const PROMOTIONS: [PieceType; 4] = [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight];
+
+type Board = [Option<Piece>; 64];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GenerateError {
+    InvalidMaterial,
+    TooManyPieces,
+    NoMate,
+}
+
+impl GenerateError {
+    pub fn to_string(&self) -> &str {
+        match self {
+            Self::InvalidMaterial => "The material must be given like KRvK, with one king on each side",
+            Self::TooManyPieces => "Tables can have at most four pieces",
+            Self::NoMate => "Neither side can ever mate with this material",
+        }
+    }
+}
+
+/// The result of a position with best play, in plies to mate.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Dtm {
+    Win(u32),
+    Loss(u32),
+    Draw,
+}
+
+impl Dtm {
+    fn from_value(value: u8) -> Option<Dtm> {
+        match value {
+            ILLEGAL => None,
+            DRAW => Some(Dtm::Draw),
+            value if value % 2 == 0 => Some(Dtm::Win(value as u32 - 1)),
+            value => Some(Dtm::Loss(value as u32 - 1)),
+        }
+    }
+}
+
+/// The pieces besides the kings, in the order of the material key.
+#[derive(Clone, Debug, PartialEq)]
+struct Material {
+    white: Vec<PieceType>,
+    black: Vec<PieceType>,
+}
+
+impl Material {
+    fn parse(key: &str) -> Result<Material, GenerateError> {
+        let (white, black) = key.split_once('v').ok_or(GenerateError::InvalidMaterial)?;
+        let side = |side: &str| -> Result<Vec<PieceType>, GenerateError> {
+            let rest = side.strip_prefix('K').ok_or(GenerateError::InvalidMaterial)?;
+            rest.chars()
+                .map(|c| PieceType::from_letter(c).filter(|&piece_type| piece_type != PieceType::King))
+                .collect::<Option<Vec<_>>>()
+                .ok_or(GenerateError::InvalidMaterial)
+        };
+        let material = Material::new(side(white)?, side(black)?);
+        if material.white.len() + material.black.len() + 2 > MAX_PIECES {
+            return Err(GenerateError::TooManyPieces);
+        }
+        Ok(material)
+    }
+
+    fn new(mut white: Vec<PieceType>, mut black: Vec<PieceType>) -> Material {
+        let order = |piece_type: &PieceType| PIECE_ORDER.iter().position(|p| p == piece_type);
+        white.sort_by_key(order);
+        black.sort_by_key(order);
+        Material { white, black }
+    }
+
+    fn of_board(board: &Board) -> Material {
+        let pieces = |player: Player| board.iter().flatten()
+            .filter(|piece| piece.player == player && piece.piece_type != PieceType::King)
+            .map(|piece| piece.piece_type)
+            .collect();
+        Material::new(pieces(Player::White), pieces(Player::Black))
+    }
+
+    fn key(&self) -> String {
+        let side = |player: Player, pieces: &[PieceType]| -> Vec<Piece> {
+            std::iter::once(PieceType::King).chain(pieces.iter().copied()).map(|piece_type| Piece::new(piece_type, player)).collect()
+        };
+        tablebase::pieces_key(&[side(Player::White, &self.white), side(Player::Black, &self.black)].concat())
+    }
+
+    fn swapped(&self) -> Material {
+        Material { white: self.black.clone(), black: self.white.clone() }
+    }
+
+    fn has_pawns(&self) -> bool {
+        self.white.contains(&PieceType::Pawn) || self.black.contains(&PieceType::Pawn)
+    }
+
+    // The side with more material is white in the tables
+    fn normalized(&self) -> Material {
+        let value = |pieces: &[PieceType]| pieces.iter().map(|piece_type| piece_type.value()).sum::<i32>();
+        if (value(&self.black), self.black.len()) > (value(&self.white), self.white.len()) {
+            self.swapped()
+        } else {
+            self.clone()
+        }
+    }
+
+    // Neither side can ever mate with at most one minor piece on the board
+    fn is_dead_draw(&self) -> bool {
+        let pieces: Vec<PieceType> = self.white.iter().chain(self.black.iter()).copied().collect();
+        matches!(pieces.as_slice(), [] | [PieceType::Bishop] | [PieceType::Knight])
+    }
+
+    fn side(&self, player: Player) -> &Vec<PieceType> {
+        match player {
+            Player::White => &self.white,
+            Player::Black => &self.black,
+        }
+    }
+
+    fn with_side(&self, player: Player, pieces: Vec<PieceType>) -> Material {
+        match player {
+            Player::White => Material::new(pieces, self.black.clone()),
+            Player::Black => Material::new(self.white.clone(), pieces),
+        }
+    }
+
+    // The materials a capture or a promotion leads to
+    fn successors(&self) -> Vec<Material> {
+        let mut successors = Vec::new();
+        for &player in [Player::White, Player::Black].iter() {
+            for index in 0..self.side(player).len() {
+                let mut pieces = self.side(player).clone();
+                pieces.remove(index);
+                successors.push(self.with_side(player, pieces));
+            }
+        }
+        for &player in [Player::White, Player::Black].iter() {
+            for (index, &piece_type) in self.side(player).iter().enumerate() {
+                if piece_type != PieceType::Pawn {
+                    continue;
+                }
+                for &promotion in PROMOTIONS.iter() {
+                    let mut pieces = self.side(player).clone();
+                    pieces[index] = promotion;
+                    let promoted = self.with_side(player, pieces);
+                    // Promoting with a capture
+                    for captured in 0..promoted.side(player.opponent()).len() {
+                        let mut pieces = promoted.side(player.opponent()).clone();
+                        pieces.remove(captured);
+                        successors.push(promoted.with_side(player.opponent(), pieces));
+                    }
+                    successors.push(promoted);
+                }
+            }
+        }
+        successors
+    }
+}
+
+fn square(x: i32, y: i32) -> Option<usize> {
+    if (0..8).contains(&x) && (0..8).contains(&y) {
+        Some((y * 8 + x) as usize)
+    } else {
+        None
+    }
+}
+
+// One of the eight symmetries of the board: bit 0 mirrors the files, bit 1
+// the ranks and bit 2 swaps files and ranks
+fn transform(square: u8, symmetry: u8) -> u8 {
+    let (mut x, mut y) = (square % 8, square / 8);
+    if symmetry & 1 != 0 {
+        x = 7 - x;
+    }
+    if symmetry & 2 != 0 {
+        y = 7 - y;
+    }
+    if symmetry & 4 != 0 {
+        std::mem::swap(&mut x, &mut y);
+    }
+    y * 8 + x
+}
+
+/// How the positions of one material are numbered.
+#[derive(Clone, Debug)]
+struct Layout {
+    // The white king, the black king and then the other pieces
+    pieces: Vec<Piece>,
+    symmetries: Vec<u8>,
+    // The squares the white king is moved to, and each square's number
+    king_squares: Vec<u8>,
+    king_numbers: [Option<usize>; 64],
+    size: usize,
+}
+
+impl Layout {
+    fn new(material: &Material) -> Layout {
+        let mut pieces = vec![Piece::new(PieceType::King, Player::White), Piece::new(PieceType::King, Player::Black)];
+        pieces.extend(material.white.iter().map(|&piece_type| Piece::new(piece_type, Player::White)));
+        pieces.extend(material.black.iter().map(|&piece_type| Piece::new(piece_type, Player::Black)));
+
+        let (symmetries, king_squares): (Vec<u8>, Vec<u8>) = if material.has_pawns() {
+            (vec![0, 1], (0..64).filter(|square| square % 8 < 4).collect())
+        } else {
+            ((0..8).collect(), (0..64).filter(|square| square % 8 < 4 && square / 8 <= square % 8).collect())
+        };
+        let mut king_numbers = [None; 64];
+        for (number, &square) in king_squares.iter().enumerate() {
+            king_numbers[square as usize] = Some(number);
+        }
+        let size = king_squares.len() * 64usize.pow(pieces.len() as u32 - 1);
+        Layout { pieces, symmetries, king_squares, king_numbers, size }
+    }
+
+    // The index of the position with the pieces on `squares`, in the order of
+    // `pieces`. Of all the ways to turn the board and to order equal pieces,
+    // the smallest index is used, so every position has exactly one.
+    fn index(&self, squares: &[u8]) -> usize {
+        let mut best = usize::MAX;
+        for &symmetry in self.symmetries.iter() {
+            let king = match self.king_numbers[transform(squares[0], symmetry) as usize] {
+                Some(king) => king,
+                None => continue,
+            };
+            let mut moved = [0u8; MAX_PIECES];
+            for (target, &square) in moved.iter_mut().zip(squares.iter()) {
+                *target = transform(square, symmetry);
+            }
+            // Equal pieces are next to each other and go in the order of their squares
+            let mut start = 2;
+            while start < squares.len() {
+                let mut end = start + 1;
+                while end < squares.len() && self.pieces[end] == self.pieces[start] {
+                    end += 1;
+                }
+                moved[start..end].sort_unstable();
+                start = end;
+            }
+
+            let mut index = king;
+            for &square in moved[1..squares.len()].iter() {
+                index = index * 64 + square as usize;
+            }
+            best = best.min(index);
+        }
+        best
+    }
+
+    fn squares(&self, mut index: usize) -> [u8; MAX_PIECES] {
+        let mut squares = [0u8; MAX_PIECES];
+        for slot in (1..self.pieces.len()).rev() {
+            squares[slot] = (index % 64) as u8;
+            index /= 64;
+        }
+        squares[0] = self.king_squares[index];
+        squares
+    }
+
+    fn board(&self, squares: &[u8]) -> Option<Board> {
+        let mut board = [None; 64];
+        for (piece, &square) in self.pieces.iter().zip(squares.iter()) {
+            if board[square as usize].is_some() {
+                return None;
+            }
+            let rank = square / 8;
+            if piece.piece_type == PieceType::Pawn && (rank == 0 || rank == 7) {
+                return None;
+            }
+            board[square as usize] = Some(*piece);
+        }
+        Some(board)
+    }
+
+    // The squares of the pieces on `board` in the order of `pieces`
+    fn squares_of(&self, board: &Board) -> Option<[u8; MAX_PIECES]> {
+        let mut squares = [0u8; MAX_PIECES];
+        let mut used = [false; 64];
+        for (slot, piece) in self.pieces.iter().enumerate() {
+            let square = (0..64).find(|&square| !used[square] && board[square] == Some(*piece))?;
+            used[square] = true;
+            squares[slot] = square as u8;
+        }
+        Some(squares)
+    }
+}
+
+// The empty squares a piece on `from` can have come from with a move that
+// did not capture or promote
+fn retreat_targets(board: &Board, from: usize, piece: Piece) -> Vec<usize> {
+    let (x, y) = ((from % 8) as i32, (from / 8) as i32);
+    let mut targets = Vec::new();
+    let steps = |deltas: &[(i32, i32)], targets: &mut Vec<usize>| {
+        for &(dx, dy) in deltas {
+            if let Some(target) = square(x + dx, y + dy) {
+                if board[target].is_none() {
+                    targets.push(target);
+                }
+            }
+        }
+    };
+    let lines = |deltas: &[(i32, i32)], targets: &mut Vec<usize>| {
+        for &(dx, dy) in deltas {
+            let mut step = 1;
+            while let Some(target) = square(x + dx * step, y + dy * step) {
+                if board[target].is_some() {
+                    break;
+                }
+                targets.push(target);
+                step += 1;
+            }
+        }
+    };
+
+    match piece.piece_type {
+        PieceType::King => steps(&KING_STEPS, &mut targets),
+        PieceType::Knight => steps(&KNIGHT_STEPS, &mut targets),
+        PieceType::Rook => lines(&ROOK_DIRECTIONS, &mut targets),
+        PieceType::Bishop => lines(&BISHOP_DIRECTIONS, &mut targets),
+        PieceType::Queen => {
+            lines(&ROOK_DIRECTIONS, &mut targets);
+            lines(&BISHOP_DIRECTIONS, &mut targets);
+        }
+        PieceType::Pawn => {
+            let (backward, first_rank, double_rank) = match piece.player {
+                Player::White => (-1, 0, 3),
+                Player::Black => (1, 7, 4),
+            };
+            if let Some(target) = square(x, y + backward).filter(|&target| board[target].is_none()) {
+                // A pawn never stands on its first rank
+                if y + backward != first_rank {
+                    targets.push(target);
+                }
+                if y == double_rank {
+                    if let Some(target) = square(x, y + 2 * backward).filter(|&target| board[target].is_none()) {
+                        targets.push(target);
+                    }
+                }
+            }
+        }
+    }
+    targets
+}
+
+fn flip_colors(board: &Board) -> Board {
+    let mut flipped = [None; 64];
+    for (square, piece) in board.iter().enumerate() {
+        if let Some(piece) = piece {
+            flipped[transform(square as u8, 2) as usize] = Some(Piece::new(piece.piece_type, piece.player.opponent()));
+        }
+    }
+    flipped
+}
+
+/// The distance to mate for every position of one material.
+#[derive(Clone, Debug)]
+pub struct Table {
+    material: Material,
+    layout: Layout,
+    // One byte per position for each player to move: 0 for a draw, 255 for
+    // an impossible position and otherwise the plies to mate plus one, which
+    // is even for a win and odd for a loss
+    values: [Vec<u8>; 2],
+}
+
+impl Table {
+    /// The material like `KRvK`.
+    pub fn key(&self) -> String {
+        self.material.key()
+    }
+
+    /// The number of positions for each player to move.
+    pub fn size(&self) -> usize {
+        self.layout.size
+    }
+
+    fn value(&self, board: &Board, player: Player) -> Option<u8> {
+        let squares = self.layout.squares_of(board)?;
+        let index = self.layout.index(&squares[..self.layout.pieces.len()]);
+        Some(self.values[player_index(player)][index])
+    }
+
+    /// The longest win in plies with `player` to move.
+    pub fn longest_win(&self, player: Player) -> u32 {
+        self.values[player_index(player)].iter()
+            .filter_map(|&value| match Dtm::from_value(value) {
+                Some(Dtm::Win(plies)) => Some(plies),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    fn write(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let key = self.key();
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[key.len() as u8])?;
+        writer.write_all(key.as_bytes())?;
+        writer.write_all(&self.values[0])?;
+        writer.write_all(&self.values[1])
+    }
+
+    fn read(reader: &mut dyn Read) -> io::Result<Table> {
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "not a generated table");
+        let mut header = [0; 5];
+        reader.read_exact(&mut header)?;
+        if header[..4] != MAGIC {
+            return Err(invalid());
+        }
+        let mut key = vec![0; header[4] as usize];
+        reader.read_exact(&mut key)?;
+        let material = Material::parse(std::str::from_utf8(&key).map_err(|_| invalid())?).map_err(|_| invalid())?;
+
+        let layout = Layout::new(&material);
+        let mut values = [vec![0; layout.size], vec![0; layout.size]];
+        reader.read_exact(&mut values[0])?;
+        reader.read_exact(&mut values[1])?;
+        Ok(Table { material, layout, values })
+    }
+}
+
+/// A set of generated tables that can be probed like any `Tablebase`.
+#[derive(Clone, Debug, Default)]
+pub struct GeneratedTables {
+    tables: BTreeMap<String, Table>,
+}
+
+impl GeneratedTables {
+    pub fn new() -> GeneratedTables {
+        GeneratedTables::default()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Table> {
+        self.tables.get(key)
+    }
+
+    pub fn keys(&self) -> Vec<&str> {
+        self.tables.keys().map(String::as_str).collect()
+    }
+
+    /// Generates the table for a material like `KBNvK`, and first every
+    /// smaller table that captures and promotions lead to.
+    pub fn generate(&mut self, key: &str) -> Result<&Table, GenerateError> {
+        let material = Material::parse(key)?.normalized();
+        if material.is_dead_draw() {
+            return Err(GenerateError::NoMate);
+        }
+        self.generate_material(&material);
+        Ok(&self.tables[&material.key()])
+    }
+
+    fn generate_material(&mut self, material: &Material) {
+        if material.is_dead_draw() || self.tables.contains_key(&material.key()) {
+            return;
+        }
+        for successor in material.successors() {
+            self.generate_material(&successor.normalized());
+        }
+        let table = Generator::new(self, material.clone()).run();
+        self.tables.insert(material.key(), table);
+    }
+
+    // The value of a position in any of the tables
+    fn lookup(&self, state: &State) -> Option<u8> {
+        let material = Material::of_board(&state.board);
+        if material.is_dead_draw() {
+            return Some(DRAW);
+        }
+        let player = state.get_current_player();
+        if let Some(table) = self.tables.get(&tablebase::material_key(state)) {
+            return table.value(&state.board, player);
+        }
+        let table = self.tables.get(&material.swapped().key())?;
+        table.value(&flip_colors(&state.board), player.opponent())
+    }
+
+    fn probe(&self, state: &State) -> Option<Dtm> {
+        if state.variant.name() != "Standard" || state.castling_rights.iter().flatten().any(Option::is_some) {
+            return None;
+        }
+        Dtm::from_value(self.lookup(state)?)
+    }
+
+    /// Writes every table to a file in `dir`, named like `KRvK.rtb`.
+    pub fn save(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        for (key, table) in self.tables.iter() {
+            let mut writer = BufWriter::new(File::create(dir.join(format!("{}.{}", key, EXTENSION)))?);
+            table.write(&mut writer)?;
+            writer.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Reads every table in `dir`.
+    pub fn load(dir: &Path) -> io::Result<GeneratedTables> {
+        let mut tables = GeneratedTables::new();
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) == Some(EXTENSION) {
+                let table = Table::read(&mut BufReader::new(File::open(&path)?))?;
+                tables.tables.insert(table.key(), table);
+            }
+        }
+        Ok(tables)
+    }
+}
+
+impl Tablebase for GeneratedTables {
+    fn max_pieces(&self) -> u32 {
+        self.tables.values().map(|table| table.layout.pieces.len() as u32).max().unwrap_or(0)
+    }
+
+    fn probe_wdl(&self, state: &State) -> Option<Wdl> {
+        Some(match self.probe(state)? {
+            Dtm::Win(_) => Wdl::Win,
+            Dtm::Loss(_) => Wdl::Loss,
+            Dtm::Draw => Wdl::Draw,
+        })
+    }
+
+    // Only the distance to mate is known
+    fn probe_dtz(&self, _state: &State) -> Option<i32> {
+        None
+    }
+
+    fn probe_dtm(&self, state: &State) -> Option<i32> {
+        match self.probe(state)? {
+            Dtm::Win(plies) => Some(plies as i32),
+            Dtm::Loss(plies) => Some(-(plies as i32)),
+            Dtm::Draw => Some(0),
+        }
+    }
+}
+
+// The retrograde analysis of one table
+struct Generator<'a> {
+    tables: &'a GeneratedTables,
+    material: Material,
+    layout: Layout,
+    values: [Vec<u8>; 2],
+    // Moves not yet known to lose, counted once per position they lead to
+    moves_left: [Vec<u8>; 2],
+    // The longest loss among the moves known to lose
+    longest_loss: [Vec<u8>; 2],
+    // Positions found to be won or lost, by their plies to mate
+    found: Vec<Vec<(Player, usize)>>,
+}
+
+impl<'a> Generator<'a> {
+    fn new(tables: &'a GeneratedTables, material: Material) -> Generator<'a> {
+        let layout = Layout::new(&material);
+        let size = layout.size;
+        Generator {
+            tables,
+            material,
+            layout,
+            values: [vec![ILLEGAL; size], vec![ILLEGAL; size]],
+            moves_left: [vec![0; size], vec![0; size]],
+            longest_loss: [vec![0; size], vec![0; size]],
+            found: vec![Vec::new(); ILLEGAL as usize],
+        }
+    }
+
+    fn board(&self, index: usize) -> Option<Board> {
+        let squares = self.layout.squares(index);
+        let squares = &squares[..self.layout.pieces.len()];
+        if self.layout.index(squares) != index {
+            return None;
+        }
+        self.layout.board(squares)
+    }
+
+    // Looks at every legal move of every position, and at where the captures
+    // and promotions lead in the smaller tables. The positions can neither
+    // castle nor take en passant.
+    fn initialize(&mut self) {
+        let mut state = State::new();
+        state.castling_rights = [[None; 2]; 2];
+        for index in 0..self.layout.size {
+            state.board = match self.board(index) {
+                Some(board) => board,
+                None => continue,
+            };
+            for &player in [Player::White, Player::Black].iter() {
+                state.current_player = player;
+                if !state.is_in_check(player.opponent()) {
+                    self.initialize_position(&state, index);
+                }
+            }
+        }
+    }
+
+    fn initialize_position(&mut self, state: &State, index: usize) {
+        let player = state.get_current_player();
+        let side = player_index(player);
+        let moves = state.get_legal_moves();
+        if moves.is_empty() {
+            self.values[side][index] = DRAW;
+            if state.is_in_check(player) {
+                self.found[0].push((player, index));
+            }
+            return;
+        }
+
+        let mut children = Vec::new();
+        let mut cannot_lose = false;
+        let mut longest_loss = 0;
+        let mut shortest_win = None;
+        for mv in moves {
+            // Captures and promotions leave the table, the other moves only
+            // move a piece
+            if !state.is_capture(mv) && mv.promotion.is_none() {
+                let mut after = state.board;
+                after[mv.to.index()] = after[mv.from.index()].take();
+                children.push(self.layout.index(&self.layout.squares_of(&after).unwrap()[..self.layout.pieces.len()]));
+                continue;
+            }
+            match self.tables.lookup(&state.preview_move(mv)).and_then(Dtm::from_value) {
+                Some(Dtm::Loss(plies)) => {
+                    cannot_lose = true;
+                    shortest_win = Some(shortest_win.unwrap_or(u32::MAX).min(plies + 1));
+                }
+                Some(Dtm::Win(plies)) => longest_loss = longest_loss.max(plies + 1),
+                _ => cannot_lose = true,
+            }
+        }
+        children.sort_unstable();
+        children.dedup();
+
+        self.values[side][index] = DRAW;
+        self.longest_loss[side][index] = longest_loss as u8;
+        self.moves_left[side][index] = if cannot_lose { CANNOT_LOSE } else { children.len() as u8 };
+        if let Some(plies) = shortest_win {
+            self.found[plies as usize].push((player, index));
+        } else if children.is_empty() && !cannot_lose {
+            self.found[longest_loss as usize].push((player, index));
+        }
+    }
+
+    // The positions with `player` to move from which the opponent's move led
+    // to `board`, without repeats
+    fn predecessors(&self, board: &Board, player: Player) -> Vec<usize> {
+        let mut predecessors = Vec::new();
+        for from in 0..64 {
+            let piece = match board[from] {
+                Some(piece) if piece.player == player => piece,
+                _ => continue,
+            };
+            for to in retreat_targets(board, from, piece) {
+                let mut before = *board;
+                before[from] = None;
+                before[to] = Some(piece);
+                let squares = self.layout.squares_of(&before).unwrap();
+                predecessors.push(self.layout.index(&squares[..self.layout.pieces.len()]));
+            }
+        }
+        predecessors.sort_unstable();
+        predecessors.dedup();
+        predecessors
+    }
+
+    fn run(mut self) -> Table {
+        self.initialize();
+
+        for plies in 0..self.found.len() {
+            let value = plies as u8 + 1;
+            let positions = std::mem::take(&mut self.found[plies]);
+            for (player, index) in positions {
+                let side = player_index(player);
+                if self.values[side][index] != DRAW || value == ILLEGAL {
+                    continue;
+                }
+                self.values[side][index] = value;
+
+                let board = self.board(index).unwrap();
+                let mover = player.opponent();
+                let mover_side = player_index(mover);
+                for predecessor in self.predecessors(&board, mover) {
+                    if self.values[mover_side][predecessor] != DRAW {
+                        continue;
+                    }
+                    if plies % 2 == 0 {
+                        // The player to move here loses, so the move to here wins
+                        if plies + 1 < self.found.len() {
+                            self.found[plies + 1].push((mover, predecessor));
+                        }
+                    } else if self.moves_left[mover_side][predecessor] != CANNOT_LOSE {
+                        let longest = self.longest_loss[mover_side][predecessor].max(value);
+                        self.longest_loss[mover_side][predecessor] = longest;
+                        self.moves_left[mover_side][predecessor] -= 1;
+                        if self.moves_left[mover_side][predecessor] == 0 && (longest as usize) < self.found.len() {
+                            self.found[longest as usize].push((mover, predecessor));
+                        }
+                    }
+                }
+            }
+        }
+
+        Table { material: self.material, layout: self.layout, values: self.values }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::{self, Score, SearchLimits};
+    use crate::tablebase;
+    use std::sync::Arc;
+
+    fn probe(tables: &GeneratedTables, fen: &str) -> Option<Dtm> {
+        tables.probe(&State::from_fen(fen).unwrap())
+    }
+
+    #[test]
+    fn material_test() {
+        assert_eq!(Material::parse("KBNvK").unwrap().key(), "KBNvK");
+        assert_eq!(Material::parse("KNBvK").unwrap().key(), "KBNvK");
+        assert_eq!(Material::parse("KvKR").unwrap().normalized().key(), "KRvK");
+        assert_eq!(Material::parse("KRvKQ").unwrap().normalized().key(), "KQvKR");
+        assert_eq!(Material::parse("KRR"), Err(GenerateError::InvalidMaterial));
+        assert_eq!(Material::parse("KQRvKR"), Err(GenerateError::TooManyPieces));
+        assert_eq!(GeneratedTables::new().generate("KBvK").err(), Some(GenerateError::NoMate));
+
+        let successors: Vec<String> = Material::parse("KPvK").unwrap().successors().iter().map(Material::key).collect();
+        assert_eq!(successors, ["KvK", "KQvK", "KRvK", "KBvK", "KNvK"]);
+    }
+
+    #[test]
+    fn index_test() {
+        let layout = Layout::new(&Material::parse("KRvK").unwrap());
+        assert_eq!(layout.size, 10 * 64 * 64);
+        // Kh8 Rg7 against Ka8 is Ka1 Rb2 against Kh1 turned around
+        assert_eq!(layout.index(&[63, 54, 56]), layout.index(&[0, 9, 7]));
+        for squares in [[4, 27, 60], [20, 3, 40], [63, 0, 18]] {
+            let index = layout.index(&squares);
+            assert_eq!(layout.index(&layout.squares(index)[..3]), index);
+        }
+
+        let layout = Layout::new(&Material::parse("KPvK").unwrap());
+        assert_eq!(layout.size, 32 * 64 * 64);
+        assert_eq!(layout.index(&[7, 14, 40]), layout.index(&[0, 9, 47]));
+        assert!(layout.index(&[7, 14, 40]) != layout.index(&[56, 49, 0]));
+
+        let layout = Layout::new(&Material::parse("KRRvK").unwrap());
+        assert_eq!(layout.index(&[0, 63, 10, 20]), layout.index(&[0, 63, 20, 10]));
+    }
+
+    // The moves taken back must be the legal moves of the game, quiet ones
+    // that stay in the table
+    #[test]
+    fn predecessors_test() {
+        let tables = GeneratedTables::new();
+        let fens = ["8/8/8/3k4/8/8/8/R3K3 w - - 0 1", "8/2k5/8/8/4Q3/8/1r6/6K1 b - - 0 1",
+                    "8/8/8/8/4k3/8/3P4/3K4 w - - 0 1", "8/3p4/8/2k5/8/8/6N1/3K4 b - - 0 1",
+                    "1k6/8/1K6/8/8/8/8/B6b w - - 0 1"];
+        for fen in fens.iter() {
+            let state = State::from_fen(fen).unwrap();
+            let generator = Generator::new(&tables, Material::of_board(&state.board));
+            let index = |board: &Board| generator.layout.index(&generator.layout.squares_of(board).unwrap()[..generator.layout.pieces.len()]);
+            let player = state.get_current_player();
+            let quiet = |state: &State| -> Vec<usize> {
+                state.get_legal_moves().into_iter()
+                    .filter(|&mv| !state.is_capture(mv) && mv.promotion.is_none())
+                    .map(|mv| index(&state.preview_move(mv).board))
+                    .collect()
+            };
+
+            for after in quiet(&state) {
+                let board = generator.board(after).unwrap();
+                assert!(generator.predecessors(&board, player).contains(&index(&state.board)), "{}", fen);
+            }
+            for predecessor in generator.predecessors(&state.board, player.opponent()) {
+                let mut before = state.clone();
+                before.board = generator.board(predecessor).unwrap();
+                before.current_player = player.opponent();
+                if !before.is_in_check(player) {
+                    assert!(quiet(&before).contains(&index(&state.board)), "{}", fen);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn krk_test() {
+        let mut tables = GeneratedTables::new();
+        let table = tables.generate("KRvK").unwrap();
+        // The longest mate is 16 moves
+        assert_eq!(table.longest_win(Player::White), 31);
+
+        assert_eq!(probe(&tables, "k7/8/1K6/8/8/8/8/7R w - - 0 1"), Some(Dtm::Win(1)));
+        assert_eq!(probe(&tables, "R1k5/8/2K5/8/8/8/8/8 b - - 0 1"), Some(Dtm::Loss(0)));
+        // The kings touch
+        assert_eq!(probe(&tables, "8/8/8/8/8/8/1k6/R1K5 b - - 0 1"), None);
+        // Black takes the rook
+        assert_eq!(probe(&tables, "8/8/8/8/8/8/1kR5/4K3 b - - 0 1"), Some(Dtm::Draw));
+        // Colors swapped
+        assert_eq!(probe(&tables, "K7/8/1k6/8/8/8/8/7r b - - 0 1"), Some(Dtm::Win(1)));
+        assert_eq!(probe(&tables, "8/8/8/3k4/8/8/8/R3K3 w Q - 0 1"), None);
+
+        // The search plays the mate the tables know
+        let state = State::from_fen("8/8/8/3k4/8/8/8/R3K3 w - - 0 1").unwrap();
+        let limits = SearchLimits { tablebase: Some(Arc::new(tables.clone())), ..SearchLimits::default() };
+        let info = search::search(&state, &limits, &mut |_| ());
+        let Some(Dtm::Win(plies)) = tables.probe(&state) else { panic!("not a win") };
+        assert_eq!(info.score, Score::Mate((plies as i32 + 1) / 2));
+        let moves = tablebase::probe_root(&tables, &state).unwrap();
+        assert_eq!(moves[0].dtm, Some(plies as i32));
+        assert_eq!(info.best_move(), Some(moves[0].mv));
+    }
+
+    #[test]
+    fn kpk_test() {
+        let mut tables = GeneratedTables::new();
+        tables.generate("KPvK").unwrap();
+        assert_eq!(tables.keys(), ["KPvK", "KQvK", "KRvK"]);
+        assert_eq!(tables.get("KQvK").unwrap().longest_win(Player::White), 19);
+
+        // With the king in front of the pawn on the sixth rank white wins
+        assert!(matches!(probe(&tables, "3k4/8/3K4/3P4/8/8/8/8 w - - 0 1"), Some(Dtm::Win(_))));
+        assert!(matches!(probe(&tables, "3k4/8/3K4/3P4/8/8/8/8 b - - 0 1"), Some(Dtm::Loss(_))));
+        assert!(matches!(probe(&tables, "3k4/3P4/3K4/8/8/8/8/8 w - - 0 1"), Some(Dtm::Win(_))));
+        // Stalemate
+        assert_eq!(probe(&tables, "3k4/3P4/3K4/8/8/8/8/8 b - - 0 1"), Some(Dtm::Draw));
+        // The king in the corner holds against a rook pawn
+        assert_eq!(probe(&tables, "k7/8/8/8/8/8/P7/K7 w - - 0 1"), Some(Dtm::Draw));
+        // Black is outside the square of the pawn
+        assert!(matches!(probe(&tables, "7k/8/8/8/P7/8/8/K7 b - - 0 1"), Some(Dtm::Loss(_))));
+        // Colors swapped
+        assert!(matches!(probe(&tables, "k7/8/8/p7/8/8/8/7K w - - 0 1"), Some(Dtm::Loss(_))));
+    }
+
+    #[test]
+    fn save_test() {
+        let mut tables = GeneratedTables::new();
+        tables.generate("KQvK").unwrap();
+        let dir = std::env::temp_dir().join(format!("retrograde_save_test_{}", std::process::id()));
+        tables.save(&dir).unwrap();
+        let loaded = GeneratedTables::load(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.keys(), ["KQvK"]);
+        assert_eq!(loaded.get("KQvK").unwrap().values, tables.get("KQvK").unwrap().values);
+        assert_eq!(loaded.max_pieces(), 3);
+    }
+
+    #[test]
+    fn kbnk_test() {
+        let mut tables = GeneratedTables::new();
+        let table = tables.generate("KBNvK").unwrap();
+        // The longest mate is 33 moves
+        assert_eq!(table.longest_win(Player::White), 65);
+    }
+}
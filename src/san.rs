@@ -0,0 +1,179 @@
+use crate::castling::KING_SIDE;
+use crate::{Move, PieceType, Pos, State};
+
+impl State {
+    /// SAN for a move without the check or mate suffix. The move is assumed to be legal.
+    pub(crate) fn get_san_base(&self, mv: Move) -> String {
+        if let Some(piece_type) = mv.drop {
+            return format!("{}@{}", piece_type.to_letter(), mv.to.to_notation());
+        }
+
+        match self.get_castling_side(mv.from, mv.to) {
+            Some(KING_SIDE) => return String::from("O-O"),
+            Some(_) => return String::from("O-O-O"),
+            None => (),
+        }
+
+        let piece = self.get(mv.from).unwrap();
+        let is_capture = self.is_capture(mv);
+        let mut san = String::new();
+
+        if piece.piece_type == PieceType::Pawn {
+            if is_capture {
+                san.push((b'a' + mv.from.x as u8) as char);
+            }
+        } else {
+            san.push(piece.piece_type.to_letter());
+            san.push_str(&self.get_disambiguation(mv));
+        }
+
+        if is_capture {
+            san.push('x');
+        }
+        san.push_str(&mv.to.to_notation());
+        if let Some(promotion) = mv.promotion {
+            san.push('=');
+            san.push(promotion.to_letter());
+        }
+        san
+    }
+
+    // Other pieces of the same kind that can reach the same square decide
+    // whether the file, the rank or the whole square has to be added
+    fn get_disambiguation(&self, mv: Move) -> String {
+        let piece = self.get(mv.from).unwrap();
+        let others: Vec<Pos> = self.get_all_pieces_for_player(piece.player)
+            .into_iter()
+            .filter(|&pos| pos != mv.from && self.get(pos) == Some(piece) && self.is_legal_move(pos, mv.to))
+            .collect();
+
+        if others.is_empty() {
+            String::new()
+        } else if others.iter().all(|pos| pos.x != mv.from.x) {
+            mv.from.to_notation()[..1].to_string()
+        } else if others.iter().all(|pos| pos.y != mv.from.y) {
+            mv.from.to_notation()[1..].to_string()
+        } else {
+            mv.from.to_notation()
+        }
+    }
+
+    /// SAN for a legal move in the current position, including `+` or `#`.
+    pub fn move_to_san(&self, mv: Move) -> Option<String> {
+        let mut state_copy = self.clone();
+        state_copy.play_move(mv).ok()?;
+        state_copy.history.pop().map(|entry| entry.san)
+    }
+
+    /// The legal move written as `san` in the current position. Check marks and
+    /// annotations like `!?` are optional, and `0-0` is read as `O-O`.
+    pub fn move_from_san(&self, san: &str) -> Option<Move> {
+        let strip = |san: &str| san.trim_end_matches(|c| "+#!?".contains(c)).replace('0', "O");
+        let wanted = strip(san);
+        // Only the moves of the named piece to the named square get their SAN
+        // written, which is the same as in `move_to_san` without the suffix
+        let castling = wanted.starts_with("O-O");
+        let target = wanted.split('=').next()
+            .and_then(|base| base.get(base.len().saturating_sub(2)..))
+            .and_then(Pos::from_notation);
+        let piece_type = match wanted.chars().next()? {
+            c if c.is_ascii_uppercase() => PieceType::from_letter(c),
+            _ => Some(PieceType::Pawn),
+        };
+        self.get_legal_moves().into_iter()
+            .filter(|&mv| if castling {
+                mv.drop.is_none() && self.get_castling_side(mv.from, mv.to).is_some()
+            } else {
+                Some(mv.to) == target && mv.drop.or_else(|| self.get(mv.from).map(|piece| piece.piece_type)) == piece_type
+            })
+            .find(|&mv| strip(&self.get_san_base(mv)) == wanted)
+    }
+
+    /// SAN for a line of moves played from the current position, up to the
+    /// first move that is not legal.
+    pub fn line_to_san(&self, line: &[Move]) -> Vec<String> {
+        let mut state_copy = self.clone();
+        let mut sans = Vec::new();
+        for &mv in line {
+            if state_copy.play_move(mv).is_err() {
+                break;
+            }
+            sans.push(state_copy.history.last().unwrap().san.clone());
+        }
+        sans
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Move, Piece, PieceType, Player, Pos, State};
+
+    fn play(state: &mut State, moves: &[(&str, &str)]) {
+        for (from, to) in moves {
+            let from = Pos::from_notation(from).unwrap();
+            let to = Pos::from_notation(to).unwrap();
+            assert!(state.move_piece(from, to).is_ok());
+        }
+    }
+
+    #[test]
+    fn san_pawn_and_piece_test() {
+        let mut state = State::new();
+        play(&mut state, &[("e2", "e4"), ("d7", "d5"), ("e4", "d5"), ("g8", "f6"), ("b1", "c3")]);
+        let sans: Vec<&str> = state.get_history().iter().map(|e| e.san.as_str()).collect();
+        assert_eq!(sans, ["e4", "d5", "exd5", "Nf6", "Nc3"]);
+    }
+
+    #[test]
+    fn san_check_and_mate_test() {
+        let mut state = State::new();
+        play(&mut state, &[("f2", "f3"), ("e7", "e5"), ("g2", "g4"), ("d8", "h4")]);
+        assert_eq!(state.get_history()[3].san, "Qh4#");
+
+        let mut state = State::new();
+        play(&mut state, &[("e2", "e4"), ("f7", "f6"), ("d1", "h5")]);
+        assert_eq!(state.get_history()[2].san, "Qh5+");
+    }
+
+    #[test]
+    fn san_disambiguation_test() {
+        let mut state = State::new();
+        play(&mut state, &[("g1", "f3"), ("a7", "a6"), ("f3", "d4"), ("a6", "a5")]);
+        let mv = Move::new(Pos::from_notation("b1").unwrap(), Pos::from_notation("c3").unwrap());
+        assert_eq!(state.move_to_san(mv).unwrap(), "Nc3");
+
+        state.set(Pos::from_notation("b1").unwrap(), None);
+        state.set(Pos::from_notation("h4").unwrap(), Some(Piece::new(PieceType::Knight, Player::White)));
+        let mv = Move::new(Pos::from_notation("h4").unwrap(), Pos::from_notation("f3").unwrap());
+        assert_eq!(state.move_to_san(mv).unwrap(), "Nhf3");
+
+        state.set(Pos::from_notation("h4").unwrap(), None);
+        state.set(Pos::from_notation("d7").unwrap(), None);
+        state.set(Pos::from_notation("d6").unwrap(), Some(Piece::new(PieceType::Knight, Player::White)));
+        let mv = Move::new(Pos::from_notation("d6").unwrap(), Pos::from_notation("f5").unwrap());
+        assert_eq!(state.move_to_san(mv).unwrap(), "N6f5");
+    }
+
+    #[test]
+    fn san_line_test() {
+        let state = State::new();
+        let line: Vec<Move> = ["e2e4", "e7e5", "g1f3", "e5e4", "b8c6"].iter().map(|mv| Move::from_uci(mv).unwrap()).collect();
+        assert_eq!(state.line_to_san(&line), ["e4", "e5", "Nf3"]);
+
+        assert_eq!(state.move_from_san("Nf3"), Move::from_uci("g1f3"));
+        assert_eq!(state.move_from_san("e4!?"), Move::from_uci("e2e4"));
+        assert_eq!(state.move_from_san("Ke2"), None);
+        assert_eq!(state.move_from_san(""), None);
+
+        // Castling, promotions and captures
+        let state = State::from_fen("r3k2r/1P6/8/8/8/8/8/R3K1NR w KQkq - 0 1").unwrap();
+        assert_eq!(state.move_from_san("O-O-O"), Move::from_uci("e1c1"));
+        assert_eq!(state.move_from_san("0-0-0+"), Move::from_uci("e1c1"));
+        assert_eq!(state.move_from_san("O-O"), None);
+        assert_eq!(state.move_from_san("bxa8=Q+"), Move::from_uci("b7a8q"));
+        assert_eq!(state.move_from_san("b8=N"), Move::from_uci("b7b8n"));
+        assert_eq!(state.move_from_san("Rb1"), Move::from_uci("a1b1"));
+        assert_eq!(state.move_from_san("Rf1"), None);
+        assert_eq!(state.move_from_san("Nf3"), Move::from_uci("g1f3"));
+    }
+}
@@ -0,0 +1,479 @@
+//! A small alpha-beta search for the built-in engine. It uses the variant's
+//! rules for moves and game ends, so it plays every variant, if not well.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::tablebase::{self, RootMove, Tablebase, Wdl};
+use crate::{Move, Outcome, PieceType, Player, State};
+
+const MATE: i32 = 100_000;
+/// The value of a win the tablebases know, less the plies to reach it.
+pub const TB_WIN: i32 = 20_000;
+const MAX_DEPTH: u32 = 64;
+// How many nodes to search between looking at the clock
+const CHECK_INTERVAL: u64 = 256;
+
+/// An evaluation from the point of view of the player to move.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Score {
+    Centipawns(i32),
+    /// Mate in that many moves, negative when the player to move gets mated.
+    Mate(i32),
+}
+
+impl Score {
+    fn from_value(value: i32) -> Score {
+        if value >= MATE - MAX_DEPTH as i32 * 2 {
+            Score::Mate((MATE - value + 1) / 2)
+        } else if value <= -MATE + MAX_DEPTH as i32 * 2 {
+            Score::Mate(-(MATE + value + 1) / 2)
+        } else {
+            Score::Centipawns(value)
+        }
+    }
+
+    /// Centipawns, with mates as large values that favour quicker mates.
+    pub fn to_centipawns(&self) -> i32 {
+        match *self {
+            Score::Centipawns(centipawns) => centipawns,
+            Score::Mate(moves) if moves > 0 => MATE - moves,
+            Score::Mate(moves) => -MATE - moves,
+        }
+    }
+}
+
+/// When to stop searching. The search ends at whichever limit comes first
+/// and runs to `MAX_DEPTH` if none is set.
+#[derive(Clone, Debug, Default)]
+pub struct SearchLimits {
+    pub depth: Option<u32>,
+    pub time: Option<Duration>,
+    pub nodes: Option<u64>,
+    /// Set from another thread to stop the search early.
+    pub stop: Option<Arc<AtomicBool>>,
+    /// Endgame tables for exact results with few pieces left.
+    pub tablebase: Option<Arc<dyn Tablebase>>,
+}
+
+impl SearchLimits {
+    pub fn depth(depth: u32) -> SearchLimits {
+        SearchLimits { depth: Some(depth), ..SearchLimits::default() }
+    }
+
+    pub fn time(time: Duration) -> SearchLimits {
+        SearchLimits { time: Some(time), ..SearchLimits::default() }
+    }
+}
+
+/// Time to spend on a move with `time_left` on the clock, `moves_left` moves
+/// until the next time control, or a guess, and `increment` per move.
+pub fn allocate_time(time_left: Duration, moves_left: u32, increment: Duration) -> Duration {
+    (time_left / moves_left.max(1) + increment * 3 / 4).min(time_left / 2)
+}
+
+/// The result of one iteration of the search, and of the whole search.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SearchInfo {
+    pub depth: u32,
+    pub score: Score,
+    pub nodes: u64,
+    pub time: Duration,
+    /// The principal variation, starting with the best move.
+    pub pv: Vec<Move>,
+}
+
+impl SearchInfo {
+    pub fn best_move(&self) -> Option<Move> {
+        self.pv.first().copied()
+    }
+}
+
+fn piece_value(piece_type: PieceType) -> i32 {
+    match piece_type {
+        PieceType::King => 0,
+        PieceType::Queen => 900,
+        PieceType::Rook => 500,
+        PieceType::Bishop => 330,
+        PieceType::Knight => 320,
+        PieceType::Pawn => 100,
+    }
+}
+
+/// A static evaluation in centipawns from the point of view of the player to
+/// move: material, pieces in the pockets, and small bonuses for central
+/// pieces and advanced pawns.
+pub fn evaluate(state: &State) -> i32 {
+    let mut score = 0;
+    for (index, square) in state.board.iter().enumerate() {
+        let piece = match square {
+            Some(piece) => piece,
+            None => continue,
+        };
+        let (x, y) = ((index % 8) as i32, (index / 8) as i32);
+        // Twice the distance from the edge, 0 to 6 in both directions
+        let centrality = (7 - (2 * x - 7).abs()) + (7 - (2 * y - 7).abs());
+        let rank = if piece.player == Player::White { y } else { 7 - y };
+
+        let value = piece_value(piece.piece_type) + match piece.piece_type {
+            PieceType::Knight | PieceType::Bishop => 3 * centrality,
+            PieceType::Queen => centrality,
+            PieceType::Pawn => 5 * (rank - 1) + if (3..5).contains(&x) { 2 * rank } else { 0 },
+            PieceType::King | PieceType::Rook => 0,
+        };
+        score += if piece.player == Player::White { value } else { -value };
+    }
+
+    for (index, pocket) in state.pockets.iter().enumerate() {
+        let value: i32 = [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::Pawn]
+            .iter()
+            .map(|&piece_type| pocket[piece_type as usize] as i32 * piece_value(piece_type))
+            .sum();
+        score += if index == 0 { value } else { -value };
+    }
+
+    match state.current_player {
+        Player::White => score,
+        Player::Black => -score,
+    }
+}
+
+// Tablebase results as search values: a win is worth less than a mate, and
+// wins and losses the fifty-move rule turns into draws are almost draws
+fn tablebase_value(wdl: Wdl, plies: u32) -> i32 {
+    match wdl {
+        Wdl::Win => TB_WIN - plies as i32,
+        Wdl::CursedWin => 1,
+        Wdl::Draw => 0,
+        Wdl::BlessedLoss => -1,
+        Wdl::Loss => -TB_WIN + plies as i32,
+    }
+}
+
+struct Searcher<'a> {
+    limits: &'a SearchLimits,
+    start: Instant,
+    nodes: u64,
+    stopped: bool,
+    // Hashes of the positions leading to the current node, for repetitions
+    path: Vec<u64>,
+    // The moves from the root to the current node
+    line: Vec<Move>,
+    // The principal variation of the previous iteration, searched first
+    previous_pv: Vec<Move>,
+    // Moves left out at the root, because earlier lines already have them
+    excluded: Vec<Move>,
+}
+
+impl<'a> Searcher<'a> {
+    fn should_stop(&mut self) -> bool {
+        if !self.stopped {
+            let told_to_stop = self.limits.stop.as_ref().is_some_and(|stop| stop.load(Ordering::Relaxed));
+            let out_of_time = self.nodes.is_multiple_of(CHECK_INTERVAL) &&
+                              self.limits.time.is_some_and(|time| self.start.elapsed() >= time);
+            let out_of_nodes = self.limits.nodes.is_some_and(|nodes| self.nodes >= nodes);
+            self.stopped = told_to_stop || out_of_time || out_of_nodes;
+        }
+        self.stopped
+    }
+
+    fn outcome_value(state: &State, outcome: Outcome, ply: u32) -> i32 {
+        match outcome.winner {
+            Some(player) if player == state.current_player => MATE - ply as i32,
+            Some(_) => -MATE + ply as i32,
+            None => 0,
+        }
+    }
+
+    // Captures of valuable pieces by cheap ones first, then promotions, with
+    // the move from the previous principal variation before all of them
+    fn order_moves(&self, state: &State, moves: &mut [Move], ply: u32) {
+        let pv_move = self.previous_pv.get(ply as usize).copied();
+        let follows_pv = self.previous_pv.starts_with(&self.line);
+        moves.sort_by_cached_key(|&mv| {
+            if follows_pv && Some(mv) == pv_move {
+                return i32::MIN;
+            }
+            let victim = state.get(mv.to).map_or(0, |piece| piece_value(piece.piece_type));
+            let attacker = if mv.drop.is_some() { 0 } else { state.get(mv.from).map_or(0, |piece| piece_value(piece.piece_type)) };
+            let capture = if victim > 0 { 10 * victim - attacker / 10 } else { 0 };
+            let promotion = mv.promotion.map_or(0, piece_value);
+            -(capture + promotion)
+        });
+    }
+
+    fn search(&mut self, state: &State, depth: u32, ply: u32, mut alpha: i32, beta: i32, pv: &mut Vec<Move>) -> i32 {
+        pv.clear();
+        self.nodes += 1;
+        if ply > 0 && self.should_stop() {
+            return 0;
+        }
+
+        if let Some(outcome) = state.variant.get_outcome(state) {
+            return Searcher::outcome_value(state, outcome, ply);
+        }
+        let hash = state.get_hash();
+        if ply > 0 && (state.halfmove_clock >= 100 || self.path.contains(&hash)) {
+            return 0;
+        }
+        // Right after a capture or pawn move the tables have the exact result
+        if let (true, 0, Some(tablebase)) = (ply > 0, state.halfmove_clock, self.limits.tablebase.as_deref()) {
+            if tablebase::can_probe(tablebase, state) {
+                if let Some(wdl) = tablebase.probe_wdl(state) {
+                    return tablebase_value(wdl, ply);
+                }
+            }
+        }
+
+        let mut moves = state.get_legal_moves();
+        if moves.is_empty() {
+            return Searcher::outcome_value(state, state.variant.get_no_moves_outcome(state), ply);
+        }
+        if depth == 0 || ply >= MAX_DEPTH {
+            return self.quiescence(state, ply, alpha, beta);
+        }
+
+        if ply == 0 {
+            moves.retain(|mv| !self.excluded.contains(mv));
+        }
+        self.order_moves(state, &mut moves, ply);
+        self.path.push(hash);
+        let mut child_pv = Vec::new();
+        let mut best = -MATE - 1;
+        for mv in moves {
+            let child = state.preview_move(mv);
+            self.line.push(mv);
+            let value = -self.search(&child, depth - 1, ply + 1, -beta, -alpha, &mut child_pv);
+            self.line.pop();
+            if self.stopped {
+                break;
+            }
+            if value > best {
+                best = value;
+                if value > alpha {
+                    alpha = value;
+                    pv.clear();
+                    pv.push(mv);
+                    pv.extend_from_slice(&child_pv);
+                }
+                if value >= beta {
+                    break;
+                }
+            }
+        }
+        self.path.pop();
+        best
+    }
+
+    // Only captures and promotions, so the evaluation is not taken in the
+    // middle of an exchange
+    fn quiescence(&mut self, state: &State, ply: u32, mut alpha: i32, beta: i32) -> i32 {
+        let stand_pat = evaluate(state);
+        if stand_pat >= beta || ply >= MAX_DEPTH {
+            return stand_pat;
+        }
+        alpha = alpha.max(stand_pat);
+
+        let mut moves: Vec<Move> = state.get_legal_moves()
+            .into_iter()
+            .filter(|&mv| mv.promotion.is_some() || state.is_capture(mv))
+            .collect();
+        self.order_moves(state, &mut moves, ply);
+
+        let mut best = stand_pat;
+        for mv in moves {
+            self.nodes += 1;
+            if self.should_stop() {
+                break;
+            }
+            let child = state.preview_move(mv);
+            let value = match child.variant.get_outcome(&child) {
+                Some(outcome) => -Searcher::outcome_value(&child, outcome, ply + 1),
+                None => -self.quiescence(&child, ply + 1, -beta, -alpha),
+            };
+            if value > best {
+                best = value;
+                alpha = alpha.max(value);
+                if value >= beta {
+                    break;
+                }
+            }
+        }
+        best
+    }
+}
+
+fn root_move_score(root_move: &RootMove) -> Score {
+    match (root_move.wdl, root_move.dtm) {
+        (Wdl::Win, Some(dtm)) => Score::Mate((dtm + 1) / 2),
+        (Wdl::Loss, Some(dtm)) => Score::Mate(dtm / 2),
+        (wdl, distance) => Score::Centipawns(tablebase_value(wdl, distance.or(root_move.dtz).unwrap_or(0).unsigned_abs())),
+    }
+}
+
+/// Searches `state` with iterative deepening until a limit is reached, calling
+/// `on_info` after each finished depth. The result is that of the deepest
+/// finished depth, or of the first move if not even depth 1 finished.
+pub fn search(state: &State, limits: &SearchLimits, on_info: &mut dyn FnMut(&SearchInfo)) -> SearchInfo {
+    search_lines(state, limits, 1, &mut |lines| on_info(&lines[0])).remove(0)
+}
+
+/// Like `search`, but finds the best `count` moves, each with its own
+/// principal variation, best first. There are fewer lines when there are
+/// fewer legal moves, but always at least one.
+pub fn search_lines(state: &State, limits: &SearchLimits, count: usize, on_info: &mut dyn FnMut(&[SearchInfo])) -> Vec<SearchInfo> {
+    let mut searcher = Searcher {
+        limits,
+        start: Instant::now(),
+        nodes: 0,
+        stopped: false,
+        path: state.position_hashes.iter().rev().skip(1).copied().collect(),
+        line: Vec::new(),
+        previous_pv: Vec::new(),
+        excluded: Vec::new(),
+    };
+    let mut lines = vec![SearchInfo {
+        depth: 0,
+        score: Score::Centipawns(evaluate(state)),
+        nodes: 0,
+        time: Duration::from_secs(0),
+        pv: state.get_legal_moves().into_iter().take(1).collect(),
+    }];
+
+    // The tables know the best moves when they have the position
+    let root_moves = limits.tablebase.as_deref().and_then(|tablebase| tablebase::probe_root(tablebase, state));
+    if let Some(root_moves) = root_moves.filter(|moves| !moves.is_empty()) {
+        lines = root_moves.iter().take(count.max(1)).map(|root_move| SearchInfo {
+            depth: 1,
+            score: root_move_score(root_move),
+            nodes: 0,
+            time: searcher.start.elapsed(),
+            pv: vec![root_move.mv],
+        }).collect();
+        on_info(&lines);
+        return lines;
+    }
+
+    let max_depth = limits.depth.unwrap_or(MAX_DEPTH).clamp(1, MAX_DEPTH);
+    let mut pv = Vec::new();
+    for depth in 1..=max_depth {
+        let mut finished = Vec::new();
+        searcher.excluded.clear();
+        for index in 0..count.max(1) {
+            searcher.previous_pv = lines.get(index).map_or(Vec::new(), |line| line.pv.clone());
+            let value = searcher.search(state, depth, 0, -MATE - 1, MATE + 1, &mut pv);
+            if searcher.stopped {
+                // A partly searched first depth still beats an unsearched move
+                if lines[0].depth == 0 && index == 0 && !pv.is_empty() {
+                    lines[0].pv = pv.clone();
+                }
+                break;
+            }
+            // Every move has a line, or the game is over
+            if pv.is_empty() && index > 0 {
+                break;
+            }
+            finished.push(SearchInfo {
+                depth,
+                score: Score::from_value(value),
+                nodes: searcher.nodes,
+                time: searcher.start.elapsed(),
+                pv: if pv.is_empty() { lines[0].pv.clone() } else { pv.clone() },
+            });
+            searcher.excluded.extend(pv.first());
+        }
+        if searcher.stopped {
+            break;
+        }
+        lines = finished;
+        on_info(&lines);
+
+        // There is no point in looking further once every line ends in a mate
+        if lines.iter().all(|line| matches!(line.score, Score::Mate(_))) || lines[0].pv.is_empty() {
+            break;
+        }
+    }
+    for line in lines.iter_mut() {
+        line.nodes = searcher.nodes;
+        line.time = searcher.start.elapsed();
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Pos;
+
+    fn best_move(fen: &str, depth: u32) -> SearchInfo {
+        let state = State::from_fen(fen).unwrap();
+        search(&state, &SearchLimits::depth(depth), &mut |_| ())
+    }
+
+    #[test]
+    fn evaluate_test() {
+        let state = State::new();
+        assert_eq!(evaluate(&state), 0);
+        let state = State::from_fen("4k3/8/8/8/8/8/8/3QK3 b - - 0 1").unwrap();
+        assert!(evaluate(&state) < -800);
+    }
+
+    #[test]
+    fn mate_test() {
+        // Back rank mate in one
+        let result = best_move("6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", 3);
+        assert_eq!(result.best_move(), Some(Move::new(Pos::new(0, 0), Pos::new(0, 7))));
+        assert_eq!(result.score, Score::Mate(1));
+        assert_eq!(result.depth, 1);
+
+        // Mate in two with the rook and the king
+        let result = best_move("k7/8/2K5/8/8/8/8/7R w - - 0 1", 4);
+        assert_eq!(result.score, Score::Mate(2));
+        assert_eq!(result.pv.len(), 3);
+
+        // The side being mated sees it coming
+        let result = best_move("k7/8/8/8/8/r7/1r6/7K w - - 0 1", 2);
+        assert_eq!(result.score, Score::Mate(-1));
+    }
+
+    #[test]
+    fn capture_test() {
+        // The knight takes the queen rather than the pawn, both with check
+        let result = best_move("4k3/8/3q1p2/8/4N3/8/8/4K3 w - - 0 1", 2);
+        assert_eq!(result.best_move(), Some(Move::new(Pos::new(4, 3), Pos::new(3, 5))));
+        assert!(matches!(result.score, Score::Centipawns(score) if score > 0));
+    }
+
+    #[test]
+    fn limits_test() {
+        let state = State::new();
+        let mut depths = Vec::new();
+        let result = search(&state, &SearchLimits::depth(2), &mut |info| depths.push(info.depth));
+        assert_eq!(depths, vec![1, 2]);
+        assert!(state.get_legal_moves().contains(&result.best_move().unwrap()));
+
+        let stop = Arc::new(AtomicBool::new(true));
+        let limits = SearchLimits { stop: Some(stop), ..SearchLimits::default() };
+        let result = search(&state, &limits, &mut |_| ());
+        assert_eq!(result.depth, 0);
+        assert!(result.best_move().is_some());
+    }
+
+    #[test]
+    fn lines_test() {
+        // Only the queen capture wins material, the other knight moves are worse
+        let state = State::from_fen("4k3/8/3q1p2/8/4N3/8/8/4K3 w - - 0 1").unwrap();
+        let lines = search_lines(&state, &SearchLimits::depth(2), 3, &mut |lines| assert!(lines.len() <= 3));
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0].best_move(), Some(Move::new(Pos::new(4, 3), Pos::new(3, 5))));
+        assert!(lines[1].score.to_centipawns() <= lines[0].score.to_centipawns());
+        assert!(lines[2].score.to_centipawns() <= lines[1].score.to_centipawns());
+        assert!(lines[1].best_move() != lines[0].best_move() && lines[2].best_move() != lines[1].best_move());
+
+        // A king in the corner has three moves and so three lines
+        let state = State::from_fen("7k/8/8/8/8/8/8/K7 w - - 0 1").unwrap();
+        let lines = search_lines(&state, &SearchLimits::depth(1), 5, &mut |_| ());
+        assert_eq!(lines.len(), 3);
+    }
+}
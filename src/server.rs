@@ -0,0 +1,762 @@
+//! A server that owns any number of games. Clients create, join or watch games
+//! by id, and the server checks every move, runs the clocks and sends the
+//! changes to the players and spectators of the game. Finished games are
+//! appended to a PGN file.
+//!
+//! Every message is one JSON object per line with a `type` field. Requests:
+//!
+//! ```text
+//! {"type":"create","variant":"atomic","fen":"...","color":"black","time_control":"300+2","name":"..."}
+//! {"type":"join","game":1,"name":"..."}     takes the free seat of a game
+//! {"type":"watch","game":1}
+//! {"type":"move","game":1,"move":"e2e4"}    moves are UCI strings
+//! {"type":"resign","game":1}                also offer_draw, accept_draw,
+//!                                           decline_draw and claim_draw
+//! {"type":"list"}
+//! ```
+//!
+//! All fields but `type` and `game` are optional. The server answers with
+//! `welcome` on connecting, `created`, `joined` and `watching` followed by a
+//! `game` snapshot, and `games` for `list`. It sends `move`, `status` and
+//! `draw_offer` events to everyone in a game, and `error` with a `code` and
+//! a `message` when a request is rejected. Rejected moves and actions use the
+//! codes of `MoveError` and `ActionError`.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::clock::{Clock, SystemTimeSource, TimeControl, TimeSource};
+use crate::pgn::result_string;
+use crate::variant::{self, Standard};
+use crate::{ActionError, Move, MoveError, Player, State};
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+const TICK_INTERVAL: Duration = Duration::from_millis(50);
+const WRITE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Creates the time source for the clock of each new game.
+pub type TimeSourceFactory = Arc<dyn Fn() -> Box<dyn TimeSource + Send> + Send + Sync>;
+
+/// Called with the id of a finished game that could not be saved.
+pub type SaveErrorHandler = Arc<dyn Fn(u64, &io::Error) + Send + Sync>;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ServerError {
+    InvalidRequest,
+    UnknownVariant,
+    InvalidPosition,
+    InvalidTimeControl,
+    UnknownGame,
+    GameFull,
+    NotAPlayer,
+    WaitingForOpponent,
+    NotYourTurn,
+    Move(MoveError),
+    Action(ActionError),
+}
+
+impl ServerError {
+    /// A short name for the error that clients can match on.
+    pub fn code(&self) -> &str {
+        match self {
+            Self::InvalidRequest => "invalid_request",
+            Self::UnknownVariant => "unknown_variant",
+            Self::InvalidPosition => "invalid_position",
+            Self::InvalidTimeControl => "invalid_time_control",
+            Self::UnknownGame => "unknown_game",
+            Self::GameFull => "game_full",
+            Self::NotAPlayer => "not_a_player",
+            Self::WaitingForOpponent => "waiting_for_opponent",
+            Self::NotYourTurn => "not_your_turn",
+            Self::Move(error) => error.code(),
+            Self::Action(error) => error.code(),
+        }
+    }
+
+    pub fn to_string(&self) -> &str {
+        match self {
+            Self::InvalidRequest =>     "The request could not be understood",
+            Self::UnknownVariant =>     "There is no such variant",
+            Self::InvalidPosition =>    "The start position is invalid",
+            Self::InvalidTimeControl => "The time control is invalid",
+            Self::UnknownGame =>        "There is no game with that id",
+            Self::GameFull =>           "Both seats of the game are taken",
+            Self::NotAPlayer =>         "You are not playing in this game",
+            Self::WaitingForOpponent => "The game has not started yet",
+            Self::NotYourTurn =>        "It is not your turn",
+            Self::Move(error) => error.to_string(),
+            Self::Action(error) => error.to_string(),
+        }
+    }
+}
+
+fn player_index(player: Player) -> usize {
+    match player {
+        Player::White => 0,
+        Player::Black => 1,
+    }
+}
+
+fn player_name(player: Player) -> &'static str {
+    match player {
+        Player::White => "white",
+        Player::Black => "black",
+    }
+}
+
+struct Game {
+    start_fen: String,
+    time_control: Option<String>,
+    state: State,
+    clock: Option<Clock>,
+    players: [Option<u64>; 2],
+    names: [String; 2],
+    spectators: Vec<u64>,
+    // Both seats have been taken once, so moves can be played
+    started: bool,
+    saved: bool,
+}
+
+impl Game {
+    fn seat_of(&self, client: u64) -> Option<Player> {
+        [Player::White, Player::Black].iter().copied().find(|&player| self.players[player_index(player)] == Some(client))
+    }
+
+    fn clients(&self) -> Vec<u64> {
+        self.players.iter().flatten().chain(self.spectators.iter()).copied().collect()
+    }
+
+    fn clock_json(&self) -> Value {
+        match &self.clock {
+            Some(clock) => json!({
+                "white": clock.remaining(Player::White).as_millis() as u64,
+                "black": clock.remaining(Player::Black).as_millis() as u64,
+                "running": clock.get_running().map(player_name),
+            }),
+            None => Value::Null,
+        }
+    }
+
+    // The fields describing how the game stands, shared by all game events
+    fn add_status(&self, event: &mut Value) {
+        let status = self.state.get_game_status();
+        event["status"] = json!(status.code());
+        event["result"] = json!(result_string(&status));
+        event["termination"] = json!(self.state.get_termination().map(|termination| termination.to_string().to_string()));
+        event["clock"] = self.clock_json();
+    }
+
+    fn snapshot(&self, id: u64) -> Value {
+        let mut event = json!({
+            "type": "game",
+            "game": id,
+            "variant": self.state.get_variant().name(),
+            "start_fen": self.start_fen,
+            "fen": self.state.to_fen(),
+            "moves": self.state.get_history().iter().map(|entry| entry.mv.to_uci()).collect::<Vec<_>>(),
+            "white": self.names[0],
+            "black": self.names[1],
+            "white_seated": self.players[0].is_some(),
+            "black_seated": self.players[1].is_some(),
+            "draw_offered_by": self.state.get_draw_offered_by().map(player_name),
+        });
+        self.add_status(&mut event);
+        event
+    }
+
+    fn to_pgn(&self) -> String {
+        let mut tags = vec![
+            ("Event", "Server game"),
+            ("White", self.names[0].as_str()),
+            ("Black", self.names[1].as_str()),
+        ];
+        if let Some(control) = &self.time_control {
+            tags.push(("TimeControl", control));
+        }
+        if self.start_fen != self.state.get_variant().start_fen() {
+            tags.push(("FEN", &self.start_fen));
+        }
+        self.state.to_pgn(&tags)
+    }
+}
+
+struct Lobby {
+    next_client: u64,
+    next_game: u64,
+    clients: HashMap<u64, TcpStream>,
+    games: BTreeMap<u64, Game>,
+    pgn_file: Option<PathBuf>,
+    on_save_error: Option<SaveErrorHandler>,
+    time_source: TimeSourceFactory,
+}
+
+impl Lobby {
+    fn send(&mut self, client: u64, message: &Value) {
+        if let Some(stream) = self.clients.get_mut(&client) {
+            let mut line = message.to_string();
+            line.push('\n');
+            // A client that cannot keep up is dropped by its reader thread
+            if stream.write_all(line.as_bytes()).is_err() {
+                let _res = stream.shutdown(std::net::Shutdown::Both);
+            }
+        }
+    }
+
+    fn send_error(&mut self, client: u64, error: ServerError) {
+        self.send(client, &json!({ "type": "error", "code": error.code(), "message": error.to_string() }));
+    }
+
+    fn broadcast(&mut self, id: u64, message: &Value) {
+        let clients = match self.games.get(&id) {
+            Some(game) => game.clients(),
+            None => return,
+        };
+        for client in clients {
+            self.send(client, message);
+        }
+    }
+
+    fn broadcast_status(&mut self, id: u64) {
+        let mut event = json!({ "type": "status", "game": id });
+        self.games[&id].add_status(&mut event);
+        self.broadcast(id, &event);
+    }
+
+    fn disconnect(&mut self, client: u64) {
+        self.clients.remove(&client);
+        for game in self.games.values_mut() {
+            for seat in game.players.iter_mut() {
+                if *seat == Some(client) {
+                    *seat = None;
+                }
+            }
+            game.spectators.retain(|&spectator| spectator != client);
+        }
+    }
+
+    fn handle(&mut self, client: u64, line: &str) {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(_) => return self.send_error(client, ServerError::InvalidRequest),
+        };
+
+        let result = match request["type"].as_str() {
+            Some("create") => self.create(client, &request),
+            Some("join") => self.join(client, &request),
+            Some("watch") => self.watch(client, &request),
+            Some("move") => self.play_move(client, &request),
+            Some("resign") | Some("offer_draw") | Some("accept_draw") | Some("decline_draw") | Some("claim_draw") =>
+                self.action(client, &request),
+            Some("list") => {
+                self.list(client);
+                Ok(())
+            }
+            _ => Err(ServerError::InvalidRequest),
+        };
+        if let Err(error) = result {
+            self.send_error(client, error);
+        }
+    }
+
+    fn get_game_id(&self, request: &Value) -> Result<u64, ServerError> {
+        let id = request["game"].as_u64().ok_or(ServerError::InvalidRequest)?;
+        if self.games.contains_key(&id) {
+            Ok(id)
+        } else {
+            Err(ServerError::UnknownGame)
+        }
+    }
+
+    fn create(&mut self, client: u64, request: &Value) -> Result<(), ServerError> {
+        let variant = match request["variant"].as_str() {
+            Some(name) => variant::from_name(name).ok_or(ServerError::UnknownVariant)?,
+            None => Arc::new(Standard),
+        };
+        let state = match request["fen"].as_str() {
+            Some(fen) => State::from_fen_with_variant(fen, variant).map_err(|_| ServerError::InvalidPosition)?,
+            None => State::new_variant(variant),
+        };
+        let color = match request["color"].as_str() {
+            None | Some("white") => Player::White,
+            Some("black") => Player::Black,
+            Some(_) => return Err(ServerError::InvalidRequest),
+        };
+        let time_control = request["time_control"].as_str().map(String::from);
+        let clock = match &time_control {
+            Some(control) => {
+                let control = TimeControl::parse(control).ok_or(ServerError::InvalidTimeControl)?;
+                Some(Clock::new(control, (self.time_source)()))
+            }
+            None => None,
+        };
+
+        let id = self.next_game;
+        self.next_game += 1;
+        let mut game = Game {
+            start_fen: state.to_fen(),
+            time_control,
+            state,
+            clock,
+            players: [None, None],
+            names: [String::from("?"), String::from("?")],
+            spectators: Vec::new(),
+            started: false,
+            saved: false,
+        };
+        game.players[player_index(color)] = Some(client);
+        if let Some(name) = request["name"].as_str() {
+            game.names[player_index(color)] = name.to_string();
+        }
+        self.games.insert(id, game);
+
+        self.send(client, &json!({ "type": "created", "game": id, "color": player_name(color) }));
+        let snapshot = self.games[&id].snapshot(id);
+        self.send(client, &snapshot);
+        Ok(())
+    }
+
+    fn join(&mut self, client: u64, request: &Value) -> Result<(), ServerError> {
+        let id = self.get_game_id(request)?;
+        let game = self.games.get_mut(&id).unwrap();
+
+        let color = match game.seat_of(client) {
+            Some(color) => color,
+            None => {
+                let color = [Player::White, Player::Black].iter().copied()
+                    .find(|&player| game.players[player_index(player)].is_none())
+                    .ok_or(ServerError::GameFull)?;
+                game.players[player_index(color)] = Some(client);
+                game.spectators.retain(|&spectator| spectator != client);
+                if let Some(name) = request["name"].as_str() {
+                    game.names[player_index(color)] = name.to_string();
+                }
+                color
+            }
+        };
+
+        if !game.started && game.players.iter().all(Option::is_some) {
+            game.started = true;
+            let current_player = game.state.get_current_player();
+            if let Some(clock) = &mut game.clock {
+                clock.start(current_player);
+            }
+        }
+
+        self.send(client, &json!({ "type": "joined", "game": id, "color": player_name(color) }));
+        let snapshot = self.games[&id].snapshot(id);
+        self.broadcast(id, &snapshot);
+        Ok(())
+    }
+
+    fn watch(&mut self, client: u64, request: &Value) -> Result<(), ServerError> {
+        let id = self.get_game_id(request)?;
+        let game = self.games.get_mut(&id).unwrap();
+        if game.seat_of(client).is_none() && !game.spectators.contains(&client) {
+            game.spectators.push(client);
+        }
+
+        self.send(client, &json!({ "type": "watching", "game": id }));
+        let snapshot = self.games[&id].snapshot(id);
+        self.send(client, &snapshot);
+        Ok(())
+    }
+
+    // The game and the seat of a player making a move or another action
+    fn get_seat(&self, client: u64, request: &Value) -> Result<(u64, Player), ServerError> {
+        let id = self.get_game_id(request)?;
+        let game = &self.games[&id];
+        let player = game.seat_of(client).ok_or(ServerError::NotAPlayer)?;
+        if !game.started {
+            return Err(ServerError::WaitingForOpponent);
+        }
+        Ok((id, player))
+    }
+
+    fn play_move(&mut self, client: u64, request: &Value) -> Result<(), ServerError> {
+        let (id, player) = self.get_seat(client, request)?;
+        let mv = request["move"].as_str().and_then(Move::from_uci).ok_or(ServerError::InvalidRequest)?;
+        let game = self.games.get_mut(&id).unwrap();
+        if !game.state.get_game_running() {
+            return Err(ServerError::Move(MoveError::GameDone));
+        }
+        if game.state.get_current_player() != player {
+            return Err(ServerError::NotYourTurn);
+        }
+
+        let san = game.state.move_to_san(mv);
+        game.state.play_move(mv).map_err(ServerError::Move)?;
+        if let Some(clock) = &mut game.clock {
+            if let Some(flagged) = clock.press() {
+                game.state.lose_on_time(flagged);
+            }
+        }
+
+        let mut event = json!({
+            "type": "move",
+            "game": id,
+            "move": mv.to_uci(),
+            "san": san,
+            "fen": game.state.to_fen(),
+        });
+        game.add_status(&mut event);
+        self.broadcast(id, &event);
+        self.finish(id);
+        Ok(())
+    }
+
+    fn action(&mut self, client: u64, request: &Value) -> Result<(), ServerError> {
+        let (id, player) = self.get_seat(client, request)?;
+        let state = &mut self.games.get_mut(&id).unwrap().state;
+        match request["type"].as_str() {
+            Some("resign") => state.resign(player).map(|_| ()),
+            Some("offer_draw") => state.offer_draw(player),
+            Some("accept_draw") => state.accept_draw(player).map(|_| ()),
+            Some("decline_draw") => state.decline_draw(player),
+            _ => state.claim_draw(player).map(|_| ()),
+        }.map_err(ServerError::Action)?;
+
+        let offered_by = state.get_draw_offered_by();
+        let running = state.get_game_running();
+        if running {
+            self.broadcast(id, &json!({ "type": "draw_offer", "game": id, "by": offered_by.map(player_name) }));
+        } else {
+            self.finish(id);
+        }
+        Ok(())
+    }
+
+    fn list(&mut self, client: u64) {
+        let games: Vec<Value> = self.games.iter().map(|(id, game)| json!({
+            "game": id,
+            "variant": game.state.get_variant().name(),
+            "white": game.names[0],
+            "black": game.names[1],
+            "white_seated": game.players[0].is_some(),
+            "black_seated": game.players[1].is_some(),
+            "status": game.state.get_game_status().code(),
+        })).collect();
+        self.send(client, &json!({ "type": "games", "games": games }));
+    }
+
+    // Stops the clock of a game that has ended and saves it, once.
+    fn finish(&mut self, id: u64) {
+        let game = self.games.get_mut(&id).unwrap();
+        if game.state.get_game_running() || game.saved {
+            return;
+        }
+        game.saved = true;
+        if let Some(clock) = &mut game.clock {
+            clock.stop();
+        }
+        let pgn = game.to_pgn();
+
+        if let Some(path) = &self.pgn_file {
+            let written = OpenOptions::new().create(true).append(true).open(path)
+                .and_then(|mut file| writeln!(file, "{}", pgn));
+            if let (Err(error), Some(on_save_error)) = (written, &self.on_save_error) {
+                on_save_error(id, &error);
+            }
+        }
+        self.broadcast_status(id);
+    }
+
+    // Ends the games where the player to move has run out of time
+    fn check_flags(&mut self) {
+        let mut flagged_games = Vec::new();
+        for (&id, game) in self.games.iter_mut() {
+            if !game.state.get_game_running() {
+                continue;
+            }
+            if let Some(player) = game.clock.as_mut().and_then(Clock::check_flag) {
+                game.state.lose_on_time(player);
+                flagged_games.push(id);
+            }
+        }
+        for id in flagged_games {
+            self.finish(id);
+        }
+    }
+}
+
+pub struct Server {
+    listener: TcpListener,
+    lobby: Arc<Mutex<Lobby>>,
+}
+
+impl Server {
+    pub fn bind<A: ToSocketAddrs>(address: A) -> io::Result<Server> {
+        let listener = TcpListener::bind(address)?;
+        let lobby = Lobby {
+            next_client: 1,
+            next_game: 1,
+            clients: HashMap::new(),
+            games: BTreeMap::new(),
+            pgn_file: None,
+            on_save_error: None,
+            time_source: Arc::new(|| Box::new(SystemTimeSource::new())),
+        };
+        Ok(Server { listener, lobby: Arc::new(Mutex::new(lobby)) })
+    }
+
+    /// Appends every finished game to the PGN file at `path`.
+    pub fn with_pgn_file<P: Into<PathBuf>>(self, path: P) -> Server {
+        self.lobby.lock().unwrap().pgn_file = Some(path.into());
+        self
+    }
+
+    /// Calls `handler` when a finished game cannot be appended to the PGN file.
+    pub fn with_save_error_handler(self, handler: SaveErrorHandler) -> Server {
+        self.lobby.lock().unwrap().on_save_error = Some(handler);
+        self
+    }
+
+    pub fn with_time_source(self, time_source: TimeSourceFactory) -> Server {
+        self.lobby.lock().unwrap().time_source = time_source;
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Serves clients until accepting a connection fails. Each client gets a
+    /// thread, and another one watches the clocks.
+    pub fn run(self) -> io::Result<()> {
+        let lobby = Arc::downgrade(&self.lobby);
+        thread::spawn(move || {
+            while let Some(lobby) = lobby.upgrade() {
+                lobby.lock().unwrap().check_flags();
+                drop(lobby);
+                thread::sleep(TICK_INTERVAL);
+            }
+        });
+
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            let lobby = Arc::clone(&self.lobby);
+            thread::spawn(move || serve_client(stream, lobby));
+        }
+        Ok(())
+    }
+}
+
+fn serve_client(stream: TcpStream, lobby: Arc<Mutex<Lobby>>) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let reader = BufReader::new(stream.try_clone()?);
+
+    let client = {
+        let mut lobby = lobby.lock().unwrap();
+        let client = lobby.next_client;
+        lobby.next_client += 1;
+        lobby.clients.insert(client, stream);
+        lobby.send(client, &json!({ "type": "welcome", "protocol": PROTOCOL_VERSION, "client": client }));
+        client
+    };
+
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(_) => break,
+        };
+        if !line.trim().is_empty() {
+            lobby.lock().unwrap().handle(client, &line);
+        }
+    }
+    lobby.lock().unwrap().disconnect(client);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualTimeSource;
+    use std::time::Instant;
+
+    struct Client {
+        stream: TcpStream,
+        reader: BufReader<TcpStream>,
+    }
+
+    impl Client {
+        fn connect(address: SocketAddr) -> Client {
+            let stream = TcpStream::connect(address).unwrap();
+            stream.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+            let reader = BufReader::new(stream.try_clone().unwrap());
+            let mut client = Client { stream, reader };
+            assert_eq!(client.receive()["type"], "welcome");
+            client
+        }
+
+        fn send(&mut self, request: Value) {
+            writeln!(self.stream, "{}", request).unwrap();
+        }
+
+        fn receive(&mut self) -> Value {
+            let mut line = String::new();
+            self.reader.read_line(&mut line).unwrap();
+            serde_json::from_str(&line).unwrap()
+        }
+
+        fn request(&mut self, request: Value) -> Value {
+            self.send(request);
+            self.receive()
+        }
+    }
+
+    fn start_server(server: Server) -> SocketAddr {
+        let address = server.local_addr().unwrap();
+        thread::spawn(move || server.run());
+        address
+    }
+
+    fn start_game(address: SocketAddr, create: Value) -> (Client, Client, u64) {
+        let mut white = Client::connect(address);
+        let created = white.request(create);
+        assert_eq!(created["type"], "created");
+        let id = created["game"].as_u64().unwrap();
+        assert_eq!(white.receive()["type"], "game");
+
+        let mut black = Client::connect(address);
+        assert_eq!(black.request(json!({ "type": "join", "game": id, "name": "Bob" }))["color"], "black");
+        assert_eq!(black.receive()["black"], "Bob");
+        assert_eq!(white.receive()["black_seated"], true);
+        (white, black, id)
+    }
+
+    #[test]
+    fn state_is_send_test() {
+        fn assert_send<T: Send>() {}
+        assert_send::<State>();
+    }
+
+    #[test]
+    fn play_and_watch_test() {
+        let address = start_server(Server::bind("127.0.0.1:0").unwrap());
+        let (mut white, mut black, id) = start_game(address, json!({ "type": "create", "name": "Alice" }));
+
+        let mut spectator = Client::connect(address);
+        assert_eq!(spectator.request(json!({ "type": "watch", "game": id }))["type"], "watching");
+        let snapshot = spectator.receive();
+        assert_eq!(snapshot["white"], "Alice");
+        assert_eq!(snapshot["status"], "white_turn");
+
+        let error = black.request(json!({ "type": "move", "game": id, "move": "e7e5" }));
+        assert_eq!(error["code"], "not_your_turn");
+        let error = spectator.request(json!({ "type": "move", "game": id, "move": "e2e4" }));
+        assert_eq!(error["code"], "not_a_player");
+        let error = white.request(json!({ "type": "move", "game": id, "move": "e2e5" }));
+        assert_eq!(error["code"], "invalid_move");
+        assert_eq!(error["message"], MoveError::InvalidMove.to_string());
+
+        white.send(json!({ "type": "move", "game": id, "move": "e2e4" }));
+        for client in [&mut white, &mut black, &mut spectator].iter_mut() {
+            let event = client.receive();
+            assert_eq!(event["type"], "move");
+            assert_eq!(event["san"], "e4");
+            assert_eq!(event["status"], "black_turn");
+            assert_eq!(event["fen"], "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq - 0 1");
+        }
+
+        assert_eq!(black.request(json!({ "type": "resign", "game": id }))["result"], "1-0");
+        assert_eq!(white.receive()["termination"], "resignation");
+        assert_eq!(spectator.receive()["status"], "game_won_by_white");
+    }
+
+    #[test]
+    fn games_test() {
+        let address = start_server(Server::bind("127.0.0.1:0").unwrap());
+        let (_white, _black, first) = start_game(address, json!({ "type": "create" }));
+
+        let mut client = Client::connect(address);
+        let second = client.request(json!({ "type": "create", "variant": "koth", "color": "black" }));
+        assert_eq!(second["color"], "black");
+        assert_eq!(client.receive()["variant"], "King of the Hill");
+
+        let games = client.request(json!({ "type": "list" }));
+        assert_eq!(games["games"].as_array().unwrap().len(), 2);
+        assert_eq!(games["games"][0]["game"], first);
+        assert_eq!(games["games"][1]["white_seated"], false);
+
+        let mut other = Client::connect(address);
+        assert_eq!(other.request(json!({ "type": "join", "game": first }))["code"], "game_full");
+        assert_eq!(other.request(json!({ "type": "join", "game": 99 }))["code"], "unknown_game");
+        assert_eq!(other.request(json!({ "type": "create", "variant": "shogi" }))["code"], "unknown_variant");
+        assert_eq!(other.request(json!({ "type": "dance" }))["code"], "invalid_request");
+        other.send(json!({ "type": "move", "game": second["game"], "move": "e2e4" }));
+        assert_eq!(other.receive()["code"], "not_a_player");
+    }
+
+    #[test]
+    fn pgn_file_test() {
+        let path = std::env::temp_dir().join(format!("chess-server-{}.pgn", std::process::id()));
+        let _res = std::fs::remove_file(&path);
+        let address = start_server(Server::bind("127.0.0.1:0").unwrap().with_pgn_file(&path));
+        let (mut white, mut black, id) = start_game(address, json!({ "type": "create", "name": "Alice" }));
+
+        for (i, uci) in ["f2f3", "e7e5", "g2g4", "d8h4"].iter().enumerate() {
+            let (mover, other) = if i % 2 == 0 { (&mut white, &mut black) } else { (&mut black, &mut white) };
+            mover.send(json!({ "type": "move", "game": id, "move": uci }));
+            assert_eq!(mover.receive()["move"], *uci);
+            assert_eq!(other.receive()["move"], *uci);
+        }
+        let status = white.receive();
+        assert_eq!(status["type"], "status");
+        assert_eq!(status["termination"], "checkmate");
+        assert_eq!(black.receive()["result"], "0-1");
+
+        // The game is saved before the status is sent
+        let pgn = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(pgn.contains("[White \"Alice\"]\n[Black \"Bob\"]\n[Result \"0-1\"]\n"));
+        assert!(pgn.contains("\n1. f3 e5 2. g4 Qh4# 0-1\n"));
+
+        // A game that cannot be saved is reported to the handler
+        let failed = Arc::new(Mutex::new(Vec::new()));
+        let handler_failed = failed.clone();
+        let server = Server::bind("127.0.0.1:0").unwrap()
+            .with_pgn_file(std::env::temp_dir().join("no-such-directory").join("games.pgn"))
+            .with_save_error_handler(Arc::new(move |game, _| handler_failed.lock().unwrap().push(game)));
+        let address = start_server(server);
+        let (mut white, _black, id) = start_game(address, json!({ "type": "create" }));
+        assert_eq!(white.request(json!({ "type": "resign", "game": id }))["result"], "0-1");
+        assert_eq!(*failed.lock().unwrap(), [id]);
+    }
+
+    #[test]
+    fn clock_test() {
+        let time = ManualTimeSource::new();
+        let source = time.clone();
+        let server = Server::bind("127.0.0.1:0").unwrap()
+            .with_time_source(Arc::new(move || Box::new(source.clone())));
+        let address = start_server(server);
+        let create = json!({ "type": "create", "time_control": "60+1", "fen": "4k3/8/8/8/8/8/8/R3K3 w - - 0 1" });
+        let (mut white, mut black, id) = start_game(address, create);
+
+        time.advance(Duration::from_secs(10));
+        white.send(json!({ "type": "move", "game": id, "move": "a1a7" }));
+        let event = black.receive();
+        assert_eq!(event["clock"]["white"], 51000);
+        assert_eq!(event["clock"]["black"], 60000);
+        assert_eq!(event["clock"]["running"], "black");
+        white.receive();
+
+        time.advance(Duration::from_secs(61));
+        let start = Instant::now();
+        let status = white.receive();
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(status["type"], "status");
+        assert_eq!(status["termination"], "time forfeit");
+        assert_eq!(status["result"], "1-0");
+        assert_eq!(status["clock"]["black"], 0);
+    }
+}
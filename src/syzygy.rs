@@ -0,0 +1,755 @@
+//! Decoding of the Syzygy table files, following the format of the probing
+//! code published with them. A file holds one value for every position of
+//! its material, numbered by the squares of the pieces much like in
+//! `retrograde`, and compressed with a Huffman code over symbols that each
+//! stand for a pair of other symbols.
+//!
+//! A table only knows positions without castling rights, and only those in
+//! which no capture is at least as good as the best quiet move: the other
+//! positions hold whatever compresses best. `SyzygyFiles` in `tablebase`
+//! takes care of the captures.
+
+use std::convert::TryInto;
+use std::fmt;
+use std::io;
+
+use crate::tablebase::material_key;
+use crate::{Piece, PieceType, Player, State};
+
+pub(crate) const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+pub(crate) const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+
+// The squares of the white king in the a1-d1-d4 triangle, numbered b1 c1 d1
+// c2 d2 d3 off the diagonal and then a1 b2 c3 d4
+const TRIANGLE: [u8; 64] = [
+    6, 0, 1, 2, 2, 1, 0, 6,
+    0, 7, 3, 4, 4, 3, 7, 0,
+    1, 3, 8, 5, 5, 8, 3, 1,
+    2, 4, 5, 9, 9, 5, 4, 2,
+    2, 4, 5, 9, 9, 5, 4, 2,
+    1, 3, 8, 5, 5, 8, 3, 1,
+    0, 7, 3, 4, 4, 3, 7, 0,
+    6, 0, 1, 2, 2, 1, 0, 6,
+];
+
+// The order of the squares of the leading pawns, from the seventh rank of
+// the a and h files down to the second rank of the d and e files
+const PAWN_TWIST: [u8; 64] = [
+    0, 0, 0, 0, 0, 0, 0, 0,
+    47, 35, 23, 11, 10, 22, 34, 46,
+    45, 33, 21, 9, 8, 20, 32, 44,
+    43, 31, 19, 7, 6, 18, 30, 42,
+    41, 29, 17, 5, 4, 16, 28, 40,
+    39, 27, 15, 3, 2, 14, 26, 38,
+    37, 25, 13, 1, 0, 12, 24, 36,
+    0, 0, 0, 0, 0, 0, 0, 0,
+];
+
+// The squares of the triangle in the order of their numbers
+const TRIANGLE_SQUARES: [usize; 10] = [1, 2, 3, 10, 11, 19, 0, 9, 18, 27];
+const FILE_TO_FILE: [usize; 8] = [0, 1, 2, 3, 3, 2, 1, 0];
+// The number of positions of the two kings with the white king in the triangle
+const KK_POSITIONS: u64 = 462;
+// The same for any three pieces that are not all alike
+const TRIANGLE_POSITIONS: u64 = 31332;
+const KK_INDEX: [[i16; 64]; 10] = kk_index();
+const PAWN_INDEX: [[u64; 24]; 5] = pawn_index().0;
+const PAWN_FACTOR: [[u64; 4]; 5] = pawn_index().1;
+
+// The DTZ flags of a table
+const STORES_BLACK: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE_MAP: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+// Which of the four maps of a DTZ table a WDL value uses, from loss to win
+const WDL_TO_MAP: [usize; 5] = [1, 3, 0, 2, 0];
+
+const fn is_diagonal(square: usize) -> bool {
+    square / 8 == square % 8
+}
+
+// Above the a1-h8 diagonal is 1, below -1
+const fn off_diagonal(square: u8) -> i32 {
+    let (x, y) = ((square % 8) as i32, (square / 8) as i32);
+    if y > x { 1 } else if y < x { -1 } else { 0 }
+}
+
+const fn is_adjacent(a: usize, b: usize) -> bool {
+    let dx = (a % 8) as i32 - (b % 8) as i32;
+    let dy = (a / 8) as i32 - (b / 8) as i32;
+    dx >= -1 && dx <= 1 && dy >= -1 && dy <= 1
+}
+
+// The number of the kings' positions, for the white king on each square of
+// the triangle. With the white king on the diagonal the black king is below
+// it, and the positions with both kings on the diagonal come last.
+const fn kk_index() -> [[i16; 64]; 10] {
+    let mut index = [[-1; 64]; 10];
+    let mut next = 0;
+    let mut pass = 0;
+    while pass < 2 {
+        let mut triangle = 0;
+        while triangle < 10 {
+            let white = TRIANGLE_SQUARES[triangle];
+            if pass == 0 || is_diagonal(white) {
+                let mut black = 0;
+                while black < 64 {
+                    let counted = if pass == 0 {
+                        !is_diagonal(white) || off_diagonal(black as u8) < 0
+                    } else {
+                        is_diagonal(black)
+                    };
+                    if counted && !is_adjacent(white, black) {
+                        index[triangle][black] = next;
+                        next += 1;
+                    }
+                    black += 1;
+                }
+            }
+            triangle += 1;
+        }
+        pass += 1;
+    }
+    index
+}
+
+const fn binomial(n: u64, k: u64) -> u64 {
+    if k > n {
+        return 0;
+    }
+    let mut result = 1;
+    let mut i = 0;
+    while i < k {
+        result = result * (n - i) / (i + 1);
+        i += 1;
+    }
+    result
+}
+
+// Where the positions of the leading pawns start for each square of the
+// first of them, and how many there are on each file
+const fn pawn_index() -> ([[u64; 24]; 5], [[u64; 4]; 5]) {
+    let mut index = [[0; 24]; 5];
+    let mut factor = [[0; 4]; 5];
+    let mut pawns = 0;
+    while pawns < 5 {
+        let mut sum = 0;
+        let mut flap = 0;
+        while flap < 24 {
+            index[pawns][flap] = sum;
+            let square = (1 + flap % 6) * 8 + flap / 6;
+            sum += binomial(PAWN_TWIST[square] as u64, pawns as u64);
+            if flap % 6 == 5 {
+                factor[pawns][flap / 6] = sum;
+                sum = 0;
+            }
+            flap += 1;
+        }
+        pawns += 1;
+    }
+    (index, factor)
+}
+
+// The leading pawns are numbered by file, from the a to the d file, and then
+// by rank
+fn flap(square: u8) -> usize {
+    let (x, y) = ((square % 8) as usize, (square / 8) as usize);
+    x.min(7 - x) * 6 + y - 1
+}
+
+fn lower(square: u8) -> u64 {
+    let (x, y) = ((square % 8) as u64, (square / 8) as u64);
+    let (low, high) = (x.min(y), x.max(y));
+    if low == high {
+        return 28 + low;
+    }
+    // The squares below the diagonal rank by rank, b1 to h1, c2 to h2 and so on
+    (0..low).map(|rank| 7 - rank).sum::<u64>() + high - low - 1
+}
+
+fn diagonal(square: u8) -> u64 {
+    (square / 8) as u64
+}
+
+fn invalid() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "The Syzygy table is corrupt")
+}
+
+fn u16_at(data: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(data.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_at(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn piece_of_code(code: u8) -> Option<Piece> {
+    let piece_type = match code & 7 {
+        1 => PieceType::Pawn,
+        2 => PieceType::Knight,
+        3 => PieceType::Bishop,
+        4 => PieceType::Rook,
+        5 => PieceType::Queen,
+        6 => PieceType::King,
+        _ => return None,
+    };
+    Some(Piece::new(piece_type, if code & 8 == 0 { Player::White } else { Player::Black }))
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum Kind {
+    Wdl,
+    Dtz,
+}
+
+/// A DTZ value, or that the table only has the positions with the other
+/// player to move.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub(crate) enum Dtz {
+    Plies(i32),
+    OtherSide,
+}
+
+// The Huffman code and the symbols of one part of a table
+struct Pairs {
+    // Everything has this value when there are no index bits
+    single_value: u8,
+    flags: u8,
+    block_size: u32,
+    index_bits: u32,
+    index_table: usize,
+    size_table: usize,
+    data: usize,
+    min_length: usize,
+    // Of the first symbol of each code length
+    offsets: usize,
+    symbols: usize,
+    symbol_lengths: Vec<u32>,
+    base: Vec<u64>,
+}
+
+impl Pairs {
+    // Reads the code at `at` and returns it with the sizes of its index
+    // table, size table and data
+    fn read(data: &[u8], at: &mut usize, positions: u64, kind: Kind) -> Option<(Pairs, [usize; 3])> {
+        let flags = *data.get(*at)?;
+        if flags & SINGLE_VALUE != 0 {
+            let single_value = if kind == Kind::Wdl { *data.get(*at + 1)? } else { 0 };
+            *at += 2;
+            let pairs = Pairs {
+                single_value, flags, block_size: 0, index_bits: 0, index_table: 0, size_table: 0, data: 0,
+                min_length: 0, offsets: 0, symbols: 0, symbol_lengths: Vec::new(), base: Vec::new(),
+            };
+            return Some((pairs, [0; 3]));
+        }
+
+        let header = data.get(*at..*at + 10)?;
+        let (block_size, index_bits) = (header[1] as u32, header[2] as u32);
+        let real_blocks = u32_at(data, *at + 4)? as usize;
+        let blocks = real_blocks + header[3] as usize;
+        let (max_length, min_length) = (header[8] as usize, header[9] as usize);
+        if min_length == 0 || max_length < min_length || max_length >= 64 || index_bits == 0 || index_bits > 40 || block_size > 32 {
+            return None;
+        }
+        let lengths = max_length - min_length + 1;
+        let offsets = *at + 10;
+        let symbol_count = u16_at(data, offsets + 2 * lengths)? as usize;
+        let symbols = offsets + 2 * lengths + 2;
+        data.get(symbols..symbols + 3 * symbol_count)?;
+        *at = symbols + 3 * symbol_count + (symbol_count & 1);
+
+        let mut pairs = Pairs {
+            single_value: 0, flags, block_size, index_bits, index_table: 0, size_table: 0, data: 0, min_length,
+            offsets, symbols, symbol_lengths: vec![0; symbol_count], base: vec![0; lengths],
+        };
+        let mut known = vec![false; symbol_count];
+        for symbol in 0..symbol_count {
+            pairs.symbol_length(data, symbol, &mut known, 0)?;
+        }
+        for i in (0..lengths - 1).rev() {
+            let difference = (u16_at(data, offsets + 2 * i)? as u64).wrapping_sub(u16_at(data, offsets + 2 * i + 2)? as u64);
+            pairs.base[i] = pairs.base[i + 1].wrapping_add(difference) / 2;
+        }
+        for (i, base) in pairs.base.iter_mut().enumerate() {
+            *base <<= 64 - (min_length + i);
+        }
+
+        let indices = (positions + (1 << index_bits) - 1) >> index_bits;
+        Some((pairs, [6 * indices as usize, 2 * blocks, real_blocks << block_size]))
+    }
+
+    // The number of values a symbol stands for, less one
+    fn symbol_length(&mut self, data: &[u8], symbol: usize, known: &mut [bool], depth: usize) -> Option<u32> {
+        if known[symbol] {
+            return Some(self.symbol_lengths[symbol]);
+        }
+        if depth > self.symbol_lengths.len() {
+            return None;
+        }
+        let (left, right) = self.children(data, symbol)?;
+        let length = if right == 0xfff {
+            0
+        } else if left < known.len() && right < known.len() {
+            self.symbol_length(data, left, known, depth + 1)? + self.symbol_length(data, right, known, depth + 1)? + 1
+        } else {
+            return None;
+        };
+        self.symbol_lengths[symbol] = length;
+        known[symbol] = true;
+        Some(length)
+    }
+
+    fn children(&self, data: &[u8], symbol: usize) -> Option<(usize, usize)> {
+        let pattern = data.get(self.symbols + 3 * symbol..self.symbols + 3 * symbol + 3)?;
+        let left = ((pattern[1] as usize & 0xf) << 8) | pattern[0] as usize;
+        let right = ((pattern[2] as usize) << 4) | (pattern[1] as usize >> 4);
+        Some((left, right))
+    }
+
+    // The value of position `index`, the low byte and the high four bits
+    fn value(&self, data: &[u8], index: u64) -> Option<[u8; 2]> {
+        if self.index_bits == 0 {
+            return Some([self.single_value, 0]);
+        }
+
+        let main_index = (index >> self.index_bits) as usize;
+        let mut literal = (index & ((1 << self.index_bits) - 1)) as i64 - (1 << (self.index_bits - 1));
+        let entry = self.index_table + 6 * main_index;
+        let mut block = u32_at(data, entry)? as usize;
+        literal += u16_at(data, entry + 4)? as i64;
+        let block_length = |block: usize| u16_at(data, self.size_table + 2 * block).map(|size| size as i64 + 1);
+        while literal < 0 {
+            block = block.checked_sub(1)?;
+            literal += block_length(block)?;
+        }
+        while literal >= block_length(block)? {
+            literal -= block_length(block)?;
+            block += 1;
+        }
+
+        let mut at = self.data + (block << self.block_size);
+        let mut code = u64::from_be_bytes(data.get(at..at + 8)?.try_into().ok()?);
+        at += 8;
+        let mut bits = 0;
+        let mut symbol;
+        loop {
+            let mut length = self.min_length;
+            while code < *self.base.get(length - self.min_length)? {
+                length += 1;
+            }
+            let first = u16_at(data, self.offsets + 2 * (length - self.min_length))? as u64;
+            symbol = (first + ((code - self.base[length - self.min_length]) >> (64 - length))) as usize;
+            let size = *self.symbol_lengths.get(symbol)? as i64 + 1;
+            if literal < size {
+                break;
+            }
+            literal -= size;
+            code <<= length;
+            bits += length;
+            if bits >= 32 {
+                bits -= 32;
+                code |= (u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?) as u64) << bits;
+                at += 4;
+            }
+        }
+
+        while self.symbol_lengths[symbol] != 0 {
+            let (left, right) = self.children(data, symbol)?;
+            let size = self.symbol_lengths[left] as i64 + 1;
+            if literal < size {
+                symbol = left;
+            } else {
+                literal -= size;
+                symbol = right;
+            }
+        }
+        let pattern = data.get(self.symbols + 3 * symbol..self.symbols + 3 * symbol + 2)?;
+        Some([pattern[0], pattern[1] & 0xf])
+    }
+}
+
+// The pieces, groups and factors of a part and its number of positions
+type Layout = (Vec<u8>, Vec<usize>, Vec<u64>, u64);
+
+// How the positions of one part of a table are numbered: the pieces in the
+// order of the table, how many equal pieces are in the group starting at
+// each, and what each group's number is multiplied by
+struct Part {
+    pieces: Vec<u8>,
+    groups: Vec<usize>,
+    factors: Vec<u64>,
+    pairs: Pairs,
+}
+
+/// One Syzygy table file, read into memory.
+pub(crate) struct Table {
+    key: String,
+    kind: Kind,
+    data: Vec<u8>,
+    symmetric: bool,
+    has_pawns: bool,
+    // The leading pawns and the pawns of the other color
+    pawns: [usize; 2],
+    // For each file of the leading pawn, or just one, white to move and black
+    parts: Vec<[Option<Part>; 2]>,
+    // For each file, where the four maps of DTZ values start
+    maps: Vec<[usize; 4]>,
+}
+
+impl fmt::Debug for Table {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Table({} {:?})", self.key, self.kind)
+    }
+}
+
+impl Table {
+    /// Reads the table of the material `key`, like `KRPvKR`.
+    pub(crate) fn new(key: &str, kind: Kind, data: Vec<u8>) -> io::Result<Table> {
+        let magic = if kind == Kind::Wdl { WDL_MAGIC } else { DTZ_MAGIC };
+        if data.get(..4) != Some(&magic[..]) {
+            return Err(invalid());
+        }
+        let (white, black) = key.split_once('v').ok_or_else(invalid)?;
+        let count = |side: &str, letter: char| side.chars().filter(|&c| c == letter).count();
+        let (white_pawns, black_pawns) = (count(white, 'P'), count(black, 'P'));
+        let has_pawns = white_pawns + black_pawns > 0;
+        let pawns = if black_pawns > 0 && (white_pawns == 0 || white_pawns > black_pawns) {
+            [black_pawns, white_pawns]
+        } else {
+            [white_pawns, black_pawns]
+        };
+        // Two kings and nothing else alone
+        let unique = "KQRBNP".chars().map(|letter| (count(white, letter) == 1) as usize + (count(black, letter) == 1) as usize).sum::<usize>();
+        let mut table = Table {
+            key: key.to_string(), kind, data, symmetric: white == black, has_pawns, pawns,
+            parts: Vec::new(), maps: Vec::new(),
+        };
+        table.read(key.len() - 1, unique == 2).ok_or_else(invalid)?;
+        Ok(table)
+    }
+
+    fn read(&mut self, piece_count: usize, kings_apart: bool) -> Option<()> {
+        let data = &self.data;
+        let split = self.kind == Kind::Wdl && data.get(4)? & 1 != 0;
+        let files = if self.has_pawns { 4 } else { 1 };
+        let more_pawns = (self.has_pawns && self.pawns[1] > 0) as usize;
+
+        let mut at = 5;
+        let mut layouts = Vec::new();
+        for file in 0..files {
+            let bytes = data.get(at..at + 1 + more_pawns + piece_count)?;
+            let sides = if split { 2 } else { 1 };
+            let layout: Vec<_> = (0..sides).map(|side| self.layout(bytes, 4 * side as u32, file, kings_apart)).collect::<Option<_>>()?;
+            layouts.push(layout);
+            at += 1 + more_pawns + piece_count;
+        }
+        at += at & 1;
+
+        let mut parts = Vec::new();
+        let mut sizes = Vec::new();
+        for layout in layouts {
+            let mut file_parts = [None, None];
+            for (side, (pieces, groups, factors, positions)) in layout.into_iter().enumerate() {
+                let (pairs, size) = Pairs::read(data, &mut at, positions, self.kind)?;
+                file_parts[side] = Some(Part { pieces, groups, factors, pairs });
+                sizes.push(size);
+            }
+            parts.push(file_parts);
+        }
+
+        let mut maps = Vec::new();
+        if self.kind == Kind::Dtz {
+            for file_parts in parts.iter() {
+                let flags = file_parts[0].as_ref()?.pairs.flags;
+                let mut starts = [0; 4];
+                if flags & MAPPED != 0 {
+                    if flags & WIDE_MAP == 0 {
+                        for start in starts.iter_mut() {
+                            *start = at + 1;
+                            at += 1 + *data.get(at)? as usize;
+                        }
+                    } else {
+                        at += at & 1;
+                        for start in starts.iter_mut() {
+                            *start = at + 2;
+                            at += 2 + 2 * u16_at(data, at)? as usize;
+                        }
+                    }
+                }
+                maps.push(starts);
+            }
+            at += at & 1;
+        }
+
+        let mut all = parts.iter_mut().flat_map(|file_parts| file_parts.iter_mut().flatten()).collect::<Vec<_>>();
+        for (part, size) in all.iter_mut().zip(sizes.iter()) {
+            part.pairs.index_table = at;
+            at += size[0];
+        }
+        for (part, size) in all.iter_mut().zip(sizes.iter()) {
+            part.pairs.size_table = at;
+            at += size[1];
+        }
+        for (part, size) in all.iter_mut().zip(sizes.iter()) {
+            // The data of each part starts on 64 bytes
+            at = (at + 0x3f) & !0x3f;
+            part.pairs.data = at;
+            at += size[2];
+        }
+        if at > data.len() {
+            return None;
+        }
+        self.parts = parts;
+        self.maps = maps;
+        Some(())
+    }
+
+    // The pieces, groups and factors of one side of the table for the leading
+    // pawn on `file`, and the number of positions
+    fn layout(&self, bytes: &[u8], shift: u32, file: usize, kings_apart: bool) -> Option<Layout> {
+        let more_pawns = self.has_pawns && self.pawns[1] > 0;
+        let pieces: Vec<u8> = bytes[1 + more_pawns as usize..].iter().map(|byte| (byte >> shift) & 0xf).collect();
+        let count = pieces.len();
+        let order = ((bytes[0] >> shift) & 0xf) as usize;
+        let second_order = if more_pawns { ((bytes[1] >> shift) & 0xf) as usize } else { 0xf };
+
+        let mut groups = vec![0; count];
+        groups[0] = if self.has_pawns { self.pawns[0] } else if kings_apart { 2 } else { 3 };
+        let mut k = groups[0];
+        if more_pawns {
+            groups[k] = self.pawns[1];
+            k += self.pawns[1];
+        }
+        let mut i = k;
+        while i < count {
+            groups[i] = pieces[i..].iter().take_while(|&&piece| piece == pieces[i]).count();
+            i += groups[i];
+        }
+        if groups[0] == 0 || groups[0] > count || groups[0] > 5 {
+            return None;
+        }
+
+        let mut factors = vec![0; count];
+        let mut free = 64 - k as u64;
+        let mut positions = 1u64;
+        let mut i = 0;
+        while k < count || i == order || i == second_order {
+            if i == order {
+                factors[0] = positions;
+                positions *= if self.has_pawns {
+                    PAWN_FACTOR[groups[0] - 1][file]
+                } else if kings_apart {
+                    KK_POSITIONS
+                } else {
+                    TRIANGLE_POSITIONS
+                };
+            } else if i == second_order {
+                factors[groups[0]] = positions;
+                positions *= binomial(48 - groups[0] as u64, groups[groups[0]] as u64);
+            } else {
+                factors[k] = positions;
+                positions *= binomial(free, groups[k] as u64);
+                free -= groups[k] as u64;
+                k += groups[k];
+            }
+            i += 1;
+            if i > count + 2 {
+                return None;
+            }
+        }
+        Some((pieces, groups, factors, positions))
+    }
+
+    // The squares of the pieces of `state` in the order of the part, the part
+    // and the file of the leading pawn. The table is from white's side, so
+    // the colors are swapped when it is the other way round, and then the
+    // board is turned for pawns.
+    fn locate(&self, state: &State) -> Option<(Vec<u8>, &Part, usize, usize)> {
+        let player = state.get_current_player();
+        let (flip, side) = if self.symmetric {
+            (player == Player::Black, 0)
+        } else {
+            let flip = material_key(state) != self.key;
+            (flip, ((player == Player::White) == flip) as usize)
+        };
+        let mirror = if flip && self.has_pawns { 0x38 } else { 0 };
+        let fill = |squares: &mut Vec<u8>, code: u8| -> Option<()> {
+            let piece = piece_of_code(code)?;
+            let piece = if flip { Piece::new(piece.piece_type, piece.player.opponent()) } else { piece };
+            let start = squares.len();
+            squares.extend((0..64u8).filter(|&square| state.board[square as usize] == Some(piece)).map(|square| square ^ mirror));
+            if squares.len() == start { None } else { Some(()) }
+        };
+
+        let mut squares = Vec::new();
+        let mut file = 0;
+        if self.has_pawns {
+            fill(&mut squares, self.parts[0][0].as_ref()?.pieces[0])?;
+            if squares.iter().any(|&square| !(8..56).contains(&square)) {
+                return None;
+            }
+            // The leading pawn is the one on the file nearest the edge, then
+            // the lowest rank
+            for i in 1..self.pawns[0].min(squares.len()) {
+                if flap(squares[0]) > flap(squares[i]) {
+                    squares.swap(0, i);
+                }
+            }
+            file = FILE_TO_FILE[(squares[0] & 7) as usize];
+        }
+        let part_side = if self.kind == Kind::Wdl { side } else { 0 };
+        let part = self.parts.get(file)?[part_side].as_ref()?;
+        while squares.len() < part.pieces.len() {
+            let code = part.pieces[squares.len()];
+            fill(&mut squares, code)?;
+        }
+        if squares.len() != part.pieces.len() {
+            return None;
+        }
+        Some((squares, part, file, side))
+    }
+
+    // The number of the position with the pieces on `squares`
+    fn encode(&self, part: &Part, mut squares: Vec<u8>) -> Option<u64> {
+        let count = squares.len();
+        if squares[0] & 4 != 0 {
+            for square in squares.iter_mut() {
+                *square ^= 7;
+            }
+        }
+
+        let mut index;
+        let mut k;
+        if !self.has_pawns {
+            if squares[0] & 0x20 != 0 {
+                for square in squares.iter_mut() {
+                    *square ^= 0x38;
+                }
+            }
+            // Mirrored on the diagonal when the first of the leading pieces
+            // off it is above it
+            let leading = part.groups[0];
+            if let Some(i) = squares.iter().position(|&square| off_diagonal(square) != 0) {
+                if i < leading && off_diagonal(squares[i]) > 0 {
+                    for square in squares.iter_mut() {
+                        *square = (*square % 8) * 8 + *square / 8;
+                    }
+                }
+            }
+
+            let triangle = TRIANGLE[squares[0] as usize] as u64;
+            if leading == 2 {
+                let kings = KK_INDEX[triangle as usize][squares[1] as usize];
+                if kings < 0 {
+                    return None;
+                }
+                index = kings as u64;
+            } else {
+                let (first, second, third) = (squares[0] as u64, squares[1] as u64, squares[2] as u64);
+                let skip_second = (second > first) as u64;
+                let skip_third = (third > first) as u64 + (third > second) as u64;
+                index = if off_diagonal(squares[0]) != 0 {
+                    triangle * 63 * 62 + (second - skip_second) * 62 + third - skip_third
+                } else if off_diagonal(squares[1]) != 0 {
+                    6 * 63 * 62 + diagonal(squares[0]) * 28 * 62 + lower(squares[1]) * 62 + third - skip_third
+                } else if off_diagonal(squares[2]) != 0 {
+                    6 * 63 * 62 + 4 * 28 * 62 + diagonal(squares[0]) * 7 * 28 + (diagonal(squares[1]) - skip_second) * 28 + lower(squares[2])
+                } else {
+                    6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + diagonal(squares[0]) * 7 * 6 + (diagonal(squares[1]) - skip_second) * 6
+                        + diagonal(squares[2]) - skip_third
+                };
+            }
+            k = leading;
+        } else {
+            let leading = part.groups[0];
+            squares[1..leading].sort_unstable_by_key(|&square| std::cmp::Reverse(PAWN_TWIST[square as usize]));
+            index = PAWN_INDEX[leading - 1][flap(squares[0])];
+            for (i, &square) in squares.iter().enumerate().take(leading).skip(1) {
+                index += binomial(PAWN_TWIST[square as usize] as u64, (leading - i) as u64);
+            }
+            k = leading;
+        }
+        index *= part.factors[0];
+
+        // The other pawns can only be on 48 squares, the rest on any free one
+        let mut pawns_left = self.has_pawns && self.pawns[1] > 0;
+        while k < count {
+            let end = k + part.groups[k];
+            squares[k..end].sort_unstable();
+            let mut group = 0;
+            for i in k..end {
+                let square = squares[i] as u64;
+                let skips = squares[..k].iter().filter(|&&before| square > before as u64).count() as u64;
+                let free = (square - skips).checked_sub(if pawns_left { 8 } else { 0 })?;
+                group += binomial(free, (i - k + 1) as u64);
+            }
+            index += group * part.factors[k];
+            pawns_left = false;
+            k = end;
+        }
+        Some(index)
+    }
+
+    /// The WDL value of the position, from -2 for a loss to 2 for a win.
+    pub(crate) fn probe_wdl(&self, state: &State) -> Option<i32> {
+        let (squares, part, _, _) = self.locate(state)?;
+        let index = self.encode(part, squares)?;
+        Some(part.pairs.value(&self.data, index)?[0] as i32 - 2)
+    }
+
+    /// The distance stored for a position whose WDL value is `wdl`, in plies.
+    /// The DTZ is one more, and another 100 more for cursed wins and blessed
+    /// losses.
+    pub(crate) fn probe_dtz(&self, state: &State, wdl: i32) -> Option<Dtz> {
+        let (squares, part, file, side) = self.locate(state)?;
+        let flags = part.pairs.flags;
+        if (flags & STORES_BLACK != 0) != (side == 1) && !self.symmetric {
+            return Some(Dtz::OtherSide);
+        }
+        let index = self.encode(part, squares)?;
+        let [low, high] = part.pairs.value(&self.data, index)?;
+        let mut value = low as usize | (high as usize) << 8;
+        if flags & MAPPED != 0 {
+            let start = self.maps.get(file)?[WDL_TO_MAP[(wdl + 2) as usize]];
+            value = if flags & WIDE_MAP == 0 {
+                *self.data.get(start + value)? as usize
+            } else {
+                u16_at(&self.data, start + 2 * value)? as usize
+            };
+        }
+        let in_plies = match wdl {
+            2 => flags & WIN_PLIES != 0,
+            -2 => flags & LOSS_PLIES != 0,
+            _ => false,
+        };
+        Some(Dtz::Plies(if in_plies { value as i32 } else { 2 * value as i32 }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index_test() {
+        assert_eq!(KK_INDEX.iter().flatten().filter(|&&index| index >= 0).count() as u64, KK_POSITIONS);
+        assert_eq!(KK_INDEX.iter().flatten().max(), Some(&(KK_POSITIONS as i16 - 1)));
+        // The white king on b1 and the black king anywhere not next to it
+        assert_eq!(&KK_INDEX[0][..8], &[-1, -1, -1, 0, 1, 2, 3, 4]);
+        // Both kings on the diagonal come last
+        assert_eq!(KK_INDEX[6][18], 441);
+        assert_eq!(6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + 4 * 7 * 6, TRIANGLE_POSITIONS);
+        assert_eq!((0..64).filter(|&square| lower(square) < 28).count(), 56);
+        assert_eq!(lower(1), 0);
+        assert_eq!(lower(8), 0);
+        assert_eq!(lower(62), 27);
+        assert_eq!(PAWN_FACTOR[0], [6, 6, 6, 6]);
+        assert_eq!(PAWN_INDEX[0][..7], [0, 1, 2, 3, 4, 5, 0]);
+        assert_eq!(flap(8), 0);
+        assert_eq!(flap(15), 0);
+        assert_eq!(flap(51), 23);
+    }
+}
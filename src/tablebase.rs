@@ -0,0 +1,602 @@
+//! Endgame tablebases, which know the exact result of positions with few
+//! pieces. A `Tablebase` answers win, draw or loss and the distance to the
+//! next capture or pawn move (DTZ); `probe_root` turns that into the best
+//! move, and the search uses both when a table covers the position.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::syzygy::{self, Dtz, Kind, DTZ_MAGIC, WDL_MAGIC};
+use crate::{Move, Piece, PieceType, Player, State};
+
+const PIECE_ORDER: [PieceType; 6] = [PieceType::King, PieceType::Queen, PieceType::Rook, PieceType::Bishop,
+                                     PieceType::Knight, PieceType::Pawn];
+
+/// The result with best play for the player to move. Cursed wins and blessed
+/// losses are wins and losses that the fifty-move rule turns into draws.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    /// The result for the other player.
+    pub fn negate(self) -> Wdl {
+        match self {
+            Wdl::Loss => Wdl::Win,
+            Wdl::BlessedLoss => Wdl::CursedWin,
+            Wdl::Draw => Wdl::Draw,
+            Wdl::CursedWin => Wdl::BlessedLoss,
+            Wdl::Win => Wdl::Loss,
+        }
+    }
+
+    pub fn to_string(&self) -> &str {
+        match self {
+            Wdl::Loss => "loss",
+            Wdl::BlessedLoss => "blessed loss",
+            Wdl::Draw => "draw",
+            Wdl::CursedWin => "cursed win",
+            Wdl::Win => "win",
+        }
+    }
+
+    fn signum(self) -> i32 {
+        match self {
+            Wdl::Loss | Wdl::BlessedLoss => -1,
+            Wdl::Draw => 0,
+            Wdl::CursedWin | Wdl::Win => 1,
+        }
+    }
+}
+
+pub trait Tablebase: Send + Sync + fmt::Debug {
+    /// The most pieces, kings included, of any position in the tables.
+    fn max_pieces(&self) -> u32;
+
+    /// The result for the player to move, or `None` if no table has the position.
+    fn probe_wdl(&self, state: &State) -> Option<Wdl>;
+
+    /// Plies to the next capture or pawn move with best play, positive when
+    /// the player to move wins, negative when they lose and 0 for a draw.
+    fn probe_dtz(&self, state: &State) -> Option<i32>;
+
+    /// Plies to mate with best play, signed like `probe_dtz`, for tables that
+    /// know it.
+    fn probe_dtm(&self, _state: &State) -> Option<i32> {
+        None
+    }
+}
+
+// The distance for the player making a move from the distance for the
+// opponent after it
+fn distance_before(after: i32) -> i32 {
+    match after {
+        after if after > 0 => -after - 1,
+        after if after < 0 => -after + 1,
+        _ => 0,
+    }
+}
+
+pub fn piece_count(state: &State) -> u32 {
+    state.board.iter().filter(|square| square.is_some()).count() as u32
+}
+
+/// Whether tables can know the position: orthodox chess without castling
+/// rights and with few enough pieces.
+pub fn can_probe(tablebase: &dyn Tablebase, state: &State) -> bool {
+    state.variant.name() == "Standard"
+        && state.castling_rights.iter().flatten().all(Option::is_none)
+        && piece_count(state) <= tablebase.max_pieces()
+}
+
+/// The material in the usual tablebase notation, white first: `KRPvKR`.
+pub fn material_key(state: &State) -> String {
+    pieces_key(&state.board.iter().flatten().copied().collect::<Vec<Piece>>())
+}
+
+// The same key for pieces that need not be on a board
+pub(crate) fn pieces_key(pieces: &[Piece]) -> String {
+    let side = |player: Player| -> String {
+        PIECE_ORDER.iter().flat_map(|&piece_type| {
+            let count = pieces.iter().filter(|&&piece| piece == Piece::new(piece_type, player)).count();
+            std::iter::repeat_n(piece_type.to_letter().to_ascii_uppercase(), count)
+        }).collect()
+    };
+    format!("{}v{}", side(Player::White), side(Player::Black))
+}
+
+/// A legal move at the root and what the tables say about it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RootMove {
+    pub mv: Move,
+    /// The result for the player making the move.
+    pub wdl: Wdl,
+    /// Plies to the next capture or pawn move after making it, counting the
+    /// move itself, with the sign of `wdl`.
+    pub dtz: Option<i32>,
+    /// Plies to mate, counted the same way, if the tables know it.
+    pub dtm: Option<i32>,
+    pub mate: bool,
+}
+
+/// Every legal move of the position with its result, best first: mates,
+/// then wins by the fewest plies to mate, or to a capture or pawn move if the
+/// tables do not know mates, then draws, then losses that hold out the
+/// longest. Returns `None` if a table is missing.
+pub fn probe_root(tablebase: &dyn Tablebase, state: &State) -> Option<Vec<RootMove>> {
+    if !can_probe(tablebase, state) {
+        return None;
+    }
+
+    let mut moves = Vec::new();
+    for mv in state.get_legal_moves() {
+        let child = state.preview_move(mv);
+        let opponent = child.get_current_player();
+        if child.get_legal_moves().is_empty() {
+            let mate = child.is_in_check(opponent);
+            let (wdl, distance) = if mate { (Wdl::Win, 1) } else { (Wdl::Draw, 0) };
+            moves.push(RootMove { mv, wdl, dtz: Some(distance), dtm: Some(distance), mate });
+            continue;
+        }
+
+        let mut wdl = tablebase.probe_wdl(&child)?.negate();
+        let dtm = tablebase.probe_dtm(&child).map(distance_before);
+        let dtz = if child.halfmove_clock == 0 {
+            Some(wdl.signum())
+        } else {
+            tablebase.probe_dtz(&child).map(distance_before)
+        };
+        if dtz.is_none() && dtm.is_none() {
+            return None;
+        }
+        // A win that takes too long to convert is a draw under the fifty-move rule
+        if dtz.is_some_and(|dtz| dtz.abs() - 1 + child.halfmove_clock as i32 > 100) {
+            wdl = match wdl {
+                Wdl::Win => Wdl::CursedWin,
+                Wdl::Loss => Wdl::BlessedLoss,
+                wdl => wdl,
+            };
+        }
+        moves.push(RootMove { mv, wdl, dtz, dtm, mate: false });
+    }
+
+    moves.sort_by_key(|root| (std::cmp::Reverse(root.wdl), !root.mate, root.dtm.or(root.dtz)));
+    Some(moves)
+}
+
+// The DTZ of a position from its WDL value when a capture or a pawn move is
+// best, which is also the least DTZ of each result
+const WDL_TO_DTZ: [i32; 5] = [-1, -101, 0, 101, 1];
+
+/// The Syzygy tables in a directory, found by their names like `KRvK.rtbw`
+/// and checked by the magic number at the start of each file. A table is
+/// read into memory the first time it is probed.
+#[derive(Clone, Debug, Default)]
+pub struct SyzygyFiles {
+    wdl: BTreeMap<String, PathBuf>,
+    dtz: BTreeMap<String, PathBuf>,
+    loaded: Arc<Mutex<BTreeMap<PathBuf, Arc<syzygy::Table>>>>,
+}
+
+impl SyzygyFiles {
+    /// Reads all directories in `path`, separated by `:` like the usual
+    /// `SyzygyPath` option.
+    pub fn open(path: &str) -> io::Result<SyzygyFiles> {
+        let mut files = SyzygyFiles::default();
+        for dir in path.split(':').filter(|dir| !dir.is_empty()) {
+            for entry in fs::read_dir(dir)? {
+                files.add(&entry?.path())?;
+            }
+        }
+        Ok(files)
+    }
+
+    fn add(&mut self, path: &Path) -> io::Result<()> {
+        let (name, extension) = match (path.file_stem().and_then(|s| s.to_str()), path.extension().and_then(|s| s.to_str())) {
+            (Some(name), Some(extension)) => (name.to_string(), extension),
+            _ => return Ok(()),
+        };
+        let (tables, magic) = match extension {
+            "rtbw" => (&mut self.wdl, WDL_MAGIC),
+            "rtbz" => (&mut self.dtz, DTZ_MAGIC),
+            _ => return Ok(()),
+        };
+
+        let mut header = [0; 4];
+        File::open(path)?.read_exact(&mut header)?;
+        if header != magic {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is not a Syzygy table", path.display())));
+        }
+        tables.insert(name, path.to_path_buf());
+        Ok(())
+    }
+
+    /// The material of every WDL table, like `KQvK`.
+    pub fn tables(&self) -> Vec<&str> {
+        self.wdl.keys().map(String::as_str).collect()
+    }
+
+    /// The WDL and DTZ files for the material of `state`, from either side.
+    pub fn find(&self, state: &State) -> (Option<&Path>, Option<&Path>) {
+        let key = material_key(state);
+        let (white, black) = key.split_once('v').unwrap();
+        let mirrored = format!("{}v{}", black, white);
+        let wdl = self.wdl.get(&key).or_else(|| self.wdl.get(&mirrored));
+        let dtz = self.dtz.get(&key).or_else(|| self.dtz.get(&mirrored));
+        (wdl.map(PathBuf::as_path), dtz.map(PathBuf::as_path))
+    }
+
+    fn table(&self, state: &State, kind: Kind) -> Option<Arc<syzygy::Table>> {
+        let (wdl, dtz) = self.find(state);
+        let path = if kind == Kind::Wdl { wdl? } else { dtz? };
+        let mut loaded = self.loaded.lock().unwrap();
+        if let Some(table) = loaded.get(path) {
+            return Some(table.clone());
+        }
+        let key = path.file_stem()?.to_str()?;
+        let table = Arc::new(syzygy::Table::new(key, kind, fs::read(path).ok()?).ok()?);
+        loaded.insert(path.to_path_buf(), table.clone());
+        Some(table)
+    }
+
+    // The value of the position in its table, -2 to 2
+    fn probe_wdl_table(&self, state: &State) -> Option<i32> {
+        if piece_count(state) == 2 {
+            return Some(0);
+        }
+        self.table(state, Kind::Wdl)?.probe_wdl(state)
+    }
+
+    fn captures(state: &State) -> Vec<Move> {
+        state.get_legal_moves().into_iter().filter(|&mv| state.is_capture(mv)).collect()
+    }
+
+    // The value of the position when the captures are searched to the end
+    fn probe_ab(&self, state: &State, mut alpha: i32, beta: i32) -> Option<i32> {
+        for mv in SyzygyFiles::captures(state) {
+            let value = -self.probe_ab(&state.preview_move(mv), -beta, -alpha)?;
+            if value >= beta {
+                return Some(value);
+            }
+            alpha = alpha.max(value);
+        }
+        Some(alpha.max(self.probe_wdl_table(state)?))
+    }
+
+    // The WDL value of the position, -2 to 2, and whether a capture is the
+    // best move. The tables leave out positions where a capture is at least
+    // as good as any other move, and know nothing of en passant.
+    fn probe_wdl_value(&self, state: &State) -> Option<(i32, bool)> {
+        let captures = SyzygyFiles::captures(state);
+        let (mut best_capture, mut best_en_passant) = (-3, -3);
+        for &mv in captures.iter() {
+            let value = -self.probe_ab(&state.preview_move(mv), -2, -best_capture)?;
+            if value == 2 {
+                return Some((2, true));
+            }
+            if !state.is_en_passant(mv) {
+                best_capture = best_capture.max(value);
+            } else {
+                best_en_passant = best_en_passant.max(value);
+            }
+        }
+        // With nothing but captures the table may hold any value
+        if !captures.is_empty() && captures.len() == state.get_legal_moves().len() {
+            return Some((best_capture.max(best_en_passant), true));
+        }
+
+        let value = self.probe_wdl_table(state)?;
+        if best_en_passant > best_capture {
+            if best_en_passant > value {
+                return Some((best_en_passant, true));
+            }
+            best_capture = best_en_passant;
+        }
+        if best_capture >= value {
+            return Some((best_capture, best_capture > 0));
+        }
+        Some((value, false))
+    }
+
+    fn probe_dtz_value(&self, state: &State) -> Option<i32> {
+        let (wdl, capture) = self.probe_wdl_value(state)?;
+        let least = WDL_TO_DTZ[(wdl + 2) as usize];
+        if wdl == 0 || capture {
+            return Some(least);
+        }
+
+        let moves = state.get_legal_moves();
+        let is_pawn_move = |mv: Move| state.get(mv.from).is_some_and(|piece| piece.piece_type == PieceType::Pawn);
+        if wdl > 0 {
+            // A pawn move that keeps the win is best
+            for &mv in moves.iter().filter(|&&mv| is_pawn_move(mv) && !state.is_capture(mv)) {
+                if -self.probe_wdl_value(&state.preview_move(mv))?.0 == wdl {
+                    return Some(least);
+                }
+            }
+        }
+
+        match self.table(state, Kind::Dtz)?.probe_dtz(state, wdl)? {
+            Dtz::Plies(plies) => return Some(if wdl > 0 { least + plies } else { least - plies }),
+            Dtz::OtherSide => {}
+        }
+
+        // The table is from the other side, so the best move is looked for
+        // among the moves that are neither captures nor pawn moves
+        let mut best = if wdl > 0 { i32::MAX } else { least };
+        for &mv in moves.iter().filter(|&&mv| !is_pawn_move(mv) && !state.is_capture(mv)) {
+            let child = state.preview_move(mv);
+            let value = -self.probe_dtz_value(&child)?;
+            if value == 1 && child.get_legal_moves().is_empty() {
+                best = 1;
+            } else if wdl > 0 {
+                if value > 0 {
+                    best = best.min(value + 1);
+                }
+            } else {
+                best = best.min(value - 1);
+            }
+        }
+        Some(best)
+    }
+}
+
+fn wdl_from_value(value: i32) -> Wdl {
+    match value {
+        -2 => Wdl::Loss,
+        -1 => Wdl::BlessedLoss,
+        0 => Wdl::Draw,
+        1 => Wdl::CursedWin,
+        _ => Wdl::Win,
+    }
+}
+
+impl Tablebase for SyzygyFiles {
+    fn max_pieces(&self) -> u32 {
+        self.wdl.keys().map(|key| key.chars().filter(|&c| c != 'v').count() as u32).max().unwrap_or(0)
+    }
+
+    fn probe_wdl(&self, state: &State) -> Option<Wdl> {
+        if !can_probe(self, state) {
+            return None;
+        }
+        Some(wdl_from_value(self.probe_wdl_value(state)?.0))
+    }
+
+    fn probe_dtz(&self, state: &State) -> Option<i32> {
+        if !can_probe(self, state) {
+            return None;
+        }
+        self.probe_dtz_value(state)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::search::{self, Score, SearchLimits};
+    use std::sync::Arc;
+
+    /// A stand-in for real tables: whoever has the queen wins in ten plies,
+    /// anything else is a draw.
+    #[derive(Debug)]
+    struct QueenWins;
+
+    impl Tablebase for QueenWins {
+        fn max_pieces(&self) -> u32 {
+            3
+        }
+
+        fn probe_wdl(&self, state: &State) -> Option<Wdl> {
+            let key = material_key(state);
+            let mover_has_queen = key.find('Q').map(|index| index < key.find('v').unwrap());
+            Some(match (mover_has_queen, state.get_current_player()) {
+                (None, _) => Wdl::Draw,
+                (Some(white_has_queen), player) if white_has_queen == (player == Player::White) => Wdl::Win,
+                _ => Wdl::Loss,
+            })
+        }
+
+        fn probe_dtz(&self, state: &State) -> Option<i32> {
+            Some(self.probe_wdl(state)?.signum() * 10)
+        }
+    }
+
+    #[test]
+    fn wdl_test() {
+        assert_eq!(Wdl::Win.negate(), Wdl::Loss);
+        assert_eq!(Wdl::CursedWin.negate(), Wdl::BlessedLoss);
+        assert!(Wdl::Win > Wdl::CursedWin && Wdl::Draw > Wdl::BlessedLoss);
+
+        let state = State::from_fen("8/8/8/4k3/8/8/2R1P3/4K3 w - - 0 1").unwrap();
+        assert_eq!(material_key(&state), "KRPvK");
+        assert_eq!(piece_count(&state), 4);
+        assert!(!can_probe(&QueenWins, &state));
+        assert!(!can_probe(&QueenWins, &State::new()));
+    }
+
+    #[test]
+    fn root_test() {
+        // Black can take the queen for a draw, everything else loses
+        let state = State::from_fen("8/8/8/8/8/8/3kQ3/7K b - - 0 1").unwrap();
+        let moves = probe_root(&QueenWins, &state).unwrap();
+        assert_eq!(moves[0].mv, Move::from_uci("d2e2").unwrap());
+        assert_eq!((moves[0].wdl, moves[0].dtz, moves[0].dtm), (Wdl::Draw, Some(0), None));
+        assert!(moves[1..].iter().all(|root| root.wdl == Wdl::Loss && root.dtz == Some(-11)));
+
+        // Mate comes before any other win
+        let state = State::from_fen("k7/8/1K6/8/8/8/8/4Q3 w - - 0 1").unwrap();
+        let moves = probe_root(&QueenWins, &state).unwrap();
+        assert!(moves[0].mate);
+        assert_eq!(moves[0].mv, Move::from_uci("e1e8").unwrap());
+
+        // A win that runs into the fifty-move rule is cursed
+        let state = State::from_fen("k7/8/1K6/8/8/8/8/4Q3 w - - 95 80").unwrap();
+        let moves = probe_root(&QueenWins, &state).unwrap();
+        assert!(moves[0].mate);
+        assert!(moves.iter().any(|root| root.wdl == Wdl::CursedWin));
+    }
+
+    #[test]
+    fn search_test() {
+        let state = State::from_fen("8/8/8/8/8/8/3kQ3/7K b - - 0 1").unwrap();
+        let limits = SearchLimits { depth: Some(1), tablebase: Some(Arc::new(QueenWins)), ..SearchLimits::default() };
+        let info = search::search(&state, &limits, &mut |_| ());
+        assert_eq!(info.best_move(), Move::from_uci("d2e2"));
+        assert_eq!(info.score, Score::Centipawns(0));
+
+        let state = State::from_fen("8/8/8/8/8/8/8/k3Q2K b - - 0 1").unwrap();
+        let info = search::search(&state, &limits, &mut |_| ());
+        assert!(info.score.to_centipawns() < -search::TB_WIN / 2);
+    }
+
+    // Needs the 3- and 4-piece Syzygy tables, which are not in the repository:
+    // SYZYGY_PATH=/path/to/tables cargo test --release -- --ignored
+    #[test]
+    #[ignore]
+    fn syzygy_test() {
+        use crate::retrograde::GeneratedTables;
+
+        let files = SyzygyFiles::open(&std::env::var("SYZYGY_PATH").expect("SYZYGY_PATH is not set")).unwrap();
+        let probe = |fen: &str| {
+            let state = State::from_fen(fen).unwrap();
+            (files.probe_wdl(&state), files.probe_dtz(&state))
+        };
+        assert_eq!(probe("8/8/8/4k3/8/8/8/3QK3 w - - 0 1").0, Some(Wdl::Win));
+        assert_eq!(probe("8/8/8/4k3/8/8/8/3QK3 b - - 0 1").0, Some(Wdl::Loss));
+        // Black takes the queen or the rook
+        assert_eq!(probe("8/8/8/8/8/8/3kQ3/7K b - - 0 1"), (Some(Wdl::Draw), Some(0)));
+        assert_eq!(probe("k7/8/1K6/8/8/8/8/7R w - - 0 1"), (Some(Wdl::Win), Some(1)));
+        assert_eq!(probe("3k4/3P4/3K4/8/8/8/8/8 b - - 0 1"), (Some(Wdl::Draw), Some(0)));
+        // Promoting is best
+        assert_eq!(probe("8/3P4/8/8/8/8/k7/4K3 w - - 0 1"), (Some(Wdl::Win), Some(1)));
+
+        // Every position agrees with the tables generated here, and without
+        // pawns the distance to zero is the distance to mate, or one more
+        // where the tables count in moves. A mated player has a DTZ of -1.
+        let mut tables = GeneratedTables::new();
+        for key in ["KRvK", "KPvK", "KNNvK"] {
+            tables.generate(key).unwrap();
+            let pieces: Vec<Piece> = key.chars().enumerate().filter(|&(_, c)| c != 'v').map(|(i, c)| {
+                Piece::new(PieceType::from_letter(c).unwrap(), if i < key.find('v').unwrap() { Player::White } else { Player::Black })
+            }).collect();
+            let mut state = State::new();
+            state.castling_rights = [[None; 2]; 2];
+            for squares in (0..64usize.pow(pieces.len() as u32)).map(|n| (0..pieces.len()).map(move |i| n / 64usize.pow(i as u32) % 64)) {
+                state.board = [None; 64];
+                for (&piece, square) in pieces.iter().zip(squares) {
+                    state.board[square] = Some(piece);
+                }
+                for &player in [Player::White, Player::Black].iter() {
+                    state.current_player = player;
+                    if piece_count(&state) != pieces.len() as u32 || state.is_in_check(player.opponent())
+                        || state.board[..8].iter().chain(&state.board[56..]).any(|piece| piece.is_some_and(|piece| piece.piece_type == PieceType::Pawn)) {
+                        continue;
+                    }
+                    let wdl = files.probe_wdl(&state).unwrap();
+                    assert_eq!(wdl, tables.probe_wdl(&state).unwrap(), "{}", state.to_fen());
+                    if key != "KPvK" && !state.get_legal_moves().is_empty() {
+                        let (dtz, dtm) = (files.probe_dtz(&state).unwrap(), tables.probe_dtm(&state).unwrap());
+                        assert!(dtz == dtm || dtz == dtm + dtm.signum(), "{} {} {}", state.to_fen(), dtz, dtm);
+                    }
+                }
+            }
+        }
+    }
+
+    // KRvK and KQvK in the Syzygy format, written by tests/syzygy/make_tables.py
+    // rather than the official generator
+    const SYZYGY_FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/syzygy");
+
+    #[test]
+    fn syzygy_fixtures_test() {
+        let files = SyzygyFiles::open(SYZYGY_FIXTURES).unwrap();
+        assert_eq!(files.tables(), ["KQvK", "KRvK"]);
+        let probe = |fen: &str| {
+            let state = State::from_fen(fen).unwrap();
+            (files.probe_wdl(&state), files.probe_dtz(&state))
+        };
+        assert_eq!(probe("k7/8/1K6/8/8/8/8/7R w - - 0 1"), (Some(Wdl::Win), Some(1)));
+        assert_eq!(probe("8/8/8/4k3/8/8/8/R3K3 w - - 0 1"), (Some(Wdl::Win), Some(27)));
+        assert_eq!(probe("8/8/8/4k3/8/8/8/R3K3 b - - 0 1"), (Some(Wdl::Loss), Some(-28)));
+        assert_eq!(probe("8/8/8/4k3/8/8/8/3QK3 w - - 0 1"), (Some(Wdl::Win), Some(13)));
+        assert_eq!(probe("8/8/8/4k3/8/8/8/3QK3 b - - 0 1"), (Some(Wdl::Loss), Some(-16)));
+        // Black takes the queen or the rook
+        assert_eq!(probe("8/8/8/8/8/8/3kQ3/7K b - - 0 1"), (Some(Wdl::Draw), Some(0)));
+        assert_eq!(probe("8/8/8/8/8/8/8/K1kR4 b - - 0 1"), (Some(Wdl::Draw), Some(0)));
+        // The colours swapped, and a mated player
+        assert_eq!(probe("8/8/8/8/8/1k6/8/K6q w - - 0 1"), (Some(Wdl::Loss), Some(-1)));
+        // Every move takes the rook, so the table holds a made up win
+        assert_eq!(probe("8/8/8/8/8/8/1r6/K6k w - - 0 1"), (Some(Wdl::Draw), Some(0)));
+
+        let state = State::from_fen("8/8/8/4k3/8/8/8/R3K3 w - - 0 1").unwrap();
+        let moves = probe_root(&files, &state).unwrap();
+        assert_eq!(moves.len(), state.get_legal_moves().len());
+        assert_eq!((moves[0].wdl, moves[0].dtz), (Wdl::Win, Some(27)));
+        assert!(moves.windows(2).all(|pair| pair[0].wdl > pair[1].wdl || pair[0].dtz <= pair[1].dtz));
+        assert!(moves.iter().any(|root| root.mv == Move::from_uci("a1a5").unwrap() && root.dtz == Some(27)));
+        assert!(moves.iter().any(|root| root.wdl == Wdl::Win && root.dtz == Some(29)));
+
+        let state = State::from_fen("k7/8/1K6/8/8/8/8/7R w - - 0 1").unwrap();
+        let moves = probe_root(&files, &state).unwrap();
+        assert!(moves[0].mate);
+        assert_eq!(moves[0].mv, Move::from_uci("h1h8").unwrap());
+
+        // Every position agrees with the tables generated here, which by
+        // symmetry needs the white king on a1 to d1 to d4 only
+        use crate::retrograde::GeneratedTables;
+        let mut tables = GeneratedTables::new();
+        let mut state = State::new();
+        state.castling_rights = [[None; 2]; 2];
+        for key in ["KRvK", "KQvK"] {
+            tables.generate(key).unwrap();
+            let piece = Piece::new(PieceType::from_letter(key.chars().nth(1).unwrap()).unwrap(), Player::White);
+            for (&king, n) in [0, 1, 2, 3, 9, 10, 11, 18, 19, 27].iter().flat_map(|king| (0..64 * 64).map(move |n| (king, n))) {
+                state.board = [None; 64];
+                state.board[king] = Some(Piece::new(PieceType::King, Player::White));
+                state.board[n % 64] = Some(piece);
+                state.board[n / 64] = Some(Piece::new(PieceType::King, Player::Black));
+                for &player in [Player::White, Player::Black].iter() {
+                    state.current_player = player;
+                    if piece_count(&state) != 3 || state.is_in_check(player.opponent()) {
+                        continue;
+                    }
+                    assert_eq!(files.probe_wdl(&state), tables.probe_wdl(&state), "{}", state.to_fen());
+                    if !state.get_legal_moves().is_empty() {
+                        assert_eq!(files.probe_dtz(&state), tables.probe_dtm(&state), "{}", state.to_fen());
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn syzygy_files_test() {
+        let dir = std::env::temp_dir().join(format!("syzygy_files_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("KQvK.rtbw"), WDL_MAGIC).unwrap();
+        fs::write(dir.join("KQvK.rtbz"), DTZ_MAGIC).unwrap();
+        fs::write(dir.join("KRvKN.rtbw"), WDL_MAGIC).unwrap();
+        fs::write(dir.join("README.txt"), "not a table").unwrap();
+
+        let files = SyzygyFiles::open(dir.to_str().unwrap()).unwrap();
+        assert_eq!(files.tables(), ["KQvK", "KRvKN"]);
+        assert_eq!(files.max_pieces(), 4);
+        let state = State::from_fen("8/8/8/4k3/8/8/8/3qK3 w - - 0 1").unwrap();
+        let (wdl, dtz) = files.find(&state);
+        assert!(wdl.unwrap().ends_with("KQvK.rtbw") && dtz.unwrap().ends_with("KQvK.rtbz"));
+
+        fs::write(dir.join("KPvK.rtbw"), [0, 0, 0, 0]).unwrap();
+        assert!(SyzygyFiles::open(dir.to_str().unwrap()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
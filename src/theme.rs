@@ -0,0 +1,236 @@
+use sdl2::pixels::Color;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const THEME_DIR: &str = "themes";
+const THEME_FILE: &str = "theme.cfg";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum PieceFormat {
+    Png,
+    Svg,
+}
+
+/// Piece set, font and board colors read from `themes/<name>/theme.cfg`.
+///
+/// The file has one `key = value` pair per line and lines starting with `#` are comments.
+/// Paths are relative to the directory of the theme file.
+#[derive(Clone, Debug)]
+pub struct Theme {
+    pub name: String,
+    pub piece_dir: PathBuf,
+    pub piece_format: PieceFormat,
+    pub font: PathBuf,
+    pub light: Color,
+    pub dark: Color,
+    pub highlight: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            name: String::from("Default"),
+            piece_dir: PathBuf::from("images"),
+            piece_format: PieceFormat::Png,
+            font: PathBuf::from("ubuntu.ttf"),
+            light: Color::RGB(255, 206, 158),
+            dark: Color::RGB(209, 139, 71),
+            highlight: Color::RGB(255, 0, 0),
+        }
+    }
+}
+
+impl Theme {
+    pub fn load(path: &Path) -> Result<Theme, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        let base = path.parent().unwrap_or_else(|| Path::new("."));
+        Theme::parse(&text, base).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    fn parse(text: &str, base: &Path) -> Result<Theme, String> {
+        let mut theme = Theme::default();
+        theme.piece_dir = base.join(&theme.piece_dir);
+        theme.font = base.join(&theme.font);
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (key, value) = match line.find('=') {
+                Some(index) => (line[..index].trim(), line[index + 1..].trim()),
+                None => return Err(format!("line {}: expected `key = value`", number + 1)),
+            };
+
+            match key {
+                "name" => theme.name = value.to_string(),
+                "pieces" => theme.piece_dir = base.join(value),
+                "format" => theme.piece_format = match value {
+                    "png" => PieceFormat::Png,
+                    "svg" => PieceFormat::Svg,
+                    _ => return Err(format!("line {}: unknown piece format `{}`", number + 1, value)),
+                },
+                "font" => theme.font = base.join(value),
+                "light" => theme.light = parse_color(value).ok_or(format!("line {}: bad color", number + 1))?,
+                "dark" => theme.dark = parse_color(value).ok_or(format!("line {}: bad color", number + 1))?,
+                "highlight" => theme.highlight = parse_color(value).ok_or(format!("line {}: bad color", number + 1))?,
+                _ => return Err(format!("line {}: unknown key `{}`", number + 1, key)),
+            }
+        }
+        Ok(theme)
+    }
+
+    /// All theme files below the theme directory, sorted by directory name.
+    pub fn discover(dir: &Path) -> Vec<PathBuf> {
+        let mut list: Vec<PathBuf> = match fs::read_dir(dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path().join(THEME_FILE))
+                .filter(|path| path.is_file())
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        list.sort();
+        list
+    }
+
+    /// Path of the image for a piece, e.g. `white_king.png` or `white_king.svg`.
+    /// SVG sets may also use the Wikimedia names, e.g. `Chess_klt45.svg`.
+    pub fn piece_path(&self, name: &str) -> PathBuf {
+        match self.piece_format {
+            PieceFormat::Png => self.piece_dir.join(format!("{}.png", name)),
+            PieceFormat::Svg => {
+                let path = self.piece_dir.join(format!("{}.svg", name));
+                if path.is_file() {
+                    return path;
+                }
+                self.piece_dir.join(wikimedia_name(name))
+            }
+        }
+    }
+}
+
+fn wikimedia_name(name: &str) -> String {
+    let mut parts = name.split('_');
+    let color = match parts.next() {
+        Some("white") => "l",
+        _ => "d",
+    };
+    let piece = match parts.next() {
+        Some("king") => "k",
+        Some("queen") => "q",
+        Some("rook") => "r",
+        Some("bishop") => "b",
+        Some("knight") => "n",
+        _ => "p",
+    };
+    format!("Chess_{}{}t45.svg", piece, color)
+}
+
+/// Accepts `r, g, b` with decimal components or `#rrggbb`.
+fn parse_color(value: &str) -> Option<Color> {
+    if let Some(hex) = value.strip_prefix('#') {
+        if hex.len() != 6 {
+            return None;
+        }
+        let component = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).ok();
+        return Some(Color::RGB(component(0)?, component(2)?, component(4)?));
+    }
+
+    let parts: Vec<u8> = value.split(',').map(|p| p.trim().parse().ok()).collect::<Option<Vec<u8>>>()?;
+    if parts.len() == 3 {
+        Some(Color::RGB(parts[0], parts[1], parts[2]))
+    } else {
+        None
+    }
+}
+
+/// Rewrites the size of an SVG document so it is rasterized at `size` pixels.
+/// The original size becomes the view box, so the drawing is scaled instead of clipped.
+pub fn resize_svg(svg: &str, size: u32) -> String {
+    let start = match svg.find("<svg") {
+        Some(start) => start,
+        None => return svg.to_string(),
+    };
+    let end = match svg[start..].find('>') {
+        Some(end) => start + end,
+        None => return svg.to_string(),
+    };
+
+    let tag = &svg[start..end];
+    let width = svg_attribute(tag, "width").unwrap_or_else(|| String::from("45"));
+    let height = svg_attribute(tag, "height").unwrap_or_else(|| String::from("45"));
+
+    let mut new_tag = remove_svg_attribute(&remove_svg_attribute(tag, "width"), "height");
+    if svg_attribute(tag, "viewBox").is_none() {
+        new_tag.push_str(&format!(" viewBox=\"0 0 {} {}\"", width, height));
+    }
+    new_tag.push_str(&format!(" width=\"{}\" height=\"{}\"", size, size));
+
+    format!("{}{}{}", &svg[..start], new_tag, &svg[end..])
+}
+
+fn svg_attribute(tag: &str, name: &str) -> Option<String> {
+    let pattern = format!(" {}=\"", name);
+    let start = tag.find(&pattern)? + pattern.len();
+    let end = tag[start..].find('"')? + start;
+    Some(tag[start..end].to_string())
+}
+
+fn remove_svg_attribute(tag: &str, name: &str) -> String {
+    let pattern = format!(" {}=\"", name);
+    match tag.find(&pattern) {
+        Some(start) => {
+            let value_start = start + pattern.len();
+            match tag[value_start..].find('"') {
+                Some(end) => format!("{}{}", &tag[..start], &tag[value_start + end + 1..]),
+                None => tag.to_string(),
+            }
+        }
+        None => tag.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_test() {
+        let text = "# comment\nname = Ice\nformat = svg\npieces = ../../images\nlight = #dee3e6\ndark = 140, 162, 173\n";
+        let theme = Theme::parse(text, Path::new("themes/ice")).unwrap();
+        assert_eq!(theme.name, "Ice");
+        assert_eq!(theme.piece_format, PieceFormat::Svg);
+        assert_eq!(theme.piece_dir, Path::new("themes/ice/../../images"));
+        assert_eq!(theme.light, Color::RGB(0xde, 0xe3, 0xe6));
+        assert_eq!(theme.dark, Color::RGB(140, 162, 173));
+        assert_eq!(theme.highlight, Theme::default().highlight);
+    }
+
+    #[test]
+    fn parse_error_test() {
+        assert!(Theme::parse("light = 1, 2", Path::new(".")).is_err());
+        assert!(Theme::parse("colour = 1, 2, 3", Path::new(".")).is_err());
+        assert!(Theme::parse("format = gif", Path::new(".")).is_err());
+        assert!(Theme::parse("pieces", Path::new(".")).is_err());
+    }
+
+    #[test]
+    fn piece_path_test() {
+        let mut theme = Theme::default();
+        assert_eq!(theme.piece_path("black_rook"), Path::new("images/black_rook.png"));
+        theme.piece_format = PieceFormat::Svg;
+        assert_eq!(theme.piece_path("black_rook"), Path::new("images/Chess_rdt45.svg"));
+    }
+
+    #[test]
+    fn resize_svg_test() {
+        let svg = "<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\" version=\"1.1\" width=\"45\" height=\"45\"><g/></svg>";
+        let resized = resize_svg(svg, 120);
+        assert!(resized.contains("viewBox=\"0 0 45 45\""));
+        assert!(resized.contains("width=\"120\" height=\"120\""));
+        assert!(!resized.contains("width=\"45\""));
+        assert!(resized.ends_with("><g/></svg>"));
+    }
+}
@@ -0,0 +1,832 @@
+//! Matches and tournaments between engines, to measure whether a change makes
+//! an engine stronger. Engines are the built-in search with fixed limits or
+//! external UCI programs. Every opening is played twice with the colors
+//! swapped, and the games can be adjudicated by the engines' scores or by an
+//! endgame tablebase.
+
+use std::io;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::clock::{Clock, SystemTimeSource, TimeControl};
+use crate::fen::START_FEN;
+use crate::search::{self, Score, SearchLimits};
+use crate::tablebase::{self, Tablebase, Wdl};
+use crate::uci::{GoLimits, UciEngine};
+use crate::variant::Variant;
+use crate::{GameStatus, Move, Player, State};
+
+// Moves the built-in engine plans for when the time control has no move count
+const DEFAULT_MOVES_LEFT: u32 = 30;
+// Extra time an external engine gets to answer before it loses on time
+const GRACE_TIME: Duration = Duration::from_secs(1);
+
+/// Something that picks moves in a game. The score is from the point of view
+/// of the player to move.
+pub trait Engine {
+    fn new_game(&mut self) -> io::Result<()>;
+    fn think(&mut self, start: &State, state: &State, clock: &Clock) -> io::Result<(Option<Move>, Option<Score>)>;
+}
+
+/// The built-in search with optional fixed limits. Without them it plays on
+/// the clock.
+pub struct BuiltinEngine {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+}
+
+impl Engine for BuiltinEngine {
+    fn new_game(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn think(&mut self, _start: &State, state: &State, clock: &Clock) -> io::Result<(Option<Move>, Option<Score>)> {
+        let player = state.get_current_player();
+        let moves_left = clock.moves_to_go(player).unwrap_or(DEFAULT_MOVES_LEFT);
+        let limits = SearchLimits {
+            depth: self.depth,
+            nodes: self.nodes,
+            time: Some(search::allocate_time(clock.remaining(player), moves_left, clock.increment(player))),
+            ..SearchLimits::default()
+        };
+        let info = search::search(state, &limits, &mut |_| {});
+        Ok((info.best_move(), Some(info.score)))
+    }
+}
+
+impl Engine for UciEngine {
+    fn new_game(&mut self) -> io::Result<()> {
+        UciEngine::new_game(self)
+    }
+
+    fn think(&mut self, start: &State, state: &State, clock: &Clock) -> io::Result<(Option<Move>, Option<Score>)> {
+        let player = state.get_current_player();
+        let limits = GoLimits {
+            time: Some([clock.remaining(Player::White), clock.remaining(Player::Black)]),
+            increment: Some([clock.increment(Player::White), clock.increment(Player::Black)]),
+            moves_to_go: clock.moves_to_go(player),
+            ..GoLimits::default()
+        };
+        self.go(start, state, &limits)?;
+        let (mv, info) = self.wait_best_move(clock.remaining(player) + GRACE_TIME)?;
+        Ok((mv, info.and_then(|info| info.score)))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EngineKind {
+    Builtin { depth: Option<u32>, nodes: Option<u64> },
+    Uci { command: String, options: Vec<(String, String)> },
+}
+
+/// An engine taking part in a match, and the name it plays under.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EngineConfig {
+    pub name: String,
+    pub kind: EngineKind,
+}
+
+impl EngineConfig {
+    /// Parses a comma separated engine description: `builtin` with optional
+    /// `depth=N` and `nodes=N`, or `cmd=<path>` with any number of UCI options
+    /// as `option.<name>=<value>`. Both take `name=<name>`.
+    pub fn parse(spec: &str) -> Option<EngineConfig> {
+        let mut fields = spec.split(',');
+        let first = fields.next()?.trim();
+        let mut kind = if first == "builtin" {
+            EngineKind::Builtin { depth: None, nodes: None }
+        } else {
+            EngineKind::Uci { command: first.strip_prefix("cmd=")?.to_string(), options: Vec::new() }
+        };
+
+        let mut name = None;
+        for field in fields {
+            let (key, value) = field.split_once('=')?;
+            match (&mut kind, key.trim()) {
+                (_, "name") => name = Some(value.to_string()),
+                (EngineKind::Builtin { depth, .. }, "depth") => *depth = Some(value.parse().ok()?),
+                (EngineKind::Builtin { nodes, .. }, "nodes") => *nodes = Some(value.parse().ok()?),
+                (EngineKind::Uci { options, .. }, key) if key.starts_with("option.") => {
+                    options.push((key["option.".len()..].to_string(), value.to_string()));
+                }
+                _ => return None,
+            }
+        }
+
+        let name = name.unwrap_or_else(|| match &kind {
+            EngineKind::Builtin { depth: Some(depth), .. } => format!("builtin-d{}", depth),
+            EngineKind::Builtin { .. } => String::from("builtin"),
+            EngineKind::Uci { command, .. } => command.rsplit('/').next().unwrap_or(command).to_string(),
+        });
+        Some(EngineConfig { name, kind })
+    }
+
+    pub fn start(&self) -> io::Result<Box<dyn Engine>> {
+        match &self.kind {
+            EngineKind::Builtin { depth, nodes } => Ok(Box::new(BuiltinEngine { depth: *depth, nodes: *nodes })),
+            EngineKind::Uci { command, options } => {
+                let mut engine = UciEngine::start(command, &[])?;
+                for (name, value) in options {
+                    engine.set_option(name, value)?;
+                }
+                Ok(Box::new(engine))
+            }
+        }
+    }
+}
+
+/// A start position for games, with the moves played from it.
+#[derive(Clone)]
+pub struct Opening {
+    pub start: State,
+    pub moves: Vec<Move>,
+}
+
+impl Opening {
+    pub fn position(&self) -> State {
+        let mut state = self.start.clone();
+        for &mv in self.moves.iter() {
+            let _res = state.play_move(mv);
+        }
+        state
+    }
+}
+
+/// Reads openings from PGN games or from EPD lines, one position per line.
+/// Returns `None` if any of them is invalid.
+pub fn parse_openings(text: &str, variant: Arc<dyn Variant>) -> Option<Vec<Opening>> {
+    if text.lines().any(|line| line.trim_start().starts_with('[')) {
+        return parse_pgn_openings(text, variant);
+    }
+
+    let mut openings = Vec::new();
+    for line in text.lines().map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 4 {
+            return None;
+        }
+        // EPD has no move counters, but a FEN line may
+        let counters = match fields.get(4..6) {
+            Some([halfmoves, moves]) if halfmoves.parse::<u32>().is_ok() && moves.parse::<u32>().is_ok() =>
+                format!("{} {}", halfmoves, moves),
+            _ => String::from("0 1"),
+        };
+        let fen = format!("{} {}", fields[..4].join(" "), counters);
+        let start = State::from_fen_with_variant(&fen, variant.clone()).ok()?;
+        openings.push(Opening { start, moves: Vec::new() });
+    }
+    Some(openings)
+}
+
+fn parse_pgn_openings(text: &str, variant: Arc<dyn Variant>) -> Option<Vec<Opening>> {
+    let mut openings = Vec::new();
+    let mut fen: Option<String> = None;
+    let mut movetext = String::new();
+
+    for line in text.lines().map(str::trim) {
+        if line.starts_with('[') {
+            // A tag after movetext starts the next game
+            if !movetext.is_empty() {
+                openings.push(pgn_opening(fen.take().as_deref(), &movetext, variant.clone())?);
+                movetext.clear();
+            }
+            if let Some(value) = line.strip_prefix("[FEN \"") {
+                fen = Some(value.trim_end_matches(']').trim_end_matches('"').to_string());
+            }
+        } else if !line.is_empty() {
+            movetext.push_str(line);
+            movetext.push(' ');
+        }
+    }
+    if !movetext.is_empty() || fen.is_some() {
+        openings.push(pgn_opening(fen.as_deref(), &movetext, variant)?);
+    }
+    Some(openings)
+}
+
+fn pgn_opening(fen: Option<&str>, movetext: &str, variant: Arc<dyn Variant>) -> Option<Opening> {
+    let start = match fen {
+        Some(fen) => State::from_fen_with_variant(fen, variant).ok()?,
+        None => State::new_variant(variant),
+    };
+
+    // Comments and variations are skipped
+    let mut main_line = String::new();
+    let mut nesting = 0;
+    for c in movetext.chars() {
+        match c {
+            '{' | '(' => nesting += 1,
+            '}' | ')' => nesting -= 1,
+            _ if nesting == 0 => main_line.push(c),
+            _ => (),
+        }
+    }
+
+    let mut state = start.clone();
+    let mut moves = Vec::new();
+    for token in main_line.split_whitespace() {
+        if ["1-0", "0-1", "1/2-1/2", "*"].contains(&token) || token.starts_with('$') {
+            continue;
+        }
+        let san = token.trim_start_matches(|c: char| c.is_ascii_digit() || c == '.');
+        if san.is_empty() {
+            continue;
+        }
+        let mv = state.move_from_san(san)?;
+        state.play_move(mv).ok()?;
+        moves.push(mv);
+    }
+    Some(Opening { start, moves })
+}
+
+/// When to end a game early. Scores are in centipawns and taken from both
+/// engines, so both have to agree.
+#[derive(Clone, Debug, Default)]
+pub struct Adjudication {
+    /// A player wins when the scores say they are ahead by at least this
+    /// much for `resign_moves` moves in a row.
+    pub resign_score: Option<i32>,
+    pub resign_moves: u32,
+    /// The game is drawn when the scores stay within this many centipawns of
+    /// zero for `draw_moves` moves in a row, from move `draw_start` on.
+    pub draw_score: Option<i32>,
+    pub draw_moves: u32,
+    pub draw_start: u32,
+    /// Games that reach this many moves are drawn.
+    pub max_moves: Option<u32>,
+    /// Games are decided as soon as they reach a position in the tablebase.
+    pub tablebase: Option<Arc<dyn Tablebase>>,
+}
+
+impl Adjudication {
+    // The result of the game from the scores after each ply from white's
+    // point of view, if it should end here. `Some(None)` is a draw.
+    fn judge(&self, state: &State, scores: &[Option<i32>]) -> Option<Option<Player>> {
+        let last = |moves: u32| {
+            let plies = moves.max(1) as usize * 2;
+            if scores.len() < plies {
+                return None;
+            }
+            scores[scores.len() - plies..].iter().copied().collect::<Option<Vec<i32>>>()
+        };
+
+        if let (Some(resign_score), Some(scores)) = (self.resign_score, last(self.resign_moves)) {
+            if scores.iter().all(|&score| score >= resign_score) {
+                return Some(Some(Player::White));
+            } else if scores.iter().all(|&score| score <= -resign_score) {
+                return Some(Some(Player::Black));
+            }
+        }
+
+        if let Some(tablebase) = &self.tablebase {
+            let player = state.get_current_player();
+            match tablebase.probe_wdl(state).filter(|_| tablebase::can_probe(tablebase.as_ref(), state)) {
+                Some(Wdl::Win) => return Some(Some(player)),
+                Some(Wdl::Loss) => return Some(Some(player.opponent())),
+                Some(_) => return Some(None),
+                None => (),
+            }
+        }
+
+        let moves = state.total_steps / 2 + 1;
+        if let (Some(draw_score), Some(scores)) = (self.draw_score, last(self.draw_moves)) {
+            if moves >= self.draw_start && scores.iter().all(|score| score.abs() <= draw_score) {
+                return Some(None);
+            }
+        }
+        match self.max_moves {
+            Some(max_moves) if moves > max_moves => Some(None),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MatchSettings {
+    pub time_control: TimeControl,
+    pub adjudication: Adjudication,
+}
+
+/// Plays one game from `opening` and returns it when it is over. An engine
+/// that fails, plays an illegal move or has no move loses by adjudication.
+pub fn play_game<'a>(white: &'a mut dyn Engine, black: &'a mut dyn Engine, opening: &Opening, settings: &MatchSettings) -> State {
+    let mut state = opening.position();
+    let mut clock = Clock::new(settings.time_control.clone(), Box::new(SystemTimeSource::new()));
+    // Scores after each ply from white's point of view
+    let mut scores: Vec<Option<i32>> = Vec::new();
+
+    if white.new_game().is_err() {
+        state.adjudicate(Some(Player::Black));
+    } else if black.new_game().is_err() {
+        state.adjudicate(Some(Player::White));
+    }
+
+    clock.start(state.get_current_player());
+    while state.get_game_running() {
+        let player = state.get_current_player();
+        let engine = match player {
+            Player::White => &mut *white,
+            Player::Black => &mut *black,
+        };
+        let thought = engine.think(&opening.start, &state, &clock);
+        if let Some(flagged) = clock.press() {
+            state.lose_on_time(flagged);
+            break;
+        }
+
+        let (mv, score) = match thought {
+            Ok((Some(mv), score)) => (mv, score),
+            _ => {
+                state.adjudicate(Some(player.opponent()));
+                break;
+            }
+        };
+        if state.play_move(mv).is_err() {
+            state.adjudicate(Some(player.opponent()));
+            break;
+        }
+
+        let sign = if player == Player::White { 1 } else { -1 };
+        scores.push(score.map(|score| sign * score.to_centipawns()));
+
+        // Repetitions and the fifty-move rule end engine games without a claim
+        if state.get_claimable_draw().is_some() {
+            let _res = state.claim_draw(state.get_current_player());
+        } else if let Some(winner) = settings.adjudication.judge(&state, &scores) {
+            state.adjudicate(winner);
+        }
+    }
+    state
+}
+
+/// Wins, draws and losses of one player.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub wins: u32,
+    pub draws: u32,
+    pub losses: u32,
+}
+
+impl Stats {
+    pub fn games(&self) -> u32 {
+        self.wins + self.draws + self.losses
+    }
+
+    pub fn points(&self) -> f64 {
+        self.wins as f64 + self.draws as f64 / 2.0
+    }
+
+    /// The average score per game, from 0 to 1.
+    pub fn score(&self) -> f64 {
+        if self.games() == 0 {
+            0.5
+        } else {
+            self.points() / self.games() as f64
+        }
+    }
+
+    fn variance(&self) -> f64 {
+        let score = self.score();
+        let games = self.games().max(1) as f64;
+        (self.wins as f64 * (1.0 - score).powi(2)
+            + self.draws as f64 * (0.5 - score).powi(2)
+            + self.losses as f64 * score.powi(2)) / games
+    }
+
+    /// The Elo difference the score stands for, and the margin of its 95%
+    /// confidence interval.
+    pub fn elo(&self) -> (f64, f64) {
+        let score = self.score();
+        let deviation = (self.variance() / self.games().max(1) as f64).sqrt();
+        let low = score_to_elo(score - 1.96 * deviation);
+        let high = score_to_elo(score + 1.96 * deviation);
+        (score_to_elo(score), (high - low) / 2.0)
+    }
+}
+
+/// The Elo difference at which a player is expected to score `score`.
+pub fn score_to_elo(score: f64) -> f64 {
+    if score <= 0.0 {
+        f64::NEG_INFINITY
+    } else if score >= 1.0 {
+        f64::INFINITY
+    } else {
+        // Adding zero turns -0 into 0
+        -400.0 * (1.0 / score - 1.0).log10() + 0.0
+    }
+}
+
+pub fn elo_to_score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-elo / 400.0))
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SprtResult {
+    /// The engine is no stronger than `elo0`.
+    AcceptH0,
+    /// The engine is at least `elo1` stronger.
+    AcceptH1,
+    Continue,
+}
+
+/// A sequential probability ratio test of `elo0` against `elo1`, with the
+/// error rates `alpha` and `beta`. The log-likelihood ratio uses the normal
+/// approximation of the score.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+impl Sprt {
+    pub fn new(elo0: f64, elo1: f64) -> Sprt {
+        Sprt { elo0, elo1, alpha: 0.05, beta: 0.05 }
+    }
+
+    pub fn llr(&self, stats: &Stats) -> f64 {
+        let variance = stats.variance();
+        if variance == 0.0 {
+            return 0.0;
+        }
+        let score0 = elo_to_score(self.elo0);
+        let score1 = elo_to_score(self.elo1);
+        stats.games() as f64 * (score1 - score0) * (2.0 * stats.score() - score0 - score1) / (2.0 * variance)
+    }
+
+    /// The LLR below which H0 and above which H1 is accepted.
+    pub fn bounds(&self) -> (f64, f64) {
+        ((self.beta / (1.0 - self.alpha)).ln(), ((1.0 - self.beta) / self.alpha).ln())
+    }
+
+    pub fn test(&self, stats: &Stats) -> SprtResult {
+        let llr = self.llr(stats);
+        let (lower, upper) = self.bounds();
+        if llr <= lower {
+            SprtResult::AcceptH0
+        } else if llr >= upper {
+            SprtResult::AcceptH1
+        } else {
+            SprtResult::Continue
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    /// Every engine plays every other.
+    RoundRobin,
+    /// The first engine plays all the others.
+    Gauntlet,
+}
+
+/// The pairs of engines that play each other.
+pub fn pairings(format: Format, engines: usize) -> Vec<(usize, usize)> {
+    let mut pairs = Vec::new();
+    for first in 0..engines {
+        for second in first + 1..engines {
+            if format == Format::RoundRobin || first == 0 {
+                pairs.push((first, second));
+            }
+        }
+    }
+    pairs
+}
+
+/// A finished game of a tournament.
+pub struct GameRecord {
+    pub round: u32,
+    pub white: usize,
+    pub black: usize,
+    pub state: State,
+    pub pgn: String,
+}
+
+/// The score of every engine against every other.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Results {
+    pub names: Vec<String>,
+    stats: Vec<Vec<Stats>>,
+}
+
+impl Results {
+    pub fn new(names: Vec<String>) -> Results {
+        let count = names.len();
+        Results { names, stats: vec![vec![Stats::default(); count]; count] }
+    }
+
+    pub fn add(&mut self, white: usize, black: usize, status: GameStatus) {
+        match status {
+            GameStatus::GameWonByWhite => {
+                self.stats[white][black].wins += 1;
+                self.stats[black][white].losses += 1;
+            }
+            GameStatus::GameWonByBlack => {
+                self.stats[white][black].losses += 1;
+                self.stats[black][white].wins += 1;
+            }
+            GameStatus::Draw => {
+                self.stats[white][black].draws += 1;
+                self.stats[black][white].draws += 1;
+            }
+            GameStatus::WhiteTurn | GameStatus::BlackTurn => (),
+        }
+    }
+
+    /// The results of `engine` against `opponent`.
+    pub fn stats(&self, engine: usize, opponent: usize) -> Stats {
+        self.stats[engine][opponent]
+    }
+
+    pub fn total(&self, engine: usize) -> Stats {
+        self.stats[engine].iter().fold(Stats::default(), |total, stats| Stats {
+            wins: total.wins + stats.wins,
+            draws: total.draws + stats.draws,
+            losses: total.losses + stats.losses,
+        })
+    }
+
+    /// A table of the engines by points, with their score against each other.
+    pub fn crosstable(&self) -> String {
+        let mut order: Vec<usize> = (0..self.names.len()).collect();
+        order.sort_by(|a, b| self.total(*b).points().partial_cmp(&self.total(*a).points()).unwrap());
+        let width = self.names.iter().map(String::len).max().unwrap_or(0).max(6);
+
+        let mut table = format!("{:>3} {:<width$} {:>9} {:>7}", "", "Engine", "Points", "Games", width = width);
+        for rank in 1..=order.len() {
+            table.push_str(&format!(" {:>9}", rank));
+        }
+        table.push('\n');
+
+        for (rank, &engine) in order.iter().enumerate() {
+            let total = self.total(engine);
+            table.push_str(&format!("{:>3} {:<width$} {:>9} {:>7}", rank + 1, self.names[engine], total.points(),
+                                    total.games(), width = width));
+            for &opponent in order.iter() {
+                let stats = self.stats(engine, opponent);
+                let cell = if opponent == engine {
+                    String::from("---")
+                } else if stats.games() == 0 {
+                    String::from("")
+                } else {
+                    format!("{}/{}", stats.points(), stats.games())
+                };
+                table.push_str(&format!(" {:>9}", cell));
+            }
+            table.push('\n');
+        }
+        table
+    }
+}
+
+pub struct Tournament {
+    pub engines: Vec<EngineConfig>,
+    pub format: Format,
+    /// Games each pair of engines plays, half of them with each color.
+    pub games: u32,
+    /// Openings to start the games from, used in turn. The start position of
+    /// the variant is used without any.
+    pub openings: Vec<Opening>,
+    pub variant: Arc<dyn Variant>,
+    pub settings: MatchSettings,
+    pub event: String,
+}
+
+impl Tournament {
+    /// Starts the engines and plays all games, calling `on_game` after each.
+    /// The tournament ends early when `on_game` returns `false`.
+    pub fn run(&self, on_game: &mut dyn FnMut(&GameRecord, &Results) -> bool) -> io::Result<Results> {
+        let mut engines = Vec::new();
+        for config in self.engines.iter() {
+            engines.push(config.start()?);
+        }
+        let mut results = Results::new(self.engines.iter().map(|config| config.name.clone()).collect());
+        let default_opening = Opening { start: State::new_variant(self.variant.clone()), moves: Vec::new() };
+        let mut round = 0;
+
+        for (first, second) in pairings(self.format, engines.len()) {
+            for game in 0..self.games {
+                let opening = match self.openings.len() {
+                    0 => &default_opening,
+                    count => &self.openings[(game / 2) as usize % count],
+                };
+                let (white, black) = if game % 2 == 0 { (first, second) } else { (second, first) };
+                let (left, right) = engines.split_at_mut(second);
+                let (first_engine, second_engine) = (left[first].as_mut(), right[0].as_mut());
+                let state = if white == first {
+                    play_game(first_engine, second_engine, opening, &self.settings)
+                } else {
+                    play_game(second_engine, first_engine, opening, &self.settings)
+                };
+
+                round += 1;
+                results.add(white, black, state.get_game_status());
+                let record = GameRecord {
+                    round,
+                    white,
+                    black,
+                    pgn: self.to_pgn(&state, opening, round, white, black),
+                    state,
+                };
+                if !on_game(&record, &results) {
+                    return Ok(results);
+                }
+            }
+        }
+        Ok(results)
+    }
+
+    fn to_pgn(&self, state: &State, opening: &Opening, round: u32, white: usize, black: usize) -> String {
+        let round = round.to_string();
+        let time_control = self.settings.time_control.to_tag();
+        let fen = opening.start.to_fen();
+        let mut tags = vec![
+            ("Event", self.event.as_str()),
+            ("Round", round.as_str()),
+            ("White", self.engines[white].name.as_str()),
+            ("Black", self.engines[black].name.as_str()),
+            ("TimeControl", time_control.as_str()),
+        ];
+        if fen != START_FEN {
+            tags.push(("FEN", fen.as_str()));
+        }
+        state.to_pgn(&tags)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::retrograde::GeneratedTables;
+    use crate::variant::{Atomic, Standard};
+    use crate::Termination;
+
+    const MOCK_ENGINE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/mock_uci_engine.sh");
+
+    fn settings() -> MatchSettings {
+        MatchSettings {
+            time_control: TimeControl::fischer(Duration::from_secs(60), Duration::from_secs(0)),
+            adjudication: Adjudication { max_moves: Some(10), ..Adjudication::default() },
+        }
+    }
+
+    #[test]
+    fn config_test() {
+        let config = EngineConfig::parse("builtin,depth=3").unwrap();
+        assert_eq!(config.name, "builtin-d3");
+        assert_eq!(config.kind, EngineKind::Builtin { depth: Some(3), nodes: None });
+
+        let config = EngineConfig::parse("cmd=/usr/bin/stockfish,name=SF,option.Hash=64").unwrap();
+        assert_eq!(config.name, "SF");
+        assert_eq!(config.kind, EngineKind::Uci {
+            command: String::from("/usr/bin/stockfish"),
+            options: vec![(String::from("Hash"), String::from("64"))],
+        });
+
+        assert_eq!(EngineConfig::parse("cmd=stockfish").unwrap().name, "stockfish");
+        assert_eq!(EngineConfig::parse("stockfish"), None);
+        assert_eq!(EngineConfig::parse("builtin,depth=x"), None);
+    }
+
+    #[test]
+    fn openings_test() {
+        let epd = "rnbqkbnr/pppppppp/8/8/4P3/8/PPPP1PPP/RNBQKBNR b KQkq e3 id \"e4\";\n\n\
+                   4k3/8/8/8/8/8/4P3/4K3 w - - 0 40\n";
+        let openings = parse_openings(epd, Arc::new(Standard)).unwrap();
+        assert_eq!(openings.len(), 2);
+        assert_eq!(openings[0].position().get_current_player(), Player::Black);
+        assert_eq!(openings[1].start.to_fen(), "4k3/8/8/8/8/8/4P3/4K3 w - - 0 40");
+
+        let pgn = "[Event \"?\"]\n\n1. e4 e5 {open} 2. Nf3 (2. f4) Nc6 *\n\n\
+                   [Event \"?\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\"]\n\n1. Kd2 1/2-1/2\n";
+        let openings = parse_openings(pgn, Arc::new(Standard)).unwrap();
+        assert_eq!(openings.len(), 2);
+        assert_eq!(openings[0].moves.len(), 4);
+        assert_eq!(openings[0].moves[3], Move::from_uci("b8c6").unwrap());
+        assert_eq!(openings[1].moves, vec![Move::from_uci("e1d2").unwrap()]);
+
+        assert!(parse_openings("[Event \"?\"]\n\n1. e5 *\n", Arc::new(Standard)).is_none());
+    }
+
+    #[test]
+    fn stats_test() {
+        let stats = Stats { wins: 60, draws: 20, losses: 20 };
+        assert_eq!(stats.score(), 0.7);
+        let (elo, margin) = stats.elo();
+        assert!((elo - 147.2).abs() < 0.1);
+        assert!(margin > 50.0 && margin < 100.0);
+        assert_eq!(Stats { wins: 3, draws: 0, losses: 0 }.elo().0, f64::INFINITY);
+        assert!((elo_to_score(score_to_elo(0.6)) - 0.6).abs() < 1e-9);
+
+        let sprt = Sprt::new(0.0, 10.0);
+        let (lower, upper) = sprt.bounds();
+        assert!((upper - 2.944).abs() < 0.001 && (lower + 2.944).abs() < 0.001);
+        assert_eq!(sprt.test(&Stats { wins: 10, draws: 10, losses: 10 }), SprtResult::Continue);
+        assert_eq!(sprt.test(&Stats { wins: 400, draws: 200, losses: 200 }), SprtResult::AcceptH1);
+        assert_eq!(sprt.test(&Stats { wins: 200, draws: 200, losses: 400 }), SprtResult::AcceptH0);
+    }
+
+    #[test]
+    fn adjudication_test() {
+        let adjudication = Adjudication { resign_score: Some(500), resign_moves: 2, draw_score: Some(10),
+                                          draw_moves: 2, draw_start: 1, ..Adjudication::default() };
+        let state = State::new();
+        assert_eq!(adjudication.judge(&state, &[Some(600), Some(700), Some(800)]), None);
+        assert_eq!(adjudication.judge(&state, &[Some(600), Some(700), None, Some(800)]), None);
+        assert_eq!(adjudication.judge(&state, &[Some(600), Some(700), Some(900), Some(800)]), Some(Some(Player::White)));
+        assert_eq!(adjudication.judge(&state, &[Some(-600), Some(-700), Some(-900), Some(-800)]), Some(Some(Player::Black)));
+        assert_eq!(adjudication.judge(&state, &[Some(5), Some(0), Some(-10), Some(3)]), Some(None));
+
+        let mut tables = GeneratedTables::new();
+        tables.generate("KQvK").unwrap();
+        let adjudication = Adjudication { tablebase: Some(Arc::new(tables)), ..Adjudication::default() };
+        assert_eq!(adjudication.judge(&state, &[]), None);
+        let state = State::from_fen("8/8/8/3k4/8/8/8/Q3K3 b - - 0 1").unwrap();
+        assert_eq!(adjudication.judge(&state, &[]), Some(Some(Player::White)));
+        let state = State::from_fen("8/8/8/8/8/8/1kQ5/4K3 b - - 0 1").unwrap();
+        assert_eq!(adjudication.judge(&state, &[]), Some(None));
+    }
+
+    #[test]
+    fn game_test() {
+        // The mock engine knows only 1. e4 e5 and then has no move
+        let mut white = EngineConfig::parse(&format!("cmd={}", MOCK_ENGINE)).unwrap().start().unwrap();
+        let mut black = BuiltinEngine { depth: Some(1), nodes: None };
+        let opening = Opening { start: State::new(), moves: Vec::new() };
+        let state = play_game(white.as_mut(), &mut black, &opening, &settings());
+        assert!(matches!(state.get_game_status(), GameStatus::GameWonByBlack));
+        assert_eq!(state.get_termination(), Some(Termination::Adjudication));
+        assert_eq!(state.get_history()[0].san, "e4");
+
+        // Mate in one
+        let opening = Opening { start: State::from_fen("k7/8/1K6/8/8/8/8/7R w - - 0 1").unwrap(), moves: Vec::new() };
+        let mut white = BuiltinEngine { depth: Some(2), nodes: None };
+        let state = play_game(&mut white, &mut black, &opening, &settings());
+        assert_eq!(state.get_termination(), Some(Termination::Checkmate));
+    }
+
+    #[test]
+    fn tournament_test() {
+        let tournament = Tournament {
+            engines: vec![
+                EngineConfig::parse("builtin,depth=1,name=one").unwrap(),
+                EngineConfig::parse("builtin,depth=1,name=two").unwrap(),
+                EngineConfig::parse("builtin,depth=1,name=three").unwrap(),
+            ],
+            format: Format::RoundRobin,
+            games: 2,
+            openings: parse_openings("4k3/8/8/8/8/8/4P3/4K3 w - -\n", Arc::new(Standard)).unwrap(),
+            variant: Arc::new(Standard),
+            settings: settings(),
+            event: String::from("Test"),
+        };
+        let mut records = Vec::new();
+        let results = tournament.run(&mut |record, _| {
+            records.push((record.white, record.black, record.pgn.clone()));
+            true
+        }).unwrap();
+
+        assert_eq!(records.len(), 6);
+        assert_eq!((records[0].0, records[0].1), (0, 1));
+        assert_eq!((records[1].0, records[1].1), (1, 0));
+        assert!(records[0].2.contains("[White \"one\"]"));
+        assert!(records[0].2.contains("[FEN \"4k3/8/8/8/8/8/4P3/4K3 w - - 0 1\"]"));
+        assert!(records[0].2.contains("[TimeControl \"60+0\"]"));
+        assert_eq!((0..3).map(|engine| results.total(engine).games()).sum::<u32>(), 12);
+        assert_eq!(results.crosstable().lines().count(), 4);
+
+        // Stopping after the first game
+        let results = tournament.run(&mut |_, _| false).unwrap();
+        assert_eq!(results.total(0).games(), 1);
+
+        // Without openings the games start from the start position of the variant
+        let tournament = Tournament {
+            engines: tournament.engines[..2].to_vec(),
+            games: 1,
+            openings: Vec::new(),
+            variant: Arc::new(Atomic),
+            ..tournament
+        };
+        tournament.run(&mut |record, _| {
+            assert_eq!(record.state.get_variant().name(), "Atomic");
+            assert!(record.pgn.contains("[Variant \"Atomic\"]"));
+            true
+        }).unwrap();
+    }
+
+    #[test]
+    fn crosstable_test() {
+        let mut results = Results::new(vec![String::from("a"), String::from("b"), String::from("c")]);
+        results.add(0, 1, GameStatus::GameWonByBlack);
+        results.add(1, 0, GameStatus::Draw);
+        results.add(2, 1, GameStatus::GameWonByBlack);
+        assert_eq!(results.total(1), Stats { wins: 2, draws: 1, losses: 0 });
+        assert_eq!(pairings(Format::Gauntlet, 3), vec![(0, 1), (0, 2)]);
+
+        let table = results.crosstable();
+        let lines: Vec<&str> = table.lines().collect();
+        assert!(lines[1].contains(" b ") && lines[1].contains("2.5"));
+        assert!(lines[1].ends_with("---     1.5/2       1/1"));
+    }
+}
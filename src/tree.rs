@@ -0,0 +1,316 @@
+//! A game with its variations. Every node holds the position after its move,
+//! and its first child continues the line while the others are variations.
+//! Nodes are numbered when they are added and keep their number until they
+//! are deleted.
+
+use crate::eco::{self, Opening};
+use crate::{Move, MoveError, State};
+
+pub type NodeId = usize;
+
+/// The glyphs of the move annotations, by their numeric annotation glyph.
+const GLYPHS: [(u8, &str); 6] = [(1, "!"), (2, "?"), (3, "!!"), (4, "??"), (5, "!?"), (6, "?!")];
+
+/// The glyph of a numeric annotation glyph, like `!?` for 5, if it has one.
+pub fn nag_glyph(nag: u8) -> Option<&'static str> {
+    GLYPHS.iter().find(|(number, _)| *number == nag).map(|(_, glyph)| *glyph)
+}
+
+/// The numeric annotation glyph for a glyph like `??`.
+pub fn nag_from_glyph(glyph: &str) -> Option<u8> {
+    GLYPHS.iter().find(|(_, text)| *text == glyph).map(|(number, _)| *number)
+}
+
+#[derive(Clone)]
+pub struct Node {
+    /// The move that led here, which the root does not have.
+    pub mv: Option<Move>,
+    pub san: String,
+    pub position: State,
+    /// A comment on the move, or on the game for the root.
+    pub comment: String,
+    /// A comment before the move, which PGN has at the start of variations.
+    pub comment_before: String,
+    /// Numeric annotation glyphs, like 1 for `!` and 4 for `??`.
+    pub nags: Vec<u8>,
+    opening: Option<&'static Opening>,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+}
+
+impl Node {
+    pub fn get_parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    /// The opening of the last position of the line up to here that has a
+    /// name, for games from the standard start position.
+    pub fn get_opening(&self) -> Option<&'static Opening> {
+        self.opening
+    }
+
+    pub fn get_children(&self) -> &[NodeId] {
+        &self.children
+    }
+}
+
+#[derive(Clone)]
+pub struct GameTree {
+    nodes: Vec<Option<Node>>,
+    // Whether the openings of the nodes are looked up
+    classified: bool,
+}
+
+impl GameTree {
+    pub fn new(start: State) -> GameTree {
+        let classified = eco::is_standard_start(&start);
+        GameTree {
+            nodes: vec![Some(Node {
+                mv: None,
+                san: String::new(),
+                position: start,
+                comment: String::new(),
+                comment_before: String::new(),
+                nags: Vec::new(),
+                opening: None,
+                parent: None,
+                children: Vec::new(),
+            })],
+            classified,
+        }
+    }
+
+    /// The tree of the moves played in `game`, which started from `start`.
+    pub fn from_game(start: &State, game: &State) -> GameTree {
+        let mut tree = GameTree::new(start.clone());
+        let mut node = tree.root();
+        for entry in game.get_history() {
+            node = match tree.add_move(node, entry.mv) {
+                Ok(child) => child,
+                Err(_) => break,
+            };
+        }
+        tree
+    }
+
+    pub fn root(&self) -> NodeId {
+        0
+    }
+
+    pub fn get(&self, id: NodeId) -> Option<&Node> {
+        self.nodes.get(id).and_then(Option::as_ref)
+    }
+
+    pub fn get_mut(&mut self, id: NodeId) -> Option<&mut Node> {
+        self.nodes.get_mut(id).and_then(Option::as_mut)
+    }
+
+    // For ids that are known to exist
+    fn node(&self, id: NodeId) -> &Node {
+        self.get(id).expect("deleted node")
+    }
+
+    /// Plays `mv` after `parent`. A move that is already there is not added
+    /// again, and its node is returned instead.
+    pub fn add_move(&mut self, parent: NodeId, mv: Move) -> Result<NodeId, MoveError> {
+        let parent_node = self.get(parent).ok_or(MoveError::GameDone)?;
+        if let Some(&child) = parent_node.children.iter().find(|&&child| self.node(child).mv == Some(mv)) {
+            return Ok(child);
+        }
+
+        let mut position = parent_node.position.clone();
+        position.play_move(mv)?;
+        let san = position.get_history().last().map_or(String::new(), |entry| entry.san.clone());
+        let opening = if self.classified { eco::lookup(&position).or(parent_node.opening) } else { None };
+        let id = self.nodes.len();
+        self.nodes.push(Some(Node {
+            mv: Some(mv),
+            san,
+            position,
+            comment: String::new(),
+            comment_before: String::new(),
+            nags: Vec::new(),
+            opening,
+            parent: Some(parent),
+            children: Vec::new(),
+        }));
+        self.nodes[parent].as_mut().unwrap().children.push(id);
+        Ok(id)
+    }
+
+    /// Removes a node with everything after it. The root cannot be deleted.
+    pub fn delete(&mut self, id: NodeId) -> bool {
+        let parent = match self.get(id).and_then(|node| node.parent) {
+            Some(parent) => parent,
+            None => return false,
+        };
+        self.nodes[parent].as_mut().unwrap().children.retain(|&child| child != id);
+        let mut removed = vec![id];
+        while let Some(id) = removed.pop() {
+            if let Some(node) = self.nodes[id].take() {
+                removed.extend(node.children);
+            }
+        }
+        true
+    }
+
+    // The index of a node among its parent's children, and the parent
+    fn place(&self, id: NodeId) -> Option<(NodeId, usize)> {
+        let parent = self.get(id)?.parent?;
+        let index = self.node(parent).children.iter().position(|&child| child == id)?;
+        Some((parent, index))
+    }
+
+    /// Moves the variation starting at `id` one place up among its siblings.
+    /// The first one continues the line.
+    pub fn promote(&mut self, id: NodeId) -> bool {
+        match self.place(id) {
+            Some((parent, index)) if index > 0 => {
+                self.nodes[parent].as_mut().unwrap().children.swap(index, index - 1);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Moves the variation starting at `id` one place down among its siblings.
+    pub fn demote(&mut self, id: NodeId) -> bool {
+        match self.place(id) {
+            Some((parent, index)) if index + 1 < self.node(parent).children.len() => {
+                self.nodes[parent].as_mut().unwrap().children.swap(index, index + 1);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Makes the line through `id` the main line.
+    pub fn promote_to_main_line(&mut self, id: NodeId) {
+        let mut node = id;
+        while let Some((parent, index)) = self.place(node) {
+            let children = &mut self.nodes[parent].as_mut().unwrap().children;
+            let child = children.remove(index);
+            children.insert(0, child);
+            node = parent;
+        }
+    }
+
+    /// The nodes from the root to `id`, both included.
+    pub fn path(&self, id: NodeId) -> Vec<NodeId> {
+        let mut path = vec![id];
+        while let Some(parent) = self.get(path[path.len() - 1]).and_then(|node| node.parent) {
+            path.push(parent);
+        }
+        path.reverse();
+        path
+    }
+
+    /// The nodes of the main line, starting with the root.
+    pub fn main_line(&self) -> Vec<NodeId> {
+        self.line_from(self.root())
+    }
+
+    /// `id` and the nodes that continue its line.
+    pub fn line_from(&self, id: NodeId) -> Vec<NodeId> {
+        let mut line = vec![id];
+        while let Some(&child) = self.node(line[line.len() - 1]).children.first() {
+            line.push(child);
+        }
+        line
+    }
+
+    /// Whether `id` is on the main line.
+    pub fn is_main_line(&self, id: NodeId) -> bool {
+        self.place(id).is_none_or(|(parent, index)| index == 0 && self.is_main_line(parent))
+    }
+
+    /// The position at the end of the main line.
+    pub fn main_line_end(&self) -> NodeId {
+        *self.main_line().last().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn add(tree: &mut GameTree, parent: NodeId, san: &str) -> NodeId {
+        let mv = tree.get(parent).unwrap().position.move_from_san(san).unwrap();
+        tree.add_move(parent, mv).unwrap()
+    }
+
+    #[test]
+    fn nag_test() {
+        assert_eq!(nag_glyph(4), Some("??"));
+        assert_eq!(nag_glyph(14), None);
+        assert_eq!(nag_from_glyph("!?"), Some(5));
+        assert_eq!(nag_from_glyph("+-"), None);
+    }
+
+    #[test]
+    fn tree_test() {
+        let mut tree = GameTree::new(State::new());
+        let root = tree.root();
+        let e4 = add(&mut tree, root, "e4");
+        let e5 = add(&mut tree, e4, "e5");
+        let c5 = add(&mut tree, e4, "c5");
+        let nf3 = add(&mut tree, c5, "Nf3");
+        let e6 = add(&mut tree, e4, "e6");
+        assert_eq!(add(&mut tree, e4, "c5"), c5);
+        assert!(tree.add_move(e5, Move::from_uci("e1e3").unwrap()).is_err());
+
+        assert_eq!(tree.get(e4).unwrap().get_children(), [e5, c5, e6]);
+        assert_eq!(tree.get(nf3).unwrap().san, "Nf3");
+        assert_eq!(tree.main_line(), [0, e4, e5]);
+        assert_eq!(tree.path(nf3), [0, e4, c5, nf3]);
+        assert!(!tree.is_main_line(nf3));
+
+        assert!(tree.promote(e6));
+        assert_eq!(tree.get(e4).unwrap().get_children(), [e5, e6, c5]);
+        assert!(tree.demote(e5));
+        assert!(!tree.demote(c5));
+        assert_eq!(tree.main_line(), [0, e4, e6]);
+        tree.promote_to_main_line(nf3);
+        assert_eq!(tree.main_line(), [0, e4, c5, nf3]);
+        assert_eq!(tree.main_line_end(), nf3);
+        assert!(tree.get(nf3).unwrap().position.get_history().len() == 3);
+
+        assert!(tree.delete(c5));
+        assert!(tree.get(nf3).is_none());
+        assert_eq!(tree.main_line(), [0, e4, e6]);
+        assert!(!tree.delete(tree.root()));
+    }
+
+    #[test]
+    fn from_game_test() {
+        let start = State::new();
+        let mut game = start.clone();
+        for uci in ["d2d4", "d7d5", "c2c4"] {
+            game.play_move(Move::from_uci(uci).unwrap()).unwrap();
+        }
+        let tree = GameTree::from_game(&start, &game);
+        let sans: Vec<&str> = tree.main_line().iter().skip(1).map(|&id| tree.get(id).unwrap().san.as_str()).collect();
+        assert_eq!(sans, ["d4", "d5", "c4"]);
+        assert_eq!(tree.get(tree.main_line_end()).unwrap().position.to_fen(), game.to_fen());
+    }
+
+    #[test]
+    fn opening_test() {
+        let mut tree = GameTree::new(State::new());
+        let root = tree.root();
+        let e4 = add(&mut tree, root, "e4");
+        let c5 = add(&mut tree, e4, "c5");
+        let h3 = add(&mut tree, c5, "h3");
+        assert_eq!(tree.get(tree.root()).unwrap().get_opening(), None);
+        let sicilian = tree.get(c5).unwrap().get_opening().unwrap();
+        assert_eq!(sicilian.eco, "B20");
+        // A move out of the table keeps the opening of the line
+        assert_eq!(tree.get(h3).unwrap().get_opening(), Some(sicilian));
+
+        // Without castling rights it is not the start position
+        let mut tree = GameTree::new(State::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1").unwrap());
+        let root = tree.root();
+        let e4 = add(&mut tree, root, "e4");
+        assert_eq!(tree.get(e4).unwrap().get_opening(), None);
+    }
+}
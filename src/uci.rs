@@ -0,0 +1,458 @@
+//! Running an external engine that speaks the Universal Chess Interface. The
+//! engine is a child process; its output is read on a separate thread so the
+//! GUI can poll for the evaluation and the best move without blocking.
+
+use std::ffi::OsStr;
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::fen::START_FEN;
+use crate::search::Score;
+use crate::{Move, State};
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+const QUIT_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// An option the engine announced with `option name ... type ...`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EngineOption {
+    pub name: String,
+    pub kind: String,
+    pub default: Option<String>,
+}
+
+impl EngineOption {
+    fn parse(line: &str) -> Option<EngineOption> {
+        let rest = line.strip_prefix("option name ")?;
+        let type_index = rest.find(" type ")?;
+        let name = rest[..type_index].trim().to_string();
+        let mut words = rest[type_index + 6..].split_whitespace();
+        let kind = words.next()?.to_string();
+
+        let mut default = None;
+        while let Some(word) = words.next() {
+            if word == "default" {
+                default = words.next().map(String::from);
+            }
+        }
+        Some(EngineOption { name, kind, default })
+    }
+}
+
+/// What an `info` line says about the running search. Fields the engine
+/// left out are `None`, or empty for the principal variation.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct EngineInfo {
+    pub depth: Option<u32>,
+    pub multipv: Option<u32>,
+    pub score: Option<Score>,
+    pub nodes: Option<u64>,
+    pub time: Option<Duration>,
+    pub pv: Vec<Move>,
+}
+
+impl EngineInfo {
+    /// Parses an `info` line, or returns `None` for other lines and for info
+    /// lines without search results, like `info string`.
+    pub fn parse(line: &str) -> Option<EngineInfo> {
+        let mut words = line.split_whitespace();
+        if words.next() != Some("info") {
+            return None;
+        }
+
+        let mut info = EngineInfo::default();
+        while let Some(word) = words.next() {
+            match word {
+                "depth" => info.depth = words.next().and_then(|depth| depth.parse().ok()),
+                "multipv" => info.multipv = words.next().and_then(|multipv| multipv.parse().ok()),
+                "nodes" => info.nodes = words.next().and_then(|nodes| nodes.parse().ok()),
+                "time" => info.time = words.next().and_then(|time| time.parse().ok()).map(Duration::from_millis),
+                "score" => {
+                    info.score = match (words.next(), words.next().and_then(|value| value.parse().ok())) {
+                        (Some("cp"), Some(value)) => Some(Score::Centipawns(value)),
+                        (Some("mate"), Some(value)) => Some(Score::Mate(value)),
+                        _ => None,
+                    }
+                }
+                // The rest of the line is the variation
+                "pv" => {
+                    info.pv = words.by_ref().map_while(Move::from_uci).collect();
+                }
+                "string" => return None,
+                _ => (),
+            }
+        }
+
+        if info.depth.is_none() && info.score.is_none() && info.pv.is_empty() {
+            None
+        } else {
+            Some(info)
+        }
+    }
+}
+
+/// Limits for the engine's search, sent with `go`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct GoLimits {
+    pub depth: Option<u32>,
+    pub nodes: Option<u64>,
+    pub move_time: Option<Duration>,
+    /// Remaining time and increment per move for white and black.
+    pub time: Option<[Duration; 2]>,
+    pub increment: Option<[Duration; 2]>,
+    pub moves_to_go: Option<u32>,
+    /// Searches until stopped.
+    pub infinite: bool,
+}
+
+impl GoLimits {
+    pub fn move_time(move_time: Duration) -> GoLimits {
+        GoLimits { move_time: Some(move_time), ..GoLimits::default() }
+    }
+
+    pub fn depth(depth: u32) -> GoLimits {
+        GoLimits { depth: Some(depth), ..GoLimits::default() }
+    }
+
+    fn to_command(&self) -> String {
+        let mut command = String::from("go");
+        if self.infinite {
+            command.push_str(" infinite");
+        }
+        if let Some([white, black]) = self.time {
+            command.push_str(&format!(" wtime {} btime {}", white.as_millis(), black.as_millis()));
+        }
+        if let Some([white, black]) = self.increment {
+            command.push_str(&format!(" winc {} binc {}", white.as_millis(), black.as_millis()));
+        }
+        if let Some(moves_to_go) = self.moves_to_go {
+            command.push_str(&format!(" movestogo {}", moves_to_go));
+        }
+        if let Some(depth) = self.depth {
+            command.push_str(&format!(" depth {}", depth));
+        }
+        if let Some(nodes) = self.nodes {
+            command.push_str(&format!(" nodes {}", nodes));
+        }
+        if let Some(move_time) = self.move_time {
+            command.push_str(&format!(" movetime {}", move_time.as_millis()));
+        }
+        command
+    }
+}
+
+/// The `position` command for the game that started at `start` and led to `state`.
+pub fn position_command(start: &State, state: &State) -> String {
+    let fen = start.to_fen();
+    let mut command = if fen == START_FEN {
+        String::from("position startpos")
+    } else {
+        format!("position fen {}", fen)
+    };
+    if !state.get_history().is_empty() {
+        command.push_str(" moves");
+        for entry in state.get_history() {
+            command.push(' ');
+            command.push_str(&entry.mv.to_uci());
+        }
+    }
+    command
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum EngineEvent {
+    Info(EngineInfo),
+    /// The result of a search, `None` when the engine has no move (`bestmove 0000`).
+    BestMove(Option<Move>),
+    /// The engine process ended or closed its output.
+    Exited,
+}
+
+pub struct UciEngine {
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<Option<String>>,
+    name: String,
+    options: Vec<EngineOption>,
+    // Searches that were stopped and whose best move is of no interest
+    stopped_searches: usize,
+    searching: bool,
+}
+
+impl UciEngine {
+    /// Starts the engine at `path` and waits for the `uci` handshake to finish.
+    pub fn start<P: AsRef<OsStr>>(path: P, args: &[&str]) -> io::Result<UciEngine> {
+        let mut child = Command::new(path)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()?;
+        let stdin = child.stdin.take().unwrap();
+        let reader = BufReader::new(child.stdout.take().unwrap());
+
+        let (sender, lines) = mpsc::channel();
+        thread::spawn(move || {
+            for line in reader.lines() {
+                match line {
+                    Ok(line) => if sender.send(Some(line)).is_err() { return; },
+                    Err(_) => break,
+                }
+            }
+            let _res = sender.send(None);
+        });
+
+        let mut engine = UciEngine {
+            child,
+            stdin,
+            lines,
+            name: String::new(),
+            options: Vec::new(),
+            stopped_searches: 0,
+            searching: false,
+        };
+        engine.send("uci")?;
+        loop {
+            let line = engine.receive(HANDSHAKE_TIMEOUT)?;
+            if line == "uciok" {
+                break;
+            } else if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.trim().to_string();
+            } else if let Some(option) = EngineOption::parse(&line) {
+                engine.options.push(option);
+            }
+        }
+        Ok(engine)
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn get_options(&self) -> &[EngineOption] {
+        &self.options
+    }
+
+    /// Whether a search was started and its best move has not arrived yet.
+    pub fn is_searching(&self) -> bool {
+        self.searching
+    }
+
+    fn send(&mut self, command: &str) -> io::Result<()> {
+        writeln!(self.stdin, "{}", command)?;
+        self.stdin.flush()
+    }
+
+    fn receive(&mut self, timeout: Duration) -> io::Result<String> {
+        match self.lines.recv_timeout(timeout) {
+            Ok(Some(line)) => Ok(line),
+            Ok(None) | Err(RecvTimeoutError::Disconnected) =>
+                Err(io::Error::new(io::ErrorKind::UnexpectedEof, "the engine exited")),
+            Err(RecvTimeoutError::Timeout) =>
+                Err(io::Error::new(io::ErrorKind::TimedOut, "the engine did not answer")),
+        }
+    }
+
+    pub fn set_option(&mut self, name: &str, value: &str) -> io::Result<()> {
+        self.send(&format!("setoption name {} value {}", name, value))
+    }
+
+    /// Waits until the engine has handled everything sent so far. Only call it
+    /// while the engine is not searching, since other output is thrown away.
+    pub fn wait_ready(&mut self) -> io::Result<()> {
+        self.send("isready")?;
+        while self.receive(HANDSHAKE_TIMEOUT)? != "readyok" {}
+        Ok(())
+    }
+
+    pub fn new_game(&mut self) -> io::Result<()> {
+        self.send("ucinewgame")?;
+        self.wait_ready()
+    }
+
+    /// Starts searching the game from `start` to `state`. A search that is
+    /// still running is stopped first and its best move ignored.
+    pub fn go(&mut self, start: &State, state: &State, limits: &GoLimits) -> io::Result<()> {
+        self.stop()?;
+        self.send(&position_command(start, state))?;
+        self.send(&limits.to_command())?;
+        self.searching = true;
+        Ok(())
+    }
+
+    /// Stops the running search, if any. Its best move will not be reported.
+    pub fn stop(&mut self) -> io::Result<()> {
+        if self.searching {
+            self.send("stop")?;
+            self.stopped_searches += 1;
+            self.searching = false;
+        }
+        Ok(())
+    }
+
+    fn handle_line(&mut self, line: &str) -> Option<EngineEvent> {
+        if let Some(rest) = line.strip_prefix("bestmove") {
+            if self.stopped_searches > 0 {
+                self.stopped_searches -= 1;
+                return None;
+            }
+            self.searching = false;
+            Some(EngineEvent::BestMove(rest.split_whitespace().next().and_then(Move::from_uci)))
+        } else if self.stopped_searches > 0 {
+            None
+        } else {
+            EngineInfo::parse(line).map(EngineEvent::Info)
+        }
+    }
+
+    /// Everything the engine reported since the last call, without waiting.
+    pub fn poll(&mut self) -> Vec<EngineEvent> {
+        let mut events = Vec::new();
+        loop {
+            match self.lines.try_recv() {
+                Ok(Some(line)) => events.extend(self.handle_line(&line)),
+                Ok(None) | Err(TryRecvError::Disconnected) => {
+                    self.searching = false;
+                    events.push(EngineEvent::Exited);
+                    break;
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+        events
+    }
+
+    /// Waits up to `timeout` for the best move of the running search, and
+    /// returns it with the last info that came before it.
+    pub fn wait_best_move(&mut self, timeout: Duration) -> io::Result<(Option<Move>, Option<EngineInfo>)> {
+        let deadline = Instant::now() + timeout;
+        let mut last_info = None;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            let line = self.receive(left)?;
+            match self.handle_line(&line) {
+                Some(EngineEvent::BestMove(mv)) => return Ok((mv, last_info)),
+                Some(EngineEvent::Info(info)) => last_info = Some(info),
+                _ => (),
+            }
+        }
+    }
+}
+
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        let _res = self.send("quit");
+        let deadline = Instant::now() + QUIT_TIMEOUT;
+        while Instant::now() < deadline {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        let _res = self.child.kill();
+        let _res = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Pos;
+
+    const MOCK_ENGINE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/mock_uci_engine.sh");
+
+    fn start_mock() -> UciEngine {
+        UciEngine::start("sh", &[MOCK_ENGINE]).unwrap()
+    }
+
+    #[test]
+    fn parse_test() {
+        let info = EngineInfo::parse("info depth 12 seldepth 18 multipv 1 score cp -35 nodes 4021 nps 90000 time 45 pv e2e4 e7e5 g1f3").unwrap();
+        assert_eq!(info.depth, Some(12));
+        assert_eq!(info.multipv, Some(1));
+        assert_eq!(info.score, Some(Score::Centipawns(-35)));
+        assert_eq!(info.nodes, Some(4021));
+        assert_eq!(info.time, Some(Duration::from_millis(45)));
+        assert_eq!(info.pv.len(), 3);
+        assert_eq!(info.pv[2], Move::new(Pos::new(6, 0), Pos::new(5, 2)));
+
+        assert_eq!(EngineInfo::parse("info depth 3 score mate -2").unwrap().score, Some(Score::Mate(-2)));
+        assert_eq!(EngineInfo::parse("info string NNUE enabled"), None);
+        assert_eq!(EngineInfo::parse("info currmove e2e4 currmovenumber 1"), None);
+
+        let option = EngineOption::parse("option name Skill Level type spin default 20 min 0 max 20").unwrap();
+        assert_eq!(option.name, "Skill Level");
+        assert_eq!(option.kind, "spin");
+        assert_eq!(option.default.as_deref(), Some("20"));
+    }
+
+    #[test]
+    fn position_test() {
+        let mut state = State::new();
+        let start = state.clone();
+        assert_eq!(position_command(&start, &state), "position startpos");
+        state.play_move(Move::from_uci("e2e4").unwrap()).unwrap();
+        assert_eq!(position_command(&start, &state), "position startpos moves e2e4");
+
+        let start = State::from_fen("4k3/8/8/8/8/8/4P3/4K3 w - - 0 1").unwrap();
+        assert_eq!(position_command(&start, &start), "position fen 4k3/8/8/8/8/8/4P3/4K3 w - - 0 1");
+
+        let limits = GoLimits { time: Some([Duration::from_secs(60), Duration::from_millis(59500)]),
+                                increment: Some([Duration::from_secs(1); 2]), ..GoLimits::default() };
+        assert_eq!(limits.to_command(), "go wtime 60000 btime 59500 winc 1000 binc 1000");
+    }
+
+    #[test]
+    fn engine_test() {
+        let mut engine = start_mock();
+        assert_eq!(engine.get_name(), "Mock Engine");
+        assert_eq!(engine.get_options()[0].name, "Hash");
+        engine.set_option("Hash", "32").unwrap();
+        engine.new_game().unwrap();
+
+        let mut state = State::new();
+        let start = state.clone();
+        engine.go(&start, &state, &GoLimits::depth(1)).unwrap();
+        let (mv, info) = engine.wait_best_move(Duration::from_secs(5)).unwrap();
+        assert_eq!(mv, Move::from_uci("e2e4"));
+        assert_eq!(info.unwrap().score, Some(Score::Centipawns(30)));
+        assert!(!engine.is_searching());
+
+        state.play_move(mv.unwrap()).unwrap();
+        engine.go(&start, &state, &GoLimits::move_time(Duration::from_millis(10))).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut events = Vec::new();
+        while !events.iter().any(|event| matches!(event, EngineEvent::BestMove(_))) && Instant::now() < deadline {
+            events.extend(engine.poll());
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert!(events.contains(&EngineEvent::BestMove(Move::from_uci("e7e5"))));
+        let info = events.iter().find_map(|event| match event {
+            EngineEvent::Info(info) => Some(info),
+            _ => None,
+        });
+        assert_eq!(info.unwrap().pv, vec![Move::from_uci("e7e5").unwrap(), Move::from_uci("g1f3").unwrap()]);
+    }
+
+    #[test]
+    fn stop_test() {
+        // The best move of a stopped search is not reported as the answer to the next one
+        let mut engine = start_mock();
+        let mut state = State::new();
+        let start = state.clone();
+        engine.go(&start, &state, &GoLimits { infinite: true, ..GoLimits::default() }).unwrap();
+        state.play_move(Move::from_uci("e2e4").unwrap()).unwrap();
+        engine.go(&start, &state, &GoLimits::depth(1)).unwrap();
+        let (mv, _) = engine.wait_best_move(Duration::from_secs(5)).unwrap();
+        assert_eq!(mv, Move::from_uci("e7e5"));
+    }
+
+    #[test]
+    fn missing_engine_test() {
+        assert!(UciEngine::start("/nonexistent/engine", &[]).is_err());
+        assert!(UciEngine::start("sh", &["-c", "exit 0"]).is_err());
+    }
+}
@@ -0,0 +1,157 @@
+//! Rules that differ between chess variants. `State` asks its `Variant` about
+//! check, legality, forced moves and how the game ends, so new variants only
+//! have to override what they change.
+
+use std::sync::Arc;
+
+use crate::fen::START_FEN;
+use crate::{Move, MoveError, Outcome, PieceType, Player, Pos, State, Termination};
+
+mod antichess;
+mod atomic;
+mod crazyhouse;
+mod king_of_the_hill;
+mod racing_kings;
+mod three_check;
+
+pub use antichess::Antichess;
+pub use atomic::Atomic;
+pub use crazyhouse::{Bughouse, BughouseGame, Crazyhouse};
+pub use king_of_the_hill::KingOfTheHill;
+pub use racing_kings::RacingKings;
+pub use three_check::ThreeCheck;
+
+const STANDARD_PROMOTIONS: [PieceType; 4] = [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight];
+
+pub trait Variant: Send + Sync {
+    fn name(&self) -> &str;
+
+    fn start_fen(&self) -> &str {
+        START_FEN
+    }
+
+    /// Whether each player has exactly one king that has to be kept safe.
+    fn has_royal_king(&self) -> bool {
+        true
+    }
+
+    fn allows_castling(&self) -> bool {
+        true
+    }
+
+    /// Whether pieces can be dropped from the pockets.
+    fn has_drops(&self) -> bool {
+        false
+    }
+
+    /// Whether captured pieces go into the pocket of the capturing player.
+    fn pockets_captures(&self) -> bool {
+        self.has_drops()
+    }
+
+    /// Whether the number of checks each player has given is part of the
+    /// position, so that it counts for repetitions.
+    fn counts_checks(&self) -> bool {
+        false
+    }
+
+    fn promotion_pieces(&self) -> &[PieceType] {
+        &STANDARD_PROMOTIONS
+    }
+
+    fn is_check(&self, state: &State, player: Player) -> bool {
+        state.is_king_attacked(player)
+    }
+
+    /// Checks a move the piece is able to make, apart from castling which has
+    /// its own rules. By default the king may not be left in check.
+    fn check_legal(&self, state: &State, mv: Move) -> Result<(), MoveError> {
+        if state.preview_move(mv).is_in_check(state.get_current_player()) {
+            Err(MoveError::ResultsInCheck)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Rules that depend on the other moves available, like forced captures.
+    /// `legal_moves` are all moves that passed `check_legal`.
+    fn check_restrictions(&self, _state: &State, _mv: Move, _legal_moves: &[Move]) -> Result<(), MoveError> {
+        Ok(())
+    }
+
+    /// Changes to the board after a capture on `to`.
+    fn after_capture(&self, _state: &mut State, _to: Pos) {}
+
+    /// Ends the game for reasons other than running out of moves. Checked after
+    /// every move.
+    fn get_outcome(&self, _state: &State) -> Option<Outcome> {
+        None
+    }
+
+    /// The result when the player to move has no legal moves.
+    fn get_no_moves_outcome(&self, state: &State) -> Outcome {
+        let player = state.get_current_player();
+        if state.is_in_check(player) {
+            Outcome { winner: Some(player.opponent()), termination: Termination::Checkmate }
+        } else {
+            Outcome { winner: None, termination: Termination::Stalemate }
+        }
+    }
+}
+
+/// Orthodox chess.
+pub struct Standard;
+
+impl Variant for Standard {
+    fn name(&self) -> &str {
+        "Standard"
+    }
+}
+
+/// Looks up a built-in variant by name, ignoring case, spaces and dashes, so
+/// "King of the Hill", "kingofthehill" and "three-check" all work.
+pub fn from_name(name: &str) -> Option<Arc<dyn Variant>> {
+    let key: String = name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    let variant: Arc<dyn Variant> = match key.as_str() {
+        "standard" | "chess" | "normal" => Arc::new(Standard),
+        "kingofthehill" | "koth" => Arc::new(KingOfTheHill),
+        "threecheck" | "3check" => Arc::new(ThreeCheck),
+        "atomic" => Arc::new(Atomic),
+        "antichess" | "losingchess" | "giveaway" => Arc::new(Antichess),
+        "racingkings" => Arc::new(RacingKings),
+        "crazyhouse" | "zh" => Arc::new(Crazyhouse),
+        "bughouse" => Arc::new(Bughouse),
+        _ => return None,
+    };
+    Some(variant)
+}
+
+// Shorthands for the tests of the variants
+#[cfg(test)]
+mod test_util {
+    use super::*;
+
+    pub fn state(variant: impl Variant + 'static, fen: &str) -> State {
+        State::from_fen_with_variant(fen, Arc::new(variant)).unwrap()
+    }
+
+    pub fn pos(name: &str) -> Pos {
+        Pos::from_notation(name).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_name_test() {
+        assert_eq!(from_name("King of the Hill").unwrap().name(), "King of the Hill");
+        assert_eq!(from_name("three-check").unwrap().name(), "Three-check");
+        assert_eq!(from_name("Racing Kings").unwrap().name(), "Racing Kings");
+        assert!(from_name("shogi").is_none());
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{Move, MoveError, Outcome, PieceType, Player, State, Termination};
+use super::Variant;
+
+const ANTICHESS_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1";
+const ANTICHESS_PROMOTIONS: [PieceType; 5] =
+    [PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::King];
+
+/// Losing chess. Capturing is compulsory, the king is an ordinary piece and
+/// the player who runs out of pieces or moves wins.
+pub struct Antichess;
+
+impl Variant for Antichess {
+    fn name(&self) -> &str {
+        "Antichess"
+    }
+
+    fn start_fen(&self) -> &str {
+        ANTICHESS_FEN
+    }
+
+    fn has_royal_king(&self) -> bool {
+        false
+    }
+
+    fn allows_castling(&self) -> bool {
+        false
+    }
+
+    fn promotion_pieces(&self) -> &[PieceType] {
+        &ANTICHESS_PROMOTIONS
+    }
+
+    fn is_check(&self, _state: &State, _player: Player) -> bool {
+        false
+    }
+
+    fn check_legal(&self, _state: &State, _mv: Move) -> Result<(), MoveError> {
+        Ok(())
+    }
+
+    fn check_restrictions(&self, state: &State, mv: Move, legal_moves: &[Move]) -> Result<(), MoveError> {
+        if !state.is_capture(mv) && legal_moves.iter().any(|&other| state.is_capture(other)) {
+            Err(MoveError::MustCapture)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn get_no_moves_outcome(&self, state: &State) -> Outcome {
+        Outcome { winner: Some(state.get_current_player()), termination: Termination::NoMovesLeft }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::MoveSuccess;
+    use crate::variant::test_util::{pos, state};
+    use super::*;
+
+    #[test]
+    fn perft_test() {
+        let start = State::new_variant(Arc::new(Antichess));
+        assert_eq!(start.perft(1), 20);
+        assert_eq!(start.perft(2), 400);
+        assert_eq!(start.perft(3), 8067);
+        assert_eq!(start.perft(4), 153299);
+    }
+
+    #[test]
+    fn forced_capture_test() {
+        let mut position = State::new_variant(Arc::new(Antichess));
+        assert!(position.move_piece(pos("e2"), pos("e4")).is_ok());
+        assert!(position.move_piece(pos("d7"), pos("d5")).is_ok());
+        assert!(matches!(position.move_piece(pos("g1"), pos("f3")), Err(MoveError::MustCapture)));
+        assert!(position.move_piece(pos("e4"), pos("d5")).is_ok());
+    }
+
+    #[test]
+    fn promote_to_king_test() {
+        let position = state(Antichess, "8/P7/8/8/8/8/8/7k w - - 0 1");
+        assert_eq!(position.get_legal_moves().len(), 5);
+        assert!(position.get_legal_moves().contains(&Move::with_promotion(pos("a7"), pos("a8"), PieceType::King)));
+    }
+
+    #[test]
+    fn no_pieces_left_test() {
+        let mut position = state(Antichess, "8/8/8/8/8/8/1p6/R7 b - - 0 1");
+        let res = position.move_piece(pos("b2"), pos("a1"));
+        assert!(matches!(res, Ok(MoveSuccess::GameWonByWhite)));
+        assert_eq!(position.get_termination(), Some(Termination::NoMovesLeft));
+    }
+}
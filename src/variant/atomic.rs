@@ -0,0 +1,109 @@
+use crate::{Move, MoveError, Outcome, PieceType, Player, Pos, State, Termination};
+use super::Variant;
+
+/// Captures explode, removing the capturing piece and every piece next to the
+/// captured one except pawns. Blowing up the enemy king wins.
+pub struct Atomic;
+
+fn kings_touch(state: &State) -> bool {
+    match (state.get_king_pos(Player::White), state.get_king_pos(Player::Black)) {
+        (Some(white), Some(black)) => (white.x - black.x).abs() <= 1 && (white.y - black.y).abs() <= 1,
+        _ => false,
+    }
+}
+
+impl Variant for Atomic {
+    fn name(&self) -> &str {
+        "Atomic"
+    }
+
+    // Touching kings cannot capture each other, as the capture would blow up both
+    fn is_check(&self, state: &State, player: Player) -> bool {
+        !kings_touch(state) && state.is_king_attacked(player)
+    }
+
+    fn check_legal(&self, state: &State, mv: Move) -> Result<(), MoveError> {
+        let player = state.get_current_player();
+        let is_king = state.get(mv.from).map(|piece| piece.piece_type) == Some(PieceType::King);
+        if is_king && state.is_capture(mv) {
+            return Err(MoveError::InvalidMove);
+        }
+
+        let next = state.preview_move(mv);
+        if next.get_king_pos(player).is_none() {
+            Err(MoveError::ResultsInCheck)
+        } else if next.get_king_pos(player.opponent()).is_none() {
+            Ok(())
+        } else if self.is_check(&next, player) {
+            Err(MoveError::ResultsInCheck)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn after_capture(&self, state: &mut State, to: Pos) {
+        state.remove_piece(to);
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let pos = Pos::new(to.x + dx, to.y + dy);
+                if !(0..8).contains(&pos.x) || !(0..8).contains(&pos.y) {
+                    continue;
+                }
+                if state.get(pos).is_some_and(|piece| piece.piece_type != PieceType::Pawn) {
+                    state.remove_piece(pos);
+                }
+            }
+        }
+    }
+
+    fn get_outcome(&self, state: &State) -> Option<Outcome> {
+        [Player::White, Player::Black].iter()
+            .copied()
+            .find(|&player| state.get_king_pos(player).is_none())
+            .map(|player| Outcome { winner: Some(player.opponent()), termination: Termination::KingExploded })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::variant::test_util::{pos, state};
+    use super::*;
+
+    #[test]
+    fn perft_test() {
+        let start = State::new_variant(Arc::new(Atomic));
+        assert_eq!(start.perft(1), 20);
+        assert_eq!(start.perft(2), 400);
+        assert_eq!(start.perft(3), 8902);
+        assert_eq!(start.perft(4), 197326);
+    }
+
+    #[test]
+    fn explosion_test() {
+        let mut position = state(Atomic, "4k3/8/8/3npb2/4P3/8/8/4K3 w - - 0 1");
+        assert!(position.move_piece(pos("e4"), pos("d5")).is_ok());
+        assert_eq!(position.get(pos("d5")), None);
+        assert_eq!(position.get(pos("e5")).unwrap().piece_type, PieceType::Pawn);
+        assert_eq!(position.get(pos("f5")).unwrap().piece_type, PieceType::Bishop);
+        assert_eq!(position.get_white_eliminated(), &[PieceType::Pawn]);
+        assert_eq!(position.get_black_eliminated(), &[PieceType::Knight]);
+    }
+
+    #[test]
+    fn king_explodes_test() {
+        let mut position = state(Atomic, "3qk3/8/8/8/8/8/8/3RK3 w - - 0 1");
+        let res = position.move_piece(pos("d1"), pos("d8"));
+        assert!(matches!(res, Ok(crate::MoveSuccess::GameWonByWhite)));
+        assert_eq!(position.get_termination(), Some(Termination::KingExploded));
+    }
+
+    #[test]
+    fn touching_kings_test() {
+        // The rook gives no check while the kings touch, and the king may not capture
+        let position = state(Atomic, "8/8/8/8/8/3kK3/8/3r4 w - - 0 1");
+        assert!(!position.is_in_check(Player::White));
+        assert!(position.is_legal_move(pos("e3"), pos("e2")));
+        assert!(!position.is_legal_move(pos("e3"), pos("d3")));
+    }
+}
@@ -0,0 +1,153 @@
+use std::sync::Arc;
+
+use crate::{Move, MoveError, MoveSuccess, Player, State};
+use super::Variant;
+
+const CRAZYHOUSE_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR[] w KQkq - 0 1";
+
+/// Captured pieces change sides and can be dropped back onto the board
+/// instead of moving. Promoted pieces go back to the pocket as pawns.
+pub struct Crazyhouse;
+
+impl Variant for Crazyhouse {
+    fn name(&self) -> &str {
+        "Crazyhouse"
+    }
+
+    fn start_fen(&self) -> &str {
+        CRAZYHOUSE_FEN
+    }
+
+    fn has_drops(&self) -> bool {
+        true
+    }
+}
+
+/// One board of a Bughouse game. Pieces can be dropped, but captured pieces
+/// go to the partner on the other board, which `BughouseGame` takes care of.
+pub struct Bughouse;
+
+impl Variant for Bughouse {
+    fn name(&self) -> &str {
+        "Bughouse"
+    }
+
+    fn start_fen(&self) -> &str {
+        CRAZYHOUSE_FEN
+    }
+
+    fn has_drops(&self) -> bool {
+        true
+    }
+
+    fn pockets_captures(&self) -> bool {
+        false
+    }
+}
+
+/// Two Bughouse boards. White on one board and black on the other are
+/// partners, so a piece white captures on board 0 goes to black on board 1.
+pub struct BughouseGame {
+    pub boards: [State; 2],
+}
+
+impl BughouseGame {
+    pub fn new() -> BughouseGame {
+        BughouseGame {
+            boards: [State::new_variant(Arc::new(Bughouse)), State::new_variant(Arc::new(Bughouse))],
+        }
+    }
+
+    /// Plays `mv` on `board` and hands a captured piece to the partner.
+    pub fn play_move(&mut self, board: usize, mv: Move) -> Result<MoveSuccess, MoveError> {
+        let player = self.boards[board].get_current_player();
+        let captured = self.boards[board].get_pocket_capture(mv);
+        let res = self.boards[board].play_move(mv)?;
+        if let Some(piece_type) = captured {
+            self.boards[1 - board].add_to_pocket(player.opponent(), piece_type);
+        }
+        Ok(res)
+    }
+
+    /// The game ends when either board is decided.
+    pub fn get_game_running(&self) -> bool {
+        self.boards.iter().all(|board| board.get_game_running())
+    }
+
+    /// The board and color of the partner of whoever plays `player` on `board`.
+    pub fn get_partner(board: usize, player: Player) -> (usize, Player) {
+        (1 - board, player.opponent())
+    }
+}
+
+impl Default for BughouseGame {
+    fn default() -> Self {
+        BughouseGame::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::PieceType;
+    use crate::variant::test_util::{pos, state};
+    use super::*;
+
+    #[test]
+    fn perft_test() {
+        let start = State::new_variant(Arc::new(Crazyhouse));
+        assert_eq!(start.perft(1), 20);
+        assert_eq!(start.perft(2), 400);
+        assert_eq!(start.perft(3), 8902);
+
+        let drops = state(Crazyhouse, "2k5/8/8/8/8/8/8/4K3[QRBNPqrbnp] w - - 0 1");
+        assert_eq!(drops.perft(1), 301);
+        assert_eq!(drops.perft(2), 75353);
+    }
+
+    #[test]
+    fn capture_and_drop_test() {
+        let mut position = State::new_variant(Arc::new(Crazyhouse));
+        for (from, to) in [("e2", "e4"), ("d7", "d5"), ("e4", "d5"), ("d8", "d5")].iter() {
+            assert!(position.move_piece(pos(from), pos(to)).is_ok());
+        }
+        assert_eq!(position.get_pocket_count(Player::White, PieceType::Pawn), 1);
+        assert_eq!(position.get_pocket_count(Player::Black, PieceType::Pawn), 1);
+        assert_eq!(position.to_fen(), "rnb1kbnr/ppp1pppp/8/3q4/8/8/PPPP1PPP/RNBQKBNR[Pp] w KQkq - 0 3");
+
+        assert!(matches!(position.play_move(Move::new_drop(PieceType::Pawn, pos("e8"))), Err(MoveError::InvalidDrop)));
+        assert!(matches!(position.play_move(Move::new_drop(PieceType::Knight, pos("e3"))), Err(MoveError::EmptyPocket)));
+        assert!(position.play_move(Move::new_drop(PieceType::Pawn, pos("e4"))).is_ok());
+        assert_eq!(position.get_history().last().unwrap().san, "P@e4");
+        assert_eq!(position.get_pocket_count(Player::White, PieceType::Pawn), 0);
+    }
+
+    #[test]
+    fn promoted_piece_reverts_test() {
+        let mut position = state(Crazyhouse, "4k3/8/8/8/8/8/q7/Q~3K3[] b - - 0 1");
+        assert!(position.is_promoted(pos("a1")));
+        assert!(position.move_piece(pos("a2"), pos("a1")).is_ok());
+        assert_eq!(position.get_pocket_count(Player::Black, PieceType::Pawn), 1);
+        assert_eq!(position.get_pocket_count(Player::Black, PieceType::Queen), 0);
+        assert!(!position.is_promoted(pos("a1")));
+    }
+
+    #[test]
+    fn fen_test() {
+        let fen = "r1bqk2r/ppp2ppp/2n5/3np3/1b6/2N2N2/PPPP1PPP/R1BQKB1R/Pp w KQkq - 0 6";
+        let position = state(Crazyhouse, fen);
+        assert_eq!(position.get_pocket_count(Player::White, PieceType::Pawn), 1);
+        assert_eq!(position.to_fen(), "r1bqk2r/ppp2ppp/2n5/3np3/1b6/2N2N2/PPPP1PPP/R1BQKB1R[Pp] w KQkq - 0 6");
+        assert!(State::from_fen_with_variant("8/8/8/8/8/8/8/K6k[Kq] w - - 0 1", Arc::new(Crazyhouse)).is_err());
+    }
+
+    #[test]
+    fn bughouse_test() {
+        let mut game = BughouseGame::new();
+        for (from, to) in [("e2", "e4"), ("d7", "d5"), ("e4", "d5")].iter() {
+            assert!(game.play_move(0, Move::new(pos(from), pos(to))).is_ok());
+        }
+        assert_eq!(game.boards[0].get_pocket_count(Player::White, PieceType::Pawn), 0);
+        assert_eq!(game.boards[1].get_pocket_count(Player::Black, PieceType::Pawn), 1);
+        assert!(game.get_game_running());
+    }
+}
@@ -0,0 +1,51 @@
+use crate::{Outcome, Player, Pos, State, Termination};
+use super::Variant;
+
+/// Orthodox chess, but bringing the king to d4, e4, d5 or e5 also wins.
+pub struct KingOfTheHill;
+
+fn is_centre(pos: Pos) -> bool {
+    (3..=4).contains(&pos.x) && (3..=4).contains(&pos.y)
+}
+
+impl Variant for KingOfTheHill {
+    fn name(&self) -> &str {
+        "King of the Hill"
+    }
+
+    fn get_outcome(&self, state: &State) -> Option<Outcome> {
+        [Player::White, Player::Black].iter()
+            .copied()
+            .find(|&player| state.get_king_pos(player).is_some_and(is_centre))
+            .map(|player| Outcome { winner: Some(player), termination: Termination::KingOfTheHill })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::variant::test_util::{pos, state};
+    use super::*;
+
+    #[test]
+    fn perft_test() {
+        let start = State::new_variant(Arc::new(KingOfTheHill));
+        assert_eq!(start.perft(1), 20);
+        assert_eq!(start.perft(2), 400);
+        assert_eq!(start.perft(3), 8902);
+    }
+
+    #[test]
+    fn king_in_centre_test() {
+        // Once a king reaches the centre the other side has no replies
+        let position = state(KingOfTheHill, "4k3/8/8/8/8/4K3/8/8 w - - 0 1");
+        assert_eq!(position.perft(1), 8);
+        assert_eq!(position.perft(2), 6 * 5);
+
+        let mut position = state(KingOfTheHill, "4k3/8/8/8/8/4K3/8/8 w - - 0 1");
+        let res = position.move_piece(pos("e3"), pos("e4"));
+        assert!(res.is_ok());
+        assert!(!position.get_game_running());
+        assert_eq!(position.get_termination(), Some(Termination::KingOfTheHill));
+    }
+}
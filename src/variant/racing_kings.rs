@@ -0,0 +1,97 @@
+use crate::{Move, MoveError, Outcome, PieceType, Player, State, Termination};
+use super::Variant;
+
+const RACING_KINGS_FEN: &str = "8/8/8/8/8/8/krbnNBRK/qrbnNBRQ w - - 0 1";
+
+/// Both kings race to the eighth rank and giving check is not allowed. If
+/// white gets there first black has one move to draw by getting there too.
+pub struct RacingKings;
+
+fn on_last_rank(state: &State, player: Player) -> bool {
+    state.get_king_pos(player).is_some_and(|pos| pos.y == 7)
+}
+
+impl Variant for RacingKings {
+    fn name(&self) -> &str {
+        "Racing Kings"
+    }
+
+    fn start_fen(&self) -> &str {
+        RACING_KINGS_FEN
+    }
+
+    fn allows_castling(&self) -> bool {
+        false
+    }
+
+    fn check_legal(&self, state: &State, mv: Move) -> Result<(), MoveError> {
+        let player = state.get_current_player();
+        let next = state.preview_move(mv);
+        if next.is_in_check(player) {
+            Err(MoveError::ResultsInCheck)
+        } else if next.is_in_check(player.opponent()) {
+            Err(MoveError::GivesCheck)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn get_outcome(&self, state: &State) -> Option<Outcome> {
+        let winner = match (on_last_rank(state, Player::White), on_last_rank(state, Player::Black)) {
+            (true, true) => return Some(Outcome { winner: None, termination: Termination::RaceDrawn }),
+            (false, true) => Player::Black,
+            (true, false) => {
+                // Black may still catch up with its next move
+                let can_catch_up = state.get_current_player() == Player::Black &&
+                    state.get_legal_moves().iter().any(|mv| {
+                        state.get(mv.from).is_some_and(|piece| piece.piece_type == PieceType::King) && mv.to.y == 7
+                    });
+                if can_catch_up {
+                    return None;
+                }
+                Player::White
+            }
+            (false, false) => return None,
+        };
+        Some(Outcome { winner: Some(winner), termination: Termination::RaceWon })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::MoveSuccess;
+    use crate::variant::test_util::{pos, state};
+    use super::*;
+
+    #[test]
+    fn perft_test() {
+        let start = State::new_variant(Arc::new(RacingKings));
+        assert_eq!(start.perft(1), 21);
+        assert_eq!(start.perft(2), 421);
+        assert_eq!(start.perft(3), 11264);
+        assert_eq!(start.perft(4), 296242);
+    }
+
+    #[test]
+    fn no_check_test() {
+        let position = state(RacingKings, "8/8/8/8/8/8/krbnNBRK/qrbnNBRQ w - - 0 1");
+        // Nc3 would attack the black king on a2
+        assert!(matches!(position.check_move(Move::new(pos("e2"), pos("c3"))), Err(MoveError::GivesCheck)));
+    }
+
+    #[test]
+    fn race_test() {
+        let mut position = state(RacingKings, "8/6K1/8/1k6/8/8/8/8 w - - 0 1");
+        let res = position.move_piece(pos("g7"), pos("g8"));
+        assert!(matches!(res, Ok(MoveSuccess::GameWonByWhite)));
+        assert_eq!(position.get_termination(), Some(Termination::RaceWon));
+
+        // Black is one step away, so gets to answer
+        let mut position = state(RacingKings, "8/1k4K1/8/8/8/8/8/8 w - - 0 1");
+        assert!(matches!(position.move_piece(pos("g7"), pos("g8")), Ok(MoveSuccess::Ok)));
+        let res = position.move_piece(pos("b7"), pos("b8"));
+        assert!(matches!(res, Ok(MoveSuccess::Draw)));
+        assert_eq!(position.get_termination(), Some(Termination::RaceDrawn));
+    }
+}
@@ -0,0 +1,53 @@
+use crate::{Outcome, Player, State, Termination};
+use super::Variant;
+
+/// Orthodox chess where giving check for the third time also wins.
+pub struct ThreeCheck;
+
+impl Variant for ThreeCheck {
+    fn name(&self) -> &str {
+        "Three-check"
+    }
+
+    fn counts_checks(&self) -> bool {
+        true
+    }
+
+    fn get_outcome(&self, state: &State) -> Option<Outcome> {
+        [Player::White, Player::Black].iter()
+            .copied()
+            .find(|&player| state.get_checks_given(player) >= 3)
+            .map(|player| Outcome { winner: Some(player), termination: Termination::ThreeChecks })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use crate::MoveSuccess;
+    use crate::variant::test_util::pos;
+    use super::*;
+
+    #[test]
+    fn perft_test() {
+        let start = State::new_variant(Arc::new(ThreeCheck));
+        assert_eq!(start.perft(1), 20);
+        assert_eq!(start.perft(2), 400);
+        assert_eq!(start.perft(3), 8902);
+    }
+
+    #[test]
+    fn third_check_test() {
+        let mut state = State::new_variant(Arc::new(ThreeCheck));
+        let moves = [("e2", "e4"), ("d7", "d5"), ("f1", "b5"), ("c7", "c6"), ("b5", "c6"), ("b8", "d7")];
+        for (from, to) in moves.iter() {
+            assert!(state.move_piece(pos(from), pos(to)).is_ok());
+        }
+        assert_eq!(state.get_checks_given(Player::White), 2);
+        assert!(state.get_game_running());
+
+        let res = state.move_piece(pos("c6"), pos("d7"));
+        assert!(matches!(res, Ok(MoveSuccess::GameWonByWhite)));
+        assert_eq!(state.get_termination(), Some(Termination::ThreeChecks));
+    }
+}
@@ -0,0 +1,426 @@
+//! The engine side of the Chess Engine Communication Protocol used by XBoard
+//! and WinBoard, playing with the built-in search. `XBoard::handle` takes one
+//! command and returns the lines to answer, so a session can be scripted
+//! without a GUI. The search runs inside `handle`, so `?` cannot interrupt it
+//! and the engine does not ponder.
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::search::{self, Score, SearchInfo, SearchLimits};
+use crate::tablebase::{SyzygyFiles, Tablebase};
+use crate::variant::{self, Standard, Variant};
+use crate::{GameStatus, Move, Player, State};
+
+const VARIANTS: &str = "normal,kingofthehill,3check,atomic,giveaway,racingkings,crazyhouse";
+// Assumed number of moves left when the time control does not say
+const DEFAULT_MOVES_LEFT: u32 = 30;
+const DEFAULT_MOVE_TIME: Duration = Duration::from_secs(2);
+
+// The `level` command: moves per session (0 for all), base time and increment
+#[derive(Copy, Clone, Debug, PartialEq)]
+struct Level {
+    moves: u32,
+    base: Duration,
+    increment: Duration,
+}
+
+impl Level {
+    // `level 40 5 0`, `level 0 2:30 1.5`: the base is in minutes or minutes:seconds
+    fn parse(args: &[&str]) -> Option<Level> {
+        match args {
+            [moves, base, increment] => {
+                let base = match base.find(':') {
+                    Some(index) => base[..index].parse::<u64>().ok()? * 60 + base[index + 1..].parse::<u64>().ok()?,
+                    None => base.parse::<u64>().ok()? * 60,
+                };
+                let increment: f64 = increment.parse().ok()?;
+                if !(increment.is_finite() && increment >= 0.0) {
+                    return None;
+                }
+                Some(Level {
+                    moves: moves.parse().ok()?,
+                    base: Duration::from_secs(base),
+                    increment: Duration::from_secs_f64(increment),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+// No answer when an option was set, an error when its arguments were invalid
+fn option_answer(set: Option<()>, line: &str) -> Vec<String> {
+    match set {
+        Some(()) => Vec::new(),
+        None => vec![format!("Error (invalid arguments): {}", line.trim())],
+    }
+}
+
+fn parse_centiseconds(arg: Option<&&str>) -> Option<Duration> {
+    let centiseconds: i64 = arg?.parse().ok()?;
+    Some(Duration::from_millis(centiseconds.max(0) as u64 * 10))
+}
+
+pub struct XBoard {
+    variant: Arc<dyn Variant>,
+    start: State,
+    state: State,
+    force: bool,
+    engine: Player,
+    post: bool,
+    depth: Option<u32>,
+    move_time: Option<Duration>,
+    level: Option<Level>,
+    time_left: Option<Duration>,
+    opponent_time_left: Option<Duration>,
+    tablebase: Option<Arc<dyn Tablebase>>,
+    quit: bool,
+}
+
+impl Default for XBoard {
+    fn default() -> XBoard {
+        XBoard::new()
+    }
+}
+
+impl XBoard {
+    pub fn new() -> XBoard {
+        XBoard {
+            variant: Arc::new(Standard),
+            start: State::new(),
+            state: State::new(),
+            force: false,
+            engine: Player::Black,
+            post: false,
+            depth: None,
+            move_time: None,
+            level: None,
+            time_left: None,
+            opponent_time_left: None,
+            tablebase: None,
+            quit: false,
+        }
+    }
+
+    pub fn get_state(&self) -> &State {
+        &self.state
+    }
+
+    /// Whether `quit` was received.
+    pub fn has_quit(&self) -> bool {
+        self.quit
+    }
+
+    /// Handles one command from the GUI and returns the lines to send back.
+    pub fn handle(&mut self, line: &str) -> Vec<String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Vec::new(),
+        };
+
+        match command {
+            "protover" => vec![format!(
+                "feature myname=\"chess\" setboard=1 usermove=1 ping=1 playother=1 colors=0 \
+                 sigint=0 sigterm=0 variants=\"{}\" egt=\"syzygy\" done=1", VARIANTS)],
+            "new" => {
+                self.variant = Arc::new(Standard);
+                self.reset(State::new());
+                self.depth = None;
+                self.time_left = None;
+                self.opponent_time_left = None;
+                Vec::new()
+            }
+            "variant" => match args.first().and_then(|name| variant::from_name(name)) {
+                Some(variant) => {
+                    self.variant = variant.clone();
+                    self.reset(State::new_variant(variant));
+                    Vec::new()
+                }
+                None => vec![format!("Error (unsupported variant): {}", args.join(" "))],
+            },
+            "setboard" => {
+                let fen = args.join(" ");
+                match State::from_fen_with_variant(&fen, self.variant.clone()) {
+                    Ok(state) => {
+                        let force = self.force;
+                        self.reset(state);
+                        self.force = force;
+                        Vec::new()
+                    }
+                    Err(error) => vec![format!("tellusererror Illegal position: {}", error.to_string())],
+                }
+            }
+            "usermove" => match args.first() {
+                Some(mv) => self.user_move(mv),
+                None => vec![String::from("Error (missing move): usermove")],
+            },
+            "go" => {
+                self.force = false;
+                self.engine = self.state.get_current_player();
+                self.think()
+            }
+            "playother" => {
+                self.force = false;
+                self.engine = self.state.get_current_player().opponent();
+                Vec::new()
+            }
+            "force" | "result" => {
+                self.force = true;
+                Vec::new()
+            }
+            "level" => option_answer(Level::parse(args).map(|level| self.level = Some(level)), line),
+            "st" => {
+                let seconds = args.first().and_then(|seconds| seconds.parse::<f64>().ok()).filter(|s| s.is_finite() && *s > 0.0);
+                option_answer(seconds.map(|seconds| self.move_time = Some(Duration::from_secs_f64(seconds))), line)
+            }
+            "sd" => {
+                let depth = args.first().and_then(|depth| depth.parse().ok()).filter(|&depth| depth > 0);
+                option_answer(depth.map(|depth| self.depth = Some(depth)), line)
+            }
+            "time" => option_answer(parse_centiseconds(args.first()).map(|time| self.time_left = Some(time)), line),
+            "otim" => option_answer(parse_centiseconds(args.first()).map(|time| self.opponent_time_left = Some(time)), line),
+            "egtpath" => match args {
+                ["syzygy", path @ ..] => match SyzygyFiles::open(&path.join(" ")) {
+                    Ok(files) => {
+                        self.tablebase = Some(Arc::new(files));
+                        Vec::new()
+                    }
+                    Err(error) => vec![format!("tellusererror Could not read the Syzygy tables: {}", error)],
+                },
+                _ => option_answer(None, line),
+            },
+            "undo" => self.take_back(1),
+            "remove" => self.take_back(2),
+            "post" => {
+                self.post = true;
+                Vec::new()
+            }
+            "nopost" => {
+                self.post = false;
+                Vec::new()
+            }
+            "ping" => vec![format!("pong {}", args.first().unwrap_or(&""))],
+            "quit" => {
+                self.quit = true;
+                Vec::new()
+            }
+            // Commands that need no answer, or that this engine ignores
+            "xboard" | "accepted" | "rejected" | "random" | "hard" | "easy" | "computer" |
+            "name" | "rating" | "ics" | "white" | "black" | "?" | "." | "draw" => Vec::new(),
+            // Without usermove=1 being accepted, moves come without a command
+            _ if Move::from_uci(command).is_some() => self.user_move(command),
+            _ => vec![format!("Error (unknown command): {}", command)],
+        }
+    }
+
+    // Starts over from `state`, leaving force mode with the engine playing black
+    fn reset(&mut self, state: State) {
+        self.start = state.clone();
+        self.state = state;
+        self.force = false;
+        self.engine = Player::Black;
+    }
+
+    fn take_back(&mut self, plies: usize) -> Vec<String> {
+        let history = self.state.get_history();
+        if history.len() < plies {
+            return vec![String::from("Error (no moves to take back): undo")];
+        }
+        let moves: Vec<Move> = history[..history.len() - plies].iter().map(|entry| entry.mv).collect();
+        let mut state = self.start.clone();
+        for mv in moves {
+            state.play_move(mv).expect("replayed move was legal before");
+        }
+        self.state = state;
+        Vec::new()
+    }
+
+    fn user_move(&mut self, uci: &str) -> Vec<String> {
+        let mv = match Move::from_uci(uci) {
+            Some(mv) => mv,
+            None => return vec![format!("Illegal move: {}", uci)],
+        };
+        if let Err(error) = self.state.play_move(mv) {
+            return vec![format!("Illegal move ({}): {}", error.to_string(), uci)];
+        }
+
+        let mut output = self.result_line();
+        if output.is_empty() && !self.force && self.state.get_current_player() == self.engine {
+            output = self.think();
+        }
+        output
+    }
+
+    // The result command once the game has ended
+    fn result_line(&self) -> Vec<String> {
+        let result = match self.state.get_game_status() {
+            GameStatus::GameWonByWhite => "1-0",
+            GameStatus::GameWonByBlack => "0-1",
+            GameStatus::Draw => "1/2-1/2",
+            GameStatus::WhiteTurn | GameStatus::BlackTurn => return Vec::new(),
+        };
+        match self.state.get_termination() {
+            Some(termination) => vec![format!("{} {{{}}}", result, termination.to_string())],
+            None => vec![result.to_string()],
+        }
+    }
+
+    // Time for the next move from `st`, or from the clock and the time control
+    fn get_move_time(&self) -> Option<Duration> {
+        if self.move_time.is_some() {
+            return self.move_time;
+        }
+        let time_left = match (self.time_left, self.level) {
+            (Some(time_left), _) => time_left,
+            (None, Some(level)) => level.base,
+            (None, None) => return if self.depth.is_some() { None } else { Some(DEFAULT_MOVE_TIME) },
+        };
+        let (moves_left, increment) = match self.level {
+            Some(level) if level.moves > 0 => {
+                let played = self.state.get_history().len() as u32 / 2;
+                (level.moves - played % level.moves, level.increment)
+            }
+            Some(level) => (DEFAULT_MOVES_LEFT, level.increment),
+            None => (DEFAULT_MOVES_LEFT, Duration::from_secs(0)),
+        };
+        // Some of a lead on the opponent's clock is spent, and when behind the
+        // time is planned as if there were less of it
+        let planned = match self.opponent_time_left {
+            Some(opponent) if opponent > time_left => time_left.saturating_sub((opponent - time_left) / 2).max(time_left / 2),
+            Some(opponent) => time_left + (time_left - opponent) / 4,
+            None => time_left,
+        };
+        Some(search::allocate_time(planned, moves_left, increment).min(time_left / 2))
+    }
+
+    // Thinking output: depth, score in centipawns, time in centiseconds, nodes
+    // and the principal variation
+    fn thinking_line(&self, info: &SearchInfo) -> String {
+        let score = match info.score {
+            Score::Centipawns(centipawns) => centipawns,
+            // XBoard shows 100000 + N as mate in N
+            Score::Mate(moves) if moves > 0 => 100_000 + moves,
+            Score::Mate(moves) => -100_000 + moves,
+        };
+        let pv = self.state.line_to_san(&info.pv);
+        format!("{} {} {} {} {}", info.depth, score, info.time.as_millis() / 10, info.nodes, pv.join(" "))
+    }
+
+    fn think(&mut self) -> Vec<String> {
+        if !self.state.get_game_running() {
+            return Vec::new();
+        }
+
+        let limits = SearchLimits {
+            depth: self.depth,
+            time: self.get_move_time(),
+            tablebase: self.tablebase.clone(),
+            ..SearchLimits::default()
+        };
+        let mut output = Vec::new();
+        let post = self.post;
+        let result = search::search(&self.state, &limits, &mut |info| {
+            if post {
+                output.push(self.thinking_line(info));
+            }
+        });
+
+        if let Some(mv) = result.best_move() {
+            self.state.play_move(mv).expect("search returned an illegal move");
+            output.push(format!("move {}", mv.to_uci()));
+            output.extend(self.result_line());
+        }
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(xboard: &mut XBoard, script: &[&str]) -> Vec<String> {
+        script.iter().flat_map(|line| xboard.handle(line)).collect()
+    }
+
+    #[test]
+    fn handshake_test() {
+        let mut xboard = XBoard::new();
+        let output = run(&mut xboard, &["xboard", "protover 2", "accepted setboard", "ping 7"]);
+        assert_eq!(output.len(), 2);
+        assert!(output[0].starts_with("feature "));
+        assert!(output[0].contains(" usermove=1 ") && output[0].ends_with(" done=1"));
+        assert_eq!(output[1], "pong 7");
+
+        assert_eq!(run(&mut xboard, &["frobnicate"]), vec!["Error (unknown command): frobnicate"]);
+        assert_eq!(run(&mut xboard, &["sd x"]), vec!["Error (invalid arguments): sd x"]);
+        assert_eq!(run(&mut xboard, &["egtpath gaviota /tb"]), vec!["Error (invalid arguments): egtpath gaviota /tb"]);
+        assert!(run(&mut xboard, &["egtpath syzygy /no/such/dir"])[0].starts_with("tellusererror "));
+        assert!(xboard.tablebase.is_none());
+        assert!(!xboard.has_quit());
+        run(&mut xboard, &["quit"]);
+        assert!(xboard.has_quit());
+    }
+
+    #[test]
+    fn play_test() {
+        let mut xboard = XBoard::new();
+        // The engine answers as black after each move
+        let output = run(&mut xboard, &["new", "sd 1", "usermove e2e4"]);
+        assert_eq!(output.len(), 1);
+        assert!(output[0].starts_with("move "));
+        assert_eq!(xboard.get_state().get_history().len(), 2);
+
+        let output = run(&mut xboard, &["usermove e2e5"]);
+        assert_eq!(output, vec![format!("Illegal move ({}): e2e5", crate::MoveError::NoSourcePiece.to_string())]);
+
+        // In force mode moves are only recorded, and undo and remove take them back
+        let output = run(&mut xboard, &["force", "usermove d2d4", "usermove d7d5", "remove", "undo"]);
+        assert!(output.is_empty());
+        assert_eq!(xboard.get_state().get_history().len(), 1);
+        assert_eq!(xboard.get_state().get_current_player(), Player::Black);
+
+        let output = run(&mut xboard, &["go"]);
+        assert!(output[0].starts_with("move "));
+        assert_eq!(xboard.get_state().get_history().len(), 2);
+    }
+
+    #[test]
+    fn mate_test() {
+        let mut xboard = XBoard::new();
+        let output = run(&mut xboard, &["new", "force", "setboard 6k1/5ppp/8/8/8/8/8/R5K1 w - - 0 1", "post", "sd 3", "go"]);
+        assert_eq!(output.len(), 3);
+        assert!(output[0].starts_with("1 100001 "));
+        assert!(output[0].ends_with(" Ra8#"));
+        assert_eq!(output[1], "move a1a8");
+        assert_eq!(output[2], "1-0 {checkmate}");
+
+        let output = run(&mut xboard, &["setboard 8/8/8 w - - 0 1"]);
+        assert!(output[0].starts_with("tellusererror Illegal position"));
+    }
+
+    #[test]
+    fn time_test() {
+        let mut xboard = XBoard::new();
+        run(&mut xboard, &["new", "level 40 5 0", "time 30000"]);
+        assert_eq!(xboard.get_move_time().unwrap().as_millis(), 7500);
+        run(&mut xboard, &["otim 60000"]);
+        assert_eq!(xboard.get_move_time().unwrap().as_millis(), 3750);
+        run(&mut xboard, &["otim 10000"]);
+        assert_eq!(xboard.get_move_time().unwrap().as_millis(), 8750);
+        run(&mut xboard, &["level 0 2:30 2", "time 1000", "otim 1000"]);
+        assert_eq!(xboard.get_move_time().unwrap().as_millis(), 1833);
+        run(&mut xboard, &["st 1.5"]);
+        assert_eq!(xboard.get_move_time().unwrap().as_millis(), 1500);
+    }
+
+    #[test]
+    fn variant_test() {
+        let mut xboard = XBoard::new();
+        let output = run(&mut xboard, &["new", "variant kingofthehill", "force",
+                                        "setboard 4k3/8/8/8/8/2K5/8/8 w - - 0 1", "sd 1", "go"]);
+        assert_eq!(output, vec!["move c3d4", "1-0 {king reached the centre}"]);
+        assert_eq!(run(&mut xboard, &["variant shogi"]), vec!["Error (unsupported variant): shogi"]);
+    }
+}
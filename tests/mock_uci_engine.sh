@@ -0,0 +1,58 @@
+#!/bin/sh
+# A stand-in UCI engine for the tests. It knows the answers for the first two
+# plies of one game and answers 0000 for everything else.
+
+position=""
+infinite=0
+
+while read -r line; do
+    case "$line" in
+        uci)
+            echo "id name Mock Engine"
+            echo "id author Nobody"
+            echo "option name Hash type spin default 16 min 1 max 1024"
+            echo "option name Ponder type check default false"
+            echo "uciok"
+            ;;
+        isready)
+            echo "readyok"
+            ;;
+        setoption*)
+            echo "info string $line"
+            ;;
+        ucinewgame)
+            position=""
+            ;;
+        position*)
+            position="$line"
+            ;;
+        go*infinite*)
+            infinite=1
+            ;;
+        go*)
+            case "$position" in
+                "position startpos")
+                    echo "info depth 1 score cp 30 nodes 20 time 1 pv e2e4 e7e5"
+                    echo "bestmove e2e4 ponder e7e5"
+                    ;;
+                "position startpos moves e2e4")
+                    echo "info depth 1 score cp -25 nodes 20 time 1 pv e7e5 g1f3"
+                    echo "bestmove e7e5"
+                    ;;
+                *)
+                    echo "bestmove 0000"
+                    ;;
+            esac
+            ;;
+        stop)
+            if [ "$infinite" = 1 ]; then
+                infinite=0
+                echo "info depth 5 score cp 10 pv a2a3"
+                echo "bestmove a2a3"
+            fi
+            ;;
+        quit)
+            exit 0
+            ;;
+    esac
+done
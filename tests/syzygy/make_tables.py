@@ -0,0 +1,365 @@
+#!/usr/bin/env python3
+"""Writes the small Syzygy tables the tests of src/tablebase.rs read.
+
+The tables are not from the official generator. This script solves KRvK and
+KQvK by retrograde analysis on its own and writes the results in the Syzygy
+file format: the positions numbered like the probing code of the format does,
+the values Huffman coded over pairs of symbols, split into blocks with an
+index. Like real tables, the WDL tables hold made up values where a capture
+is at least as good as the best quiet move.
+
+    python3 tests/syzygy/make_tables.py tests/syzygy
+"""
+
+import heapq
+import os
+import struct
+import sys
+from collections import Counter
+
+WDL_MAGIC = bytes([0x71, 0xE8, 0x23, 0x5D])
+DTZ_MAGIC = bytes([0xD7, 0x66, 0x0C, 0xA5])
+
+# DTZ flags
+MAPPED = 2
+WIN_PLIES = 4
+LOSS_PLIES = 8
+
+KING, QUEEN, ROOK = 6, 5, 4
+BLACK = 8
+
+TRIANGLE = [
+    6, 0, 1, 2, 2, 1, 0, 6,
+    0, 7, 3, 4, 4, 3, 7, 0,
+    1, 3, 8, 5, 5, 8, 3, 1,
+    2, 4, 5, 9, 9, 5, 4, 2,
+    2, 4, 5, 9, 9, 5, 4, 2,
+    1, 3, 8, 5, 5, 8, 3, 1,
+    0, 7, 3, 4, 4, 3, 7, 0,
+    6, 0, 1, 2, 2, 1, 0, 6,
+]
+POSITIONS = 31332
+
+
+def file_of(square):
+    return square & 7
+
+
+def rank_of(square):
+    return square >> 3
+
+
+def off_diagonal(square):
+    if rank_of(square) > file_of(square):
+        return 1
+    if rank_of(square) < file_of(square):
+        return -1
+    return 0
+
+
+def lower(square):
+    f, r = file_of(square), rank_of(square)
+    low, high = min(f, r), max(f, r)
+    if low == high:
+        return 28 + low
+    return sum(7 - i for i in range(low)) + high - low - 1
+
+
+def encode(squares):
+    """The number of the position of three different pieces."""
+    p = list(squares)
+    if p[0] & 0x04:
+        p = [s ^ 0x07 for s in p]
+    if p[0] & 0x20:
+        p = [s ^ 0x38 for s in p]
+    first = next((i for i in range(3) if off_diagonal(p[i])), 3)
+    if first < 3 and off_diagonal(p[first]) > 0:
+        p = [((s >> 3) | (s << 3)) & 63 for s in p]
+    s1 = int(p[1] > p[0])
+    s2 = int(p[2] > p[0]) + int(p[2] > p[1])
+    if off_diagonal(p[0]):
+        return TRIANGLE[p[0]] * 63 * 62 + (p[1] - s1) * 62 + (p[2] - s2)
+    if off_diagonal(p[1]):
+        return 6 * 63 * 62 + rank_of(p[0]) * 28 * 62 + lower(p[1]) * 62 + p[2] - s2
+    if off_diagonal(p[2]):
+        return (6 * 63 * 62 + 4 * 28 * 62 + rank_of(p[0]) * 7 * 28 + (rank_of(p[1]) - s1) * 28
+                + lower(p[2]))
+    return (6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank_of(p[0]) * 7 * 6 + (rank_of(p[1]) - s1) * 6
+            + rank_of(p[2]) - s2)
+
+
+def square(f, r):
+    return r * 8 + f if 0 <= f < 8 and 0 <= r < 8 else None
+
+
+def adjacent(a, b):
+    return a != b and abs(file_of(a) - file_of(b)) <= 1 and abs(rank_of(a) - rank_of(b)) <= 1
+
+
+def king_steps(s):
+    return [t for t in range(64) if adjacent(s, t)]
+
+
+def slides(s, directions, blockers):
+    targets = []
+    for df, dr in directions:
+        f, r = file_of(s) + df, rank_of(s) + dr
+        while square(f, r) is not None:
+            t = square(f, r)
+            targets.append(t)
+            if t in blockers:
+                break
+            f, r = f + df, r + dr
+    return targets
+
+
+ROOK_DIRECTIONS = [(1, 0), (-1, 0), (0, 1), (0, -1)]
+QUEEN_DIRECTIONS = ROOK_DIRECTIONS + [(1, 1), (1, -1), (-1, 1), (-1, -1)]
+
+
+def solve(directions):
+    """The distance to mate in plies of every position, white to move and black
+    to move, by position number. Draws are None."""
+    white = {}
+    black = {}
+    for wk in range(64):
+        for x in range(64):
+            for bk in range(64):
+                if len({wk, x, bk}) < 3 or adjacent(wk, bk):
+                    continue
+                index = encode((wk, x, bk))
+                if bk not in slides(x, directions, {wk, bk}):
+                    white.setdefault(index, (wk, x, bk))
+                black.setdefault(index, (wk, x, bk))
+
+    # The moves of each position by the numbers they lead to, and for black
+    # whether the rook or queen can be taken
+    white_moves = {}
+    for index, (wk, x, bk) in white.items():
+        children = [encode((t, x, bk)) for t in king_steps(wk) if t != x and not adjacent(t, bk)]
+        children += [encode((wk, t, bk)) for t in slides(x, directions, {wk, bk}) if t not in (wk, bk)]
+        white_moves[index] = children
+    black_moves = {}
+    black_captures = {}
+    in_check = {}
+    for index, (wk, x, bk) in black.items():
+        attacked = set(slides(x, directions, {wk}))
+        children = []
+        capture = False
+        for t in king_steps(bk):
+            if t == wk or adjacent(t, wk):
+                continue
+            if t == x:
+                capture = True
+            elif t not in attacked:
+                children.append(encode((wk, x, t)))
+        black_moves[index] = children
+        black_captures[index] = capture
+        in_check[index] = bk in attacked
+
+    white_dtm = {}
+    black_dtm = {}
+    for index in black:
+        if not black_moves[index] and not black_captures[index] and in_check[index]:
+            black_dtm[index] = 0
+    plies = 1
+    changed = True
+    while changed:
+        changed = False
+        if plies % 2 == 1:
+            for index, children in white_moves.items():
+                if index not in white_dtm and any(black_dtm.get(child) == plies - 1 for child in children):
+                    white_dtm[index] = plies
+                    changed = True
+        else:
+            for index, children in black_moves.items():
+                if index in black_dtm or black_captures[index] or not children:
+                    continue
+                if all(child in white_dtm for child in children):
+                    black_dtm[index] = 1 + max(white_dtm[child] for child in children)
+                    changed = True
+        plies += 1
+        changed = changed or plies % 2 == 0
+    return white, black, white_dtm, black_dtm, black_moves, black_captures
+
+
+def huffman_lengths(frequencies):
+    heap = [(frequency, i, [i]) for i, frequency in enumerate(frequencies)]
+    heapq.heapify(heap)
+    lengths = [0] * len(frequencies)
+    counter = len(frequencies)
+    while len(heap) > 1:
+        f1, _, s1 = heapq.heappop(heap)
+        f2, _, s2 = heapq.heappop(heap)
+        for symbol in s1 + s2:
+            lengths[symbol] += 1
+        heapq.heappush(heap, (f1 + f2, counter, s1 + s2))
+        counter += 1
+    return lengths
+
+
+def compress(values, block_size, index_bits):
+    """The pairs header, index table, size table and data of one part."""
+    # Leaf symbols for each value, then pairs of the most frequent neighbours
+    leaves = sorted(set(values))
+    children = [(value, 0xFFF) for value in leaves]
+    expansion = [1] * len(leaves)
+    sequence = [leaves.index(value) for value in values]
+    while len(children) < 400:
+        counts = Counter(zip(sequence, sequence[1:]))
+        (a, b), count = counts.most_common(1)[0]
+        if count < 8:
+            break
+        new = len(children)
+        children.append((a, b))
+        expansion.append(expansion[a] + expansion[b])
+        replaced = []
+        i = 0
+        while i < len(sequence):
+            if i + 1 < len(sequence) and sequence[i] == a and sequence[i + 1] == b:
+                replaced.append(new)
+                i += 2
+            else:
+                replaced.append(sequence[i])
+                i += 1
+        sequence = replaced
+    if len(children) == 1:
+        children.append((leaves[0], 0xFFF))
+        expansion.append(1)
+
+    frequencies = [0] * len(children)
+    for symbol in sequence:
+        frequencies[symbol] += 1
+    lengths = huffman_lengths(frequencies)
+    assert max(lengths) <= 32
+
+    # Longer codes get the lower symbol numbers
+    order = sorted(range(len(children)), key=lambda symbol: (-lengths[symbol], symbol))
+    number = {symbol: n for n, symbol in enumerate(order)}
+    min_length, max_length = min(lengths), max(lengths)
+    offsets = [sum(1 for length in lengths if length > min_length + i) for i in range(max_length - min_length + 1)]
+    base = [0] * len(offsets)
+    for i in range(len(offsets) - 2, -1, -1):
+        base[i] = (base[i + 1] + offsets[i] - offsets[i + 1]) // 2
+    codes = {}
+    for symbol in range(len(children)):
+        i = lengths[symbol] - min_length
+        codes[symbol] = (base[i] + number[symbol] - offsets[i], lengths[symbol])
+
+    # Whole symbols go into blocks of 2^block_size bytes
+    block_bytes = 1 << block_size
+    blocks = []
+    counts = []
+    bits, used, count = 0, 0, 0
+    for symbol in sequence:
+        code, length = codes[symbol]
+        if used + length > 8 * block_bytes or count + expansion[symbol] > 65536:
+            blocks.append(bits << (8 * block_bytes - used))
+            counts.append(count)
+            bits, used, count = 0, 0, 0
+        bits = (bits << length) | code
+        used += length
+        count += expansion[symbol]
+    blocks.append(bits << (8 * block_bytes - used))
+    counts.append(count)
+    data = b"".join(block.to_bytes(block_bytes, "big") for block in blocks)
+
+    starts = [sum(counts[:i]) for i in range(len(counts))]
+    index_table = b""
+    for k in range((len(values) + (1 << index_bits) - 1) >> index_bits):
+        middle = (k << index_bits) + (1 << (index_bits - 1))
+        block = max(i for i in range(len(starts)) if starts[i] <= middle)
+        index_table += struct.pack("<IH", block, middle - starts[block])
+    size_table = b"".join(struct.pack("<H", count - 1) for count in counts)
+
+    header = bytes([0, block_size, index_bits, 0]) + struct.pack("<I", len(blocks))
+    header += bytes([max_length, min_length])
+    header += b"".join(struct.pack("<H", offset) for offset in offsets)
+    header += struct.pack("<H", len(children))
+    for symbol in order:
+        left, right = children[symbol]
+        if right != 0xFFF:
+            left, right = number[left], number[right]
+        header += bytes([left & 0xFF, (left >> 8) | ((right & 0xF) << 4), right >> 4])
+    if len(children) % 2:
+        header += b"\0"
+    return header, index_table, size_table, data
+
+
+def table_file(magic, split, pieces, parts, maps=b""):
+    out = bytearray(magic)
+    out.append(1 if split else 0)
+    out.append(0)
+    for code in pieces:
+        out.append(code | (code << 4) if split else code)
+    if len(out) % 2:
+        out.append(0)
+    for i, (header, _, _, _) in enumerate(parts):
+        if i == 0 and magic == DTZ_MAGIC:
+            header = bytes([header[0] | parts_flags[0]]) + header[1:]
+        out += header
+    out += maps
+    if len(out) % 2:
+        out.append(0)
+    for _, index_table, _, _ in parts:
+        out += index_table
+    for _, _, size_table, _ in parts:
+        out += size_table
+    for _, _, _, data in parts:
+        out += b"\0" * (-len(out) % 64)
+        out += data
+    return bytes(out + b"\0" * 16)
+
+
+parts_flags = [0]
+
+
+def write_tables(directory, key, directions, piece, mapped):
+    white, black, white_dtm, black_dtm, black_moves, black_captures = solve(directions)
+    print(key, "longest win", max(white_dtm.values()), "plies")
+
+    # WDL values plus 2, white to move and black to move. Where capturing is
+    # as good as the best quiet move any value that is not higher will do,
+    # and when every move captures any value at all.
+    white_values, black_values = [], []
+    for index in range(POSITIONS):
+        if index in white:
+            white_values.append(4 if index in white_dtm else 2)
+        else:
+            white_values.append(white_values[-1] if white_values else 2)
+        if index not in black:
+            black_values.append(black_values[-1] if black_values else 2)
+        elif black_captures[index] and not black_moves[index]:
+            black_values.append(4)
+        elif black_captures[index]:
+            black_values.append(min(black_values[-1] if black_values else 0, 2))
+        else:
+            black_values.append(0 if index in black_dtm else 2)
+    parts_flags[0] = 0
+    wdl = table_file(WDL_MAGIC, True, [KING, piece, KING | BLACK],
+                     [compress(white_values, 6, 10), compress(black_values, 6, 10)])
+    with open(os.path.join(directory, key + ".rtbw"), "wb") as f:
+        f.write(wdl)
+
+    # DTZ for white to move, in plies less one, since nothing is ever taken
+    plies = []
+    for index in range(POSITIONS):
+        if index in white_dtm:
+            plies.append(white_dtm[index] - 1)
+        else:
+            plies.append(plies[-1] if plies else 0)
+    maps = b""
+    if mapped:
+        distinct = sorted(set(plies))
+        plies = [distinct.index(value) for value in plies]
+        maps = bytes([len(distinct)]) + bytes(distinct) + b"\0\0\0"
+    parts_flags[0] = WIN_PLIES | LOSS_PLIES | (MAPPED if mapped else 0)
+    dtz = table_file(DTZ_MAGIC, False, [KING, piece, KING | BLACK], [compress(plies, 5, 9)], maps)
+    with open(os.path.join(directory, key + ".rtbz"), "wb") as f:
+        f.write(dtz)
+
+
+if __name__ == "__main__":
+    directory = sys.argv[1] if len(sys.argv) > 1 else os.path.dirname(os.path.abspath(__file__))
+    write_tables(directory, "KRvK", ROOK_DIRECTIONS, ROOK, False)
+    write_tables(directory, "KQvK", QUEEN_DIRECTIONS, QUEEN, True)